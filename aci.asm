@@ -0,0 +1,150 @@
+; magpie ACI firmware: XXXX.YYYYR reads a tape into memory, XXXX.YYYYW
+; writes memory to tape, several ranges per line, Return goes back to Wozmon.
+; Bits are one full cycle of TAPEOUT, 1000 Hz for 1 and 2000 Hz for 0, MSB
+; first, after a leader of 1 bits and a single 0 sync bit.
+
+L       = $28
+H       = $29
+STL     = $26
+STH     = $27
+YSAV    = $2A
+LAST    = $2E
+BYTE    = $2F
+DLY     = $2D
+IN      = $0200
+KBD     = $D010
+KBDCR   = $D011
+ESCAPE  = $FF1A
+ECHO    = $FFEF
+TAPEOUT = $C000
+TAPEIN  = $C081
+ZERO    = 46
+ONE     = 97
+THRESH  = 64
+HALF    = 32
+LEADER  = 8
+
+* = C100
+C100   A9 8D     ACI       LDA #$8D
+C102   20 EF FF            JSR ECHO
+C105   A9 AA               LDA #$AA
+C107   20 EF FF            JSR ECHO
+C10A   A0 FF               LDY #$FF
+C10C   C8        NEXTCHAR  INY
+C10D   AD 11 D0  KEY       LDA KBDCR
+C110   10 FB               BPL KEY
+C112   AD 10 D0            LDA KBD
+C115   99 00 02            STA IN,Y
+C118   20 EF FF            JSR ECHO
+C11B   C9 9B               CMP #$9B
+C11D   F0 E1               BEQ ACI
+C11F   C9 8D               CMP #$8D
+C121   D0 E9               BNE NEXTCHAR
+C123   A0 FF               LDY #$FF
+C125   A9 00     CLEAR     LDA #0
+C127   85 28               STA L
+C129   85 29               STA H
+C12B   C8        NEXTITEM  INY
+C12C   B9 00 02            LDA IN,Y
+C12F   C9 8D               CMP #$8D
+C131   D0 03               BNE NOTCR
+C133   4C 1A FF            JMP ESCAPE
+C136   C9 AE     NOTCR     CMP #$AE
+C138   D0 0A               BNE NOTDOT
+C13A   A5 28               LDA L
+C13C   85 26               STA STL
+C13E   A5 29               LDA H
+C140   85 27               STA STH
+C142   B0 E1               BCS CLEAR
+C144   C9 D2     NOTDOT    CMP #$D2
+C146   F0 4D               BEQ READ
+C148   C9 D7               CMP #$D7
+C14A   F0 1C               BEQ WRITE
+C14C   49 B0               EOR #$B0
+C14E   C9 0A               CMP #$0A
+C150   90 06               BCC DIG
+C152   69 88               ADC #$88
+C154   C9 FA               CMP #$FA
+C156   90 D3               BCC NEXTITEM
+C158   0A        DIG       ASL A
+C159   0A                  ASL A
+C15A   0A                  ASL A
+C15B   0A                  ASL A
+C15C   A2 04               LDX #4
+C15E   0A        HEXSHIFT  ASL A
+C15F   26 28               ROL L
+C161   26 29               ROL H
+C163   CA                  DEX
+C164   D0 F8               BNE HEXSHIFT
+C166   F0 C3               BEQ NEXTITEM
+C168   84 2A     WRITE     STY YSAV
+C16A   A0 08               LDY #LEADER
+C16C   38        WLEAD     SEC
+C16D   20 D4 C1            JSR WRBIT
+C170   C6 2F               DEC BYTE
+C172   D0 F8               BNE WLEAD
+C174   88                  DEY
+C175   D0 F5               BNE WLEAD
+C177   18                  CLC
+C178   20 D4 C1            JSR WRBIT
+C17B   A2 00     WBYTE     LDX #0
+C17D   A1 26               LDA (STL,X)
+C17F   85 2F               STA BYTE
+C181   A0 08               LDY #8
+C183   06 2F     WBIT      ASL BYTE
+C185   20 D4 C1            JSR WRBIT
+C188   88                  DEY
+C189   D0 F8               BNE WBIT
+C18B   20 C5 C1            JSR NEXTADR
+C18E   90 EB               BCC WBYTE
+C190   AD 00 C0            LDA TAPEOUT
+C193   B0 2B               BCS DONE
+C195   84 2A     READ      STY YSAV
+C197   A2 40     RLEAD     LDX #64
+C199   20 E8 C1  RLEAD1    JSR RDCYC
+C19C   90 F9               BCC RLEAD
+C19E   CA                  DEX
+C19F   D0 F8               BNE RLEAD1
+C1A1   A0 00     RSYNC     LDY #0
+C1A3   20 ED C1            JSR RDHALF
+C1A6   C0 20               CPY #HALF
+C1A8   B0 F7               BCS RSYNC
+C1AA   20 ED C1            JSR RDHALF
+C1AD   A2 08     RBYTE     LDX #8
+C1AF   20 E8 C1  RBIT      JSR RDCYC
+C1B2   26 2F               ROL BYTE
+C1B4   CA                  DEX
+C1B5   D0 F8               BNE RBIT
+C1B7   A5 2F               LDA BYTE
+C1B9   81 26               STA (STL,X)
+C1BB   20 C5 C1            JSR NEXTADR
+C1BE   90 ED               BCC RBYTE
+C1C0   A4 2A     DONE      LDY YSAV
+C1C2   4C 25 C1            JMP CLEAR
+C1C5   A5 26     NEXTADR   LDA STL
+C1C7   C5 28               CMP L
+C1C9   A5 27               LDA STH
+C1CB   E5 29               SBC H
+C1CD   E6 26               INC STL
+C1CF   D0 02               BNE NA1
+C1D1   E6 27               INC STH
+C1D3   60        NA1       RTS
+C1D4   A2 2E     WRBIT     LDX #ZERO
+C1D6   90 02               BCC WRB1
+C1D8   A2 61               LDX #ONE
+C1DA   86 2D     WRB1      STX DLY
+C1DC   20 E1 C1            JSR WRHALF
+C1DF   A6 2D               LDX DLY
+C1E1   AD 00 C0  WRHALF    LDA TAPEOUT
+C1E4   CA        WRDLY     DEX
+C1E5   D0 FD               BNE WRDLY
+C1E7   60                  RTS
+C1E8   A0 00     RDCYC     LDY #0
+C1EA   20 ED C1            JSR RDHALF
+C1ED   C8        RDHALF    INY
+C1EE   AD 81 C0            LDA TAPEIN
+C1F1   C5 2E               CMP LAST
+C1F3   F0 F8               BEQ RDHALF
+C1F5   85 2E               STA LAST
+C1F7   C0 40               CPY #THRESH
+C1F9   60                  RTS
@@ -0,0 +1,62 @@
+; magpie CFFA1 firmware: 9000R opens the menu, JSR $900C with the command in
+; X calls the API. The commands themselves run in the emulator behind the
+; registers at $AFF0-$AFF4; see src/cffa1/mod.rs.
+
+IN      = $0200
+KBD     = $D010
+KBDCR   = $D011
+GETLINE = $FF1F
+ECHO    = $FFEF
+COMMAND = $AFF0
+STATUS  = $AFF1
+RESULT  = $AFF2
+OUTPUT  = $AFF3
+ARGUMENT = $AFF4
+MENUSTART = $F0
+MENULINE = $F2
+QUIT    = $FF
+
+* = 9000
+9000   4C 2E 90            JMP MENU
+9003   00 00 00 00 00 00 00 00 00           DB 0,0,0,0,0,0,0,0,0
+900C   E0 02     API       CPX #$02
+900E   F0 1E               BEQ MENU
+9010   8D F4 AF            STA ARGUMENT
+9013   8E F0 AF            STX COMMAND
+9016   20 22 90            JSR PRINT
+9019   AD F1 AF            LDA STATUS
+901C   C9 01               CMP #1
+901E   AD F2 AF            LDA RESULT
+9021   60                  RTS
+9022   AD F3 AF  PRINT     LDA OUTPUT
+9025   F0 06               BEQ PDONE
+9027   20 EF FF            JSR ECHO
+902A   4C 22 90            JMP PRINT
+902D   60        PDONE     RTS
+902E   A2 F0     MENU      LDX #MENUSTART
+9030   8E F0 AF            STX COMMAND
+9033   20 22 90            JSR PRINT
+9036   A0 00     LINE      LDY #0
+9038   AD 11 D0  KEY       LDA KBDCR
+903B   10 FB               BPL KEY
+903D   AD 10 D0            LDA KBD
+9040   20 EF FF            JSR ECHO
+9043   C9 DF               CMP #$DF
+9045   F0 1E               BEQ RUBOUT
+9047   C9 9B               CMP #$9B
+9049   F0 E3               BEQ MENU
+904B   99 00 02            STA IN,Y
+904E   C8                  INY
+904F   C9 8D               CMP #$8D
+9051   D0 E5               BNE KEY
+9053   A2 F2               LDX #MENULINE
+9055   8E F0 AF            STX COMMAND
+9058   20 22 90            JSR PRINT
+905B   AD F1 AF            LDA STATUS
+905E   C9 FF               CMP #QUIT
+9060   D0 D4               BNE LINE
+9062   4C 1F FF            JMP GETLINE
+9065   C0 00     RUBOUT    CPY #0
+9067   F0 CF               BEQ KEY
+9069   88                  DEY
+906A   4C 38 90            JMP KEY
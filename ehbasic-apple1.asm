@@ -0,0 +1,55 @@
+; Apple 1 glue for ehbasic.bin: E900R sets up the EhBASIC I/O vectors in page 2
+; to use the Apple 1 keyboard and Wozmon ECHO, then cold starts BASIC at $C000.
+
+KBD     = $D010
+KBDCR   = $D011
+ECHO    = $FFEF
+COLD    = $C000
+VECTORS = $0205
+
+* = E900
+E900   D8        START     CLD
+E901   A2 FF               LDX #$FF
+E903   9A                  TXS
+E904   A0 1C               LDY #$1C
+E906   B9 32 E9  COPY      LDA TABLE-1,Y
+E909   99 04 02            STA VECTORS-1,Y
+E90C   88                  DEY
+E90D   D0 F7               BNE COPY
+E90F   4C 00 C0            JMP COLD
+E912   AD 11 D0  INPUT     LDA KBDCR
+E915   10 0D               BPL NOKEY
+E917   AD 10 D0            LDA KBD
+E91A   29 7F               AND #$7F
+E91C   C9 5F               CMP #$5F
+E91E   D0 02               BNE GOTKEY
+E920   A9 08               LDA #$08
+E922   38        GOTKEY    SEC
+E923   60                  RTS
+E924   18        NOKEY     CLC
+E925   60                  RTS
+E926   C9 0A     OUTPUT    CMP #$0A
+E928   F0 07               BEQ OUTDONE
+E92A   48                  PHA
+E92B   09 80               ORA #$80
+E92D   20 EF FF            JSR ECHO
+E930   68                  PLA
+E931   60        OUTDONE   RTS
+E932   60        NOOP      RTS
+E933   12 E9 26 E9 32 E9 32 E9 TABLE     DB INPUT&$FF,INPUT>>8,OUTPUT&$FF,OUTPUT>>8,NOOP&$FF,NOOP>>8,NOOP&$FF,NOOP>>8
+E93B   48 A5 DF 4A 05 DF 85 DF 68 40           DB $48,$A5,$DF,$4A,$05,$DF,$85,$DF,$68,$40
+E945   48 A5 DC 4A 05 DC 85 DC 68 40           DB $48,$A5,$DC,$4A,$05,$DC,$85,$DC,$68,$40
+
+; The Apple 1 PIA mirrors across $Dxxx would land on EhBASIC code, so this
+; profile decodes only $D010-$D013. EhBASIC's $D00B-$D013 routine is patched
+; to JMP INDEX and the two branches to its RTS at $D013 now go to $D00E:
+;   D00B   4C 4F E9            JMP INDEX
+;   D00E   60                  RTS
+;   CFBF   4E                  (BNE $D00E)
+;   D06E   9F                  (BNE $D00E)
+E94F   98        INDEX     TYA
+E950   65 A5               ADC $A5
+E952   85 96               STA $96
+E954   A8                  TAY
+E955   A5 95               LDA $95
+E957   60                  RTS
@@ -0,0 +1,285 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use clock::APPLE1_HZ;
+use device::Device;
+
+mod tape;
+mod wav;
+
+pub use self::tape::{Block, Tape};
+
+/// Stand-in firmware for the card's ROM at $C100, listed in aci.asm. It is
+/// not Apple's code: it follows the original command syntax (`C100R`, then
+/// `XXXX.YYYYR` or `XXXX.YYYYW`) and tape encoding, but software that calls
+/// into the genuine ROM at other entry points will not work with it. A dump
+/// of the genuine ROM can be loaded instead with `Aci::with_rom`.
+pub const ACI_ROM: [u8; 256] = [
+    0xa9, 0x8d, 0x20, 0xef, 0xff, 0xa9, 0xaa, 0x20, 0xef, 0xff, 0xa0, 0xff, 0xc8, 0xad, 0x11, 0xd0,
+    0x10, 0xfb, 0xad, 0x10, 0xd0, 0x99, 0x00, 0x02, 0x20, 0xef, 0xff, 0xc9, 0x9b, 0xf0, 0xe1, 0xc9,
+    0x8d, 0xd0, 0xe9, 0xa0, 0xff, 0xa9, 0x00, 0x85, 0x28, 0x85, 0x29, 0xc8, 0xb9, 0x00, 0x02, 0xc9,
+    0x8d, 0xd0, 0x03, 0x4c, 0x1a, 0xff, 0xc9, 0xae, 0xd0, 0x0a, 0xa5, 0x28, 0x85, 0x26, 0xa5, 0x29,
+    0x85, 0x27, 0xb0, 0xe1, 0xc9, 0xd2, 0xf0, 0x4d, 0xc9, 0xd7, 0xf0, 0x1c, 0x49, 0xb0, 0xc9, 0x0a,
+    0x90, 0x06, 0x69, 0x88, 0xc9, 0xfa, 0x90, 0xd3, 0x0a, 0x0a, 0x0a, 0x0a, 0xa2, 0x04, 0x0a, 0x26,
+    0x28, 0x26, 0x29, 0xca, 0xd0, 0xf8, 0xf0, 0xc3, 0x84, 0x2a, 0xa0, 0x08, 0x38, 0x20, 0xd4, 0xc1,
+    0xc6, 0x2f, 0xd0, 0xf8, 0x88, 0xd0, 0xf5, 0x18, 0x20, 0xd4, 0xc1, 0xa2, 0x00, 0xa1, 0x26, 0x85,
+    0x2f, 0xa0, 0x08, 0x06, 0x2f, 0x20, 0xd4, 0xc1, 0x88, 0xd0, 0xf8, 0x20, 0xc5, 0xc1, 0x90, 0xeb,
+    0xad, 0x00, 0xc0, 0xb0, 0x2b, 0x84, 0x2a, 0xa2, 0x40, 0x20, 0xe8, 0xc1, 0x90, 0xf9, 0xca, 0xd0,
+    0xf8, 0xa0, 0x00, 0x20, 0xed, 0xc1, 0xc0, 0x20, 0xb0, 0xf7, 0x20, 0xed, 0xc1, 0xa2, 0x08, 0x20,
+    0xe8, 0xc1, 0x26, 0x2f, 0xca, 0xd0, 0xf8, 0xa5, 0x2f, 0x81, 0x26, 0x20, 0xc5, 0xc1, 0x90, 0xed,
+    0xa4, 0x2a, 0x4c, 0x25, 0xc1, 0xa5, 0x26, 0xc5, 0x28, 0xa5, 0x27, 0xe5, 0x29, 0xe6, 0x26, 0xd0,
+    0x02, 0xe6, 0x27, 0x60, 0xa2, 0x2e, 0x90, 0x02, 0xa2, 0x61, 0x86, 0x2d, 0x20, 0xe1, 0xc1, 0xa6,
+    0x2d, 0xad, 0x00, 0xc0, 0xca, 0xd0, 0xfd, 0x60, 0xa0, 0x00, 0x20, 0xed, 0xc1, 0xc8, 0xad, 0x81,
+    0xc0, 0xc5, 0x2e, 0xf0, 0xf8, 0x85, 0x2e, 0xc0, 0x40, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+pub const ACI_START : u16 = 0xc000;
+pub const ACI_END : u16 = 0xc1ff;
+
+/// Half a cycle of the 2000 Hz tone that encodes a 0 bit.
+pub const ZERO_HALF : u32 = (APPLE1_HZ / 4000) as u32;
+/// Half a cycle of the 1000 Hz tone that encodes a 1 bit and the leader.
+pub const ONE_HALF : u32 = (APPLE1_HZ / 2000) as u32;
+
+#[derive(Debug)]
+pub enum TapeError {
+    Io(io::Error),
+    Format(String)
+}
+
+impl fmt::Display for TapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TapeError::Io(ref err) => write!(f, "{}", err),
+            TapeError::Format(ref message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl Error for TapeError {}
+
+impl From<io::Error> for TapeError {
+    fn from(err: io::Error) -> TapeError {
+        TapeError::Io(err)
+    }
+}
+
+/// Apple Cassette Interface card at $C000-$C1FF.
+///
+/// Any access to $C000-$C0FF toggles the tape output flip-flop. The ROM
+/// answers the whole range, and on reads with A7 set the tape input level
+/// replaces A0, so the firmware sees the input change as two different ROM
+/// bytes at TAPEIN ($C081).
+///
+/// Time comes from the CPU cycle counter through `tick`: the inserted tape
+/// starts playing on the first TAPEIN read and advances with emulated
+/// cycles, and every output toggle is recorded with its cycle timestamp.
+pub struct Aci {
+    rom: [u8; 256],
+    now: u64,
+    input: Tape,
+    playing: bool,
+    segment: usize,
+    segment_left: u64,
+    level: u8,
+    recording: Vec<u32>,
+    last_toggle: Option<u64>
+}
+
+impl Aci {
+    pub fn new() -> Aci {
+        Aci::with_rom(ACI_ROM)
+    }
+
+    pub fn with_rom(rom: [u8; 256]) -> Aci {
+        Aci {
+            rom,
+            now: 0,
+            input: Tape::new(),
+            playing: false,
+            segment: 0,
+            segment_left: 0,
+            level: 0,
+            recording: Vec::new(),
+            last_toggle: None
+        }
+    }
+
+    /// Puts a tape in the player, rewound. It starts when the firmware first
+    /// looks at the input.
+    pub fn insert_tape(&mut self, tape: Tape) {
+        self.segment_left = tape.halves().first().map_or(0, |&half| half as u64);
+        self.input = tape;
+        self.playing = false;
+        self.segment = 0;
+        self.level = 0;
+    }
+
+    /// True once the inserted tape has played to the end.
+    pub fn tape_finished(&self) -> bool {
+        self.segment >= self.input.halves().len()
+    }
+
+    /// Everything written to the tape output so far.
+    pub fn recording(&self) -> Tape {
+        Tape::from_halves(self.recording.clone())
+    }
+
+    fn toggle(&mut self) {
+        if let Some(last) = self.last_toggle {
+            self.recording.push((self.now - last).min(u32::MAX as u64) as u32);
+        }
+        self.last_toggle = Some(self.now);
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        let offset = (address & 0xff) as usize;
+        if address >= 0xc100 {
+            return self.rom[offset];
+        }
+        self.toggle();
+        if offset & 0x80 != 0 {
+            self.playing = true;
+            self.rom[(offset & 0xfe) | self.level as usize]
+        } else {
+            self.rom[offset]
+        }
+    }
+
+    pub fn write(&mut self, address: u16) {
+        if address < 0xc100 {
+            self.toggle();
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+        if !self.playing {
+            return;
+        }
+        let mut cycles = cycles as u64;
+        let halves = self.input.halves();
+        while cycles > 0 && self.segment < halves.len() {
+            if cycles < self.segment_left {
+                self.segment_left -= cycles;
+                break;
+            }
+            cycles -= self.segment_left;
+            self.segment += 1;
+            self.level ^= 1;
+            self.segment_left = halves.get(self.segment).map_or(0, |&half| half as u64);
+        }
+    }
+}
+
+impl Default for Aci {
+    fn default() -> Aci {
+        Aci::new()
+    }
+}
+
+/// As a `Device` the registers are offsets into $C000-$C1FF. The tape is
+/// only seen through reads, so it plays on without a `next_event`.
+impl Device for Aci {
+    fn read(&mut self, register: u16) -> u8 {
+        Aci::read(self, ACI_START + register)
+    }
+
+    fn write(&mut self, register: u16, _value: u8) {
+        Aci::write(self, ACI_START + register)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Aci::tick(self, cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apple1::Apple1;
+    use cpu::MOS6502;
+    use platform::Platform;
+
+    /// Runs the firmware with a command line until it returns to Wozmon.
+    fn run_firmware(aci: Aci, memory: &[(u16, u8)], command: &str) -> MOS6502 {
+        let mut apple1 = Apple1::new();
+        apple1.load(Vec::new(), 0);
+        apple1.attach_aci(aci);
+        for &(address, value) in memory {
+            apple1.poke(address, value);
+        }
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0xc100);
+        let mut keys = command.bytes().map(|b| b | 0x80).chain(Some(0x8d));
+        while cpu.get_pc() != 0xff1a {
+            assert!(cpu.get_total_cycles() < 20_000_000, "firmware did not finish");
+            if cpu.key_ready() {
+                if let Some(key) = keys.next() {
+                    cpu.key_pressed(key);
+                }
+            }
+            cpu.step();
+        }
+        cpu
+    }
+
+    #[test]
+    fn rom_and_tape_in() {
+        let mut aci = Aci::new();
+        assert_eq!(aci.read(0xc100), 0xa9);
+        assert_eq!(aci.read(0xc000), 0xa9);
+        assert_ne!(ACI_ROM[0x80], ACI_ROM[0x81]);
+
+        aci.insert_tape(Tape::from_halves(vec![100, 200]));
+        aci.tick(500);
+        assert_eq!(aci.read(0xc081), ACI_ROM[0x80]);
+        aci.tick(99);
+        assert_eq!(aci.read(0xc081), ACI_ROM[0x80]);
+        aci.tick(1);
+        assert_eq!(aci.read(0xc081), ACI_ROM[0x81]);
+        aci.tick(200);
+        assert_eq!(aci.read(0xc081), ACI_ROM[0x80]);
+        assert!(aci.tape_finished());
+    }
+
+    #[test]
+    fn tape_out() {
+        let mut aci = Aci::new();
+        aci.tick(1000);
+        aci.read(0xc000);
+        aci.tick(ONE_HALF);
+        aci.write(0xc0ff);
+        aci.tick(ZERO_HALF);
+        aci.read(0xc100);
+        aci.read(0xc000);
+        assert_eq!(aci.recording().halves(), &[ONE_HALF, ZERO_HALF]);
+    }
+
+    #[test]
+    fn firmware_writes_and_reads() {
+        let data: Vec<(u16, u8)> = (0..16).map(|i| (0x0300 + i, (i as u8).wrapping_mul(37))).collect();
+        let cpu = run_firmware(Aci::new(), &data, "300.30FW");
+        let tape = cpu.platform().aci().unwrap().recording();
+        let blocks = tape.blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].bytes(), data.iter().map(|&(_, value)| value).collect::<Vec<u8>>());
+
+        let mut aci = Aci::new();
+        aci.insert_tape(tape);
+        let mut cpu = run_firmware(aci, &[], "1000.100FR");
+        for (i, &(_, value)) in data.iter().enumerate() {
+            assert_eq!(cpu.read_u8(0x1000 + i as u16), value);
+        }
+    }
+
+    #[test]
+    fn firmware_reads_synthesized_tape() {
+        let mut aci = Aci::new();
+        aci.insert_tape(Tape::from_blocks(&[Block::from_bytes(b"WOZ")]));
+        let mut cpu = run_firmware(aci, &[], "0.2R");
+        assert_eq!(cpu.read_u8(0x0000), b'W');
+        assert_eq!(cpu.read_u8(0x0001), b'O');
+        assert_eq!(cpu.read_u8(0x0002), b'Z');
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use super::{TapeError, ZERO_HALF, ONE_HALF};
+use super::wav;
+use clock::APPLE1_HZ;
+
+/// Header of the compact tape format.
+pub const MAGIC : &[u8] = b"ACITAPE1";
+
+/// Leader written in front of synthesized blocks, in 1 bit cycles (2 seconds).
+pub const DEFAULT_LEADER : u16 = 2000;
+
+/// Silence between synthesized blocks.
+const GAP_HALF : u32 = (APPLE1_HZ / 2) as u32;
+
+/// Halves longer than this are silence rather than signal.
+const GAP_LIMIT : u32 = ONE_HALF * 4;
+/// Halves shorter than this belong to a 0 bit.
+const SHORT_LIMIT : u32 = (ZERO_HALF + ONE_HALF) / 2;
+/// Full cycles at least this long are 1 bits.
+const CYCLE_LIMIT : u32 = ZERO_HALF + ONE_HALF;
+/// Leader halves needed before a short half counts as the sync bit.
+const MIN_LEADER : u32 = 64;
+
+/// A recording on tape: the leader tone, the 0 sync bit and the data bits
+/// that follow it, most significant bit first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub leader: u16,
+    pub bits: Vec<bool>
+}
+
+impl Block {
+    pub fn from_bytes(data: &[u8]) -> Block {
+        let bits = data.iter()
+            .flat_map(|&byte| (0..8).rev().map(move |bit| byte & (1 << bit) != 0))
+            .collect();
+        Block { leader: DEFAULT_LEADER, bits }
+    }
+
+    /// The data bits packed into bytes. A partial last byte is dropped.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bits.chunks(8)
+            .filter(|chunk| chunk.len() == 8)
+            .map(|chunk| chunk.iter().fold(0, |byte, &bit| (byte << 1) | bit as u8))
+            .collect()
+    }
+}
+
+/// A tape as the durations, in CPU cycles, between changes of the signal
+/// level. The level starts low; a long half stands for silence.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tape {
+    halves: Vec<u32>
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape { halves: Vec::new() }
+    }
+
+    pub fn from_halves(halves: Vec<u32>) -> Tape {
+        Tape { halves }
+    }
+
+    pub fn halves(&self) -> &[u32] {
+        &self.halves
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.halves.is_empty()
+    }
+
+    /// Synthesizes the ideal waveform for a list of blocks.
+    pub fn from_blocks(blocks: &[Block]) -> Tape {
+        let mut halves = Vec::new();
+        for block in blocks {
+            halves.extend(std::iter::repeat_n(ONE_HALF, block.leader as usize * 2));
+            halves.extend([ZERO_HALF, ZERO_HALF]);
+            for &bit in &block.bits {
+                let half = if bit { ONE_HALF } else { ZERO_HALF };
+                halves.extend([half, half]);
+            }
+            halves.push(GAP_HALF);
+        }
+        Tape { halves }
+    }
+
+    /// Decodes the blocks on the tape: a run of leader halves, a short half
+    /// starting the sync bit, then full cycles up to the next silence.
+    pub fn blocks(&self) -> Vec<Block> {
+        let halves = &self.halves;
+        let mut blocks = Vec::new();
+        let mut i = 0;
+        loop {
+            let mut leader = 0;
+            while i < halves.len() && !(leader >= MIN_LEADER && halves[i] < SHORT_LIMIT) {
+                if halves[i] >= SHORT_LIMIT && halves[i] < GAP_LIMIT {
+                    leader += 1;
+                } else {
+                    leader = 0;
+                }
+                i += 1;
+            }
+            if i >= halves.len() {
+                break;
+            }
+            i += 2;
+            let mut bits = Vec::new();
+            while i + 1 < halves.len() && halves[i] < GAP_LIMIT && halves[i + 1] < GAP_LIMIT {
+                bits.push(halves[i] + halves[i + 1] >= CYCLE_LIMIT);
+                i += 2;
+            }
+            blocks.push(Block { leader: (leader / 2).min(u16::MAX as u32) as u16, bits });
+        }
+        blocks
+    }
+
+    /// Parses the compact format: `MAGIC`, then for each block the leader
+    /// length (u16) and bit count (u32), little endian, and the bits packed
+    /// most significant first.
+    pub fn parse_compact(data: &[u8]) -> Result<Tape, TapeError> {
+        if !data.starts_with(MAGIC) {
+            return Err(TapeError::Format(String::from("not a compact tape file")));
+        }
+        let truncated = || TapeError::Format(String::from("compact tape file is truncated"));
+        let mut blocks = Vec::new();
+        let mut rest = &data[MAGIC.len()..];
+        while !rest.is_empty() {
+            if rest.len() < 6 {
+                return Err(truncated());
+            }
+            let leader = u16::from_le_bytes([rest[0], rest[1]]);
+            let count = u32::from_le_bytes([rest[2], rest[3], rest[4], rest[5]]) as usize;
+            let len = count.div_ceil(8);
+            if rest.len() < 6 + len {
+                return Err(truncated());
+            }
+            let packed = &rest[6..6 + len];
+            let bits = (0..count).map(|i| packed[i / 8] & (0x80 >> (i % 8)) != 0).collect();
+            blocks.push(Block { leader, bits });
+            rest = &rest[6 + len..];
+        }
+        Ok(Tape::from_blocks(&blocks))
+    }
+
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        for block in self.blocks() {
+            data.extend_from_slice(&block.leader.to_le_bytes());
+            data.extend_from_slice(&(block.bits.len() as u32).to_le_bytes());
+            let mut packed = vec![0u8; block.bits.len().div_ceil(8)];
+            for (i, &bit) in block.bits.iter().enumerate() {
+                if bit {
+                    packed[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+            data.extend(packed);
+        }
+        data
+    }
+
+    /// Reads a WAV file, or the compact format for any other extension.
+    pub fn read_file(filename: &str) -> Result<Tape, TapeError> {
+        let mut f = File::open(filename)?;
+        let mut data = Vec::new();
+        f.read_to_end(&mut data)?;
+        if is_wav(filename) {
+            wav::parse(&data)
+        } else {
+            Tape::parse_compact(&data)
+        }
+    }
+
+    pub fn write_file(&self, filename: &str) -> Result<(), TapeError> {
+        let data = if is_wav(filename) { wav::write(self) } else { self.to_compact() };
+        let mut f = File::create(filename)?;
+        f.write_all(&data)?;
+        Ok(())
+    }
+}
+
+fn is_wav(filename: &str) -> bool {
+    Path::new(filename).extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks() {
+        let block = Block::from_bytes(&[0xa5, 0x01]);
+        assert_eq!(block.bits.len(), 16);
+        assert!(block.bits[0] && !block.bits[1]);
+        assert_eq!(block.bytes(), vec![0xa5, 0x01]);
+
+        let tape = Tape::from_blocks(&[block.clone(), Block::from_bytes(b"HI")]);
+        let decoded = tape.blocks();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0], block);
+        assert_eq!(decoded[1].bytes(), b"HI".to_vec());
+    }
+
+    #[test]
+    fn compact() {
+        let tape = Tape::from_blocks(&[Block { leader: 100, bits: vec![true, false, true] }]);
+        let data = tape.to_compact();
+        assert_eq!(data, [MAGIC, &[100, 0, 3, 0, 0, 0, 0xa0]].concat());
+        assert_eq!(Tape::parse_compact(&data).unwrap(), tape);
+        assert!(Tape::parse_compact(&data[..data.len() - 1]).is_err());
+        assert!(Tape::parse_compact(b"RIFF").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use super::{Tape, TapeError};
+use clock::APPLE1_HZ;
+
+/// Sample rate of the WAV files written.
+pub const SAMPLE_RATE : u32 = 44_100;
+
+fn format_error(message: &str) -> TapeError {
+    TapeError::Format(message.to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Reads an 8 or 16-bit PCM WAV file and turns the first channel into a tape
+/// by finding where the signal crosses zero. A little hysteresis keeps noise
+/// around the crossings from being counted as extra edges.
+pub fn parse(data: &[u8]) -> Result<Tape, TapeError> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(format_error("not a WAV file"));
+    }
+    let mut format = None;
+    let mut samples = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let len = u32_at(data, offset + 4) as usize;
+        let body = &data[offset + 8..(offset + 8 + len).min(data.len())];
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                format = Some((u16_at(body, 0), u16_at(body, 2), u32_at(body, 4), u16_at(body, 14)));
+            }
+            b"data" => samples = Some(body),
+            _ => {}
+        }
+        offset += 8 + len + (len & 1);
+    }
+    let (tag, channels, rate, bits) = format.ok_or_else(|| format_error("WAV file has no fmt chunk"))?;
+    let samples = samples.ok_or_else(|| format_error("WAV file has no data chunk"))?;
+    if tag != 1 || channels == 0 || rate == 0 || (bits != 8 && bits != 16) {
+        return Err(format_error("only 8 and 16-bit PCM WAV files are supported"));
+    }
+
+    let frame = channels as usize * bits as usize / 8;
+    let values: Vec<i32> = samples.chunks(frame)
+        .filter(|chunk| chunk.len() == frame)
+        .map(|chunk| if bits == 8 {
+            chunk[0] as i32 - 128
+        } else {
+            i16::from_le_bytes([chunk[0], chunk[1]]) as i32
+        })
+        .collect();
+    let peak = values.iter().map(|value| value.abs()).max().unwrap_or(0);
+    let hysteresis = peak / 8;
+
+    let mut halves = Vec::new();
+    let mut high = false;
+    let mut last_edge = 0u64;
+    for (i, &value) in values.iter().enumerate() {
+        if (high && value < -hysteresis) || (!high && value > hysteresis) {
+            high = !high;
+            let edge = i as u64 * APPLE1_HZ / rate as u64;
+            halves.push((edge - last_edge).min(u32::MAX as u64) as u32);
+            last_edge = edge;
+        }
+    }
+    Ok(Tape::from_halves(halves))
+}
+
+/// Writes the tape as a mono 8-bit square wave.
+pub fn write(tape: &Tape) -> Vec<u8> {
+    let mut samples = Vec::new();
+    let mut high = false;
+    let mut elapsed = 0u64;
+    for &half in tape.halves() {
+        elapsed += half as u64;
+        let end = (elapsed * SAMPLE_RATE as u64 / APPLE1_HZ) as usize;
+        let level = if high { 0xc0 } else { 0x40 };
+        samples.resize(end.max(samples.len()), level);
+        high = !high;
+    }
+    // hold the last level for a moment so the final edge is on the tape
+    let level = if high { 0xc0 } else { 0x40 };
+    samples.extend(std::iter::repeat_n(level, SAMPLE_RATE as usize / 10));
+    // keep the data chunk an even length so it needs no pad byte
+    if samples.len() & 1 != 0 {
+        let last = *samples.last().unwrap();
+        samples.push(last);
+    }
+
+    let mut data = Vec::with_capacity(44 + samples.len());
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    data.extend_from_slice(b"WAVEfmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    data.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&8u16.to_le_bytes());
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    data.extend(samples);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aci::Block;
+
+    #[test]
+    fn round_trip() {
+        let tape = Tape::from_blocks(&[Block::from_bytes(b"APPLE")]);
+        let data = write(&tape);
+        assert_eq!(&data[0..4], b"RIFF");
+        let decoded = parse(&data).unwrap();
+        let blocks = decoded.blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].bytes(), b"APPLE".to_vec());
+
+        // a recording ends right at the last edge, with no silence after it
+        let mut halves = tape.halves().to_vec();
+        halves.pop();
+        let decoded = parse(&write(&Tape::from_halves(halves))).unwrap();
+        assert_eq!(decoded.blocks()[0].bytes(), b"APPLE".to_vec());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse(b"ACITAPE1").is_err());
+        let mut data = write(&Tape::new());
+        data[20] = 3;
+        assert!(parse(&data).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+#[cfg(unix)]
+use termios;
+
+/// The far end of an ACIA's serial line.
+pub trait SerialLine {
+    /// The next byte from the far end, if one has arrived.
+    fn receive(&mut self) -> Option<u8>;
+    fn transmit(&mut self, byte: u8);
+
+    /// Whether anything is listening, reported as carrier detect.
+    fn connected(&self) -> bool {
+        true
+    }
+}
+
+/// Copies everything read from `reader` into a channel on a background
+/// thread, stopping at end of file.
+fn spawn_copier<R: Read + Send + 'static>(mut reader: R, tx: Sender<u8>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n
+            };
+            for &b in &buf[..n] {
+                if tx.send(b).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// The serial line on the host terminal. The bytes come from the caller,
+/// which keeps reading the keyboard for the reset and quit keys.
+pub struct StdioLine {
+    input: Receiver<u8>
+}
+
+impl StdioLine {
+    pub fn new(input: Receiver<u8>) -> StdioLine {
+        StdioLine { input }
+    }
+}
+
+impl SerialLine for StdioLine {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        let _ = handle.write_all(&[byte]);
+        let _ = handle.flush();
+    }
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::ffi::CStr;
+    use std::io;
+    use std::os::raw::{c_char, c_int};
+    use termios::O_NOCTTY;
+
+    extern "C" {
+        fn posix_openpt(flags: c_int) -> c_int;
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *mut c_char;
+    }
+
+    const O_RDWR : c_int = 0o2;
+
+    /// Opens a pseudo-terminal master, returning its descriptor and the
+    /// path of the slave side.
+    pub fn open() -> io::Result<(c_int, String)> {
+        unsafe {
+            let fd = posix_openpt(O_RDWR | O_NOCTTY);
+            if fd < 0 || grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let name = ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((fd, CStr::from_ptr(name).to_string_lossy().into_owned()))
+        }
+    }
+}
+
+/// A pseudo-terminal: point a terminal program such as screen or minicom
+/// at `path()`.
+pub struct PtyLine {
+    master: File,
+    path: String,
+    // holding the slave open keeps reads on the master from failing while
+    // no terminal program is attached
+    _slave: File,
+    input: Receiver<u8>
+}
+
+impl PtyLine {
+    #[cfg(unix)]
+    pub fn open() -> io::Result<PtyLine> {
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+
+        let (fd, path) = pty::open()?;
+        let master = unsafe { File::from_raw_fd(fd) };
+        let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+        termios::make_raw(slave.as_raw_fd())?;
+        let (tx, rx) = mpsc::channel();
+        spawn_copier(master.try_clone()?, tx);
+        Ok(PtyLine { master, path, _slave: slave, input: rx })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open() -> io::Result<PtyLine> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals need a Unix host"))
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl SerialLine for PtyLine {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let _ = self.master.write_all(&[byte]);
+    }
+}
+
+/// A TCP listener on the loopback interface. One client at a time is
+/// connected to the line; a new connection replaces the old one.
+pub struct TcpLine {
+    port: u16,
+    client: Arc<Mutex<Option<TcpStream>>>,
+    input: Receiver<u8>
+}
+
+impl TcpLine {
+    /// Listens on 127.0.0.1:`port`; port 0 picks a free one.
+    pub fn listen(port: u16) -> io::Result<TcpLine> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let port = listener.local_addr()?.port();
+        let client : Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+        let (tx, rx) = mpsc::channel();
+        let accepted = client.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                let _ = stream.set_nodelay(true);
+                if let Ok(reader) = stream.try_clone() {
+                    let mut client = accepted.lock().unwrap();
+                    // Hang up on the old client, which also ends its copier,
+                    // so it can't keep typing into the new session.
+                    if let Some(old) = client.take() {
+                        let _ = old.shutdown(Shutdown::Both);
+                    }
+                    spawn_copier(reader, tx.clone());
+                    *client = Some(stream);
+                }
+            }
+        });
+        Ok(TcpLine { port, client, input: rx })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl SerialLine for TcpLine {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let mut client = self.client.lock().unwrap();
+        let failed = match *client {
+            Some(ref mut stream) => stream.write_all(&[byte]).is_err(),
+            None => false
+        };
+        if failed {
+            *client = None;
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.client.lock().unwrap().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for<F: FnMut() -> bool>(mut done: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test]
+    fn tcp() {
+        let mut line = TcpLine::listen(0).unwrap();
+        assert!(!line.connected());
+        let mut client = TcpStream::connect(("127.0.0.1", line.port())).unwrap();
+        assert!(wait_for(|| line.connected()));
+        client.write_all(b"hi").unwrap();
+        let mut received = Vec::new();
+        assert!(wait_for(|| {
+            received.extend(line.receive());
+            received.len() == 2
+        }));
+        assert_eq!(received, b"hi");
+        line.transmit(b'!');
+        let mut buf = [0u8; 1];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"!");
+    }
+
+    #[test]
+    fn tcp_new_client_replaces_old() {
+        let mut line = TcpLine::listen(0).unwrap();
+        let mut old = TcpStream::connect(("127.0.0.1", line.port())).unwrap();
+        assert!(wait_for(|| line.connected()));
+        let mut new = TcpStream::connect(("127.0.0.1", line.port())).unwrap();
+        // the old client is hung up on once the new one is accepted
+        let mut buf = [0u8; 1];
+        assert_eq!(old.read(&mut buf).unwrap(), 0);
+        let _ = old.write_all(b"x");
+        new.write_all(b"y").unwrap();
+        let mut received = None;
+        assert!(wait_for(|| {
+            received = line.receive();
+            received.is_some()
+        }));
+        assert_eq!(received, Some(b'y'));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(line.receive(), None);
+        line.transmit(b'!');
+        new.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"!");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty_is_raw() {
+        let mut line = PtyLine::open().unwrap();
+        let mut terminal = OpenOptions::new().read(true).write(true).open(line.path()).unwrap();
+        line.transmit(b'\r');
+        let mut buf = [0u8; 1];
+        terminal.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\r");
+        terminal.write_all(b"\n").unwrap();
+        let mut received = None;
+        assert!(wait_for(|| {
+            received = line.receive();
+            received.is_some()
+        }));
+        // neither echoed back nor turned into CR LF
+        assert_eq!(received, Some(b'\n'));
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(line.receive(), None);
+    }
+}
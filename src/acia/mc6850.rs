@@ -0,0 +1,287 @@
+use acia::SerialLine;
+use device::Device;
+
+/// Registers: control and status share RS=0, transmit and receive data
+/// RS=1.
+pub const STATUS : u16 = 0;
+pub const CONTROL : u16 = 0;
+pub const DATA : u16 = 1;
+
+/// Status register bits.
+pub const RDRF : u8 = 0x01;
+pub const TDRE : u8 = 0x02;
+pub const NO_CARRIER : u8 = 0x04;
+pub const NO_CTS : u8 = 0x08;
+pub const FRAMING_ERROR : u8 = 0x10;
+pub const OVERRUN : u8 = 0x20;
+pub const PARITY_ERROR : u8 = 0x40;
+pub const IRQ : u8 = 0x80;
+
+/// Control register fields.
+const DIVIDE : u8 = 0x03;
+const MASTER_RESET : u8 = 0x03;
+const TX_CONTROL : u8 = 0x60;
+const TX_IRQ : u8 = 0x20;
+const RX_IRQ : u8 = 0x80;
+
+/// Bits per character for word select CR4-CR2, start and stop included.
+const FRAME_BITS : [u32; 8] = [11, 11, 10, 10, 11, 10, 11, 11];
+
+/// Motorola 6850 Asynchronous Communications Interface Adapter.
+///
+/// RS is usually A0, so callers pass `address & 1`. The chip powers up
+/// held in master reset until the control register is written with a
+/// divide ratio; the bit rate is the external clock over 1, 16 or 64.
+/// Like the 6551, bytes move at the speed of a character frame in CPU
+/// cycles and the far end of the line is a `SerialLine`.
+pub struct Mc6850 {
+    line: Option<Box<dyn SerialLine>>,
+    cpu_hz: u64,
+    clock_hz: u64,
+    control: u8,
+    rx_data: u8,
+    tx_data: Option<u8>,
+    tx_cycles: u64,
+    rx_cycles: u64,
+    status: u8
+}
+
+impl Mc6850 {
+    /// An ACIA whose transmit and receive clocks run at `clock_hz`.
+    pub fn new(cpu_hz: u64, clock_hz: u64) -> Mc6850 {
+        Mc6850 {
+            line: None,
+            cpu_hz,
+            clock_hz,
+            control: MASTER_RESET,
+            rx_data: 0,
+            tx_data: None,
+            tx_cycles: 0,
+            rx_cycles: 0,
+            status: 0
+        }
+    }
+
+    pub fn with_line(cpu_hz: u64, clock_hz: u64, line: Box<dyn SerialLine>) -> Mc6850 {
+        let mut acia = Mc6850::new(cpu_hz, clock_hz);
+        acia.line = Some(line);
+        acia
+    }
+
+    fn in_reset(&self) -> bool {
+        self.control & DIVIDE == MASTER_RESET
+    }
+
+    pub fn baud(&self) -> f64 {
+        let divide = match self.control & DIVIDE {
+            0 => 1.0,
+            1 => 16.0,
+            _ => 64.0
+        };
+        self.clock_hz as f64 / divide
+    }
+
+    /// CPU cycles per character frame.
+    pub fn char_cycles(&self) -> u64 {
+        let bits = FRAME_BITS[((self.control >> 2) & 0x07) as usize];
+        ((self.cpu_hz as f64 * bits as f64 / self.baud()) as u64).max(1)
+    }
+
+    fn word_mask(&self) -> u8 {
+        // word selects 0-3 are seven bits
+        if self.control & 0x10 == 0 { 0x7f } else { 0xff }
+    }
+
+    fn update_irq(&mut self) {
+        let rx = self.control & RX_IRQ != 0 && self.status & (RDRF | OVERRUN) != 0;
+        let tx = self.control & TX_CONTROL == TX_IRQ && self.status & TDRE != 0;
+        if rx || tx {
+            self.status |= IRQ;
+        } else {
+            self.status &= !IRQ;
+        }
+    }
+
+    /// Reads a register without the side effects of a bus read.
+    pub fn peek(&self, register: u16) -> u8 {
+        if register & 0x01 == DATA {
+            self.rx_data
+        } else {
+            let carrier = match self.line {
+                Some(ref line) if line.connected() => 0,
+                _ => NO_CARRIER
+            };
+            self.status | carrier
+        }
+    }
+
+    /// Reading data empties the receiver and clears overrun.
+    pub fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        if register & 0x01 == DATA {
+            self.status &= !(RDRF | OVERRUN | FRAMING_ERROR | PARITY_ERROR);
+            self.update_irq();
+        }
+        value
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        if register & 0x01 == DATA {
+            if !self.in_reset() {
+                self.tx_data = Some(value & self.word_mask());
+                self.status &= !TDRE;
+            }
+        } else {
+            self.control = value;
+            if self.in_reset() {
+                self.tx_data = None;
+                self.status = 0;
+            } else if self.tx_data.is_none() {
+                self.status |= TDRE;
+            }
+        }
+        self.update_irq();
+    }
+
+    /// The IRQ output: a byte arrived with receive interrupts on, or the
+    /// transmitter is empty with transmit interrupts on.
+    pub fn irq(&self) -> bool {
+        self.status & IRQ != 0
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.in_reset() {
+            return;
+        }
+        let cycles = cycles as u64;
+
+        if self.tx_cycles > cycles {
+            self.tx_cycles -= cycles;
+        } else {
+            self.tx_cycles = 0;
+            if let Some(byte) = self.tx_data.take() {
+                if let Some(ref mut line) = self.line {
+                    line.transmit(byte);
+                }
+                self.tx_cycles = self.char_cycles();
+                self.status |= TDRE;
+            }
+        }
+
+        if self.rx_cycles > cycles {
+            self.rx_cycles -= cycles;
+        } else {
+            self.rx_cycles = self.char_cycles();
+            let byte = match self.line {
+                Some(ref mut line) => line.receive(),
+                None => None
+            };
+            if let Some(byte) = byte {
+                // a byte arriving before the last was read is lost
+                if self.status & RDRF != 0 {
+                    self.status |= OVERRUN;
+                } else {
+                    self.rx_data = byte & self.word_mask();
+                    self.status |= RDRF;
+                }
+            }
+        }
+        self.update_irq();
+    }
+}
+
+impl Device for Mc6850 {
+    fn read(&mut self, register: u16) -> u8 {
+        Mc6850::read(self, register)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        Mc6850::write(self, register, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Mc6850::tick(self, cycles)
+    }
+
+    /// The end of the character being sent, or the next look at the line
+    /// for one arriving; nothing while held in master reset.
+    fn next_event(&self) -> Option<u64> {
+        if self.in_reset() {
+            return None;
+        }
+        let tx = self.tx_data.map(|_| self.tx_cycles.max(1));
+        let rx = self.line.as_ref().map(|_| self.rx_cycles.max(1));
+        [tx, rx].iter().flatten().min().cloned()
+    }
+
+    fn irq(&self) -> bool {
+        Mc6850::irq(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct TestLine {
+        input: Rc<RefCell<VecDeque<u8>>>,
+        output: Rc<RefCell<Vec<u8>>>
+    }
+
+    impl SerialLine for TestLine {
+        fn receive(&mut self) -> Option<u8> {
+            self.input.borrow_mut().pop_front()
+        }
+
+        fn transmit(&mut self, byte: u8) {
+            self.output.borrow_mut().push(byte);
+        }
+    }
+
+    #[test]
+    fn master_reset_and_timing() {
+        let line = TestLine::default();
+        let mut acia = Mc6850::with_line(1_000_000, 4800, Box::new(line.clone()));
+        assert_eq!(acia.read(STATUS), 0);
+        acia.write(DATA, b'x');
+        acia.tick(100_000);
+        assert!(line.output.borrow().is_empty());
+
+        // divide by 16, 8N2: 300 baud
+        acia.write(CONTROL, 0x11);
+        assert_eq!(acia.read(STATUS), TDRE);
+        assert_eq!(acia.char_cycles(), 1_000_000 * 11 / 300);
+        acia.write(DATA, b'O');
+        acia.write(DATA, b'K');
+        assert_eq!(acia.read(STATUS) & TDRE, 0);
+        acia.tick(1);
+        assert_eq!(*line.output.borrow(), b"K");
+    }
+
+    #[test]
+    fn receive_and_interrupts() {
+        let line = TestLine::default();
+        let mut acia = Mc6850::with_line(1_000_000, 4800, Box::new(line.clone()));
+        // divide by 16, 7E1, receive interrupts on
+        acia.write(CONTROL, RX_IRQ | 0x09);
+        assert!(!acia.irq());
+        line.input.borrow_mut().extend(&[b'a' | 0x80, b'b']);
+        acia.tick(1);
+        assert!(acia.irq());
+        assert_eq!(acia.read(STATUS) & (RDRF | IRQ), RDRF | IRQ);
+        acia.tick(acia.char_cycles() as u32);
+        assert_eq!(acia.read(STATUS) & OVERRUN, OVERRUN);
+        assert_eq!(acia.read(DATA), b'a');
+        assert!(!acia.irq());
+
+        // transmit interrupts while the transmitter is empty
+        acia.write(CONTROL, TX_IRQ | 0x15);
+        assert!(acia.irq());
+        acia.write(DATA, b'c');
+        assert!(!acia.irq());
+    }
+}
@@ -0,0 +1,326 @@
+mod line;
+pub mod mc6850;
+
+pub use self::line::{SerialLine, StdioLine, PtyLine, TcpLine};
+pub use self::mc6850::Mc6850;
+
+use device::Device;
+
+pub const DATA : u16 = 0;
+pub const STATUS : u16 = 1;
+pub const COMMAND : u16 = 2;
+pub const CONTROL : u16 = 3;
+
+/// Status register bits.
+pub const PARITY_ERROR : u8 = 0x01;
+pub const FRAMING_ERROR : u8 = 0x02;
+pub const OVERRUN : u8 = 0x04;
+pub const RX_FULL : u8 = 0x08;
+pub const TX_EMPTY : u8 = 0x10;
+pub const NO_CARRIER : u8 = 0x20;
+pub const NOT_READY : u8 = 0x40;
+pub const IRQ : u8 = 0x80;
+
+/// Command register bits.
+const DTR : u8 = 0x01;
+const RX_IRQ_DISABLE : u8 = 0x02;
+const TX_CONTROL : u8 = 0x0c;
+const TX_IRQ : u8 = 0x04;
+const ECHO : u8 = 0x10;
+const PARITY_ENABLE : u8 = 0x20;
+
+/// Baud rates for control register bits 0-3. Rate 0 is the 16x external
+/// clock, which with the usual 1.8432 MHz crystal is 115200 baud.
+const BAUD_RATES : [f64; 16] = [
+    115200.0, 50.0, 75.0, 109.92, 134.58, 150.0, 300.0, 600.0,
+    1200.0, 1800.0, 2400.0, 3600.0, 4800.0, 7200.0, 9600.0, 19200.0];
+
+/// MOS 6551 Asynchronous Communications Interface Adapter.
+///
+/// Registers are selected by RS1-RS0, so callers pass `address & 3`. Bytes
+/// take as long to move as a character frame at the programmed baud rate
+/// and word format, counted in CPU cycles. The far end of the serial line
+/// is a `SerialLine`; without one, transmitted bytes are dropped and
+/// nothing is received.
+pub struct Acia {
+    line: Option<Box<dyn SerialLine>>,
+    cpu_hz: u64,
+    rx_data: u8,
+    tx_data: Option<u8>,
+    tx_cycles: u64,
+    rx_cycles: u64,
+    status: u8,
+    command: u8,
+    control: u8
+}
+
+impl Acia {
+    pub fn new(cpu_hz: u64) -> Acia {
+        Acia {
+            line: None,
+            cpu_hz,
+            rx_data: 0,
+            tx_data: None,
+            tx_cycles: 0,
+            rx_cycles: 0,
+            status: TX_EMPTY,
+            command: RX_IRQ_DISABLE,
+            control: 0
+        }
+    }
+
+    pub fn with_line(cpu_hz: u64, line: Box<dyn SerialLine>) -> Acia {
+        let mut acia = Acia::new(cpu_hz);
+        acia.line = Some(line);
+        acia
+    }
+
+    /// The RESET line: receiver and transmitter idle, interrupts off.
+    pub fn reset(&mut self) {
+        self.tx_data = None;
+        self.status = TX_EMPTY;
+        self.command = RX_IRQ_DISABLE;
+        self.control = 0;
+    }
+
+    pub fn baud(&self) -> f64 {
+        BAUD_RATES[(self.control & 0x0f) as usize]
+    }
+
+    fn word_bits(&self) -> u32 {
+        8 - ((self.control >> 5) & 0x03) as u32
+    }
+
+    /// CPU cycles per character: start bit, data, parity and stop bits.
+    pub fn char_cycles(&self) -> u64 {
+        let parity = if self.command & PARITY_ENABLE != 0 { 1 } else { 0 };
+        let stop = if self.control & 0x80 != 0 { 2 } else { 1 };
+        let bits = 1 + self.word_bits() + parity + stop;
+        ((self.cpu_hz as f64 * bits as f64 / self.baud()) as u64).max(1)
+    }
+
+    fn word_mask(&self) -> u8 {
+        (0xff >> (8 - self.word_bits())) as u8
+    }
+
+    fn carrier(&self) -> bool {
+        self.line.as_ref().is_some_and(|line| line.connected())
+    }
+
+    /// Reads a register without the side effects of a bus read.
+    pub fn peek(&self, register: u16) -> u8 {
+        match register & 0x03 {
+            DATA => self.rx_data,
+            STATUS => {
+                let carrier = if self.carrier() { 0 } else { NO_CARRIER };
+                self.status | carrier
+            }
+            COMMAND => self.command,
+            _ => self.control
+        }
+    }
+
+    /// Reading data empties the receiver and clears its errors; reading
+    /// status clears the interrupt.
+    pub fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        match register & 0x03 {
+            DATA => self.status &= !(RX_FULL | OVERRUN | FRAMING_ERROR | PARITY_ERROR),
+            STATUS => self.status &= !IRQ,
+            _ => {}
+        }
+        value
+    }
+
+    /// Writing status is a programmed reset, which keeps the control
+    /// register and the command's parity bits.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x03 {
+            DATA => {
+                self.tx_data = Some(value & self.word_mask());
+                self.status &= !TX_EMPTY;
+            }
+            STATUS => {
+                self.command &= 0xe0;
+                self.status &= !OVERRUN;
+            }
+            COMMAND => self.command = value,
+            _ => self.control = value
+        }
+    }
+
+    /// The IRQ output: a byte arrived with receive interrupts enabled, or
+    /// the transmitter emptied with transmit interrupts enabled, and the
+    /// status register has not been read since.
+    pub fn irq(&self) -> bool {
+        self.status & IRQ != 0
+    }
+
+    fn receiver_enabled(&self) -> bool {
+        self.command & DTR != 0
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let cycles = cycles as u64;
+
+        if self.tx_cycles > cycles {
+            self.tx_cycles -= cycles;
+        } else {
+            self.tx_cycles = 0;
+            if let Some(byte) = self.tx_data.take() {
+                if let Some(ref mut line) = self.line {
+                    line.transmit(byte);
+                }
+                self.tx_cycles = self.char_cycles();
+                self.status |= TX_EMPTY;
+                if self.command & TX_CONTROL == TX_IRQ && self.receiver_enabled() {
+                    self.status |= IRQ;
+                }
+            }
+        }
+
+        if self.rx_cycles > cycles {
+            self.rx_cycles -= cycles;
+        } else if self.receiver_enabled() {
+            self.rx_cycles = self.char_cycles();
+            let byte = match self.line {
+                Some(ref mut line) => line.receive(),
+                None => None
+            };
+            if let Some(byte) = byte {
+                self.receive(byte);
+            }
+        }
+    }
+
+    /// A character finished arriving. If the last one was not read yet it
+    /// is kept and the new one is lost.
+    fn receive(&mut self, byte: u8) {
+        if self.status & RX_FULL != 0 {
+            self.status |= OVERRUN;
+        } else {
+            self.rx_data = byte & self.word_mask();
+            self.status |= RX_FULL;
+        }
+        if self.command & RX_IRQ_DISABLE == 0 {
+            self.status |= IRQ;
+        }
+        if self.command & (ECHO | TX_CONTROL) == ECHO {
+            if let Some(ref mut line) = self.line {
+                line.transmit(byte);
+            }
+        }
+    }
+}
+
+impl Device for Acia {
+    fn read(&mut self, register: u16) -> u8 {
+        Acia::read(self, register)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        Acia::write(self, register, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Acia::tick(self, cycles)
+    }
+
+    fn reset(&mut self) {
+        Acia::reset(self)
+    }
+
+    /// The end of the character being sent, or the next look at the line
+    /// for one arriving.
+    fn next_event(&self) -> Option<u64> {
+        let tx = self.tx_data.map(|_| self.tx_cycles.max(1));
+        let rx = if self.line.is_some() && self.receiver_enabled() {
+            Some(self.rx_cycles.max(1))
+        } else {
+            None
+        };
+        [tx, rx].iter().flatten().min().cloned()
+    }
+
+    fn irq(&self) -> bool {
+        Acia::irq(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct TestLine {
+        input: Rc<RefCell<VecDeque<u8>>>,
+        output: Rc<RefCell<Vec<u8>>>
+    }
+
+    impl SerialLine for TestLine {
+        fn receive(&mut self) -> Option<u8> {
+            self.input.borrow_mut().pop_front()
+        }
+
+        fn transmit(&mut self, byte: u8) {
+            self.output.borrow_mut().push(byte);
+        }
+    }
+
+    fn acia() -> (Acia, TestLine) {
+        let line = TestLine::default();
+        let mut acia = Acia::with_line(1_000_000, Box::new(line.clone()));
+        // 9600 baud 8N1, DTR on, receive interrupts on
+        acia.write(CONTROL, 0x1e);
+        acia.write(COMMAND, 0x09);
+        (acia, line)
+    }
+
+    #[test]
+    fn timing() {
+        let (mut acia, _) = acia();
+        assert_eq!(acia.char_cycles(), 1041);
+        acia.write(CONTROL, 0x80 | 0x40 | 0x06);
+        acia.write(COMMAND, PARITY_ENABLE | DTR);
+        assert_eq!(acia.char_cycles(), 1_000_000 * 10 / 300);
+    }
+
+    #[test]
+    fn transmit() {
+        let (mut acia, line) = acia();
+        assert_eq!(acia.read(STATUS) & TX_EMPTY, TX_EMPTY);
+        acia.write(DATA, b'H');
+        assert_eq!(acia.read(STATUS) & TX_EMPTY, 0);
+        acia.tick(1);
+        assert_eq!(acia.read(STATUS) & TX_EMPTY, TX_EMPTY);
+        acia.write(DATA, b'i');
+        acia.tick(1000);
+        assert_eq!(*line.output.borrow(), b"H");
+        acia.tick(41);
+        assert_eq!(*line.output.borrow(), b"Hi");
+    }
+
+    #[test]
+    fn receive_and_interrupt() {
+        let (mut acia, line) = acia();
+        line.input.borrow_mut().extend(b"ab");
+        acia.tick(1);
+        assert!(acia.irq());
+        assert_eq!(acia.read(STATUS) & (RX_FULL | IRQ | NO_CARRIER), RX_FULL | IRQ);
+        assert!(!acia.irq());
+        acia.tick(1041);
+        assert_eq!(acia.read(STATUS) & OVERRUN, OVERRUN);
+        assert_eq!(acia.read(DATA), b'a');
+        assert_eq!(acia.read(STATUS) & (RX_FULL | OVERRUN), 0);
+
+        // DTR off stops the receiver
+        line.input.borrow_mut().push_back(b'c');
+        acia.write(STATUS, 0);
+        acia.tick(2000);
+        assert_eq!(acia.read(STATUS) & RX_FULL, 0);
+        assert_eq!(acia.peek(COMMAND), 0x00);
+    }
+}
@@ -1,5 +1,11 @@
-use std::io::{stdout, Write};
 use platform::Platform;
+use display::Display;
+use console::{OutputSink, InputSource, WriteSink};
+use pia::{Pia, Port, SELECT_OUTPUT, C1_RISING, C2_OUTPUT, C1_IRQ_ENABLE};
+use aci::{Aci, ACI_START, ACI_END};
+use cffa1::{Cffa1, CFFA1_START, CFFA1_END};
+use acia::Acia;
+use device::{Line, Scheduler};
 
 const WOZMON: [u8; 256] = [
     0xd8, 0x58, 0xa0, 0x7f, 0x8c, 0x12, 0xd0, 0xa9, 0xa7, 0x8d, 0x11, 0xd0, 0x8d, 0x13, 0xd0, 0xc9,
@@ -26,72 +32,441 @@ pub const DSP : u16 = 0xd012;
 pub const DSPCR : u16 = 0xd013;
 const MEMORY_SIZE : usize = 65536;
 
+/// The PIA is selected anywhere in $D000-$DFFF with A4 set, and only A0-A1
+/// pick the register, so e.g. $D0F2 and $DFF2 are both DSP.
+pub fn pia_register(address: u16) -> Option<u16> {
+    if address & 0xf010 == 0xd010 {
+        Some(KBD | (address & 0x03))
+    } else {
+        None
+    }
+}
+
+/// The PIA, the cassette interface and the serial card run on a
+/// `Scheduler`. Only the serial card's IRQ output is wired, to IRQ.
 pub struct Apple1 {
-    ram: [u8; MEMORY_SIZE]
+    ram: [u8; MEMORY_SIZE],
+    ram_ranges: Vec<(u16, u16)>,
+    roms: Vec<(u16, Vec<u8>)>,
+    pia_mirrors: bool,
+    scheduler: Scheduler,
+    pia: usize,
+    display: Display,
+    aci: Option<usize>,
+    cffa1: Option<Cffa1>,
+    acia: Option<(u16, usize)>,
+    output: Box<dyn OutputSink>,
+    input: Option<Box<dyn InputSource>>
 }
 
 impl Apple1 {
     pub fn new() -> Apple1 {
+        let mut scheduler = Scheduler::new();
+        let pia = scheduler.add(Box::new(Apple1::wozmon_pia()), Line::None);
         Apple1 {
-            ram : [0; MEMORY_SIZE]
+            ram : [0; MEMORY_SIZE],
+            ram_ranges : vec![(0x0000, 0xffff)],
+            roms : vec![(0xff00, WOZMON.to_vec())],
+            pia_mirrors : true,
+            scheduler,
+            pia,
+            display : Display::new(),
+            aci : None,
+            cffa1 : None,
+            acia : None,
+            output : Box::new(WriteSink::stdout()),
+            input : None
+        }
+    }
+
+    /// The PIA as Wozmon's reset leaves it, so programs started elsewhere
+    /// still see the keyboard and display: PB0-PB6 outputs and both sides
+    /// with the output registers selected, rising C1 edges and the C2
+    /// handshake.
+    fn wozmon_pia() -> Pia {
+        let mut pia = Pia::new();
+        pia.write(DSP & 3, 0x7f);
+        pia.write(KBDCR & 3, SELECT_OUTPUT | C1_RISING | C2_OUTPUT | C1_IRQ_ENABLE);
+        pia.write(DSPCR & 3, SELECT_OUTPUT | C1_RISING | C2_OUTPUT | C1_IRQ_ENABLE);
+        pia
+    }
+
+    pub fn pia(&self) -> &Pia {
+        self.scheduler.device(self.pia)
+    }
+
+    /// Sets which address ranges (inclusive) have RAM. Reads elsewhere see
+    /// the floating bus and writes are lost. The default is all 64K.
+    pub fn set_ram(&mut self, ranges: &[(u16, u16)]) {
+        self.ram_ranges = ranges.to_vec();
+    }
+
+    /// Maps a read-only image at `address`. Wozmon is always present at $FF00
+    /// unless another image covers it.
+    pub fn install_rom(&mut self, address: u16, data: &[u8]) {
+        let end = (address as usize + data.len()).min(MEMORY_SIZE);
+        self.roms.push((address, data[..end - address as usize].to_vec()));
+        self.map_roms();
+    }
+
+    fn map_roms(&mut self) {
+        for &(address, ref data) in &self.roms {
+            let start = address as usize;
+            self.ram[start..start + data.len()].copy_from_slice(data);
+        }
+    }
+
+    /// With mirrors off the PIA only answers at $D010-$D013, which leaves
+    /// the rest of $D000-$DFFF free for ROM.
+    pub fn set_pia_mirrors(&mut self, mirrors: bool) {
+        self.pia_mirrors = mirrors;
+    }
+
+    fn pia_at(&self, address: u16) -> Option<u16> {
+        match pia_register(address) {
+            Some(register) if self.pia_mirrors || address == register => Some(register),
+            _ => None
+        }
+    }
+
+    fn is_rom(&self, address: u16) -> bool {
+        self.roms.iter().any(|&(start, ref data)| address >= start && ((address - start) as usize) < data.len())
+    }
+
+    fn is_ram(&self, address: u16) -> bool {
+        self.ram_ranges.iter().any(|&(start, end)| address >= start && address <= end)
+    }
+
+    /// Plugs the cassette interface card into $C000-$C1FF.
+    pub fn attach_aci(&mut self, aci: Aci) {
+        match self.aci {
+            Some(id) => self.scheduler.replace(id, Box::new(aci)),
+            None => self.aci = Some(self.scheduler.add(Box::new(aci), Line::None))
+        }
+    }
+
+    /// Plugs the CFFA1 storage card into $9000-$AFFF.
+    pub fn attach_cffa1(&mut self, cffa1: Cffa1) {
+        self.cffa1 = Some(cffa1);
+    }
+
+    /// Plugs a serial card with its 6551 at `address`-`address`+3.
+    pub fn attach_acia(&mut self, address: u16, acia: Acia) {
+        let id = match self.acia {
+            Some((_, id)) => {
+                self.scheduler.replace(id, Box::new(acia));
+                id
+            }
+            None => self.scheduler.add(Box::new(acia), Line::Irq)
+        };
+        self.acia = Some((address, id));
+    }
+
+    fn acia_at(&self, address: u16) -> Option<usize> {
+        match self.acia {
+            Some((start, id)) if address >= start && address - start < 4 => Some(id),
+            _ => None
         }
     }
+
+    pub fn display_mut(&mut self) -> &mut Display {
+        &mut self.display
+    }
+
+    /// Sends what the display shows somewhere other than stdout.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    /// Types keys from `input` whenever the keyboard is ready for one, in
+    /// addition to those passed to `key_pressed`.
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = Some(input);
+    }
+}
+
+impl Default for Apple1 {
+    fn default() -> Apple1 {
+        Apple1::new()
+    }
 }
 
 impl Platform for Apple1 {
 
     fn read(&mut self, address: u16) -> u8 {
-        let result = self.ram[address as usize];
-        if address == KBD {
-            let kbdcr = self.ram[KBDCR as usize] & 0x7f;
-            self.ram[KBDCR as usize] = kbdcr;
+        if let (ACI_START..=ACI_END, Some(aci)) = (address, self.aci) {
+            return self.scheduler.read(aci, address - ACI_START);
+        }
+        if let (CFFA1_START..=CFFA1_END, Some(card)) = (address, self.cffa1.as_mut()) {
+            return card.read(address);
+        }
+        if let Some(acia) = self.acia_at(address) {
+            return self.scheduler.read(acia, address & 3);
+        }
+        if let Some(register) = self.pia_at(address) {
+            if register == DSP {
+                // PB7 is the terminal's busy line
+                let busy = if self.display.is_busy() { 0x80 } else { 0x00 };
+                self.scheduler.with(self.pia, |pia: &mut Pia| pia.set_input(Port::B, busy));
+            }
+            return self.scheduler.read(self.pia, register & 3);
+        }
+        if !self.is_ram(address) && !self.is_rom(address) {
+            return (address >> 8) as u8;
         }
-        result
+        self.ram[address as usize]
     }
 
     fn write(&mut self, address: u16, value: u8) {
-        if address == KBD {
-            let value = self.ram[KBDCR as usize] | 0x80;
-            self.ram[KBDCR as usize] = value;
-        }
-        if address == DSP {
-            if value > 0 {
-                let ch = (value & 0x7f) as u8;
-                if (ch == 0x0a) || (ch == 0x0d) {
-                    println!();
-                } else if (value & 0x7f) != 0x7f {
-                    print!("{}",ch as char);
+        if let (ACI_START..=ACI_END, Some(aci)) = (address, self.aci) {
+            self.scheduler.write(aci, address - ACI_START, value);
+            return;
+        }
+        if let (CFFA1_START..=CFFA1_END, Some(card)) = (address, self.cffa1.as_mut()) {
+            card.write(address, value, &mut self.ram);
+            return;
+        }
+        if let Some(acia) = self.acia_at(address) {
+            self.scheduler.write(acia, address & 3, value);
+            return;
+        }
+        if let Some(register) = self.pia_at(address) {
+            self.scheduler.write(self.pia, register & 3, value);
+            if register == DSP && self.pia().selects_output(Port::B) {
+                let value = self.pia().output(Port::B);
+                if let Some(glyph) = self.display.output(value) {
+                    self.output.output(glyph);
                 }
-                stdout().flush().unwrap();
+                // the terminal acknowledges on CB1, ending the CB2 handshake
+                self.scheduler.with(self.pia, |pia: &mut Pia| {
+                    pia.set_c1(Port::B, true);
+                    pia.set_c1(Port::B, false);
+                });
             }
-            self.ram[address as usize] = value & 0x7f;
-        } else {
-            self.ram[address as usize] = value;
+            return;
         }
+        if !self.is_ram(address) || self.is_rom(address) {
+            return;
+        }
+        self.ram[address as usize] = value;
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        self.ram[address as usize] = value;
     }
 
+    /// Loads what fits below $FFFF and reports the rest as cut off.
     fn load(&mut self, program: Vec<u8>, address: u16) {
         self.ram = [0; MEMORY_SIZE];
-        if program.len() > 0 {
-            for i in 0..program.len() {
-                let location = i + address as usize;
-                self.ram[location] = program[i];
-            }
+        self.display.clear();
+        self.scheduler.replace(self.pia, Box::new(Apple1::wozmon_pia()));
+        let start = address as usize;
+        let end = (start + program.len()).min(MEMORY_SIZE);
+        if end - start < program.len() {
+            eprintln!("{} byte image at ${:04X} runs past $FFFF, {} bytes cut off",
+                program.len(), address, program.len() - (end - start));
         }
-        for j in 0..WOZMON.len() {
-            let location = j + 0xff00 as usize;
-            self.ram[location] = WOZMON[j];
+        self.ram[start..end].copy_from_slice(&program[..end - start]);
+        self.map_roms();
+    }
+
+    /// RESET reaches the keyboard PIA and the serial card; Wozmon sets the
+    /// PIA up again. The cassette interface has no RESET input.
+    fn reset(&mut self) {
+        self.scheduler.reset();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.display.tick(cycles);
+        self.scheduler.advance(cycles);
+        if self.key_ready() {
+            if let Some(key) = self.input.as_mut().and_then(|input| input.next_key()) {
+                self.key_pressed(key);
+            }
         }
     }
 
+    fn display(&self) -> Option<&Display> {
+        Some(&self.display)
+    }
+
+    fn aci(&self) -> Option<&Aci> {
+        self.aci.map(|id| self.scheduler.device(id))
+    }
+
+    fn irq(&self) -> bool {
+        self.scheduler.irq()
+    }
+
+    /// Ready for another key once the last one's CA1 flag was cleared by
+    /// reading KBD.
     fn key_ready(&self) -> bool {
-        (self.ram[KBDCR as usize] & 0x80) != 80
+        self.pia().peek(KBDCR & 3) & 0x80 == 0
     }
 
+    /// PA0-PA6 carry the key with PA7 held high, and the strobe pulses CA1.
     fn key_pressed(&mut self, key: u8) {
         if key != 0x0a {
-            self.write(KBD, key | 0x80);
-            self.write(KBDCR, 0x80)
+            self.scheduler.with(self.pia, |pia: &mut Pia| {
+                pia.set_input(Port::A, key | 0x80);
+                pia.set_c1(Port::A, true);
+                pia.set_c1(Port::A, false);
+            });
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::MOS6502;
+    use console::{Buffer, Keys};
+
+    #[test]
+    fn display_output() {
+        let mut apple1 = Apple1::new();
+        for b in "hi\r".bytes() {
+            apple1.write(DSP, b | 0x80);
+        }
+        assert_eq!(apple1.display().unwrap().line(0), "HI");
+        assert_eq!(apple1.display().unwrap().cursor(), (0, 1));
+    }
+
+    #[test]
+    fn oversized_load() {
+        let mut apple1 = Apple1::new();
+        apple1.load(vec![0xea; 0x200], 0xfe80);
+        assert_eq!(apple1.read(0xfe80), 0xea);
+        assert_eq!(apple1.read(0xfeff), 0xea);
+    }
+
+    #[test]
+    fn pluggable_io() {
+        let output = Buffer::new();
+        let mut apple1 = Apple1::new();
+        apple1.set_output(Box::new(output.clone()));
+        apple1.set_input(Box::new(Keys::from_text("ff00.ff01\n")));
+        apple1.load(Vec::new(), 0);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        while cpu.get_total_cycles() < 20_000 {
+            cpu.step();
+        }
+        assert_eq!(output.contents(), "\\\nFF00.FF01\n\nFF00: D8 58\n");
+    }
+
+    #[test]
+    fn reset_line() {
+        let output = Buffer::new();
+        let mut apple1 = Apple1::new();
+        apple1.set_output(Box::new(output.clone()));
+        apple1.set_input(Box::new(Keys::from_text("ff00\n")));
+        apple1.load(Vec::new(), 0);
+        apple1.reset();
+        assert_eq!(apple1.pia().peek(KBDCR & 3), 0);
+        assert_eq!(apple1.pia().peek(DSPCR & 3), 0);
+        // Wozmon's reset code sets the PIA up again
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        while cpu.get_total_cycles() < 20_000 {
+            cpu.step();
+        }
+        assert_eq!(output.contents(), "\\\nFF00\n\nFF00: D8\n");
+    }
+
+    #[test]
+    fn acia_interrupt() {
+        let (tx, rx) = ::std::sync::mpsc::channel();
+        let mut apple1 = Apple1::new();
+        apple1.load(vec![
+            0xa9, 0x1e, 0x8d, 0x03, 0xc3,   // LDA #$1E, STA $C303: 9600 8N1
+            0xa9, 0x09, 0x8d, 0x02, 0xc3,   // LDA #$09, STA $C302: DTR, receive IRQ
+            0x58,                           // CLI
+            0x4c, 0x0b, 0x03], 0x0300);     // JMP *
+        // Wozmon's $FFFE vector points at $0000
+        for (address, &byte) in [
+            0xad, 0x01, 0xc3,               // LDA $C301
+            0xad, 0x00, 0xc3,               // LDA $C300
+            0x85, 0x10,                     // STA $10
+            0x40].iter().enumerate() {      // RTI
+            apple1.poke(address as u16, byte);
+        }
+        apple1.attach_acia(0xc300, Acia::with_line(::clock::APPLE1_HZ, Box::new(::acia::StdioLine::new(rx))));
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.set_pc(0x0300);
+        while cpu.get_total_cycles() < 2_000 {
+            cpu.step();
+        }
+        assert!(!cpu.platform().irq());
+        tx.send(b'Z').unwrap();
+        while cpu.read_u8(0x0010) != b'Z' {
+            assert!(cpu.get_total_cycles() < 10_000, "no interrupt taken");
+            cpu.step();
+        }
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x030b);
+        assert!(!cpu.platform().irq());
+    }
+
+    #[test]
+    fn display_direction_register() {
+        let mut apple1 = Apple1::new();
+        apple1.write(DSPCR, 0x00);
+        apple1.write(DSP, b'A' | 0x80);
+        assert_eq!(apple1.display().unwrap().output_count(), 0);
+        apple1.write(DSP, 0x7f);
+        apple1.write(DSPCR, 0xa7);
+        apple1.write(DSP, b'A' | 0x80);
+        assert_eq!(apple1.display().unwrap().line(0), "A");
+        assert_eq!(apple1.pia().output(::pia::Port::B), b'A');
+    }
+
+    #[test]
+    fn display_busy_flag() {
+        let mut apple1 = Apple1::new();
+        apple1.display_mut().set_rate_limit(true);
+        assert_eq!(apple1.read(DSP) & 0x80, 0);
+        apple1.write(DSP, b'A' | 0x80);
+        assert_eq!(apple1.read(DSP) & 0x80, 0x80);
+        apple1.tick(::display::CYCLES_PER_CHAR);
+        assert_eq!(apple1.read(DSP) & 0x80, 0);
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut apple1 = Apple1::new();
+        apple1.set_ram(&[(0x0000, 0x0fff)]);
+        apple1.install_rom(0xe000, &[0x4c, 0x00, 0xe0]);
+        apple1.load(Vec::new(), 0);
+        apple1.write(0x0800, 0x55);
+        apple1.write(0x2000, 0x55);
+        apple1.write(0xe000, 0x55);
+        apple1.write(0xff00, 0x55);
+        assert_eq!(apple1.read(0x0800), 0x55);
+        assert_eq!(apple1.read(0x2000), 0x20);
+        assert_eq!(apple1.read(0xe000), 0x4c);
+        assert_eq!(apple1.read(0xff00), WOZMON[0]);
+    }
+
+    #[test]
+    fn pia_mirrors() {
+        assert_eq!(pia_register(0xd0f2), Some(DSP));
+        assert_eq!(pia_register(0xdff1), Some(KBDCR));
+        assert_eq!(pia_register(0xd00f), None);
+        assert_eq!(pia_register(0xc010), None);
+
+        let mut apple1 = Apple1::new();
+        apple1.key_pressed(b'A');
+        assert_eq!(apple1.read(0xd031) & 0x80, 0x80);
+        assert_eq!(apple1.read(0xd0f0), 0xc1);
+        assert!(apple1.key_ready());
+
+        apple1.set_pia_mirrors(false);
+        apple1.install_rom(0xd000, &[0xea; 0x10]);
+        apple1.write(0xd030, 0x55);
+        assert_eq!(apple1.read(0xd030), 0x55);
+        assert_eq!(apple1.read(0xd00f), 0xea);
+        apple1.key_pressed(b'B');
+        assert_eq!(apple1.read(0xd010), 0xc2);
+    }
+}
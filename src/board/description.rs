@@ -0,0 +1,375 @@
+use std::fs;
+use std::path::Path;
+use cli::SerialLineSpec;
+use symbols::parse_address;
+use cpu::Variant;
+
+/// The chips a description can place on the bus.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip {
+    Pia,
+    Via,
+    Riot,
+    Acia,
+    Mc6850
+}
+
+impl Chip {
+    fn from_name(name: &str) -> Option<Chip> {
+        match name.to_ascii_lowercase().as_str() {
+            "pia" | "6520" | "6821" => Some(Chip::Pia),
+            "via" | "6522" => Some(Chip::Via),
+            "riot" | "6532" => Some(Chip::Riot),
+            "acia" | "6551" => Some(Chip::Acia),
+            "mc6850" | "6850" => Some(Chip::Mc6850),
+            _ => None
+        }
+    }
+
+    /// How many registers the chip decodes from the low address bits.
+    pub fn registers(&self) -> u16 {
+        match *self {
+            Chip::Pia | Chip::Acia => 4,
+            Chip::Via => 16,
+            Chip::Riot => 32,
+            Chip::Mc6850 => 2
+        }
+    }
+}
+
+/// Which CPU line a chip's IRQ output drives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interrupt {
+    None,
+    Irq,
+    Nmi
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomRegion {
+    pub start: u16,
+    pub end: u16,
+    pub file: String
+}
+
+/// A chip at `start`, repeating through `end`. `line` connects an ACIA,
+/// `clock_hz` is a 6850's external clock and `ram` places a RIOT's 128
+/// bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSpec {
+    pub chip: Chip,
+    pub start: u16,
+    pub end: u16,
+    pub interrupt: Interrupt,
+    pub line: Option<SerialLineSpec>,
+    pub clock_hz: Option<u64>,
+    pub ram: Option<u16>
+}
+
+/// A board described in a TOML file rather than in Rust:
+///
+/// ```toml
+/// [machine]
+/// name = "breadboard"
+/// cpu = "6502"             # or "65c02"
+/// clock = 1_000_000
+///
+/// [[ram]]
+/// start = 0x0000
+/// end = 0x3fff
+///
+/// [[rom]]
+/// start = 0x8000
+/// end = 0xffff
+/// file = "a.out"          # relative to the description, ending at `end`
+///
+/// [[device]]
+/// chip = "via"            # pia, via, riot, acia (6551) or mc6850
+/// start = 0x6000
+/// end = 0x7fff            # optional, the chip repeats through it
+/// interrupt = "irq"       # irq, nmi or none
+///
+/// [[device]]
+/// chip = "acia"
+/// start = 0x5000
+/// line = "stdio"          # stdio, pty or tcp:<port>
+/// ```
+///
+/// Only the TOML the format needs is understood: tables, arrays of
+/// tables, and string and integer values. Addresses may also be
+/// written as strings like `"$C000"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Description {
+    pub name: String,
+    pub cpu: Variant,
+    pub clock_hz: u64,
+    pub ram: Vec<(u16, u16)>,
+    pub roms: Vec<RomRegion>,
+    pub devices: Vec<DeviceSpec>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(u64)
+}
+
+/// A `[table]` or `[[array]]` entry with its keys in file order.
+struct Section {
+    name: String,
+    line: usize,
+    values: Vec<(String, Value, usize)>
+}
+
+impl Section {
+    fn take(&mut self, key: &str) -> Option<(Value, usize)> {
+        let index = self.values.iter().position(|(k, _, _)| k == key)?;
+        let (_, value, line) = self.values.remove(index);
+        Some((value, line))
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<(String, usize)>, String> {
+        match self.take(key) {
+            Some((Value::Str(text), line)) => Ok(Some((text, line))),
+            Some((_, line)) => Err(format!("line {}: {} must be a string", line, key)),
+            None => Ok(None)
+        }
+    }
+
+    fn number(&mut self, key: &str) -> Result<Option<u64>, String> {
+        match self.take(key) {
+            Some((Value::Int(value), _)) => Ok(Some(value)),
+            Some((_, line)) => Err(format!("line {}: {} must be a number", line, key)),
+            None => Ok(None)
+        }
+    }
+
+    fn address(&mut self, key: &str) -> Result<Option<u16>, String> {
+        match self.take(key) {
+            Some((Value::Int(value), _)) if value <= 0xffff => Ok(Some(value as u16)),
+            Some((Value::Str(ref text), _)) if parse_address(text).is_some() => Ok(parse_address(text)),
+            Some((_, line)) => Err(format!("line {}: {} must be an address from $0000 to $FFFF", line, key)),
+            None => Ok(None)
+        }
+    }
+
+    fn required_address(&mut self, key: &str) -> Result<u16, String> {
+        self.address(key)?.ok_or_else(|| format!("line {}: [[{}]] needs {}", self.line, self.name, key))
+    }
+
+    /// Fails on keys nothing asked for, which are usually typos.
+    fn finish(&self) -> Result<(), String> {
+        match self.values.first() {
+            Some(&(ref key, _, line)) => Err(format!("line {}: unknown key {} in {}", line, key, self.name)),
+            None => Ok(())
+        }
+    }
+}
+
+fn parse_value(text: &str, line: usize) -> Result<Value, String> {
+    if let Some(rest) = text.strip_prefix('"') {
+        return match rest.strip_suffix('"') {
+            Some(inner) if !inner.contains('"') => Ok(Value::Str(inner.to_string())),
+            _ => Err(format!("line {}: unterminated string", line))
+        };
+    }
+    let digits = text.replace('_', "");
+    let number = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse::<u64>()
+    };
+    number.map(Value::Int).map_err(|_| format!("line {}: invalid value {}", line, text))
+}
+
+/// Splits the text into sections, dropping comments and blank lines.
+fn sections(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections : Vec<Section> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        // a # starts a comment unless it's inside a string
+        let mut quoted = false;
+        let end = line.char_indices().find(|&(_, c)| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == '#' && !quoted
+        }).map_or(line.len(), |(index, _)| index);
+        let line = line[..end].trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("[[").and_then(|rest| rest.strip_suffix("]]"))
+            .or_else(|| line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))) {
+            sections.push(Section { name: name.trim().to_string(), line: number, values: Vec::new() });
+            continue;
+        }
+        let eq = line.find('=').ok_or_else(|| format!("line {}: expected key = value", number))?;
+        let key = line[..eq].trim().to_string();
+        let value = parse_value(line[eq + 1..].trim(), number)?;
+        match sections.last_mut() {
+            Some(section) => section.values.push((key, value, number)),
+            None => return Err(format!("line {}: {} is outside any table", number, key))
+        }
+    }
+    Ok(sections)
+}
+
+fn range(section: &mut Section) -> Result<(u16, u16), String> {
+    let start = section.required_address("start")?;
+    let end = section.required_address("end")?;
+    if end < start {
+        return Err(format!("line {}: {} ends before it starts", section.line, section.name));
+    }
+    Ok((start, end))
+}
+
+impl Description {
+    /// The clock when the description doesn't give one.
+    pub const DEFAULT_CLOCK_HZ : u64 = 1_000_000;
+
+    pub fn parse(text: &str) -> Result<Description, String> {
+        let mut description = Description {
+            name: String::from("board"),
+            cpu: Variant::Nmos,
+            clock_hz: Description::DEFAULT_CLOCK_HZ,
+            ram: Vec::new(),
+            roms: Vec::new(),
+            devices: Vec::new()
+        };
+        for mut section in sections(text)? {
+            match section.name.as_str() {
+                "machine" => {
+                    if let Some((name, _)) = section.string("name")? {
+                        description.name = name;
+                    }
+                    if let Some((cpu, line)) = section.string("cpu")? {
+                        description.cpu = match cpu.to_ascii_lowercase().as_str() {
+                            "6502" => Variant::Nmos,
+                            "65c02" | "w65c02" | "w65c02s" => Variant::Cmos,
+                            _ => return Err(format!("line {}: the cpu is 6502 or 65c02, not {}", line, cpu))
+                        };
+                    }
+                    if let Some(hz) = section.number("clock")? {
+                        description.clock_hz = hz.max(1);
+                    }
+                }
+                "ram" => description.ram.push(range(&mut section)?),
+                "rom" => {
+                    let (start, end) = range(&mut section)?;
+                    let (file, _) = section.string("file")?
+                        .ok_or_else(|| format!("line {}: [[rom]] needs a file", section.line))?;
+                    description.roms.push(RomRegion { start, end, file });
+                }
+                "device" => {
+                    let (name, line) = section.string("chip")?
+                        .ok_or_else(|| format!("line {}: [[device]] needs a chip", section.line))?;
+                    let chip = Chip::from_name(&name)
+                        .ok_or_else(|| format!("line {}: unknown chip {}", line, name))?;
+                    let start = section.required_address("start")?;
+                    let end = section.address("end")?.unwrap_or(start.saturating_add(chip.registers() - 1));
+                    if end < start {
+                        return Err(format!("line {}: device ends before it starts", section.line));
+                    }
+                    let interrupt = match section.string("interrupt")? {
+                        None => Interrupt::None,
+                        Some((ref wire, _)) if wire == "none" => Interrupt::None,
+                        Some((ref wire, _)) if wire == "irq" => Interrupt::Irq,
+                        Some((ref wire, _)) if wire == "nmi" => Interrupt::Nmi,
+                        Some((wire, line)) => return Err(format!("line {}: interrupt must be irq, nmi or none, not {}", line, wire))
+                    };
+                    let line = match section.string("line")? {
+                        Some((text, line)) => Some(SerialLineSpec::parse(&text).map_err(|message| format!("line {}: {}", line, message))?),
+                        None => None
+                    };
+                    if line.is_some() && chip != Chip::Acia && chip != Chip::Mc6850 {
+                        return Err(format!("line {}: only an ACIA takes a serial line", section.line));
+                    }
+                    let clock_hz = section.number("clock")?;
+                    let ram = section.address("ram")?;
+                    description.devices.push(DeviceSpec { chip, start, end, interrupt, line, clock_hz, ram });
+                }
+                name => return Err(format!("line {}: unknown table {}", section.line, name))
+            }
+            section.finish()?;
+        }
+        Ok(description)
+    }
+
+    /// Reads a description, with ROM files relative to its directory.
+    pub fn from_file(filename: &str) -> Result<Description, String> {
+        let text = fs::read_to_string(filename).map_err(|err| format!("error reading {}: {}", filename, err))?;
+        let mut description = Description::parse(&text).map_err(|message| format!("{}: {}", filename, message))?;
+        let directory = Path::new(filename).parent().unwrap_or_else(|| Path::new(""));
+        for rom in &mut description.roms {
+            rom.file = directory.join(&rom.file).to_string_lossy().into_owned();
+        }
+        Ok(description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOARD : &str = r#"
+# a 6502 with a VIA and a serial port
+[machine]
+name = "test board"
+cpu = "65C02"
+clock = 2_000_000
+
+[[ram]]
+start = 0x0000
+end = "$3FFF"
+
+[[rom]]
+start = 0xe000
+end = 0xffff
+file = "mon#1.rom"      # not a comment inside the string
+
+[[device]]
+chip = "6522"
+start = 0x6000
+end = 0x7fff
+interrupt = "irq"
+
+[[device]]
+chip = "mc6850"
+start = 0x5000
+line = "tcp:6502"
+clock = 4800
+"#;
+
+    #[test]
+    fn parses_boards() {
+        let description = Description::parse(BOARD).unwrap();
+        assert_eq!(description.name, "test board");
+        assert_eq!(description.cpu, Variant::Cmos);
+        assert_eq!(description.clock_hz, 2_000_000);
+        assert_eq!(description.ram, vec![(0x0000, 0x3fff)]);
+        assert_eq!(description.roms, vec![RomRegion { start: 0xe000, end: 0xffff, file: String::from("mon#1.rom") }]);
+        assert_eq!(description.devices[0].chip, Chip::Via);
+        assert_eq!(description.devices[0].interrupt, Interrupt::Irq);
+        assert_eq!(description.devices[1], DeviceSpec {
+            chip: Chip::Mc6850,
+            start: 0x5000,
+            end: 0x5001,
+            interrupt: Interrupt::None,
+            line: Some(SerialLineSpec::Tcp(6502)),
+            clock_hz: Some(4800),
+            ram: None
+        });
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(Description::parse("[machine]\ncpu = \"65816\"").unwrap_err().contains("line 2"));
+        assert!(Description::parse("[[ram]]\nstart = 0\nend = 0x10000").is_err());
+        assert!(Description::parse("[[ram]]\nstart = 0x100\nend = 0").is_err());
+        assert!(Description::parse("[[device]]\nchip = \"sid\"\nstart = 0").is_err());
+        assert!(Description::parse("[[device]]\nchip = \"via\"\nstart = 0\nline = \"stdio\"").is_err());
+        assert!(Description::parse("[[rom]]\nstart = 0\nend = 1\nfile = \"a\"\nsize = 2").unwrap_err().contains("unknown key size"));
+        assert!(Description::parse("name = \"x\"").is_err());
+        assert!(Description::parse("[video]").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+mod description;
+
+pub use self::description::{Chip, Description, DeviceSpec, Interrupt, RomRegion};
+
+use platform::Platform;
+use device::{Device, Line, Scheduler};
+use pia::Pia;
+use via::Via;
+use riot::Riot;
+use acia::{Acia, Mc6850, SerialLine};
+use loader;
+
+/// A 6850's clock when the description doesn't give one: 9600 baud
+/// divided by 16.
+pub const DEFAULT_6850_CLOCK_HZ : u64 = 153_600;
+
+/// What answers at an address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Unmapped,
+    Ram,
+    Rom,
+    Device(usize),
+    RiotRam(usize)
+}
+
+/// A machine assembled at runtime from a `Description`. Chips take
+/// priority over memory where their ranges overlap, and unmapped reads
+/// return the floating bus. The chips run on a `Scheduler`, which wires
+/// their IRQ outputs to the CPU's IRQ or NMI line.
+pub struct Board {
+    name: String,
+    cpu_hz: u64,
+    memory: Vec<u8>,
+    decode: Vec<Target>,
+    devices: Vec<DeviceSpec>,
+    scheduler: Scheduler
+}
+
+impl Board {
+    /// The board with empty ROMs and unconnected serial lines.
+    pub fn new(description: &Description) -> Board {
+        let mut decode = vec![Target::Unmapped; 0x10000];
+        for &(start, end) in &description.ram {
+            for target in &mut decode[start as usize..=end as usize] {
+                *target = Target::Ram;
+            }
+        }
+        for rom in &description.roms {
+            for target in &mut decode[rom.start as usize..=rom.end as usize] {
+                *target = Target::Rom;
+            }
+        }
+        let cpu_hz = description.clock_hz;
+        let mut scheduler = Scheduler::new();
+        for (i, spec) in description.devices.iter().enumerate() {
+            for target in &mut decode[spec.start as usize..=spec.end as usize] {
+                *target = Target::Device(i);
+            }
+            if let Some(ram) = spec.ram {
+                let end = (ram as usize + ::riot::RAM_SIZE).min(0x10000);
+                for target in &mut decode[ram as usize..end] {
+                    *target = Target::RiotRam(i);
+                }
+            }
+            let device : Box<dyn Device> = match spec.chip {
+                Chip::Pia => Box::new(Pia::new()),
+                Chip::Via => Box::new(Via::new()),
+                Chip::Riot => Box::new(Riot::new()),
+                Chip::Acia => Box::new(Acia::new(cpu_hz)),
+                Chip::Mc6850 => Box::new(Mc6850::new(cpu_hz, spec.clock_hz.unwrap_or(DEFAULT_6850_CLOCK_HZ)))
+            };
+            let line = match spec.interrupt {
+                Interrupt::None => Line::None,
+                Interrupt::Irq => Line::Irq,
+                Interrupt::Nmi => Line::Nmi
+            };
+            scheduler.add(device, line);
+        }
+        Board {
+            name: description.name.clone(),
+            cpu_hz,
+            memory: vec![0; 0x10000],
+            decode,
+            devices: description.devices.clone(),
+            scheduler
+        }
+    }
+
+    /// The board with its ROM images read from their files.
+    pub fn from_description(description: &Description) -> Result<Board, String> {
+        let mut board = Board::new(description);
+        for rom in &description.roms {
+            let data = loader::read_file(&rom.file).map_err(|err| format!("error loading {}: {}", rom.file, err))?;
+            board.install_rom(rom, &data)?;
+        }
+        Ok(board)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Copies an image into a ROM region so that it ends at the top of it.
+    pub fn install_rom(&mut self, rom: &RomRegion, data: &[u8]) -> Result<(), String> {
+        let size = rom.end as usize - rom.start as usize + 1;
+        if data.len() > size {
+            return Err(format!("{} is {} bytes, too big for ${:04X}-${:04X}", rom.file, data.len(), rom.start, rom.end));
+        }
+        let start = rom.end as usize + 1 - data.len();
+        self.memory[start..=rom.end as usize].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Connects the serial line of the ACIA that is device `index` in the
+    /// description.
+    pub fn connect(&mut self, index: usize, line: Box<dyn SerialLine>) {
+        let spec = &self.devices[index];
+        let device : Box<dyn Device> = match spec.chip {
+            Chip::Acia => Box::new(Acia::with_line(self.cpu_hz, line)),
+            Chip::Mc6850 => Box::new(Mc6850::with_line(self.cpu_hz, spec.clock_hz.unwrap_or(DEFAULT_6850_CLOCK_HZ), line)),
+            chip => panic!("{:?} has no serial line", chip)
+        };
+        self.scheduler.replace(index, device);
+    }
+
+    fn register(&self, index: usize, address: u16) -> u16 {
+        let spec = &self.devices[index];
+        (address - spec.start) & (spec.chip.registers() - 1)
+    }
+}
+
+impl Platform for Board {
+
+    fn read(&mut self, address: u16) -> u8 {
+        match self.decode[address as usize] {
+            Target::Ram | Target::Rom => self.memory[address as usize],
+            Target::Device(i) => {
+                let register = self.register(i, address);
+                self.scheduler.read(i, register)
+            }
+            Target::RiotRam(i) => self.scheduler.read(i, 0x80 | (address & 0x7f)),
+            Target::Unmapped => (address >> 8) as u8
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match self.decode[address as usize] {
+            Target::Ram => self.memory[address as usize] = value,
+            Target::Device(i) => {
+                let register = self.register(i, address);
+                self.scheduler.write(i, register, value);
+            }
+            Target::RiotRam(i) => self.scheduler.write(i, 0x80 | (address & 0x7f), value),
+            Target::Rom | Target::Unmapped => {}
+        }
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        match self.decode[address as usize] {
+            Target::Device(_) | Target::RiotRam(_) => self.write(address, value),
+            _ => self.memory[address as usize] = value
+        }
+    }
+
+    fn load(&mut self, program: Vec<u8>, address: u16) {
+        for (byte, target) in self.memory.iter_mut().zip(&self.decode) {
+            if *target == Target::Ram {
+                *byte = 0;
+            }
+        }
+        for (i, &byte) in program.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.scheduler.reset();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.scheduler.advance(cycles);
+    }
+
+    fn irq(&self) -> bool {
+        self.scheduler.irq()
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        self.scheduler.take_nmi()
+    }
+
+    /// Input only arrives through the serial lines.
+    fn key_ready(&self) -> bool {
+        true
+    }
+
+    fn key_pressed(&mut self, _key: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::MOS6502;
+
+    const BOARD : &str = r#"
+[[ram]]
+start = 0
+end = 0x7fff
+
+[[rom]]
+start = 0xe000
+end = 0xffff
+file = "rom"
+
+[[device]]
+chip = "via"
+start = 0x6000
+end = 0x6fff
+interrupt = "irq"
+
+[[device]]
+chip = "riot"
+start = 0x7000
+ram = 0x7080
+interrupt = "nmi"
+
+[[device]]
+chip = "6850"
+start = 0xc000
+end = 0xc0ff
+"#;
+
+    #[test]
+    fn address_decoding() {
+        let description = Description::parse(BOARD).unwrap();
+        let mut board = Board::new(&description);
+        board.install_rom(&description.roms[0], &[0x11, 0x22]).unwrap();
+        assert!(board.install_rom(&description.roms[0], &[0; 0x2001]).is_err());
+        board.write(0x7fff, 0x55);
+        board.write(0xfffe, 0x55);
+        assert_eq!(board.read(0x7fff), 0x55);
+        assert_eq!(board.read(0xfffe), 0x11);
+        assert_eq!(board.read(0x8000), 0x80);
+
+        // the VIA repeats every 16 bytes, its DDRA here at $6FF3
+        board.write(0x6ff3, 0xa5);
+        assert_eq!(board.read(0x6003), 0xa5);
+        // RIOT RAM and timer, 6850 status out of master reset
+        board.write(0x70ff, 0x42);
+        assert_eq!(board.read(0x70ff), 0x42);
+        board.write(0x7014, 0x05);
+        assert_eq!(board.read(0x7004), 0x05);
+        board.write(0xc0fe, 0x15);
+        assert_eq!(board.read(0xc000) & ::acia::mc6850::TDRE, ::acia::mc6850::TDRE);
+    }
+
+    #[test]
+    fn interrupt_wiring() {
+        let description = Description::parse(BOARD).unwrap();
+        let mut board = Board::new(&description);
+        let mut rom = vec![0xea; 0x2000];
+        let program = [
+            0xa9, 0xc0, 0x8d, 0x0e, 0x60,       // lda #$c0; sta IER
+            0xa9, 0x10, 0x8d, 0x04, 0x60,       // lda #$10; sta T1C_L
+            0xa9, 0x00, 0x8d, 0x05, 0x60,       // lda #$00; sta T1C_H
+            0x58,                               // cli
+            0x4c, 0x10, 0xe0                    // jmp *
+        ];
+        let handler = [
+            0xad, 0x04, 0x60,                   // lda T1C_L
+            0xe6, 0x00,                         // inc $00
+            0x00                                // brk
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x20..0x20 + handler.len()].copy_from_slice(&handler);
+        rom[0x1ffc..].copy_from_slice(&[0x00, 0xe0, 0x20, 0xe0]);
+        board.install_rom(&description.roms[0], &rom).unwrap();
+
+        let mut cpu = MOS6502::new(Box::new(board));
+        cpu.reset();
+        while cpu.is_running() && cpu.get_total_cycles() < 1000 {
+            cpu.step();
+        }
+        assert!(!cpu.is_running());
+        assert_eq!(cpu.read_u8(0x0000), 1);
+        // the return address points back into the loop
+        assert_eq!(cpu.read_u8(0x01fd), 0xe0);
+    }
+}
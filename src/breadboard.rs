@@ -0,0 +1,398 @@
+use platform::Platform;
+use console::{OutputSink, RawSink};
+use pia::Port;
+use via::Via;
+use acia::Acia;
+use hd44780::Hd44780;
+use device::{Line, Scheduler};
+use symbols::parse_address;
+
+/// Ben Eater's breadboard computer runs from a 1 MHz can oscillator.
+pub const BREADBOARD_HZ : u64 = 1_000_000;
+
+/// LCD control lines on VIA port A; the data bus is port B.
+pub const LCD_E : u8 = 0x80;
+pub const LCD_RW : u8 = 0x40;
+pub const LCD_RS : u8 = 0x20;
+
+/// How often a changed LCD is redrawn, about 50 times a second.
+const RENDER_CYCLES : u32 = 20_000;
+
+/// Which chip answers where, each an inclusive range. The VIA and ACIA
+/// repeat through their ranges on the low 4 and 2 address bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AddressMap {
+    pub ram: (u16, u16),
+    pub rom: (u16, u16),
+    pub via: (u16, u16),
+    pub acia: (u16, u16)
+}
+
+/// The decoding on the breadboard: 16K of RAM, the ACIA at $5000, the VIA
+/// at $6000 and 32K of ROM at $8000.
+impl Default for AddressMap {
+    fn default() -> AddressMap {
+        AddressMap {
+            ram: (0x0000, 0x3fff),
+            rom: (0x8000, 0xffff),
+            via: (0x6000, 0x7fff),
+            acia: (0x5000, 0x5fff)
+        }
+    }
+}
+
+impl AddressMap {
+    /// Parses comma-separated `chip=start-end` entries, e.g.
+    /// `ram=0000-7fff,via=8000-800f`; chips not mentioned keep their
+    /// default range.
+    pub fn parse(text: &str) -> Result<AddressMap, String> {
+        let mut map = AddressMap::default();
+        for entry in text.split(',') {
+            let eq = entry.find('=').ok_or_else(|| format!("--map expects <chip>=<start>-<end>, got {}", entry))?;
+            let range = &entry[eq + 1..];
+            let range = match range.find('-') {
+                Some(dash) => (parse_address(&range[..dash]), parse_address(&range[dash + 1..])),
+                None => (None, None)
+            };
+            let range = match range {
+                (Some(start), Some(end)) if start <= end => (start, end),
+                _ => return Err(format!("invalid address range in {}", entry))
+            };
+            match &entry[..eq] {
+                "ram" => map.ram = range,
+                "rom" => map.rom = range,
+                "via" => map.via = range,
+                "acia" => map.acia = range,
+                chip => return Err(format!("--map expects ram, rom, via or acia, got {}", chip))
+            }
+        }
+        Ok(map)
+    }
+}
+
+fn within(address: u16, range: (u16, u16)) -> bool {
+    address >= range.0 && address <= range.1
+}
+
+/// A 6502 breadboard computer after Ben Eater's design: RAM, ROM, a 6522
+/// VIA driving a 16x2 HD44780 LCD, and optionally a 6551 ACIA, decoded
+/// by an `AddressMap`. Where ranges overlap the VIA wins, then the ACIA,
+/// ROM and RAM; unmapped addresses read the floating bus.
+///
+/// The LCD hangs off the VIA in 8-bit mode, D0-D7 on port B and E, R/W and
+/// RS on PA7-PA5. It is redrawn in place on the terminal's current line
+/// whenever its contents change. Keys from the host go to the ACIA's
+/// serial line, not through `key_pressed`. The chips run on a
+/// `Scheduler`, with the VIA's and ACIA's IRQ outputs wired to IRQ.
+///
+/// The real board has a W65C02S, so the CPU runs as `Variant::Cmos`.
+pub struct Breadboard {
+    map: AddressMap,
+    ram: Vec<u8>,
+    rom: Vec<u8>,
+    scheduler: Scheduler,
+    via: usize,
+    lcd: usize,
+    acia: Option<usize>,
+    lcd_control: u8,
+    render_cycles: u32,
+    rendered: String,
+    output: Box<dyn OutputSink>
+}
+
+impl Breadboard {
+    pub fn new(map: AddressMap) -> Breadboard {
+        let rom_len = (map.rom.1 - map.rom.0) as usize + 1;
+        let mut scheduler = Scheduler::new();
+        let via = scheduler.add(Box::new(Via::new()), Line::Irq);
+        let lcd = scheduler.add(Box::new(Hd44780::new(BREADBOARD_HZ)), Line::None);
+        Breadboard {
+            map,
+            ram: vec![0; 0x10000],
+            rom: vec![0xff; rom_len],
+            scheduler,
+            via,
+            lcd,
+            acia: None,
+            lcd_control: 0,
+            render_cycles: 0,
+            rendered: String::new(),
+            output: Box::new(RawSink::stdout())
+        }
+    }
+
+    pub fn map(&self) -> AddressMap {
+        self.map
+    }
+
+    /// Burns a ROM image. Images are aligned to the top of the ROM range,
+    /// so a 32K image assembled for $8000 fills it and a smaller one
+    /// still supplies the vectors; a larger one keeps its last bytes.
+    pub fn install_rom(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(self.rom.len())..];
+        let start = self.rom.len() - data.len();
+        self.rom[start..].copy_from_slice(data);
+    }
+
+    pub fn attach_acia(&mut self, acia: Acia) {
+        match self.acia {
+            Some(id) => self.scheduler.replace(id, Box::new(acia)),
+            None => self.acia = Some(self.scheduler.add(Box::new(acia), Line::Irq))
+        }
+    }
+
+    /// Sends the LCD's redraws somewhere other than stdout.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    pub fn via(&self) -> &Via {
+        self.scheduler.device(self.via)
+    }
+
+    fn lcd_device(&self) -> &Hd44780 {
+        self.scheduler.device(self.lcd)
+    }
+
+    /// Clocks the LCD from the VIA's port A: data is read as E rises and
+    /// written as it falls. RS is the LCD's register number.
+    fn update_lcd(&mut self) {
+        let control = self.via().output(Port::A);
+        let rising = control & LCD_E != 0 && self.lcd_control & LCD_E == 0;
+        let falling = control & LCD_E == 0 && self.lcd_control & LCD_E != 0;
+        if rising && control & LCD_RW != 0 {
+            let value = self.scheduler.read(self.lcd, (control & LCD_RS != 0) as u16);
+            self.scheduler.with(self.via, |via: &mut Via| via.set_input(Port::B, value));
+        } else if falling && self.lcd_control & LCD_RW == 0 {
+            let value = self.via().output(Port::B);
+            self.scheduler.write(self.lcd, (self.lcd_control & LCD_RS != 0) as u16, value);
+        }
+        self.lcd_control = control;
+    }
+
+    fn render(&mut self) {
+        let text = format!("[{}|{}]", self.lcd_device().line(0), self.lcd_device().line(1));
+        if text != self.rendered {
+            self.output.output(0x0d);
+            for byte in text.bytes() {
+                self.output.output(byte);
+            }
+            self.output.flush();
+            self.rendered = text;
+        }
+    }
+}
+
+impl Default for Breadboard {
+    fn default() -> Breadboard {
+        Breadboard::new(AddressMap::default())
+    }
+}
+
+impl Platform for Breadboard {
+
+    fn read(&mut self, address: u16) -> u8 {
+        if within(address, self.map.via) {
+            return self.scheduler.read(self.via, address & 0x0f);
+        }
+        if let (true, Some(acia)) = (within(address, self.map.acia), self.acia) {
+            return self.scheduler.read(acia, address & 0x03);
+        }
+        if within(address, self.map.rom) {
+            return self.rom[(address - self.map.rom.0) as usize];
+        }
+        if within(address, self.map.ram) {
+            return self.ram[address as usize];
+        }
+        (address >> 8) as u8
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if within(address, self.map.via) {
+            self.scheduler.write(self.via, address & 0x0f, value);
+            self.update_lcd();
+        } else if within(address, self.map.acia) {
+            if let Some(acia) = self.acia {
+                self.scheduler.write(acia, address & 0x03, value);
+            }
+        } else if within(address, self.map.ram) && !within(address, self.map.rom) {
+            self.ram[address as usize] = value;
+        }
+    }
+
+    /// Like a write, but also patches the ROM, e.g. for --reset-vector.
+    fn poke(&mut self, address: u16, value: u8) {
+        if within(address, self.map.rom) {
+            self.rom[(address - self.map.rom.0) as usize] = value;
+        } else if within(address, self.map.ram) {
+            self.ram[address as usize] = value;
+        }
+    }
+
+    fn load(&mut self, program: Vec<u8>, address: u16) {
+        for b in self.ram.iter_mut() {
+            *b = 0;
+        }
+        for (i, &byte) in program.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// The LCD has no reset pin, so only the VIA and ACIA see RESET.
+    fn reset(&mut self) {
+        self.scheduler.reset();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.scheduler.advance(cycles);
+        self.render_cycles += cycles;
+        if self.render_cycles >= RENDER_CYCLES {
+            self.render_cycles = 0;
+            self.render();
+        }
+    }
+
+    fn lcd(&self) -> Option<&Hd44780> {
+        Some(self.lcd_device())
+    }
+
+    fn irq(&self) -> bool {
+        self.scheduler.irq()
+    }
+
+    fn key_ready(&self) -> bool {
+        true
+    }
+
+    fn key_pressed(&mut self, _key: u8) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::MOS6502;
+
+    #[test]
+    fn address_map() {
+        assert_eq!(AddressMap::parse("ram=0-7fff,via=$9000-900F").unwrap(), AddressMap {
+            ram: (0x0000, 0x7fff),
+            via: (0x9000, 0x900f),
+            ..AddressMap::default()
+        });
+        assert!(AddressMap::parse("vdp=8000-8001").is_err());
+        assert!(AddressMap::parse("ram=4000-0000").is_err());
+        assert!(AddressMap::parse("ram").is_err());
+
+        let mut board = Breadboard::default();
+        board.install_rom(&[0x00, 0x80]);
+        board.write(0x1234, 0x55);
+        board.write(0x4000, 0x55);
+        board.write(0xfffe, 0x55);
+        assert_eq!(board.read(0x1234), 0x55);
+        assert_eq!(board.read(0x4000), 0x40);
+        assert_eq!(board.read(0xfffe), 0x00);
+        assert_eq!(board.read(0xffff), 0x80);
+        assert_eq!(board.read(0x8000), 0xff);
+        board.write(0x7ff3, 0xff);
+        assert_eq!(board.read(0x6003), 0xff);
+    }
+
+    #[test]
+    fn hello_world() {
+        // the LCD setup and print loop from the first breadboard videos,
+        // checking the busy flag before each write
+        let program = [
+            0xa2, 0xff, 0x9a,                   // ldx #$ff; txs
+            0xa9, 0xff, 0x8d, 0x02, 0x60,       // lda #$ff; sta DDRB
+            0xa9, 0xe0, 0x8d, 0x03, 0x60,       // lda #$e0; sta DDRA
+            0xa9, 0x38, 0x20, 0x00, 0x81,       // lda #$38; jsr lcd_instruction
+            0xa9, 0x0e, 0x20, 0x00, 0x81,       // lda #$0e; jsr lcd_instruction
+            0xa9, 0x06, 0x20, 0x00, 0x81,       // lda #$06; jsr lcd_instruction
+            0xa9, 0x01, 0x20, 0x00, 0x81,       // lda #$01; jsr lcd_instruction
+            0xa2, 0x00,                         // ldx #0
+            0xbd, 0xa0, 0x80,                   // print: lda message,x
+            0xf0, 0x06,                         // beq done
+            0x20, 0x40, 0x81,                   // jsr print_char
+            0xe8, 0xd0, 0xf5,                   // inx; bne print
+            0x00                                // done: brk
+        ];
+        let lcd_instruction = [
+            0x48,                               // pha
+            0xa9, 0x00, 0x8d, 0x02, 0x60,       // wait: lda #0; sta DDRB
+            0xa9, 0x40, 0x8d, 0x01, 0x60,       // lda #RW; sta PORTA
+            0xa9, 0xc0, 0x8d, 0x01, 0x60,       // lda #RW|E; sta PORTA
+            0xad, 0x00, 0x60,                   // lda PORTB
+            0x29, 0x80, 0xd0, 0xea,             // and #$80; bne wait
+            0xa9, 0x40, 0x8d, 0x01, 0x60,       // lda #RW; sta PORTA
+            0xa9, 0xff, 0x8d, 0x02, 0x60,       // lda #$ff; sta DDRB
+            0x68,                               // pla
+            0x8d, 0x00, 0x60,                   // sta PORTB
+            0xa9, 0x00, 0x8d, 0x01, 0x60,       // lda #0; sta PORTA
+            0xa9, 0x80, 0x8d, 0x01, 0x60,       // lda #E; sta PORTA
+            0xa9, 0x00, 0x8d, 0x01, 0x60,       // lda #0; sta PORTA
+            0x60                                // rts
+        ];
+        let mut rom = vec![0xea; 0x8000];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x100..0x100 + lcd_instruction.len()].copy_from_slice(&lcd_instruction);
+        // print_char is lcd_instruction with RS set on the final write
+        let mut print_char = lcd_instruction;
+        print_char[38] = 0x20;
+        print_char[43] = 0xa0;
+        print_char[48] = 0x20;
+        rom[0x140..0x140 + print_char.len()].copy_from_slice(&print_char);
+        rom[0xa0..0xae].copy_from_slice(b"Hello, world!\0");
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+
+        let mut board = Breadboard::default();
+        board.install_rom(&rom);
+        let mut cpu = MOS6502::new(Box::new(board));
+        cpu.reset();
+        while cpu.is_running() && cpu.get_total_cycles() < 100_000 {
+            cpu.step();
+        }
+        assert!(!cpu.is_running());
+        assert!(cpu.get_total_cycles() > 1520);
+        let lcd = cpu.platform().lcd().unwrap();
+        assert_eq!(lcd.text(), "Hello, world!   \n                ");
+        assert!(lcd.cursor_visible());
+    }
+
+    #[test]
+    fn via_interrupt() {
+        let program = [
+            0xa9, 0xc0, 0x8d, 0x0e, 0x60,       // lda #$c0; sta IER
+            0xa9, 0x10, 0x8d, 0x04, 0x60,       // lda #$10; sta T1CL
+            0xa9, 0x00, 0x8d, 0x05, 0x60,       // lda #0; sta T1CH
+            0x58,                               // cli
+            0x4c, 0x10, 0x80                    // loop: jmp loop
+        ];
+        let handler = [
+            0xad, 0x04, 0x60,                   // lda T1CL
+            0xe6, 0x00,                         // inc $00
+            0x40                                // rti
+        ];
+        let mut rom = vec![0xea; 0x8000];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x100..0x100 + handler.len()].copy_from_slice(&handler);
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+        rom[0x7ffe] = 0x00;
+        rom[0x7fff] = 0x81;
+
+        let mut board = Breadboard::default();
+        board.install_rom(&rom);
+        board.set_output(Box::new(::console::Buffer::new()));
+        let mut cpu = MOS6502::new(Box::new(board));
+        cpu.reset();
+        while cpu.get_total_cycles() < 1_000 {
+            cpu.step();
+        }
+        // T1 is one-shot, so the handler ran once
+        assert_eq!(cpu.read_u8(0x0000), 1);
+        assert_eq!(cpu.get_pc(), 0x8010);
+        assert!(!cpu.platform().irq());
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::PathBuf;
+use super::{Entry, StorageError, Volume, prodos_name, TYPE_BIN};
+
+/// A host directory used as the card's volume. File types travel in the
+/// name the way CiderPress keeps them, `NAME#TTAAAA` with the type and aux
+/// type in hex; other files show up as BIN with aux type 0.
+pub struct HostDirectory {
+    path: PathBuf
+}
+
+/// Splits a host file name into its ProDOS name, type and aux type.
+pub fn parse_host_name(filename: &str) -> Option<(String, u8, u16)> {
+    let (name, file_type, aux_type) = match filename.rfind('#') {
+        Some(hash) if filename.len() == hash + 7 && filename[hash + 1..].is_ascii() => {
+            let suffix = &filename[hash + 1..];
+            let file_type = u8::from_str_radix(&suffix[..2], 16).ok()?;
+            let aux_type = u16::from_str_radix(&suffix[2..], 16).ok()?;
+            (&filename[..hash], file_type, aux_type)
+        }
+        _ => (filename, TYPE_BIN, 0)
+    };
+    prodos_name(name).ok().map(|name| (name, file_type, aux_type))
+}
+
+impl HostDirectory {
+    pub fn new(path: &str) -> HostDirectory {
+        HostDirectory { path: PathBuf::from(path) }
+    }
+
+    /// The entries with the host file each one came from.
+    fn files(&self) -> Result<Vec<(Entry, PathBuf)>, StorageError> {
+        let mut files = Vec::new();
+        for dir_entry in fs::read_dir(&self.path)? {
+            let dir_entry = dir_entry?;
+            let metadata = dir_entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let filename = dir_entry.file_name().to_string_lossy().to_string();
+            if let Some((name, file_type, aux_type)) = parse_host_name(&filename) {
+                let entry = Entry { name, file_type, aux_type, size: metadata.len().min(u32::MAX as u64) as u32 };
+                files.push((entry, dir_entry.path()));
+            }
+        }
+        files.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+        Ok(files)
+    }
+
+    fn find(&self, name: &str) -> Result<(Entry, PathBuf), StorageError> {
+        let name = prodos_name(name)?;
+        self.files()?.into_iter()
+            .find(|file| file.0.name == name)
+            .ok_or(StorageError::FileNotFound)
+    }
+
+    fn host_path(&self, name: &str, file_type: u8, aux_type: u16) -> PathBuf {
+        self.path.join(format!("{}#{:02X}{:04X}", name, file_type, aux_type))
+    }
+}
+
+impl Volume for HostDirectory {
+    fn catalog(&mut self) -> Result<Vec<Entry>, StorageError> {
+        Ok(self.files()?.into_iter().map(|file| file.0).collect())
+    }
+
+    fn read_file(&mut self, name: &str) -> Result<(Entry, Vec<u8>), StorageError> {
+        let (entry, path) = self.find(name)?;
+        let data = fs::read(path)?;
+        Ok((entry, data))
+    }
+
+    fn write_file(&mut self, name: &str, file_type: u8, aux_type: u16, data: &[u8]) -> Result<(), StorageError> {
+        let name = prodos_name(name)?;
+        match self.delete_file(&name) {
+            Ok(()) | Err(StorageError::FileNotFound) => {}
+            Err(err) => return Err(err)
+        }
+        fs::write(self.host_path(&name, file_type, aux_type), data)?;
+        Ok(())
+    }
+
+    fn delete_file(&mut self, name: &str) -> Result<(), StorageError> {
+        let (_, path) = self.find(name)?;
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn rename_file(&mut self, old: &str, new: &str) -> Result<(), StorageError> {
+        let new = prodos_name(new)?;
+        let (entry, path) = self.find(old)?;
+        if entry.name != new && self.find(&new).is_ok() {
+            return Err(StorageError::DuplicateName);
+        }
+        fs::rename(path, self.host_path(&new, entry.file_type, entry.aux_type))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn host_names() {
+        assert_eq!(parse_host_name("HELLO#06A000"), Some((String::from("HELLO"), 0x06, 0xa000)));
+        assert_eq!(parse_host_name("game.bin"), Some((String::from("GAME.BIN"), TYPE_BIN, 0)));
+        assert_eq!(parse_host_name("1st"), None);
+        assert_eq!(parse_host_name("BAD#ZZ0000"), None);
+        assert_eq!(parse_host_name("a#1\u{e9}234"), None);
+    }
+
+    #[test]
+    fn files() {
+        let path = env::temp_dir().join(format!("magpie-cffa1-{}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let mut volume = HostDirectory::new(path.to_str().unwrap());
+        volume.write_file("prog", 0x06, 0x0300, &[1, 2, 3]).unwrap();
+        assert!(path.join("PROG#060300").exists());
+        volume.write_file("PROG", 0x06, 0x0400, &[4]).unwrap();
+        volume.rename_file("prog", "other").unwrap();
+        let catalog = volume.catalog().unwrap();
+        assert_eq!(catalog, vec![Entry { name: String::from("OTHER"), file_type: 0x06, aux_type: 0x0400, size: 1 }]);
+        assert_eq!(volume.read_file("other").unwrap().1, vec![4]);
+        volume.delete_file("OTHER").unwrap();
+        assert!(matches!(volume.read_file("OTHER"), Err(StorageError::FileNotFound)));
+        assert!(matches!(volume.write_file("9LIVES", 0x06, 0, &[]), Err(StorageError::InvalidName)));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
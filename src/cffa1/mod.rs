@@ -0,0 +1,551 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+mod hostdir;
+mod prodos;
+
+pub use self::hostdir::HostDirectory;
+pub use self::prodos::{Disk, ProdosImage};
+
+/// Stand-in card firmware at $9000, listed in cffa1.asm. `9000R` opens the
+/// menu and `JSR $900C` with a command in X calls the API; both hand the
+/// real work to `Cffa1::execute` through the command register. This is
+/// partial emulation: the genuine firmware, its other entry points and the
+/// card's IDE registers are not provided.
+pub const FIRMWARE: [u8; 109] = [
+    0x4c, 0x2e, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0x02, 0xf0, 0x1e,
+    0x8d, 0xf4, 0xaf, 0x8e, 0xf0, 0xaf, 0x20, 0x22, 0x90, 0xad, 0xf1, 0xaf, 0xc9, 0x01, 0xad, 0xf2,
+    0xaf, 0x60, 0xad, 0xf3, 0xaf, 0xf0, 0x06, 0x20, 0xef, 0xff, 0x4c, 0x22, 0x90, 0x60, 0xa2, 0xf0,
+    0x8e, 0xf0, 0xaf, 0x20, 0x22, 0x90, 0xa0, 0x00, 0xad, 0x11, 0xd0, 0x10, 0xfb, 0xad, 0x10, 0xd0,
+    0x20, 0xef, 0xff, 0xc9, 0xdf, 0xf0, 0x1e, 0xc9, 0x9b, 0xf0, 0xe3, 0x99, 0x00, 0x02, 0xc8, 0xc9,
+    0x8d, 0xd0, 0xe5, 0xa2, 0xf2, 0x8e, 0xf0, 0xaf, 0x20, 0x22, 0x90, 0xad, 0xf1, 0xaf, 0xc9, 0xff,
+    0xd0, 0xd4, 0x4c, 0x1f, 0xff, 0xc0, 0x00, 0xf0, 0xcf, 0x88, 0x4c, 0x38, 0x90];
+
+pub const CFFA1_START : u16 = 0x9000;
+pub const CFFA1_END : u16 = 0xafff;
+pub const MENU : u16 = 0x9000;
+pub const API : u16 = 0x900c;
+
+/// Bytes software checks to find the card.
+pub const ID1 : u16 = 0xafdc;
+pub const ID2 : u16 = 0xafdd;
+
+const COMMAND : u16 = 0xaff0;
+const STATUS : u16 = 0xaff1;
+const RESULT : u16 = 0xaff2;
+const OUTPUT : u16 = 0xaff3;
+const ARGUMENT : u16 = 0xaff4;
+
+/// Zero page parameter block of the API.
+pub const DESTINATION : usize = 0x00;
+pub const FILENAME : usize = 0x02;
+pub const OLD_FILENAME : usize = 0x04;
+pub const FILE_TYPE : usize = 0x06;
+pub const AUX_TYPE : usize = 0x07;
+pub const FILE_SIZE : usize = 0x09;
+
+pub const CMD_VERSION : u8 = 0x00;
+pub const CMD_MENU : u8 = 0x02;
+pub const CMD_DISPLAY_ERROR : u8 = 0x04;
+pub const CMD_OPEN_DIR : u8 = 0x10;
+pub const CMD_READ_DIR : u8 = 0x12;
+pub const CMD_WRITE_FILE : u8 = 0x20;
+pub const CMD_READ_FILE : u8 = 0x22;
+pub const CMD_DELETE_FILE : u8 = 0x24;
+pub const CMD_RENAME_FILE : u8 = 0x26;
+const CMD_MENU_START : u8 = 0xf0;
+const CMD_MENU_LINE : u8 = 0xf2;
+const STATUS_QUIT : u8 = 0xff;
+
+pub const VERSION : u8 = 0x10;
+
+pub const TYPE_TXT : u8 = 0x04;
+pub const TYPE_BIN : u8 = 0x06;
+pub const TYPE_INT : u8 = 0xfa;
+
+/// Integer BASIC zero page pointers used to load and save programs.
+const LOMEM : usize = 0x4a;
+const HIMEM : usize = 0x4c;
+const PP : usize = 0xca;
+const PV : usize = 0xcc;
+
+/// A file on the card: ProDOS name, file type and aux type (the load
+/// address for BIN files).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub file_type: u8,
+    pub aux_type: u16,
+    pub size: u32
+}
+
+/// Errors, reported to 6502 code with their ProDOS error numbers.
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    BadCommand,
+    InvalidName,
+    FileNotFound,
+    DuplicateName,
+    VolumeFull,
+    DirectoryFull,
+    EndOfDirectory,
+    NotProdos
+}
+
+impl StorageError {
+    pub fn code(&self) -> u8 {
+        match *self {
+            StorageError::Io(_) => 0x27,
+            StorageError::BadCommand => 0x01,
+            StorageError::InvalidName => 0x40,
+            StorageError::FileNotFound => 0x46,
+            StorageError::DuplicateName => 0x47,
+            StorageError::VolumeFull => 0x48,
+            StorageError::DirectoryFull => 0x49,
+            StorageError::EndOfDirectory => 0x4c,
+            StorageError::NotProdos => 0x52
+        }
+    }
+
+    fn message(code: u8) -> &'static str {
+        match code {
+            0x01 => "BAD COMMAND",
+            0x27 => "I/O ERROR",
+            0x40 => "INVALID NAME",
+            0x46 => "FILE NOT FOUND",
+            0x47 => "DUPLICATE NAME",
+            0x48 => "VOLUME FULL",
+            0x49 => "DIRECTORY FULL",
+            0x4c => "END OF DIRECTORY",
+            0x52 => "NOT A PRODOS VOLUME",
+            _ => "ERROR"
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Io(ref err) => write!(f, "{}", err),
+            _ => write!(f, "{}", StorageError::message(self.code()).to_lowercase())
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> StorageError {
+        StorageError::Io(err)
+    }
+}
+
+/// Storage behind the card: a flat ProDOS-style directory of files.
+pub trait Volume {
+    fn catalog(&mut self) -> Result<Vec<Entry>, StorageError>;
+    fn read_file(&mut self, name: &str) -> Result<(Entry, Vec<u8>), StorageError>;
+    /// Creates the file, replacing any file with the same name.
+    fn write_file(&mut self, name: &str, file_type: u8, aux_type: u16, data: &[u8]) -> Result<(), StorageError>;
+    fn delete_file(&mut self, name: &str) -> Result<(), StorageError>;
+    fn rename_file(&mut self, old: &str, new: &str) -> Result<(), StorageError>;
+}
+
+/// Checks and upper-cases a ProDOS file name: a letter followed by up to 14
+/// letters, digits or periods.
+pub fn prodos_name(name: &str) -> Result<String, StorageError> {
+    let name = name.to_ascii_uppercase();
+    let valid = !name.is_empty() && name.len() <= 15
+        && name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '.');
+    if valid { Ok(name) } else { Err(StorageError::InvalidName) }
+}
+
+pub fn type_name(file_type: u8) -> String {
+    match file_type {
+        TYPE_TXT => String::from("TXT"),
+        TYPE_BIN => String::from("BIN"),
+        TYPE_INT => String::from("INT"),
+        0xfc => String::from("BAS"),
+        0xff => String::from("SYS"),
+        _ => format!("${:02X}", file_type)
+    }
+}
+
+fn word(ram: &[u8], address: usize) -> u16 {
+    ram[address] as u16 | (ram[address + 1] as u16) << 8
+}
+
+fn set_word(ram: &mut [u8], address: usize, value: u16) {
+    ram[address] = value as u8;
+    ram[address + 1] = (value >> 8) as u8;
+}
+
+/// Reads a counted string, as the API passes file names.
+fn counted_string(ram: &[u8], address: u16) -> String {
+    let len = ram[address as usize] as usize;
+    (1..=len).map(|i| (ram[(address as usize + i) & 0xffff] & 0x7f) as char).collect()
+}
+
+/// CFFA1 mass storage card at $9000-$AFFF, backed by a host directory or a
+/// ProDOS disk image.
+///
+/// The firmware is a thin shim: writing a command number to the command
+/// register runs the command here, against the machine's memory, and the
+/// firmware then prints whatever text the command queued on the output
+/// register and returns the error status with carry set on failure. The
+/// API takes its parameters in the zero page block at $00-$0A, as on the
+/// real card, but only the `CMD_` commands are implemented and a
+/// dump of the real card's ROM cannot be used.
+pub struct Cffa1 {
+    volume: Box<dyn Volume>,
+    status: u8,
+    result: u8,
+    argument: u8,
+    output: VecDeque<u8>,
+    directory: Vec<Entry>,
+    directory_index: usize
+}
+
+impl Cffa1 {
+    pub fn new(volume: Box<dyn Volume>) -> Cffa1 {
+        Cffa1 {
+            volume,
+            status: 0,
+            result: 0,
+            argument: 0,
+            output: VecDeque::new(),
+            directory: Vec::new(),
+            directory_index: 0
+        }
+    }
+
+    /// Opens a host directory, or a ProDOS order disk image (.po, .hdv).
+    pub fn open(path: &str) -> Result<Cffa1, StorageError> {
+        let volume : Box<dyn Volume> = if Path::new(path).is_dir() {
+            Box::new(HostDirectory::new(path))
+        } else {
+            Box::new(ProdosImage::open(path)?)
+        };
+        Ok(Cffa1::new(volume))
+    }
+
+    pub fn volume_mut(&mut self) -> &mut dyn Volume {
+        &mut *self.volume
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        match address {
+            STATUS => self.status,
+            RESULT => self.result,
+            OUTPUT => self.output.pop_front().unwrap_or(0),
+            ID1 => 0xcf,
+            ID2 => 0xfa,
+            _ => FIRMWARE.get((address - CFFA1_START) as usize).cloned().unwrap_or(0)
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, ram: &mut [u8]) {
+        match address {
+            COMMAND => self.execute(value, ram),
+            ARGUMENT => self.argument = value,
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        for b in text.bytes() {
+            let b = if b == b'\n' { 0x0d } else { b.to_ascii_uppercase() };
+            self.output.push_back(b | 0x80);
+        }
+    }
+
+    /// Runs an API or menu command against the machine's memory.
+    pub fn execute(&mut self, command: u8, ram: &mut [u8]) {
+        let result = match command {
+            CMD_VERSION => Ok(VERSION),
+            CMD_DISPLAY_ERROR => {
+                let message = format!("\nERR ${:02X} {}\n", self.argument, StorageError::message(self.argument));
+                self.print(&message);
+                Ok(0)
+            }
+            CMD_OPEN_DIR => self.open_dir(),
+            CMD_READ_DIR => self.read_dir(ram),
+            CMD_WRITE_FILE => self.write_file(ram),
+            CMD_READ_FILE => self.read_file(ram),
+            CMD_DELETE_FILE => {
+                let name = counted_string(ram, word(ram, FILENAME));
+                self.volume.delete_file(&name).map(|_| 0)
+            }
+            CMD_RENAME_FILE => {
+                let old = counted_string(ram, word(ram, OLD_FILENAME));
+                let new = counted_string(ram, word(ram, FILENAME));
+                self.volume.rename_file(&old, &new).map(|_| 0)
+            }
+            CMD_MENU_START => {
+                self.print("\nCFFA1 STORAGE  (? FOR HELP)\n>");
+                Ok(0)
+            }
+            CMD_MENU_LINE => {
+                let line: String = ram[0x0200..0x0300].iter()
+                    .take_while(|&&b| b != 0x8d)
+                    .map(|&b| (b & 0x7f) as char)
+                    .collect();
+                let quit = self.menu_line(&line, ram);
+                if quit {
+                    self.status = STATUS_QUIT;
+                    self.result = 0;
+                } else {
+                    self.print(">");
+                }
+                return;
+            }
+            _ => Err(StorageError::BadCommand)
+        };
+        match result {
+            Ok(value) => {
+                self.status = 0;
+                self.result = value;
+            }
+            Err(err) => {
+                self.status = err.code();
+                self.result = err.code();
+            }
+        }
+    }
+
+    fn open_dir(&mut self) -> Result<u8, StorageError> {
+        self.directory = self.volume.catalog()?;
+        self.directory_index = 0;
+        Ok(0)
+    }
+
+    /// Copies the next entry's name to Destination as a counted string and
+    /// its type, aux type and size to the parameter block.
+    fn read_dir(&mut self, ram: &mut [u8]) -> Result<u8, StorageError> {
+        let entry = self.directory.get(self.directory_index).cloned().ok_or(StorageError::EndOfDirectory)?;
+        self.directory_index += 1;
+        let destination = word(ram, DESTINATION) as usize;
+        ram[destination & 0xffff] = entry.name.len() as u8;
+        for (i, b) in entry.name.bytes().enumerate() {
+            ram[(destination + 1 + i) & 0xffff] = b | 0x80;
+        }
+        ram[FILE_TYPE] = entry.file_type;
+        set_word(ram, AUX_TYPE, entry.aux_type);
+        set_word(ram, FILE_SIZE, entry.size.min(0xffff) as u16);
+        Ok(0)
+    }
+
+    fn write_file(&mut self, ram: &mut [u8]) -> Result<u8, StorageError> {
+        let name = counted_string(ram, word(ram, FILENAME));
+        let start = word(ram, DESTINATION) as usize;
+        let end = (start + word(ram, FILE_SIZE) as usize).min(ram.len());
+        self.volume.write_file(&name, ram[FILE_TYPE], word(ram, AUX_TYPE), &ram[start..end])?;
+        Ok(0)
+    }
+
+    /// Loads a file at Destination, or at its aux type when Destination is
+    /// zero, and fills in its type, aux type and size.
+    fn read_file(&mut self, ram: &mut [u8]) -> Result<u8, StorageError> {
+        let name = counted_string(ram, word(ram, FILENAME));
+        let (entry, data) = self.volume.read_file(&name)?;
+        let destination = match word(ram, DESTINATION) {
+            0 => entry.aux_type,
+            address => address
+        };
+        copy_in(ram, destination, &data);
+        ram[FILE_TYPE] = entry.file_type;
+        set_word(ram, AUX_TYPE, entry.aux_type);
+        set_word(ram, FILE_SIZE, data.len().min(0xffff) as u16);
+        Ok(0)
+    }
+
+    /// Runs one line typed at the menu. Returns true to leave the menu.
+    fn menu_line(&mut self, line: &str, ram: &mut [u8]) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.first().map(|w| w.to_ascii_uppercase()).as_deref() {
+            None => Ok(()),
+            Some("Q") => return true,
+            Some("?") | Some("H") => {
+                self.print("C            CATALOG\nL NAME [ADDR] LOAD\nS NAME START END  SAVE MEMORY\nS NAME       SAVE BASIC PROGRAM\nD NAME       DELETE\nR OLD NEW    RENAME\nQ            BACK TO WOZMON\n");
+                Ok(())
+            }
+            Some("C") => self.menu_catalog(),
+            Some("L") if words.len() == 2 || words.len() == 3 => self.menu_load(&words[1..], ram),
+            Some("S") if words.len() == 2 => self.menu_save_basic(words[1], ram),
+            Some("S") if words.len() == 4 => self.menu_save(&words[1..], ram),
+            Some("D") if words.len() == 2 => self.volume.delete_file(words[1]),
+            Some("R") if words.len() == 3 => self.volume.rename_file(words[1], words[2]),
+            _ => Err(StorageError::BadCommand)
+        };
+        if let Err(err) = result {
+            let message = format!("ERR ${:02X} {}\n", err.code(), StorageError::message(err.code()));
+            self.print(&message);
+        }
+        false
+    }
+
+    fn menu_catalog(&mut self) -> Result<(), StorageError> {
+        let entries = self.volume.catalog()?;
+        for entry in &entries {
+            let line = format!("{:<15} {:<3} ${:04X} {:>5}\n", entry.name, type_name(entry.file_type), entry.aux_type, entry.size);
+            self.print(&line);
+        }
+        let count = format!("{} FILES\n", entries.len());
+        self.print(&count);
+        Ok(())
+    }
+
+    fn menu_load(&mut self, args: &[&str], ram: &mut [u8]) -> Result<(), StorageError> {
+        let (entry, data) = self.volume.read_file(args[0])?;
+        if entry.file_type == TYPE_INT && args.len() == 1 {
+            // the program sits right below HIMEM; variables are cleared
+            let start = word(ram, HIMEM).wrapping_sub(data.len() as u16);
+            copy_in(ram, start, &data);
+            set_word(ram, PP, start);
+            let lomem = word(ram, LOMEM);
+            set_word(ram, PV, lomem);
+            self.print("BASIC PROGRAM LOADED\n");
+            return Ok(());
+        }
+        let start = match args.get(1) {
+            Some(text) => ::symbols::parse_address(text).ok_or(StorageError::BadCommand)?,
+            None => entry.aux_type
+        };
+        copy_in(ram, start, &data);
+        let message = format!("{:04X}-{:04X}\n", start, start as usize + data.len().max(1) - 1);
+        self.print(&message);
+        Ok(())
+    }
+
+    fn menu_save(&mut self, args: &[&str], ram: &mut [u8]) -> Result<(), StorageError> {
+        let start = ::symbols::parse_address(args[1]).ok_or(StorageError::BadCommand)?;
+        let end = ::symbols::parse_address(args[2]).ok_or(StorageError::BadCommand)?;
+        if end < start {
+            return Err(StorageError::BadCommand);
+        }
+        self.volume.write_file(args[0], TYPE_BIN, start, &ram[start as usize..=end as usize])
+    }
+
+    fn menu_save_basic(&mut self, name: &str, ram: &mut [u8]) -> Result<(), StorageError> {
+        let start = word(ram, PP) as usize;
+        let end = word(ram, HIMEM) as usize;
+        if end < start {
+            return Err(StorageError::BadCommand);
+        }
+        self.volume.write_file(name, TYPE_INT, start as u16, &ram[start..end])
+    }
+}
+
+/// Copies into memory, stopping at the top of the address space.
+fn copy_in(ram: &mut [u8], address: u16, data: &[u8]) {
+    let start = address as usize;
+    let end = (start + data.len()).min(ram.len());
+    ram[start..end].copy_from_slice(&data[..end - start]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use apple1::Apple1;
+    use cpu::MOS6502;
+    use platform::Platform;
+
+    fn card() -> Cffa1 {
+        let image = ProdosImage::format(Box::new(Cursor::new(Vec::new())), "TEST", 280).unwrap();
+        Cffa1::new(Box::new(image))
+    }
+
+    fn set_name(ram: &mut [u8], pointer: usize, address: u16, name: &str) {
+        set_word(ram, pointer, address);
+        ram[address as usize] = name.len() as u8;
+        ram[address as usize + 1..address as usize + 1 + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    #[test]
+    fn api() {
+        let mut card = card();
+        let mut ram = vec![0u8; 0x10000];
+        ram[0x0300..0x0304].copy_from_slice(&[1, 2, 3, 4]);
+        set_name(&mut ram, FILENAME, 0x0280, "PROG");
+        set_word(&mut ram, DESTINATION, 0x0300);
+        ram[FILE_TYPE] = TYPE_BIN;
+        set_word(&mut ram, AUX_TYPE, 0x0300);
+        set_word(&mut ram, FILE_SIZE, 4);
+        card.execute(CMD_WRITE_FILE, &mut ram);
+        assert_eq!(card.read(STATUS), 0);
+
+        set_word(&mut ram, DESTINATION, 0x1000);
+        card.execute(CMD_READ_FILE, &mut ram);
+        assert_eq!(&ram[0x1000..0x1004], &[1, 2, 3, 4]);
+
+        card.execute(CMD_OPEN_DIR, &mut ram);
+        card.execute(CMD_READ_DIR, &mut ram);
+        assert_eq!(card.read(STATUS), 0);
+        assert_eq!(&ram[0x1000..0x1005], &[4, b'P' | 0x80, b'R' | 0x80, b'O' | 0x80, b'G' | 0x80]);
+        assert_eq!(word(&ram, FILE_SIZE), 4);
+        card.execute(CMD_READ_DIR, &mut ram);
+        assert_eq!(card.read(STATUS), 0x4c);
+
+        set_name(&mut ram, FILENAME, 0x0280, "NONE");
+        card.execute(CMD_READ_FILE, &mut ram);
+        assert_eq!(card.read(STATUS), 0x46);
+        card.execute(CMD_VERSION, &mut ram);
+        assert_eq!((card.read(STATUS), card.read(RESULT)), (0, VERSION));
+        assert_eq!((card.read(ID1), card.read(ID2)), (0xcf, 0xfa));
+    }
+
+    #[test]
+    fn firmware_api_sets_carry() {
+        let mut apple1 = Apple1::new();
+        apple1.load(Vec::new(), 0);
+        apple1.attach_cffa1(card());
+        // LDX #$22, JSR $900C, STA $10, LDA #0, ROL A, STA $11
+        for (i, &b) in [0xa2, CMD_READ_FILE, 0x20, 0x0c, 0x90, 0x85, 0x10, 0xa9, 0x00, 0x2a, 0x85, 0x11].iter().enumerate() {
+            apple1.poke(0x0300 + i as u16, b);
+        }
+        // Filename points at the counted string "X" at $0280
+        for &(address, value) in &[(0x02, 0x80), (0x03, 0x02), (0x0280, 1), (0x0281, b'X')] {
+            apple1.poke(address, value);
+        }
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        while cpu.get_pc() != 0x030c {
+            cpu.step();
+        }
+        assert_eq!(cpu.read_u8(0x10), 0x46);
+        assert_eq!(cpu.read_u8(0x11), 1);
+    }
+
+    #[test]
+    fn menu() {
+        let mut apple1 = Apple1::new();
+        apple1.load(Vec::new(), 0);
+        apple1.attach_cffa1(card());
+        apple1.poke(0x0300, 0xa9);
+        apple1.poke(0x0301, 0x42);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(MENU);
+        let mut keys = "S HELLO 300 301\rC\rL HELLO 1000\rL NOPE\rQ\r".bytes().map(|b| b | 0x80);
+        while cpu.get_pc() != 0xff1f {
+            assert!(cpu.get_total_cycles() < 5_000_000, "menu did not quit");
+            if cpu.key_ready() {
+                if let Some(key) = keys.next() {
+                    cpu.key_pressed(key);
+                }
+            }
+            cpu.step();
+        }
+        assert_eq!(cpu.read_u8(0x1001), 0x42);
+        let text = cpu.platform().display().unwrap().text();
+        assert!(text.contains("HELLO           BIN $0300     2"), "{}", text);
+        assert!(text.contains("1000-1001"), "{}", text);
+        assert!(text.contains("ERR $46 FILE NOT FOUND"), "{}", text);
+    }
+}
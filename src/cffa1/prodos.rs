@@ -0,0 +1,383 @@
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use super::{Entry, StorageError, Volume, prodos_name};
+
+const BLOCK_SIZE : usize = 512;
+const ROOT_BLOCK : u16 = 2;
+const ROOT_BLOCKS : u16 = 4;
+const ENTRY_LENGTH : usize = 0x27;
+const ENTRIES_PER_BLOCK : usize = 0x0d;
+
+const SEEDLING : u8 = 1;
+const SAPLING : u8 = 2;
+const TREE : u8 = 3;
+const VOLUME_HEADER : u8 = 0xf;
+
+type Block = [u8; BLOCK_SIZE];
+
+/// Anything a disk image can live in: a file, or a buffer in tests.
+pub trait Disk: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Disk for T {}
+
+/// Where a file entry sits in the volume directory.
+struct Slot {
+    block: u16,
+    offset: usize
+}
+
+fn word(block: &Block, offset: usize) -> u16 {
+    block[offset] as u16 | (block[offset + 1] as u16) << 8
+}
+
+fn set_word(block: &mut Block, offset: usize, value: u16) {
+    block[offset] = value as u8;
+    block[offset + 1] = (value >> 8) as u8;
+}
+
+/// A ProDOS order disk image (.po, .hdv). Files live in the volume
+/// directory; seedling, sapling and tree files can be read, and files up to
+/// 128K are written as seedlings or saplings.
+pub struct ProdosImage {
+    disk: Box<dyn Disk>,
+    total_blocks: u16,
+    bitmap: u16
+}
+
+impl ProdosImage {
+    pub fn open(filename: &str) -> Result<ProdosImage, StorageError> {
+        let file = OpenOptions::new().read(true).write(true).open(filename)?;
+        ProdosImage::new(Box::new(file))
+    }
+
+    pub fn new(disk: Box<dyn Disk>) -> Result<ProdosImage, StorageError> {
+        let mut image = ProdosImage { disk, total_blocks: ROOT_BLOCK + 1, bitmap: 0 };
+        let header = image.read_block(ROOT_BLOCK)?;
+        if header[4] >> 4 != VOLUME_HEADER || word(&header, 0) != 0 {
+            return Err(StorageError::NotProdos);
+        }
+        image.bitmap = word(&header, 0x27);
+        image.total_blocks = word(&header, 0x29);
+        Ok(image)
+    }
+
+    /// Writes an empty volume: boot blocks, a four block volume directory and
+    /// the free block bitmap.
+    pub fn format(disk: Box<dyn Disk>, name: &str, total_blocks: u16) -> Result<ProdosImage, StorageError> {
+        let name = prodos_name(name)?;
+        let bitmap = ROOT_BLOCK + ROOT_BLOCKS;
+        let bitmap_blocks = (total_blocks as usize).div_ceil(BLOCK_SIZE * 8) as u16;
+        let mut image = ProdosImage { disk, total_blocks, bitmap };
+        for block in 0..total_blocks {
+            image.write_block(block, &[0; BLOCK_SIZE])?;
+        }
+        for i in 0..ROOT_BLOCKS {
+            let mut block = [0; BLOCK_SIZE];
+            let number = ROOT_BLOCK + i;
+            set_word(&mut block, 0, if i == 0 { 0 } else { number - 1 });
+            set_word(&mut block, 2, if i + 1 == ROOT_BLOCKS { 0 } else { number + 1 });
+            if i == 0 {
+                block[4] = VOLUME_HEADER << 4 | name.len() as u8;
+                block[5..5 + name.len()].copy_from_slice(name.as_bytes());
+                block[0x22] = 0xc3;
+                block[0x23] = ENTRY_LENGTH as u8;
+                block[0x24] = ENTRIES_PER_BLOCK as u8;
+                set_word(&mut block, 0x27, bitmap);
+                set_word(&mut block, 0x29, total_blocks);
+            }
+            image.write_block(number, &block)?;
+        }
+        for block in bitmap + bitmap_blocks..total_blocks {
+            image.set_free(block, true)?;
+        }
+        Ok(image)
+    }
+
+    fn read_block(&mut self, number: u16) -> Result<Block, StorageError> {
+        if number >= self.total_blocks {
+            return Err(StorageError::NotProdos);
+        }
+        let mut block = [0; BLOCK_SIZE];
+        self.disk.seek(SeekFrom::Start(number as u64 * BLOCK_SIZE as u64))?;
+        self.disk.read_exact(&mut block)?;
+        Ok(block)
+    }
+
+    fn write_block(&mut self, number: u16, block: &Block) -> Result<(), StorageError> {
+        self.disk.seek(SeekFrom::Start(number as u64 * BLOCK_SIZE as u64))?;
+        self.disk.write_all(block)?;
+        Ok(())
+    }
+
+    fn set_free(&mut self, number: u16, free: bool) -> Result<(), StorageError> {
+        let bit = number as usize;
+        let block_number = self.bitmap + (bit / (BLOCK_SIZE * 8)) as u16;
+        let mut block = self.read_block(block_number)?;
+        let byte = (bit / 8) % BLOCK_SIZE;
+        let mask = 0x80 >> (bit % 8);
+        if free {
+            block[byte] |= mask;
+        } else {
+            block[byte] &= !mask;
+        }
+        self.write_block(block_number, &block)
+    }
+
+    /// Finds and claims free blocks, lowest first.
+    fn allocate(&mut self, count: usize) -> Result<Vec<u16>, StorageError> {
+        let mut blocks = Vec::with_capacity(count);
+        let bitmap_blocks = (self.total_blocks as usize).div_ceil(BLOCK_SIZE * 8) as u16;
+        'search: for i in 0..bitmap_blocks {
+            let block = self.read_block(self.bitmap + i)?;
+            for (byte, &bits) in block.iter().enumerate() {
+                for bit in 0..8 {
+                    let number = (i as usize * BLOCK_SIZE + byte) * 8 + bit;
+                    if blocks.len() == count || number >= self.total_blocks as usize {
+                        break 'search;
+                    }
+                    if bits & (0x80 >> bit) != 0 {
+                        blocks.push(number as u16);
+                    }
+                }
+            }
+        }
+        if blocks.len() < count {
+            return Err(StorageError::VolumeFull);
+        }
+        for &block in &blocks {
+            self.set_free(block, false)?;
+        }
+        Ok(blocks)
+    }
+
+    /// Every entry slot of the volume directory, used or not.
+    fn slots(&mut self) -> Result<Vec<(Slot, Block)>, StorageError> {
+        let mut slots = Vec::new();
+        let mut number = ROOT_BLOCK;
+        while number != 0 {
+            let block = self.read_block(number)?;
+            let first = if number == ROOT_BLOCK { 1 } else { 0 };
+            for i in first..ENTRIES_PER_BLOCK {
+                slots.push((Slot { block: number, offset: 4 + i * ENTRY_LENGTH }, block));
+            }
+            number = word(&block, 2);
+        }
+        Ok(slots)
+    }
+
+    fn entry_at(block: &Block, offset: usize) -> Option<Entry> {
+        let storage = block[offset] >> 4;
+        if storage == 0 {
+            return None;
+        }
+        let len = (block[offset] & 0x0f) as usize;
+        Some(Entry {
+            name: String::from_utf8_lossy(&block[offset + 1..offset + 1 + len]).to_string(),
+            file_type: block[offset + 0x10],
+            aux_type: word(block, offset + 0x1f),
+            size: block[offset + 0x15] as u32 | (block[offset + 0x16] as u32) << 8 | (block[offset + 0x17] as u32) << 16
+        })
+    }
+
+    fn find(&mut self, name: &str) -> Result<(Slot, Block, Entry), StorageError> {
+        let name = prodos_name(name)?;
+        for (slot, block) in self.slots()? {
+            if let Some(entry) = ProdosImage::entry_at(&block, slot.offset) {
+                if entry.name == name {
+                    return Ok((slot, block, entry));
+                }
+            }
+        }
+        Err(StorageError::FileNotFound)
+    }
+
+    fn adjust_file_count(&mut self, delta: i32) -> Result<(), StorageError> {
+        let mut header = self.read_block(ROOT_BLOCK)?;
+        let count = word(&header, 0x25) as i32 + delta;
+        set_word(&mut header, 0x25, count.max(0) as u16);
+        self.write_block(ROOT_BLOCK, &header)
+    }
+
+    /// Data blocks of a file in order; 0 stands for a sparse block.
+    fn data_blocks(&mut self, storage: u8, key: u16) -> Result<Vec<u16>, StorageError> {
+        let index_pointers = |index: &Block| -> Vec<u16> {
+            (0..256).map(|i| index[i] as u16 | (index[256 + i] as u16) << 8).collect()
+        };
+        match storage {
+            SEEDLING => Ok(vec![key]),
+            SAPLING => {
+                let index = self.read_block(key)?;
+                Ok(index_pointers(&index))
+            }
+            TREE => {
+                let master = self.read_block(key)?;
+                let mut blocks = Vec::new();
+                for pointer in index_pointers(&master) {
+                    if pointer == 0 {
+                        blocks.extend(std::iter::repeat_n(0, 256));
+                    } else {
+                        let index = self.read_block(pointer)?;
+                        blocks.extend(index_pointers(&index));
+                    }
+                }
+                Ok(blocks)
+            }
+            _ => Err(StorageError::NotProdos)
+        }
+    }
+}
+
+impl Volume for ProdosImage {
+    fn catalog(&mut self) -> Result<Vec<Entry>, StorageError> {
+        Ok(self.slots()?.iter()
+            .filter_map(|(slot, block)| ProdosImage::entry_at(block, slot.offset))
+            .collect())
+    }
+
+    fn read_file(&mut self, name: &str) -> Result<(Entry, Vec<u8>), StorageError> {
+        let (slot, block, entry) = self.find(name)?;
+        let storage = block[slot.offset] >> 4;
+        let key = word(&block, slot.offset + 0x11);
+        let mut data = Vec::with_capacity(entry.size as usize);
+        for number in self.data_blocks(storage, key)? {
+            if data.len() >= entry.size as usize {
+                break;
+            }
+            if number == 0 {
+                data.extend_from_slice(&[0; BLOCK_SIZE]);
+            } else {
+                data.extend_from_slice(&self.read_block(number)?);
+            }
+        }
+        data.truncate(entry.size as usize);
+        Ok((entry, data))
+    }
+
+    fn write_file(&mut self, name: &str, file_type: u8, aux_type: u16, data: &[u8]) -> Result<(), StorageError> {
+        let name = prodos_name(name)?;
+        match self.delete_file(&name) {
+            Ok(()) | Err(StorageError::FileNotFound) => {}
+            Err(err) => return Err(err)
+        }
+        let count = data.len().div_ceil(BLOCK_SIZE).max(1);
+        if count > 256 {
+            return Err(StorageError::VolumeFull);
+        }
+        let (slot, mut dir_block) = self.slots()?.into_iter()
+            .find(|(slot, block)| block[slot.offset] >> 4 == 0)
+            .ok_or(StorageError::DirectoryFull)?;
+
+        let storage = if count == 1 { SEEDLING } else { SAPLING };
+        let blocks = self.allocate(count + (storage == SAPLING) as usize)?;
+        let (key, data_blocks) = if storage == SEEDLING { (blocks[0], &blocks[..]) } else { (blocks[0], &blocks[1..]) };
+        let mut index = [0; BLOCK_SIZE];
+        for (i, &number) in data_blocks.iter().enumerate() {
+            let mut block = [0; BLOCK_SIZE];
+            let chunk = data.chunks(BLOCK_SIZE).nth(i).unwrap_or(&[]);
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.write_block(number, &block)?;
+            index[i] = number as u8;
+            index[256 + i] = (number >> 8) as u8;
+        }
+        if storage == SAPLING {
+            self.write_block(key, &index)?;
+        }
+
+        let o = slot.offset;
+        dir_block[o..o + ENTRY_LENGTH].copy_from_slice(&[0; ENTRY_LENGTH]);
+        dir_block[o] = storage << 4 | name.len() as u8;
+        dir_block[o + 1..o + 1 + name.len()].copy_from_slice(name.as_bytes());
+        dir_block[o + 0x10] = file_type;
+        set_word(&mut dir_block, o + 0x11, key);
+        set_word(&mut dir_block, o + 0x13, blocks.len() as u16);
+        dir_block[o + 0x15] = data.len() as u8;
+        dir_block[o + 0x16] = (data.len() >> 8) as u8;
+        dir_block[o + 0x17] = (data.len() >> 16) as u8;
+        dir_block[o + 0x1e] = 0xe3;
+        set_word(&mut dir_block, o + 0x1f, aux_type);
+        set_word(&mut dir_block, o + 0x25, ROOT_BLOCK);
+        self.write_block(slot.block, &dir_block)?;
+        self.adjust_file_count(1)
+    }
+
+    fn delete_file(&mut self, name: &str) -> Result<(), StorageError> {
+        let (slot, mut block, _) = self.find(name)?;
+        let storage = block[slot.offset] >> 4;
+        let key = word(&block, slot.offset + 0x11);
+        let mut freed = self.data_blocks(storage, key)?;
+        if storage == SAPLING || storage == TREE {
+            freed.push(key);
+        }
+        if storage == TREE {
+            let master = self.read_block(key)?;
+            freed.extend((0..256).map(|i| master[i] as u16 | (master[256 + i] as u16) << 8));
+        }
+        for number in freed.into_iter().filter(|&number| number != 0) {
+            self.set_free(number, true)?;
+        }
+        block[slot.offset] &= 0x0f;
+        self.write_block(slot.block, &block)?;
+        self.adjust_file_count(-1)
+    }
+
+    fn rename_file(&mut self, old: &str, new: &str) -> Result<(), StorageError> {
+        let new = prodos_name(new)?;
+        let (slot, mut block, entry) = self.find(old)?;
+        if entry.name != new && self.find(&new).is_ok() {
+            return Err(StorageError::DuplicateName);
+        }
+        let o = slot.offset;
+        block[o] = (block[o] & 0xf0) | new.len() as u8;
+        block[o + 1..o + 16].copy_from_slice(&[0; 15]);
+        block[o + 1..o + 1 + new.len()].copy_from_slice(new.as_bytes());
+        self.write_block(slot.block, &block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn volume() -> ProdosImage {
+        ProdosImage::format(Box::new(Cursor::new(Vec::new())), "MAGPIE", 280).unwrap()
+    }
+
+    #[test]
+    fn format() {
+        let mut image = volume();
+        assert!(image.catalog().unwrap().is_empty());
+        // boot blocks, directory and bitmap are in use
+        assert_eq!(image.allocate(1).unwrap(), vec![7]);
+    }
+
+    #[test]
+    fn files() {
+        let mut image = volume();
+        let small = vec![0xa9; 100];
+        let large: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        image.write_file("small", 0x06, 0x0300, &small).unwrap();
+        image.write_file("LARGE", 0xfa, 0x0800, &large).unwrap();
+        let catalog = image.catalog().unwrap();
+        assert_eq!(catalog.len(), 2);
+        assert_eq!(catalog[1], Entry { name: String::from("LARGE"), file_type: 0xfa, aux_type: 0x0800, size: 3000 });
+        assert_eq!(image.read_file("small").unwrap().1, small);
+        assert_eq!(image.read_file("large").unwrap().1, large);
+
+        image.rename_file("large", "BIG").unwrap();
+        assert!(matches!(image.rename_file("big", "small"), Err(StorageError::DuplicateName)));
+        image.delete_file("BIG").unwrap();
+        assert!(matches!(image.read_file("BIG"), Err(StorageError::FileNotFound)));
+        // the sapling's index and data blocks are free again
+        assert_eq!(image.allocate(8).unwrap(), (8..16).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn reopen() {
+        let mut image = volume();
+        image.write_file("HELLO", 0x04, 0, b"HELLO").unwrap();
+        let mut image = ProdosImage::new(image.disk).unwrap();
+        assert_eq!(image.read_file("hello").unwrap().1, b"HELLO".to_vec());
+        assert!(ProdosImage::new(Box::new(Cursor::new(vec![0; 4096]))).is_err());
+    }
+}
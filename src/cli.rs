@@ -0,0 +1,526 @@
+use symbols::parse_address;
+use profile;
+use profile::RamSize;
+use clock;
+use breadboard;
+use breadboard::AddressMap;
+use osi;
+use sim65;
+use board;
+
+pub const EXIT_OK : i32 = 0;
+pub const EXIT_STOPPED : i32 = 1;
+pub const EXIT_USAGE : i32 = 2;
+pub const EXIT_LOAD : i32 = 3;
+pub const EXIT_LIMIT : i32 = 4;
+pub const EXIT_FAIL : i32 = 5;
+pub const EXIT_WRITE : i32 = 6;
+
+pub const USAGE : &str = "usage: magpie [options] [file]
+       magpie run-test [options] [file]
+
+  file                      raw binary loaded at $4000, or any format the loader detects
+  --machine <name>          machine to emulate (apple1, apple1-basic, replica1, apple1-ehbasic, kim1, breadboard, osi, sim65);
+                            the breadboard has a 65C02, the others an NMOS 6502 whose undocumented opcodes abort the run
+  --list-machines           describe the machines and the ROMs they need
+  --ram <size>              RAM size: 4K, 8K, 32K, 48K or 64K (up to 32K on the OSI)
+  --rom <name>=<file>       ROM image for one of the machine's slots, e.g. basic=basic.rom (repeatable)
+  --board <file>            machine described in a TOML file: memory, ROM images, chips and interrupts
+  --map <chip>=<range>,...  breadboard address decoding, e.g. ram=0000-3fff,acia=5000-5fff,via=6000-7fff,rom=8000-ffff
+  --tty                     KIM-1 with the TTY jumper: the terminal is its teletype instead of the keypad
+  --load <file>[@addr]      load a file, optionally at/relocated to addr (repeatable)
+  --pc <addr>               start executing at addr instead of the reset vector
+  --reset-vector <addr>     write addr to $FFFC/$FFFD before reset
+  --max-cycles <n>          stop after n cycles
+  --max-instructions <n>    stop after n instructions
+  --exit-on <addr>          stop when the PC reaches addr (repeatable)
+  --headless                run without reading the keyboard, unthrottled unless --clock is given
+                            (always on for sim65, whose program's exit code is magpie's)
+  --clock <mhz>             target clock speed in MHz (default: the machine's own clock, 1.023 for the Apple 1)
+  --turbo                   run as fast as the host allows
+  --show-speed              print the effective clock speed every second
+  --slow-display            limit display output to the real ~60 characters per second
+  --aci-rom <file>          256-byte ROM image for the cassette interface at $C100; without it the card runs a
+                            stand-in firmware that only supports C100R and the XXXX.YYYYR/W commands
+  --tape-in <file>          tape to play into the cassette interface (.wav or compact)
+  --tape-out <file>         save what the cassette interface wrote at exit (.wav or compact)
+  --acia <line>[@addr]      6551 serial card (default $C300) on stdio, pty or tcp:<port>; the breadboard's is on stdio unless given,
+                            the OSI's 6850 at $F000 is unconnected unless given
+  --listen <port>           serve the Apple 1's keyboard and display to telnet clients on 127.0.0.1:<port>;
+                            the first client types, later ones watch
+  --cffa1 <dir|image>       CFFA1 storage card backed by a directory or ProDOS image (9000R for its menu); partial emulation
+                            with stand-in firmware: only the menu and the JSR $900C API work, not the genuine ROM
+  --paste <file>            type the contents of a file into the keyboard
+  --type <text>             type text into the keyboard, \\n for Return
+  --line-delay <ms>         pause after each typed line, in emulated milliseconds
+  --wait-for <prompt>       wait for the prompt on the display before typing each line (Apple 1 and KIM-1)
+  --trace                   print every executed instruction to stderr
+  --profile <prefix>        write <prefix>.txt and <prefix>.folded profiles at exit
+  --coverage <prefix>       write <prefix>.txt coverage, plus .lst/.info with --listing
+  --symbols <file>          symbol file for profile and coverage output
+  --listing <file>          assembler listing for coverage output
+  --expect <file>           run-test: the display output must match this file
+  --until-output <text>     run-test: stop once the display shows text, case and all (repeatable);
+                            --exit-on and --max-cycles also end the test
+  --until-halted            run-test: pass when the CPU stops instead of failing
+  --help                    show this message
+
+run-test runs an Apple 1 headless with the typed input and prints PASS or FAIL.
+
+keys: Ctrl-\\ presses RESET, Ctrl-] quits
+      KIM-1 keypad: 0-F, + or space, Return for GO, Ctrl-A AD, Ctrl-D DA,
+      Ctrl-P PC, Ctrl-S ST
+
+exit codes: 0 quit or exit address reached, 1 CPU stopped, 2 usage error,
+            3 load error, 4 cycle or instruction limit reached, 5 test failed,
+            6 profile or coverage report could not be written";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Machine {
+    Apple1,
+    Kim1,
+    Breadboard,
+    Osi,
+    Sim65,
+    Board
+}
+
+impl Machine {
+    /// Every Apple 1 profile name selects the Apple 1.
+    pub fn from_name(name: &str) -> Option<Machine> {
+        match name.to_ascii_lowercase().as_str() {
+            "kim1" | "kim-1" => Some(Machine::Kim1),
+            "breadboard" => Some(Machine::Breadboard),
+            "osi" | "c1p" | "superboard" => Some(Machine::Osi),
+            "sim65" | "sim" => Some(Machine::Sim65),
+            _ => profile::find(name).map(|_| Machine::Apple1)
+        }
+    }
+
+    /// The machine's own clock rate.
+    pub fn clock_hz(&self) -> u64 {
+        match *self {
+            Machine::Apple1 => clock::APPLE1_HZ,
+            Machine::Kim1 => clock::KIM1_HZ,
+            Machine::Breadboard => breadboard::BREADBOARD_HZ,
+            Machine::Osi => osi::OSI_HZ,
+            Machine::Sim65 => sim65::SIM65_HZ,
+            Machine::Board => board::Description::DEFAULT_CLOCK_HZ
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadSpec {
+    pub filename: String,
+    pub address: Option<u16>
+}
+
+impl LoadSpec {
+    /// Parses `file` or `file@addr`.
+    pub fn parse(text: &str) -> Result<LoadSpec, String> {
+        match text.rfind('@') {
+            Some(at) => {
+                let address = parse_address(&text[at + 1..])
+                    .ok_or_else(|| format!("invalid load address in {}", text))?;
+                Ok(LoadSpec { filename: text[..at].to_string(), address: Some(address) })
+            }
+            None => Ok(LoadSpec { filename: text.to_string(), address: None })
+        }
+    }
+}
+
+/// Where an ACIA's serial line goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SerialLineSpec {
+    Stdio,
+    Pty,
+    Tcp(u16)
+}
+
+impl SerialLineSpec {
+    /// Parses `stdio`, `pty` or `tcp:<port>`.
+    pub fn parse(text: &str) -> Result<SerialLineSpec, String> {
+        match text {
+            "stdio" => Ok(SerialLineSpec::Stdio),
+            "pty" => Ok(SerialLineSpec::Pty),
+            _ if text.starts_with("tcp:") => text[4..].parse::<u16>().map(SerialLineSpec::Tcp)
+                .map_err(|_| format!("invalid TCP port in {}", text)),
+            _ => Err(format!("expected stdio, pty or tcp:<port>, got {}", text))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AciaSpec {
+    pub line: SerialLineSpec,
+    pub address: u16
+}
+
+pub const DEFAULT_ACIA : u16 = 0xc300;
+
+impl AciaSpec {
+    /// Parses `stdio`, `pty` or `tcp:<port>`, each optionally followed by
+    /// `@addr`.
+    pub fn parse(text: &str) -> Result<AciaSpec, String> {
+        let (line, address) = match text.rfind('@') {
+            Some(at) => (&text[..at], parse_address(&text[at + 1..])
+                .ok_or_else(|| format!("invalid ACIA address in {}", text))?),
+            None => (text, DEFAULT_ACIA)
+        };
+        let line = SerialLineSpec::parse(line).map_err(|message| format!("--acia: {}", message))?;
+        Ok(AciaSpec { line, address })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub machine: Machine,
+    pub machine_name: String,
+    pub ram: Option<RamSize>,
+    pub roms: Vec<(String, String)>,
+    pub list_machines: bool,
+    pub tty: bool,
+    pub address_map: Option<AddressMap>,
+    pub board: Option<String>,
+    pub loads: Vec<LoadSpec>,
+    pub pc: Option<u16>,
+    pub reset_vector: Option<u16>,
+    pub max_cycles: Option<u64>,
+    pub max_instructions: Option<u64>,
+    pub exit_on: Vec<u16>,
+    pub headless: bool,
+    pub clock_hz: Option<u64>,
+    pub turbo: bool,
+    pub show_speed: bool,
+    pub slow_display: bool,
+    pub aci_rom: Option<String>,
+    pub tape_in: Option<String>,
+    pub tape_out: Option<String>,
+    pub cffa1: Option<String>,
+    pub acia: Option<AciaSpec>,
+    pub listen: Option<u16>,
+    pub paste: Vec<String>,
+    pub type_text: Vec<String>,
+    pub line_delay_ms: u64,
+    pub wait_for: Option<String>,
+    pub trace: bool,
+    pub profile: Option<String>,
+    pub coverage: Option<String>,
+    pub symbols: Option<String>,
+    pub listing: Option<String>,
+    pub run_test: bool,
+    pub expect: Option<String>,
+    pub until_output: Vec<String>,
+    pub until_halted: bool,
+    pub help: bool
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            machine: Machine::Apple1,
+            machine_name: String::from("apple1"),
+            ram: None,
+            roms: Vec::new(),
+            list_machines: false,
+            tty: false,
+            address_map: None,
+            board: None,
+            loads: Vec::new(),
+            pc: None,
+            reset_vector: None,
+            max_cycles: None,
+            max_instructions: None,
+            exit_on: Vec::new(),
+            headless: false,
+            clock_hz: None,
+            turbo: false,
+            show_speed: false,
+            slow_display: false,
+            aci_rom: None,
+            tape_in: None,
+            tape_out: None,
+            cffa1: None,
+            acia: None,
+            listen: None,
+            paste: Vec::new(),
+            type_text: Vec::new(),
+            line_delay_ms: 0,
+            wait_for: None,
+            trace: false,
+            profile: None,
+            coverage: None,
+            symbols: None,
+            listing: None,
+            run_test: false,
+            expect: None,
+            until_output: Vec::new(),
+            until_halted: false,
+            help: false
+        }
+    }
+}
+
+fn address(option: &str, value: &str) -> Result<u16, String> {
+    parse_address(value).ok_or_else(|| format!("{} expects an address, got {}", option, value))
+}
+
+fn megahertz(option: &str, value: &str) -> Result<u64, String> {
+    // anything that rounds down to 0 Hz would mean no throttle at all
+    match value.parse::<f64>().map(|mhz| mhz * 1_000_000.0) {
+        Ok(hz) if hz.is_finite() && hz >= 1.0 => Ok(hz as u64),
+        _ => Err(format!("{} expects a speed in MHz, got {}", option, value))
+    }
+}
+
+fn count(option: &str, value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| format!("{} expects a number, got {}", option, value))
+}
+
+impl Options {
+    /// Target clock rate, or `None` to run unthrottled. A machine shared
+    /// over telnet keeps its own speed even when headless.
+    pub fn target_hz(&self, default_hz: u64) -> Option<u64> {
+        if self.turbo || (self.headless && self.clock_hz.is_none() && self.listen.is_none()) {
+            None
+        } else {
+            Some(self.clock_hz.unwrap_or(default_hz))
+        }
+    }
+
+    /// Parses the arguments after the program name.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut i = 0;
+        if args.first().is_some_and(|arg| arg == "run-test") {
+            options.run_test = true;
+            options.headless = true;
+            i = 1;
+        }
+        while i < args.len() {
+            let arg = args[i].as_str();
+            let takes_value = match arg {
+                "--headless" => { options.headless = true; false }
+                "--trace" => { options.trace = true; false }
+                "--turbo" => { options.turbo = true; false }
+                "--show-speed" => { options.show_speed = true; false }
+                "--slow-display" => { options.slow_display = true; false }
+                "--list-machines" => { options.list_machines = true; false }
+                "--tty" => { options.tty = true; false }
+                "--until-halted" => { options.until_halted = true; false }
+                "--help" | "-h" => { options.help = true; false }
+                _ if arg.starts_with("--") => true,
+                _ => {
+                    // a bare file keeps the original behaviour of loading raw binaries at $4000
+                    options.loads.push(LoadSpec { filename: arg.to_string(), address: None });
+                    false
+                }
+            };
+            if !takes_value {
+                i += 1;
+                continue;
+            }
+
+            let value = match args.get(i + 1) {
+                Some(value) => value.as_str(),
+                None => return Err(format!("{} expects a value", arg))
+            };
+            match arg {
+                "--machine" => {
+                    options.machine = Machine::from_name(value)
+                        .ok_or_else(|| format!("unknown machine {}", value))?;
+                    options.machine_name = match options.machine {
+                        Machine::Apple1 => profile::find(value).map_or(String::new(), |profile| profile.name.to_string()),
+                        Machine::Kim1 => String::from("kim1"),
+                        Machine::Breadboard => String::from("breadboard"),
+                        Machine::Osi => String::from("osi"),
+                        Machine::Sim65 => String::from("sim65"),
+                        Machine::Board => unreachable!("described machines come from --board")
+                    };
+                }
+                "--ram" => {
+                    options.ram = Some(RamSize::from_name(value)
+                        .ok_or_else(|| format!("--ram expects 4K, 8K, 32K, 48K or 64K, got {}", value))?);
+                }
+                "--rom" => {
+                    let eq = value.find('=').ok_or_else(|| format!("--rom expects <name>=<file>, got {}", value))?;
+                    options.roms.push((value[..eq].to_string(), value[eq + 1..].to_string()));
+                }
+                "--map" => options.address_map = Some(AddressMap::parse(value)?),
+                "--board" => {
+                    options.machine = Machine::Board;
+                    options.machine_name = String::from("board");
+                    options.board = Some(value.to_string());
+                }
+                "--load" => options.loads.push(LoadSpec::parse(value)?),
+                "--pc" => options.pc = Some(address(arg, value)?),
+                "--reset-vector" => options.reset_vector = Some(address(arg, value)?),
+                "--max-cycles" => options.max_cycles = Some(count(arg, value)?),
+                "--max-instructions" => options.max_instructions = Some(count(arg, value)?),
+                "--clock" => options.clock_hz = Some(megahertz(arg, value)?),
+                "--aci-rom" => options.aci_rom = Some(value.to_string()),
+                "--tape-in" => options.tape_in = Some(value.to_string()),
+                "--tape-out" => options.tape_out = Some(value.to_string()),
+                "--cffa1" => options.cffa1 = Some(value.to_string()),
+                "--acia" => options.acia = Some(AciaSpec::parse(value)?),
+                "--listen" => options.listen = Some(value.parse::<u16>()
+                    .map_err(|_| format!("--listen expects a TCP port, got {}", value))?),
+                "--paste" => options.paste.push(value.to_string()),
+                "--type" => options.type_text.push(value.replace("\\n", "\n")),
+                "--line-delay" => options.line_delay_ms = count(arg, value)?,
+                "--wait-for" => options.wait_for = Some(value.to_string()),
+                "--exit-on" => options.exit_on.push(address(arg, value)?),
+                "--profile" => options.profile = Some(value.to_string()),
+                "--coverage" => options.coverage = Some(value.to_string()),
+                "--symbols" => options.symbols = Some(value.to_string()),
+                "--listing" => options.listing = Some(value.to_string()),
+                "--expect" => options.expect = Some(value.to_string()),
+                "--until-output" => options.until_output.push(value.replace("\\n", "\n")),
+                _ => return Err(format!("unknown option {}", arg))
+            }
+            i += 2;
+        }
+        if !options.run_test && (options.expect.is_some() || !options.until_output.is_empty() || options.until_halted) {
+            return Err(String::from("--expect, --until-output and --until-halted are only for run-test"));
+        }
+        // test programs read stdin themselves and only stop by exiting
+        if options.machine == Machine::Sim65 {
+            options.headless = true;
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = Options::parse(&args("--machine apple1 --load basic.hex --load prog.bin@$0300 --pc 0x0300 --max-cycles 1000 --exit-on FF1F --exit-on $0000 --headless --trace game.bin")).unwrap();
+        assert_eq!(options.machine, Machine::Apple1);
+        assert_eq!(options.loads, vec![
+            LoadSpec { filename: String::from("basic.hex"), address: None },
+            LoadSpec { filename: String::from("prog.bin"), address: Some(0x0300) },
+            LoadSpec { filename: String::from("game.bin"), address: None }
+        ]);
+        assert_eq!(options.pc, Some(0x0300));
+        assert_eq!(options.max_cycles, Some(1000));
+        assert_eq!(options.exit_on, vec![0xff1f, 0x0000]);
+        assert!(options.headless);
+        assert!(options.trace);
+        assert!(!options.help);
+    }
+
+    #[test]
+    fn typing() {
+        let options = Options::parse(&args("--type 10\\nRUN --paste prog.bas --line-delay 50 --wait-for >")).unwrap();
+        assert_eq!(options.type_text, vec![String::from("10\nRUN")]);
+        assert_eq!(options.paste, vec![String::from("prog.bas")]);
+        assert_eq!(options.line_delay_ms, 50);
+        assert_eq!(options.wait_for, Some(String::from(">")));
+    }
+
+    #[test]
+    fn tapes() {
+        let options = Options::parse(&args("--aci-rom aci.rom --tape-in in.wav --tape-out out.aci")).unwrap();
+        assert_eq!(options.aci_rom, Some(String::from("aci.rom")));
+        assert_eq!(options.tape_in, Some(String::from("in.wav")));
+        assert_eq!(options.tape_out, Some(String::from("out.aci")));
+        assert_eq!(Options::parse(&args("--cffa1 disk.po")).unwrap().cffa1, Some(String::from("disk.po")));
+    }
+
+    #[test]
+    fn serial() {
+        assert_eq!(Options::parse(&args("--acia pty")).unwrap().acia,
+            Some(AciaSpec { line: SerialLineSpec::Pty, address: DEFAULT_ACIA }));
+        assert_eq!(AciaSpec::parse("tcp:6502@$A000"),
+            Ok(AciaSpec { line: SerialLineSpec::Tcp(6502), address: 0xa000 }));
+        assert_eq!(AciaSpec::parse("stdio").unwrap().line, SerialLineSpec::Stdio);
+        assert!(AciaSpec::parse("tcp:lots").is_err());
+        assert!(AciaSpec::parse("modem").is_err());
+        assert_eq!(Options::parse(&args("--listen 6502")).unwrap().listen, Some(6502));
+        assert!(Options::parse(&args("--listen telnet")).is_err());
+    }
+
+    #[test]
+    fn machines() {
+        let options = Options::parse(&args("--machine Replica1 --ram 48k --rom basic=basic.rom --rom krusader=k.rom")).unwrap();
+        assert_eq!(options.machine, Machine::Apple1);
+        assert_eq!(options.machine_name, "replica1");
+        assert_eq!(options.ram, Some(RamSize::K48));
+        assert_eq!(options.roms, vec![
+            (String::from("basic"), String::from("basic.rom")),
+            (String::from("krusader"), String::from("k.rom"))
+        ]);
+        assert!(Options::parse(&args("--list-machines")).unwrap().list_machines);
+        assert!(Options::parse(&args("--ram 16K")).is_err());
+        assert!(Options::parse(&args("--rom basic.rom")).is_err());
+
+        let options = Options::parse(&args("--machine KIM-1 --tty --rom 002=kim.rom")).unwrap();
+        assert_eq!(options.machine, Machine::Kim1);
+        assert_eq!(options.machine_name, "kim1");
+        assert!(options.tty);
+        assert_eq!(options.machine.clock_hz(), clock::KIM1_HZ);
+
+        let options = Options::parse(&args("--machine breadboard --map ram=0-7fff --rom rom=a.out")).unwrap();
+        assert_eq!(options.machine, Machine::Breadboard);
+        assert_eq!(options.address_map.unwrap().ram, (0x0000, 0x7fff));
+        assert!(Options::parse(&args("--map ram=7fff")).is_err());
+
+        let options = Options::parse(&args("--machine C1P --ram 32k --rom monitor=syn600.rom")).unwrap();
+        assert_eq!(options.machine, Machine::Osi);
+        assert_eq!(options.machine_name, "osi");
+        assert_eq!(options.machine.clock_hz(), osi::OSI_HZ);
+
+        let options = Options::parse(&args("--board boards/eater.toml")).unwrap();
+        assert_eq!(options.machine, Machine::Board);
+        assert_eq!(options.board, Some(String::from("boards/eater.toml")));
+
+        let options = Options::parse(&args("--machine sim65 test.sim")).unwrap();
+        assert_eq!(options.machine, Machine::Sim65);
+        assert!(options.headless);
+    }
+
+    #[test]
+    fn run_test() {
+        let options = Options::parse(&args("run-test --type ff00\\n --until-output D8\\n --expect out.txt --exit-on ff1f prog.bin")).unwrap();
+        assert!(options.run_test);
+        assert!(options.headless);
+        assert_eq!(options.until_output, vec![String::from("D8\n")]);
+        assert_eq!(options.expect, Some(String::from("out.txt")));
+        assert_eq!(options.loads.len(), 1);
+        assert!(!Options::parse(&args("prog.bin")).unwrap().run_test);
+        assert!(Options::parse(&args("--expect out.txt prog.bin")).is_err());
+        assert!(Options::parse(&args("run-test --until-halted prog.bin")).unwrap().until_halted);
+        assert!(Options::parse(&args("--until-halted prog.bin")).is_err());
+    }
+
+    #[test]
+    fn clock() {
+        assert_eq!(Options::parse(&args("--clock 2")).unwrap().target_hz(1_000), Some(2_000_000));
+        assert_eq!(Options::parse(&args("")).unwrap().target_hz(1_000), Some(1_000));
+        assert_eq!(Options::parse(&args("--turbo")).unwrap().target_hz(1_000), None);
+        assert_eq!(Options::parse(&args("--headless")).unwrap().target_hz(1_000), None);
+        assert_eq!(Options::parse(&args("--headless --clock 1")).unwrap().target_hz(1_000), Some(1_000_000));
+        assert_eq!(Options::parse(&args("--headless --listen 6502")).unwrap().target_hz(1_000), Some(1_000));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Options::parse(&args("--machine c64")).is_err());
+        assert!(Options::parse(&args("--pc")).is_err());
+        assert!(Options::parse(&args("--pc zzzz")).is_err());
+        assert!(Options::parse(&args("--max-cycles lots")).is_err());
+        assert!(Options::parse(&args("--clock -1")).is_err());
+        assert!(Options::parse(&args("--clock 0.0000001")).is_err());
+        assert!(Options::parse(&args("--clock inf")).is_err());
+        assert!(Options::parse(&args("--frobnicate 1")).is_err());
+        assert!(Options::parse(&args("--load prog.bin@xyz")).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Apple 1 CPU clock: the 14.318 MHz crystal divided by 14.
+pub const APPLE1_HZ : u64 = 1_022_727;
+
+/// KIM-1 CPU clock from its 1 MHz crystal.
+pub const KIM1_HZ : u64 = 1_000_000;
+
+/// Falling further behind than this (host stalls, debugger pauses) resets the
+/// reference point instead of running flat out to catch up.
+const MAX_LAG : Duration = Duration::from_millis(250);
+
+/// Paces emulation to a target clock rate using the CPU's cycle count.
+/// A throttle without a target rate runs unthrottled ("turbo").
+pub struct Throttle {
+    hz: Option<u64>,
+    start: Instant,
+    start_cycles: u64,
+    measure_start: Instant,
+    measure_cycles: u64
+}
+
+impl Throttle {
+    pub fn new(hz: u64) -> Throttle {
+        Throttle::with_rate(Some(hz))
+    }
+
+    pub fn turbo() -> Throttle {
+        Throttle::with_rate(None)
+    }
+
+    pub fn with_rate(hz: Option<u64>) -> Throttle {
+        let now = Instant::now();
+        Throttle {
+            hz: hz.filter(|hz| *hz > 0),
+            start: now,
+            start_cycles: 0,
+            measure_start: now,
+            measure_cycles: 0
+        }
+    }
+
+    pub fn target_hz(&self) -> Option<u64> {
+        self.hz
+    }
+
+    /// Cycles to run between pacing calls: 10 ms worth at the target rate,
+    /// at least one so that very slow clocks still make progress.
+    pub fn slice(&self) -> u64 {
+        self.hz.map_or(20_000, |hz| (hz / 100).max(1))
+    }
+
+    /// Starts measuring from `total_cycles`, e.g. after reset or a pause.
+    pub fn sync(&mut self, total_cycles: u64) {
+        let now = Instant::now();
+        self.start = now;
+        self.start_cycles = total_cycles;
+        self.measure_start = now;
+        self.measure_cycles = total_cycles;
+    }
+
+    /// Wall-clock time `cycles` take at the target rate.
+    pub fn duration_of(&self, cycles: u64) -> Duration {
+        match self.hz {
+            Some(hz) => Duration::from_nanos((cycles as u128 * 1_000_000_000 / hz as u128) as u64),
+            None => Duration::from_secs(0)
+        }
+    }
+
+    /// How long to wait so that `total_cycles` line up with `elapsed` wall time,
+    /// or `None` when emulation is behind schedule.
+    pub fn delay(&self, total_cycles: u64, elapsed: Duration) -> Option<Duration> {
+        let due = self.duration_of(total_cycles.saturating_sub(self.start_cycles));
+        due.checked_sub(elapsed).filter(|d| *d > Duration::from_secs(0))
+    }
+
+    /// Sleeps until real time catches up with the emulated time.
+    pub fn pace(&mut self, total_cycles: u64) {
+        if self.hz.is_none() {
+            return;
+        }
+        let elapsed = self.start.elapsed();
+        match self.delay(total_cycles, elapsed) {
+            Some(wait) => thread::sleep(wait),
+            None => {
+                let due = self.duration_of(total_cycles.saturating_sub(self.start_cycles));
+                if elapsed > due + MAX_LAG {
+                    self.start = Instant::now();
+                    self.start_cycles = total_cycles;
+                }
+            }
+        }
+    }
+
+    /// Effective speed in MHz since the last call (or since `sync`).
+    pub fn effective_mhz(&mut self, total_cycles: u64) -> f64 {
+        let now = Instant::now();
+        let seconds = now.duration_since(self.measure_start).as_secs_f64();
+        let cycles = total_cycles.saturating_sub(self.measure_cycles);
+        self.measure_start = now;
+        self.measure_cycles = total_cycles;
+        if seconds > 0.0 {
+            cycles as f64 / seconds / 1_000_000.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let throttle = Throttle::new(1_000_000);
+        assert_eq!(throttle.duration_of(1_000), Duration::from_millis(1));
+        assert_eq!(throttle.delay(10_000, Duration::from_millis(4)), Some(Duration::from_millis(6)));
+        assert_eq!(throttle.delay(10_000, Duration::from_millis(12)), None);
+    }
+
+    #[test]
+    fn turbo() {
+        let mut throttle = Throttle::turbo();
+        assert_eq!(throttle.target_hz(), None);
+        assert_eq!(throttle.delay(1_000_000, Duration::from_millis(0)), None);
+        throttle.pace(1_000_000);
+        assert_eq!(Throttle::with_rate(Some(0)).target_hz(), None);
+    }
+
+    #[test]
+    fn slice() {
+        assert_eq!(Throttle::new(1_000_000).slice(), 10_000);
+        assert_eq!(Throttle::new(50).slice(), 1);
+        assert_eq!(Throttle::turbo().slice(), 20_000);
+    }
+}
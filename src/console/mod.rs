@@ -0,0 +1,217 @@
+mod telnet;
+
+pub use self::telnet::{TelnetServer, TelnetOutput};
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
+use terminal;
+use terminal::KeyEvent;
+
+const CR : u8 = 0x0d;
+
+/// Where the Apple 1's terminal section sends what it shows: one call per
+/// displayed character, upper case ASCII with CR (0x0d) ending a line.
+pub trait OutputSink {
+    fn output(&mut self, glyph: u8);
+
+    /// Pushes out what was held back, after characters that belong
+    /// together such as a redrawn screen.
+    fn flush(&mut self) {}
+}
+
+/// Where the Apple 1's keyboard gets its keys from. Keys are Apple 1
+/// keyboard codes as `terminal::translate` makes them.
+pub trait InputSource {
+    /// The next key, if one is waiting.
+    fn next_key(&mut self) -> Option<u8>;
+}
+
+/// Writes the output as text to a stream such as stdout or a file, with
+/// host line ends, flushing after each character so it appears as the
+/// program prints it.
+pub struct WriteSink<W: Write> {
+    writer: W
+}
+
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> WriteSink<W> {
+        WriteSink { writer }
+    }
+}
+
+impl WriteSink<io::Stdout> {
+    pub fn stdout() -> WriteSink<io::Stdout> {
+        WriteSink::new(io::stdout())
+    }
+}
+
+impl<W: Write> OutputSink for WriteSink<W> {
+    fn output(&mut self, glyph: u8) {
+        let byte = if glyph == CR { b'\n' } else { glyph };
+        // a closed pipe or full disk doesn't stop the machine
+        let _ = self.writer.write_all(&[byte]).and_then(|_| self.writer.flush());
+    }
+}
+
+/// Writes bytes to a stream unchanged, for machines that draw on the host
+/// terminal with carriage returns and escape sequences of their own. They
+/// appear when the machine flushes.
+pub struct RawSink<W: Write> {
+    writer: W
+}
+
+impl<W: Write> RawSink<W> {
+    pub fn new(writer: W) -> RawSink<W> {
+        RawSink { writer }
+    }
+}
+
+impl RawSink<io::Stdout> {
+    pub fn stdout() -> RawSink<io::Stdout> {
+        RawSink::new(io::stdout())
+    }
+}
+
+impl<W: Write> OutputSink for RawSink<W> {
+    fn output(&mut self, byte: u8) {
+        let _ = self.writer.write_all(&[byte]);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Collects the output as text in memory. Clones share the same buffer, so
+/// one can be handed to the machine and another kept to read it.
+#[derive(Clone, Default)]
+pub struct Buffer {
+    text: Arc<Mutex<String>>
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    /// Everything written so far, with `\n` line ends.
+    pub fn contents(&self) -> String {
+        self.text.lock().unwrap().clone()
+    }
+
+    /// Returns the contents and empties the buffer.
+    pub fn take(&self) -> String {
+        ::std::mem::take(&mut *self.text.lock().unwrap())
+    }
+}
+
+impl OutputSink for Buffer {
+    fn output(&mut self, glyph: u8) {
+        self.text.lock().unwrap().push(if glyph == CR { '\n' } else { glyph as char });
+    }
+}
+
+/// Sends each character, unchanged, to another thread.
+impl OutputSink for Sender<u8> {
+    fn output(&mut self, glyph: u8) {
+        let _ = self.send(glyph);
+    }
+}
+
+/// Sends the output to each of several sinks.
+impl OutputSink for Vec<Box<dyn OutputSink>> {
+    fn output(&mut self, glyph: u8) {
+        for sink in self.iter_mut() {
+            sink.output(glyph);
+        }
+    }
+
+    fn flush(&mut self) {
+        for sink in self.iter_mut() {
+            sink.flush();
+        }
+    }
+}
+
+/// Keys from another thread.
+impl InputSource for Receiver<u8> {
+    fn next_key(&mut self) -> Option<u8> {
+        self.try_recv().ok()
+    }
+}
+
+/// Text typed in order, translated as the terminal would.
+pub struct Keys {
+    keys: VecDeque<u8>
+}
+
+impl Keys {
+    pub fn from_text(text: &str) -> Keys {
+        let keys = terminal::translate(text.as_bytes()).into_iter()
+            .filter_map(|event| match event {
+                KeyEvent::Key(key) => Some(key),
+                _ => None
+            })
+            .collect();
+        Keys { keys }
+    }
+}
+
+impl InputSource for Keys {
+    fn next_key(&mut self) -> Option<u8> {
+        self.keys.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn sinks() {
+        let mut file = WriteSink::new(Vec::new());
+        for &b in b"HI\rTHERE" {
+            file.output(b);
+        }
+        assert_eq!(file.writer, b"HI\nTHERE");
+
+        let mut raw = RawSink::new(Vec::new());
+        for &b in b"\rHI\x1b[K" {
+            raw.output(b);
+        }
+        raw.flush();
+        assert_eq!(raw.writer, b"\rHI\x1b[K");
+
+        let buffer = Buffer::new();
+        let mut sink : Box<dyn OutputSink> = Box::new(buffer.clone());
+        sink.output(b'A');
+        sink.output(CR);
+        assert_eq!(buffer.contents(), "A\n");
+        assert_eq!(buffer.take(), "A\n");
+        assert_eq!(buffer.contents(), "");
+
+        let (tx, rx) = mpsc::channel();
+        let mut both : Vec<Box<dyn OutputSink>> = vec![Box::new(tx), Box::new(buffer.clone())];
+        both.output(CR);
+        assert_eq!(rx.try_recv(), Ok(CR));
+        assert_eq!(buffer.contents(), "\n");
+    }
+
+    #[test]
+    fn sources() {
+        let mut keys = Keys::from_text("a1\n");
+        assert_eq!(keys.next_key(), Some(0xc1));
+        assert_eq!(keys.next_key(), Some(0xb1));
+        assert_eq!(keys.next_key(), Some(0x8d));
+        assert_eq!(keys.next_key(), None);
+
+        let (tx, mut rx) = mpsc::channel();
+        assert_eq!(rx.next_key(), None);
+        tx.send(0xc1).unwrap();
+        assert_eq!(rx.next_key(), Some(0xc1));
+    }
+}
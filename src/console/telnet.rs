@@ -0,0 +1,386 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use terminal;
+use terminal::KeyEvent;
+use super::{InputSource, OutputSink, CR};
+
+const IAC : u8 = 255;
+const DONT : u8 = 254;
+const DO : u8 = 253;
+const WONT : u8 = 252;
+const WILL : u8 = 251;
+const SB : u8 = 250;
+const SE : u8 = 240;
+const ECHO : u8 = 1;
+const SUPPRESS_GO_AHEAD : u8 = 3;
+
+/// The machine echoes what it reads and there is no line editing, so the
+/// client is asked to send each key as it is typed and not to echo it.
+const NEGOTIATION : [u8; 9] = [IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DO, SUPPRESS_GO_AHEAD];
+
+/// Bytes of recent output replayed to each new client.
+const HISTORY : usize = 2048;
+
+/// Bytes of output a client may fall behind by before it is disconnected,
+/// so a stalled connection can't hold up the machine or the others.
+const BACKLOG : usize = 64 * 1024;
+
+const KEYBOARD : &[u8] = b"\r\n[magpie: you have the keyboard]\r\n";
+const WATCHING : &[u8] = b"\r\n[magpie: watching, another session has the keyboard]\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Data,
+    Cr,
+    Iac,
+    Option,
+    Sub,
+    SubIac
+}
+
+/// Strips telnet commands from what a client sends and turns its line
+/// ends, CR LF or CR NUL, into a plain CR.
+struct Filter {
+    state: State
+}
+
+impl Filter {
+    fn new() -> Filter {
+        Filter { state: State::Data }
+    }
+
+    fn filter(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &b in input {
+            self.state = match (self.state, b) {
+                (State::Data, IAC) | (State::Cr, IAC) => State::Iac,
+                (State::Cr, b'\n') | (State::Cr, 0) => State::Data,
+                (State::Data, b'\r') | (State::Cr, b'\r') => {
+                    data.push(b);
+                    State::Cr
+                }
+                (State::Data, _) | (State::Cr, _) => {
+                    data.push(b);
+                    State::Data
+                }
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL) | (State::Iac, WONT) | (State::Iac, DO) | (State::Iac, DONT) => State::Option,
+                (State::Iac, SB) => State::Sub,
+                (State::Iac, _) | (State::Option, _) => State::Data,
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => State::Sub,
+                (State::SubIac, SE) => State::Data,
+                (State::SubIac, _) => State::Sub
+            };
+        }
+        data
+    }
+}
+
+/// A connected client. Its output is queued for a writer thread, so
+/// nothing writes to the socket while holding the sessions' lock.
+struct Client {
+    id: u64,
+    stream: TcpStream,
+    queue: Sender<Vec<u8>>,
+    pending: Arc<AtomicUsize>
+}
+
+impl Client {
+    /// Queues bytes for the client, failing if it is too far behind.
+    fn queue(&self, bytes: &[u8]) -> bool {
+        if self.pending.load(Ordering::SeqCst) + bytes.len() > BACKLOG {
+            return false;
+        }
+        self.pending.fetch_add(bytes.len(), Ordering::SeqCst);
+        self.queue.send(bytes.to_vec()).is_ok()
+    }
+
+    /// Closes the connection, which ends both of its threads.
+    fn disconnect(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Writes what is queued for one client until the queue closes or the
+/// connection fails, everything waiting at once.
+fn write_queued(mut stream: TcpStream, queue: Receiver<Vec<u8>>, pending: Arc<AtomicUsize>) {
+    for mut bytes in queue.iter() {
+        bytes.extend(queue.try_iter().flatten());
+        if stream.write_all(&bytes).is_err() {
+            break;
+        }
+        pending.fetch_sub(bytes.len(), Ordering::SeqCst);
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// The connected clients, the first of which has the keyboard, and the
+/// output they have all seen.
+struct Sessions {
+    clients: Vec<Client>,
+    next_id: u64,
+    history: VecDeque<u8>
+}
+
+impl Sessions {
+    fn has_keyboard(&self, id: u64) -> bool {
+        self.clients.first().is_some_and(|client| client.id == id)
+    }
+
+    fn remove(&mut self, id: u64) {
+        let had_keyboard = self.has_keyboard(id);
+        self.clients.retain(|client| client.id != id);
+        if had_keyboard {
+            self.promote();
+        }
+    }
+
+    /// Hands the keyboard to the longest-connected observer.
+    fn promote(&mut self) {
+        if let Some(client) = self.clients.first() {
+            client.queue(KEYBOARD);
+        }
+    }
+
+    /// Queues output for every client, disconnecting those too far behind
+    /// to take it.
+    fn send(&mut self, bytes: &[u8]) {
+        let had_keyboard = self.clients.first().map(|client| client.id);
+        self.clients.retain(|client| {
+            let queued = client.queue(bytes);
+            if !queued {
+                client.disconnect();
+            }
+            queued
+        });
+        if self.clients.first().map(|client| client.id) != had_keyboard {
+            self.promote();
+        }
+        self.history.extend(bytes);
+        let excess = self.history.len().saturating_sub(HISTORY);
+        self.history.drain(..excess);
+    }
+}
+
+/// Reads one client's keys until it disconnects. Only the client with the
+/// keyboard is listened to; the others' typing is dropped.
+fn serve(id: u64, mut stream: TcpStream, sessions: Arc<Mutex<Sessions>>, keys: Sender<u8>) {
+    let mut filter = Filter::new();
+    let mut buf = [0u8; 256];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n
+        };
+        if !sessions.lock().unwrap().has_keyboard(id) {
+            continue;
+        }
+        // the reset and quit keys stay with whoever runs magpie
+        for event in terminal::translate(&filter.filter(&buf[..n])) {
+            if let KeyEvent::Key(key) = event {
+                if keys.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    sessions.lock().unwrap().remove(id);
+}
+
+/// A telnet server on the loopback interface for the Apple 1's keyboard
+/// and display. The first client to connect types on the keyboard; later
+/// ones watch, and the longest-waiting of them takes over the keyboard
+/// when it is given up. New clients see the last few lines of output, and
+/// clients that fall too far behind it are disconnected.
+///
+/// The server is the keyboard's `InputSource`; `output()` gives the sink
+/// for the display.
+pub struct TelnetServer {
+    port: u16,
+    sessions: Arc<Mutex<Sessions>>,
+    keys: Receiver<u8>
+}
+
+impl TelnetServer {
+    /// Listens on 127.0.0.1:`port`; port 0 picks a free one.
+    pub fn listen(port: u16) -> io::Result<TelnetServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let port = listener.local_addr()?.port();
+        let sessions = Arc::new(Mutex::new(Sessions { clients: Vec::new(), next_id: 0, history: VecDeque::new() }));
+        let (tx, rx) = mpsc::channel();
+        let accepted = sessions.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                let _ = stream.set_nodelay(true);
+                let (reader, writer) = match (stream.try_clone(), stream.try_clone()) {
+                    (Ok(reader), Ok(writer)) => (reader, writer),
+                    _ => continue
+                };
+                let (queue, queued) = mpsc::channel();
+                let pending = Arc::new(AtomicUsize::new(0));
+                let client_pending = pending.clone();
+                thread::spawn(move || write_queued(writer, queued, client_pending));
+
+                let mut sessions = accepted.lock().unwrap();
+                let id = sessions.next_id;
+                sessions.next_id += 1;
+                let banner = if sessions.clients.is_empty() { KEYBOARD } else { WATCHING };
+                let mut greeting = NEGOTIATION.to_vec();
+                greeting.extend_from_slice(banner);
+                greeting.extend(sessions.history.iter());
+                let client = Client { id, stream, queue, pending };
+                client.queue(&greeting);
+                sessions.clients.push(client);
+                let (sessions, keys) = (accepted.clone(), tx.clone());
+                thread::spawn(move || serve(id, reader, sessions, keys));
+            }
+        });
+        Ok(TelnetServer { port, sessions, keys: rx })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.sessions.lock().unwrap().clients.len()
+    }
+
+    /// A sink sending the display's output to every client.
+    pub fn output(&self) -> TelnetOutput {
+        TelnetOutput { sessions: self.sessions.clone() }
+    }
+}
+
+impl InputSource for TelnetServer {
+    fn next_key(&mut self) -> Option<u8> {
+        self.keys.try_recv().ok()
+    }
+}
+
+/// The display side of a `TelnetServer`.
+pub struct TelnetOutput {
+    sessions: Arc<Mutex<Sessions>>
+}
+
+impl OutputSink for TelnetOutput {
+    fn output(&mut self, glyph: u8) {
+        let mut sessions = self.sessions.lock().unwrap();
+        match glyph {
+            CR => sessions.send(b"\r\n"),
+            IAC => sessions.send(&[IAC, IAC]),
+            _ => sessions.send(&[glyph])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_for<F: FnMut() -> bool>(mut done: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if done() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    /// Reads from a client until what arrived ends with `text`.
+    fn expect(client: &mut TcpStream, text: &[u8]) -> Vec<u8> {
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0u8; 256];
+        while !received.ends_with(text) {
+            let n = client.read(&mut buf).unwrap();
+            assert!(n > 0, "connection closed after {:?}", received);
+            received.extend_from_slice(&buf[..n]);
+        }
+        received
+    }
+
+    #[test]
+    fn telnet_commands() {
+        let mut filter = Filter::new();
+        assert_eq!(filter.filter(&[IAC, DO, ECHO, b'a', IAC, IAC, b'\r', 0, b'b', b'\r']), vec![b'a', IAC, b'\r', b'b', b'\r']);
+        assert_eq!(filter.filter(&[b'\n', IAC, SB, 24, 0, b'x', IAC, SE, b'c']), vec![b'c']);
+    }
+
+    #[test]
+    fn keyboard_and_observers() {
+        let mut server = TelnetServer::listen(0).unwrap();
+        let mut output = server.output();
+        let mut typist = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        let received = expect(&mut typist, KEYBOARD);
+        assert!(received.starts_with(&NEGOTIATION));
+        assert!(wait_for(|| server.clients() == 1));
+
+        output.output(b'A');
+        output.output(CR);
+        let mut observer = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        expect(&mut observer, b"A\r\n");
+        assert!(wait_for(|| server.clients() == 2));
+
+        observer.write_all(b"x").unwrap();
+        typist.write_all(b"y\r\n").unwrap();
+        let mut keys = Vec::new();
+        assert!(wait_for(|| {
+            keys.extend(server.next_key());
+            keys.len() == 2
+        }));
+        assert_eq!(keys, vec![b'Y' | 0x80, 0x8d]);
+
+        output.output(b'B');
+        expect(&mut typist, b"B");
+        expect(&mut observer, b"B");
+
+        drop(typist);
+        expect(&mut observer, KEYBOARD);
+        observer.write_all(b"z").unwrap();
+        assert!(wait_for(|| server.next_key() == Some(b'Z' | 0x80)));
+        assert_eq!(server.clients(), 1);
+    }
+
+    #[test]
+    fn stalled_client() {
+        let server = TelnetServer::listen(0).unwrap();
+        let mut output = server.output();
+        // connected, but never reads
+        let _stalled = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        assert!(wait_for(|| server.clients() == 1));
+
+        // output never waits for it; it falls behind and is disconnected
+        let chunk = [b'.'; 16 * 1024];
+        let start = Instant::now();
+        while server.clients() == 1 {
+            assert!(start.elapsed() < Duration::from_secs(10), "stalled client was not dropped");
+            server.sessions.lock().unwrap().send(&chunk);
+        }
+
+        output.output(b'C');
+        let mut typist = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        let received = expect(&mut typist, b"C");
+        assert!(received.starts_with(&NEGOTIATION));
+        assert!(received.windows(KEYBOARD.len()).any(|window| window == KEYBOARD));
+    }
+}
@@ -0,0 +1,218 @@
+use std::io;
+use std::io::Write;
+use listing::Listing;
+use symbols::SymbolTable;
+
+const ADDRESS_SPACE : usize = 65536;
+
+pub const FETCHED : u8 = 0x01;
+pub const READ : u8 = 0x02;
+pub const WRITTEN : u8 = 0x04;
+
+/// Records which bytes were executed as opcodes, fetched as operands,
+/// and read or written as data.
+pub struct Coverage {
+    executed: Vec<u64>,
+    flags: Vec<u8>
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![0; ADDRESS_SPACE],
+            flags: vec![0; ADDRESS_SPACE]
+        }
+    }
+
+    pub fn mark_executed(&mut self, address: u16) {
+        self.executed[address as usize] += 1;
+    }
+
+    pub fn mark_fetched(&mut self, address: u16) {
+        self.flags[address as usize] |= FETCHED;
+    }
+
+    pub fn mark_read(&mut self, address: u16) {
+        self.flags[address as usize] |= READ;
+    }
+
+    pub fn mark_written(&mut self, address: u16) {
+        self.flags[address as usize] |= WRITTEN;
+    }
+
+    pub fn executed(&self, address: u16) -> u64 {
+        self.executed[address as usize]
+    }
+
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags[address as usize]
+    }
+
+    fn count(&self, mask: u8) -> usize {
+        self.flags.iter().filter(|f| *f & mask != 0).count()
+    }
+
+    /// Hit count for a listing line: opcode executions for code, 1 for data
+    /// that was read or written, otherwise 0.
+    fn line_hits(&self, address: u16, len: usize) -> u64 {
+        let executed = self.executed(address);
+        if executed > 0 {
+            return executed;
+        }
+        let touched = (0..len).any(|i| self.flags(address.wrapping_add(i as u16)) & (READ | WRITTEN) != 0);
+        if touched { 1 } else { 0 }
+    }
+
+    fn line_flags(&self, address: u16, len: usize) -> String {
+        let mut flags = 0;
+        for i in 0..len {
+            flags |= self.flags(address.wrapping_add(i as u16));
+        }
+        format!("{}{}",
+            if flags & READ != 0 { "R" } else { "-" },
+            if flags & WRITTEN != 0 { "W" } else { "-" })
+    }
+
+    /// Writes the totals, then each range of bytes fetched as opcodes or
+    /// operands.
+    pub fn write_summary<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let opcodes = self.executed.iter().filter(|c| **c > 0).count();
+        writeln!(out, "opcodes executed {}, bytes fetched {}, bytes read {}, bytes written {}",
+            opcodes, self.count(FETCHED), self.count(READ), self.count(WRITTEN))?;
+
+        let mut start : Option<usize> = None;
+        for addr in 0..=ADDRESS_SPACE {
+            let fetched = addr < ADDRESS_SPACE && self.flags[addr] & FETCHED != 0;
+            match (start, fetched) {
+                (None, true) => start = Some(addr),
+                (Some(first), false) => {
+                    writeln!(out, "fetched ${:04X}-${:04X}", first, addr - 1)?;
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the listing with an execution count and R/W data flags in front
+    /// of each line. Code lines that never ran are marked `#####`.
+    pub fn write_listing<W: Write>(&self, listing: &Listing, out: &mut W) -> io::Result<()> {
+        for line in &listing.lines {
+            match line.address {
+                Some(address) if !line.bytes.is_empty() => {
+                    let hits = self.line_hits(address, line.bytes.len());
+                    let count = if hits > 0 { hits.to_string() } else { String::from("#####") };
+                    writeln!(out, "{:>10} {} | {}", count, self.line_flags(address, line.bytes.len()), line.text)?;
+                }
+                _ => writeln!(out, "{:>13} | {}", "", line.text)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an lcov tracefile for the listing, with one function record
+    /// per symbol that lands on a listing line.
+    pub fn write_lcov<W: Write>(&self, listing: &Listing, symbols: Option<&SymbolTable>, out: &mut W) -> io::Result<()> {
+        writeln!(out, "TN:magpie")?;
+        writeln!(out, "SF:{}", listing.filename)?;
+
+        let mut functions : Vec<(usize, String, u64)> = Vec::new();
+        if let Some(table) = symbols {
+            for line in &listing.lines {
+                if let Some(address) = line.address {
+                    if let Some(name) = table.lookup(address) {
+                        functions.push((line.number, name.to_string(), self.executed(address)));
+                    }
+                }
+            }
+        }
+        for (number, name, _) in &functions {
+            writeln!(out, "FN:{},{}", number, name)?;
+        }
+        for (_, name, count) in &functions {
+            writeln!(out, "FNDA:{},{}", count, name)?;
+        }
+        writeln!(out, "FNF:{}", functions.len())?;
+        writeln!(out, "FNH:{}", functions.iter().filter(|f| f.2 > 0).count())?;
+
+        let mut found = 0;
+        let mut hit = 0;
+        for line in &listing.lines {
+            if let Some(address) = line.address {
+                if line.bytes.is_empty() {
+                    continue;
+                }
+                let hits = self.line_hits(address, line.bytes.len());
+                writeln!(out, "DA:{},{}", line.number, hits)?;
+                found += 1;
+                if hits > 0 {
+                    hit += 1;
+                }
+            }
+        }
+        writeln!(out, "LF:{}", found)?;
+        writeln!(out, "LH:{}", hit)?;
+        writeln!(out, "end_of_record")
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> (Coverage, Listing) {
+        let listing = Listing::parse("prog.asm", "* = C000\nC000   A9 01      LDA #$01\nC002   8D 00 02   STA $0200\nC005   4C 00 C0   JMP $C000\nC008   01         .BYTE $01\n");
+        let mut coverage = Coverage::new();
+        for addr in 0xc000..0xc005 {
+            coverage.mark_fetched(addr);
+        }
+        coverage.mark_executed(0xc000);
+        coverage.mark_executed(0xc000);
+        coverage.mark_executed(0xc002);
+        coverage.mark_written(0x0200);
+        coverage.mark_read(0xc008);
+        (coverage, listing)
+    }
+
+    #[test]
+    fn listing() {
+        let (coverage, listing) = sample();
+        let mut out : Vec<u8> = Vec::new();
+        coverage.write_listing(&listing, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines : Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1], "         2 -- | C000   A9 01      LDA #$01");
+        assert_eq!(lines[3], "     ##### -- | C005   4C 00 C0   JMP $C000");
+        assert_eq!(lines[4], "         1 R- | C008   01         .BYTE $01");
+    }
+
+    #[test]
+    fn lcov() {
+        let (coverage, listing) = sample();
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0xc000, "START");
+        symbols.insert(0xc005, "LOOP");
+        let mut out : Vec<u8> = Vec::new();
+        coverage.write_lcov(&listing, Some(&symbols), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("FN:2,START\nFN:4,LOOP\nFNDA:2,START\nFNDA:0,LOOP\nFNF:2\nFNH:1\n"));
+        assert!(text.contains("DA:2,2\nDA:3,1\nDA:4,0\nDA:5,1\nLF:4\nLH:3\nend_of_record\n"));
+    }
+
+    #[test]
+    fn summary() {
+        let (coverage, _) = sample();
+        let mut out : Vec<u8> = Vec::new();
+        coverage.write_summary(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("opcodes executed 2, bytes fetched 5, bytes read 1, bytes written 1\n"));
+        assert!(text.contains("fetched $C000-$C004\n"));
+    }
+}
@@ -1,5 +1,19 @@
 use std::collections::VecDeque;
-use platform::Platform;
+use platform::{Platform, Registers};
+use profiler::Profiler;
+use coverage::Coverage;
+
+/// Which member of the 6502 family the CPU is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    /// The original NMOS 6502. Its undocumented opcodes stop the run.
+    Nmos,
+    /// The WDC 65C02: BRA, PHX/PHY/PLX/PLY, STZ, TSB/TRB, the bit
+    /// instructions, INC/DEC A, more BIT modes, (zp) and JMP (abs,X), with
+    /// valid N and Z in decimal mode, D cleared on interrupts, JMP ($xxFF)
+    /// fixed and the other opcodes as NOPs.
+    Cmos
+}
 
 pub struct DebugFrame {
     pc: u16,
@@ -27,14 +41,22 @@ pub struct MOS6502 {
     f_carry: bool,
 
     cycle_count: i32,
+    total_cycles: u64,
+    instruction_count: u64,
     is_stopped: bool,
+    is_waiting: bool,
+    brk_halts: bool,
+    variant: Variant,
+    trace: bool,
 
     debug_vector : VecDeque<DebugFrame>,
-    platform : Box<Platform>
+    profiler : Option<Profiler>,
+    coverage : Option<Coverage>,
+    platform : Box<dyn Platform>
 }
 
 impl MOS6502 {
-    pub fn new(platform : Box<Platform>) -> MOS6502 {
+    pub fn new(platform : Box<dyn Platform>) -> MOS6502 {
         MOS6502 {
             reg_a: 0,
             reg_x: 0,
@@ -50,9 +72,17 @@ impl MOS6502 {
             f_zero: false,
             f_carry: false,
             cycle_count: 0,
+            total_cycles: 0,
+            instruction_count: 0,
             is_stopped: false,
-            platform: platform,
-            debug_vector :  VecDeque::new()
+            is_waiting: false,
+            brk_halts: true,
+            variant: Variant::Nmos,
+            trace: false,
+            platform,
+            debug_vector :  VecDeque::new(),
+            profiler : None,
+            coverage : None
         }
     }
 
@@ -70,6 +100,7 @@ impl MOS6502 {
             self.f_zero = false;
             self.f_carry = false;
             self.is_stopped = false;
+            self.is_waiting = false;
 
             let lo = self.platform.read(0xfffc) as u16;
             let hi = self.platform.read(0xfffd) as u16;
@@ -80,12 +111,18 @@ impl MOS6502 {
 
     fn read_pc(&mut self) -> u8 {
         let addr = self.reg_pc;
-        let ret = self.read_u8(addr);
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.mark_fetched(addr);
+        }
+        let ret = self.platform.read(addr);
         self.reg_pc += 1;
         ret
     }
 
     pub fn read_u8(&mut self, address: u16) -> u8 {
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.mark_read(address);
+        }
         self.platform.read(address)
     }
 
@@ -97,7 +134,18 @@ impl MOS6502 {
         self.platform.key_pressed(key);
     }
 
+    pub fn platform(&self) -> &dyn Platform {
+        &*self.platform
+    }
+
+    pub fn platform_mut(&mut self) -> &mut dyn Platform {
+        &mut *self.platform
+    }
+
     pub fn write_u8(&mut self, address: u16, value: u8) {
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.mark_written(address);
+        }
         self.platform.write(address, value);
     }
 
@@ -117,37 +165,43 @@ impl MOS6502 {
     fn get_absolute_addr(&mut self, offset: u8) -> u16 {
         let lo = self.read_pc() as u16;
         let mut hi = self.read_pc() as u16;
-        hi = hi << 8;
+        hi <<= 8;
         lo + hi + (offset as u16)
     }
 
     fn get_zeropage_addr(&mut self, offset: u8) -> u16 {
-        let addr = self.read_pc() as u16;
-        addr + (offset as u16)
+        self.read_pc().wrapping_add(offset) as u16
     }
 
     fn get_indirect_addr(&mut self, index: u16) -> u16 {
         let lo = self.read_u8(index) as u16;
-        let hi = (self.read_u8(index + 1) as u16) << 8;
+        let hi = (self.read_u8(index.wrapping_add(1)) as u16) << 8;
+        lo + hi
+    }
+
+    // A zero page pointer at $FF takes its high byte from $00, not $0100.
+    fn get_zeropage_pointer(&mut self, index: u8) -> u16 {
+        let lo = self.read_u8(index as u16) as u16;
+        let hi = (self.read_u8(index.wrapping_add(1) as u16) as u16) << 8;
         lo + hi
     }
 
     fn get_indirect_x_addr(&mut self) -> u16 {
         let offset = self.reg_x;
-        let index = (offset as u16) + (self.read_pc() as u16);
-        self.get_indirect_addr(index)
+        let index = self.read_pc().wrapping_add(offset);
+        self.get_zeropage_pointer(index)
     }
 
     fn get_indirect_y_addr(&mut self) -> u16 {
         let offset = self.reg_y;
-        let index = self.read_pc() as u16;
-        self.get_indirect_addr(index) + (offset as u16)
+        let index = self.read_pc();
+        self.get_zeropage_pointer(index).wrapping_add(offset as u16)
     }
 
     fn stack_push(&mut self, value: u8) {
-        let addr = 0x100 + (self.reg_sp as u16) as u16;
+        let addr = 0x100 + (self.reg_sp as u16);
         self.write_u8(addr, value);
-        self.reg_sp = self.reg_sp - 1;
+        self.reg_sp -= 1;
         if self.reg_sp <= 1 {
             println!("push: stack overflow {:?}", self.reg_pc);
             self.is_stopped = true;
@@ -155,14 +209,13 @@ impl MOS6502 {
     }
 
     fn stack_pull(&mut self) -> u8 {
-        if self.reg_sp >= 0xff {
+        if self.reg_sp == 0xff {
             println!("pull: stack overflow {:?}", self.reg_pc);
             self.is_stopped = true;
         }
-        self.reg_sp = self.reg_sp + 1;
-        let addr = 0x100 + (self.reg_sp as u16) as u16;
-        let result = self.read_u8(addr);
-        result
+        self.reg_sp += 1;
+        let addr = 0x100 + (self.reg_sp as u16);
+        self.read_u8(addr)
     }
 
     fn set_status_registers(&mut self, value: u8) {
@@ -214,10 +267,43 @@ impl MOS6502 {
         let result = sum as u8;
         self.f_carry = sum & 0xff00 > 0;
         self.f_overflow = (self.reg_a ^ value) & 0x80 == 0 && (self.reg_a ^ result) & 0x80 == 0x80;
+        if self.f_decimal {
+            self.adc_decimal(value, result);
+            return;
+        }
         self.reg_a = result;
         self.update_flags_zn(result);
     }
 
+    /// BCD addition. The NMOS 6502 takes Z from the binary sum and N and V
+    /// from the sum before the high digit is adjusted; the 65C02 takes N
+    /// and Z from the result, and spends a cycle more.
+    fn adc_decimal(&mut self, value: u8, binary: u8) {
+        let mut lo = (self.reg_a & 0x0f) as u16 + (value & 0x0f) as u16 + self.get_carry_amount() as u16;
+        if lo >= 0x0a {
+            lo = ((lo + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = (self.reg_a & 0xf0) as u16 + (value & 0xf0) as u16 + lo;
+        self.f_overflow = (self.reg_a ^ value) & 0x80 == 0 && (self.reg_a as u16 ^ sum) & 0x80 == 0x80;
+        let unadjusted = sum as u8;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.f_carry = sum >= 0x100;
+        self.reg_a = sum as u8;
+        match self.variant {
+            Variant::Nmos => {
+                self.f_zero = binary == 0;
+                self.f_negative = unadjusted & 0x80 == 0x80;
+            }
+            Variant::Cmos => {
+                let result = self.reg_a;
+                self.update_flags_zn(result);
+                self.cycles(1);
+            }
+        }
+    }
+
     fn sbc(&mut self, value: u8) {
         let borrow = if self.f_carry { 1 } else { 0 };
         let complement = 255 - value;
@@ -225,16 +311,61 @@ impl MOS6502 {
         let result = sum as u8;
         self.f_carry = sum & 0xff00 > 0;
         self.f_overflow = (self.reg_a ^ complement) & 0x80 == 0 && (self.reg_a ^ result) & 0x80 == 0x80;
+        if self.f_decimal {
+            self.sbc_decimal(value, borrow);
+            // C and V are the binary ones on both chips
+            if self.variant == Variant::Nmos {
+                self.update_flags_zn(result);
+            }
+            return;
+        }
         self.reg_a = result;
         self.update_flags_zn(result);
     }
 
+    /// BCD subtraction. The NMOS 6502 keeps the binary N and Z; the 65C02
+    /// takes them from the result, and spends a cycle more.
+    fn sbc_decimal(&mut self, value: u8, borrow: i16) {
+        let a = self.reg_a as i16;
+        let value = value as i16;
+        let result = match self.variant {
+            Variant::Nmos => {
+                let mut lo = (a & 0x0f) - (value & 0x0f) + borrow - 1;
+                if lo < 0 {
+                    lo = ((lo - 0x06) & 0x0f) - 0x10;
+                }
+                let mut result = (a & 0xf0) - (value & 0xf0) + lo;
+                if result < 0 {
+                    result -= 0x60;
+                }
+                result
+            }
+            Variant::Cmos => {
+                let lo = (a & 0x0f) - (value & 0x0f) + borrow - 1;
+                let mut result = a - value + borrow - 1;
+                if result < 0 {
+                    result -= 0x60;
+                }
+                if lo < 0 {
+                    result -= 0x06;
+                }
+                result
+            }
+        };
+        self.reg_a = result as u8;
+        if self.variant == Variant::Cmos {
+            let result = self.reg_a;
+            self.update_flags_zn(result);
+            self.cycles(1);
+        }
+    }
+
     fn ror(&mut self, value: u8) -> u8 {
         let carry = if self.f_carry { 0x80 } else { 0 };
         self.f_carry = (value & 0x01) == 0x01;
         let result = carry | (value >> 1);
-        self.update_flags_zn(result as u8);
-        result as u8
+        self.update_flags_zn(result);
+        result
     }
 
     fn rol(&mut self, value: u8) -> u8 {
@@ -259,14 +390,101 @@ impl MOS6502 {
         result
     }
 
+    pub fn get_pc(&self) -> u16 {
+        self.reg_pc
+    }
+
+    pub fn set_pc(&mut self, address: u16) {
+        self.reg_pc = address;
+    }
+
     pub fn get_cycle_count(&mut self) -> i32 {
         self.cycle_count
     }
 
+    pub fn get_total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn get_instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Whether BRK stops the CPU, which test programs use to end a run,
+    /// or takes the interrupt through $FFFE as on the real chip.
+    pub fn set_brk_halts(&mut self, halts: bool) {
+        self.brk_halts = halts;
+    }
+
+    /// Picks the NMOS 6502 or the 65C02 instruction set.
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
     pub fn is_running(&mut self) -> bool {
         !self.is_stopped
     }
 
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.reg_pc));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    /// Pushes PC and status and continues at the address in `vector`.
+    fn interrupt(&mut self, vector: u16) {
+        let reg_pc = self.reg_pc;
+        self.stack_push((reg_pc >> 8) as u8);
+        self.stack_push(reg_pc as u8);
+        let status = self.get_status_registers() & !0x10;
+        self.stack_push(status);
+        self.f_interrupt = true;
+        if self.variant == Variant::Cmos {
+            self.f_decimal = false;
+        }
+        let lo = self.platform.read(vector) as u16;
+        let hi = self.platform.read(vector.wrapping_add(1)) as u16;
+        self.reg_pc = lo | (hi << 8);
+        self.cycle_count += 7;
+        self.total_cycles += 7;
+        self.platform.tick(7);
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.interrupt(7, self.reg_pc);
+        }
+    }
+
+    /// Takes a non-maskable interrupt through $FFFA.
+    pub fn nmi(&mut self) {
+        self.interrupt(0xfffa);
+    }
+
+    /// Takes an interrupt request through $FFFE.
+    pub fn irq(&mut self) {
+        self.interrupt(0xfffe);
+    }
+
     pub fn run(&mut self, target_cycles: i32) -> i32 {
         self.cycle_count = 0;
         while self.cycle_count < target_cycles && !self.is_stopped {
@@ -276,8 +494,280 @@ impl MOS6502 {
         self.cycle_count
     }
 
+    /// Runs the subroutine at `address` as if called with JSR from the
+    /// current PC, and returns true once its RTS brings PC and SP back.
+    /// Gives up after `max_cycles` or when the CPU stops.
+    pub fn call(&mut self, address: u16, max_cycles: u64) -> bool {
+        let (reg_pc, reg_sp) = (self.reg_pc, self.reg_sp);
+        let ret = reg_pc.wrapping_sub(1);
+        self.stack_push((ret >> 8) as u8);
+        self.stack_push(ret as u8);
+        self.reg_pc = address;
+        let limit = self.total_cycles + max_cycles;
+        while self.total_cycles < limit && !self.is_stopped {
+            self.step();
+            if self.reg_pc == reg_pc && self.reg_sp == reg_sp {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Lets the platform run the routine at PC in place of the 6502 code,
+    /// counted as the six cycles of the RTS it ends with.
+    fn trap(&mut self) -> bool {
+        let mut registers = Registers {
+            a: self.reg_a,
+            x: self.reg_x,
+            y: self.reg_y,
+            sp: self.reg_sp,
+            pc: self.reg_pc
+        };
+        if !self.platform.trap(&mut registers) {
+            return false;
+        }
+        self.reg_a = registers.a;
+        self.reg_x = registers.x;
+        self.reg_y = registers.y;
+        self.reg_sp = registers.sp;
+        self.reg_pc = registers.pc;
+        self.cycles(6);
+        self.instruction_count += 1;
+        self.total_cycles += 6;
+        self.platform.tick(6);
+        true
+    }
+
+    /// After WAI the CPU idles a cycle at a time until IRQ or NMI is
+    /// pulled. An IRQ wakes it even with interrupts disabled, and execution
+    /// then carries on after the WAI.
+    fn wait(&mut self) {
+        self.cycles(1);
+        self.total_cycles += 1;
+        self.platform.tick(1);
+        if self.platform.take_nmi() {
+            self.is_waiting = false;
+            self.nmi();
+        } else if self.platform.irq() {
+            self.is_waiting = false;
+            if !self.f_interrupt {
+                self.irq();
+            }
+        }
+    }
+
+    /// Runs one of the opcodes the 65C02 adds to the NMOS set, returning
+    /// its name.
+    fn step_65c02(&mut self, opcode: u8) -> String {
+        let name = match opcode {
+            0x80 => {
+                //BRA,REL,2,3
+                self.branch();
+                self.cycles(3);
+                "BRA"
+            }
+            0xda => {
+                //PHX,IMP,1,3
+                let value = self.reg_x;
+                self.stack_push(value);
+                self.cycles(3);
+                "PHX"
+            }
+            0x5a => {
+                //PHY,IMP,1,3
+                let value = self.reg_y;
+                self.stack_push(value);
+                self.cycles(3);
+                "PHY"
+            }
+            0xfa => {
+                //PLX,IMP,1,4,cZidbvN
+                let value = self.stack_pull();
+                self.reg_x = value;
+                self.update_flags_zn(value);
+                self.cycles(4);
+                "PLX"
+            }
+            0x7a => {
+                //PLY,IMP,1,4,cZidbvN
+                let value = self.stack_pull();
+                self.reg_y = value;
+                self.update_flags_zn(value);
+                self.cycles(4);
+                "PLY"
+            }
+            0x64 | 0x74 | 0x9c | 0x9e => {
+                //STZ,ZP,2,3 ZPX,2,4 ABS,3,4 ABSX,3,5
+                let offset = self.reg_x;
+                let (addr, cycles) = match opcode {
+                    0x64 => (self.get_zeropage_addr(0), 3),
+                    0x74 => (self.get_zeropage_addr(offset), 4),
+                    0x9c => (self.get_absolute_addr(0), 4),
+                    _ => (self.get_absolute_addr(offset), 5)
+                };
+                self.write_u8(addr, 0);
+                self.cycles(cycles);
+                "STZ"
+            }
+            0x04 | 0x0c | 0x14 | 0x1c => {
+                //TSB,ZP,2,5 ABS,3,6 TRB,ZP,2,5 ABS,3,6,cZidbvn
+                let (addr, cycles) = if opcode & 0x08 == 0 {
+                    (self.get_zeropage_addr(0), 5)
+                } else {
+                    (self.get_absolute_addr(0), 6)
+                };
+                let value = self.read_u8(addr);
+                let a = self.reg_a;
+                self.f_zero = a & value == 0;
+                let set = opcode & 0x10 == 0;
+                self.write_u8(addr, if set { value | a } else { value & !a });
+                self.cycles(cycles);
+                if set { "TSB" } else { "TRB" }
+            }
+            0x1a => {
+                //INC,ACC,1,2,cZidbvN
+                let value = self.reg_a.wrapping_add(1);
+                self.reg_a = value;
+                self.update_flags_zn(value);
+                self.cycles(2);
+                "INC"
+            }
+            0x3a => {
+                //DEC,ACC,1,2,cZidbvN
+                let value = self.reg_a.wrapping_sub(1);
+                self.reg_a = value;
+                self.update_flags_zn(value);
+                self.cycles(2);
+                "DEC"
+            }
+            0x89 => {
+                //BIT,IMM,2,2,cZidbvn
+                let value = self.read_pc();
+                self.f_zero = self.reg_a & value == 0;
+                self.cycles(2);
+                "BIT"
+            }
+            0x34 | 0x3c => {
+                //BIT,ZPX,2,4 ABSX,3,4,cZidbVN
+                let offset = self.reg_x;
+                let addr = if opcode == 0x34 {
+                    self.get_zeropage_addr(offset)
+                } else {
+                    self.get_absolute_addr(offset)
+                };
+                let value = self.read_u8(addr);
+                self.f_zero = self.reg_a & value == 0;
+                self.f_negative = (value & 0x80) == 0x80;
+                self.f_overflow = (value & 0x40) == 0x40;
+                self.cycles(4);
+                "BIT"
+            }
+            0x12 | 0x32 | 0x52 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                //ORA AND EOR ADC STA LDA CMP SBC,ZPI,2,5
+                let index = self.read_pc();
+                let addr = self.get_zeropage_pointer(index);
+                self.cycles(5);
+                if opcode == 0x92 {
+                    let value = self.reg_a;
+                    self.write_u8(addr, value);
+                    return String::from("STA");
+                }
+                let value = self.read_u8(addr);
+                match opcode {
+                    0x72 => self.adc(value),
+                    0xf2 => self.sbc(value),
+                    0xd2 => {
+                        let result = (self.reg_a as i32) - (value as i32);
+                        self.update_flags_zcn(result);
+                    }
+                    _ => {
+                        let result = match opcode {
+                            0x12 => self.reg_a | value,
+                            0x32 => self.reg_a & value,
+                            0x52 => self.reg_a ^ value,
+                            _ => value
+                        };
+                        self.reg_a = result;
+                        self.update_flags_zn(result);
+                    }
+                }
+                ["ORA", "AND", "EOR", "ADC", "STA", "LDA", "CMP", "SBC"][(opcode >> 5) as usize]
+            }
+            0x7c => {
+                //JMP,AINX,3,6
+                let offset = self.reg_x;
+                let addr = self.get_absolute_addr(offset);
+                self.reg_pc = self.get_indirect_addr(addr);
+                self.cycles(6);
+                "JMP"
+            }
+            op if op & 0x0f == 0x07 => {
+                //RMB0-7 SMB0-7,ZP,2,5
+                let bit = 1 << ((op >> 4) & 0x07);
+                let addr = self.get_zeropage_addr(0);
+                let value = self.read_u8(addr);
+                let set = op & 0x80 != 0;
+                self.write_u8(addr, if set { value | bit } else { value & !bit });
+                self.cycles(5);
+                if set { "SMB" } else { "RMB" }
+            }
+            op if op & 0x0f == 0x0f => {
+                //BBR0-7 BBS0-7,ZPR,3,5/6
+                let bit = 1 << ((op >> 4) & 0x07);
+                let addr = self.get_zeropage_addr(0);
+                let value = self.read_u8(addr);
+                let set = op & 0x80 != 0;
+                if (value & bit != 0) == set {
+                    self.branch();
+                    self.cycles(6);
+                } else {
+                    self.read_pc();
+                    self.cycles(5);
+                }
+                if set { "BBS" } else { "BBR" }
+            }
+            0xcb => {
+                //WAI,IMP,1,3
+                self.is_waiting = true;
+                self.cycles(3);
+                "WAI"
+            }
+            0xdb => {
+                //STP,IMP,1,3
+                self.is_stopped = true;
+                self.cycles(3);
+                "STP"
+            }
+            // the rest are NOPs of various lengths and speeds
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 | 0x44 | 0x54 | 0xd4 | 0xf4 => {
+                self.read_pc();
+                self.cycles(match opcode { 0x44 => 3, 0x54 | 0xd4 | 0xf4 => 4, _ => 2 });
+                "NOP"
+            }
+            0x5c | 0xdc | 0xfc => {
+                self.read_pc();
+                self.read_pc();
+                self.cycles(if opcode == 0x5c { 8 } else { 4 });
+                "NOP"
+            }
+            _ => {
+                self.cycles(1);
+                "NOP"
+            }
+        };
+        String::from(name)
+    }
+
     pub fn step(&mut self) {
-        let _starting_pc = self.reg_pc;
+        if self.is_waiting {
+            self.wait();
+            return;
+        }
+        if self.trap() {
+            return;
+        }
+        let starting_pc = self.reg_pc;
+        let starting_cycles = self.cycle_count;
         let mut opcode_name = String::new();
         let opcode = self.read_pc();
         match opcode {
@@ -375,7 +865,7 @@ impl MOS6502 {
                 let result = self.reg_a & value;
                 self.reg_a = result;
                 self.update_flags_zn(result);
-                self.cycles(3);
+                self.cycles(4);
             }
             0x2d => {
                 //AND,ABS,3,4,cZidbVN
@@ -385,7 +875,7 @@ impl MOS6502 {
                 let result = self.reg_a & value;
                 self.reg_a = result;
                 self.update_flags_zn(result);
-                self.cycles(3);
+                self.cycles(4);
             }
             0x3d => {
                 //AND,ABSX,3,4,cZidbv
@@ -453,7 +943,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(addr);
                 value = self.asl(value);
                 self.write_u8(addr, value);
-                self.cycles(5);
+                self.cycles(6);
             }
             0x0e => {
                 //ASL,ABS,3,6,CZidbvN
@@ -462,7 +952,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(addr);
                 value = self.asl(value);
                 self.write_u8(addr, value);
-                self.cycles(5);
+                self.cycles(6);
             }
             0x1e => {
                 //ASL,ABSX,3,7,CZidbv
@@ -472,7 +962,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(addr);
                 value = self.asl(value);
                 self.write_u8(addr, value);
-                self.cycles(5);
+                self.cycles(7);
             }
             0x90 => {
                 //BCC,REL,2,2/3,czidb
@@ -567,8 +1057,8 @@ impl MOS6502 {
                 opcode_name = String::from("BIT");
                 let addr = self.get_zeropage_addr(0);
                 let value = self.read_u8(addr);
-                let result = self.reg_a & value;
-                self.update_flags_zn(result);
+                self.f_zero = self.reg_a & value == 0;
+                self.f_negative = (value & 0x80) == 0x80;
                 self.f_overflow = (value & 0x40) == 0x40; 
                 self.cycles(3);
             }
@@ -577,18 +1067,34 @@ impl MOS6502 {
                 opcode_name = String::from("BIT");
                 let addr = self.get_absolute_addr(0);
                 let value = self.read_u8(addr);
-                let result = self.reg_a & value;
+                self.f_zero = self.reg_a & value == 0;
+                self.f_negative = (value & 0x80) == 0x80;
                 self.f_overflow = (value & 0x40) == 0x40; 
-                self.update_flags_zn(result);
                 self.cycles(4);
             }
             0x00 => {
                 opcode_name = String::from("BRK");
                 //BRK,IMP,1,7,czidbVN
                 self.f_break = true;
-                self.cycles(2);
-                println!("Stopping execution on BRK {:04x}", self.reg_pc);
-                self.is_stopped = true;
+                if self.brk_halts {
+                    self.cycles(2);
+                    self.is_stopped = true;
+                } else {
+                    // the byte after BRK is skipped, so RTI returns past it
+                    let reg_pc = self.reg_pc.wrapping_add(1);
+                    self.stack_push((reg_pc >> 8) as u8);
+                    self.stack_push(reg_pc as u8);
+                    let status = self.get_status_registers();
+                    self.stack_push(status);
+                    self.f_interrupt = true;
+                    if self.variant == Variant::Cmos {
+                        self.f_decimal = false;
+                    }
+                    let lo = self.platform.read(0xfffe) as u16;
+                    let hi = self.platform.read(0xffff) as u16;
+                    self.reg_pc = lo | (hi << 8);
+                    self.cycles(7);
+                }
             }
             0x18 => {
                 //CLC,IMP,1,2,CzidbVN
@@ -663,8 +1169,8 @@ impl MOS6502 {
                 opcode_name = String::from("RTS");
                 let mut addr = self.stack_pull() as u16;
                 addr |= (self.stack_pull() as u16) << 8;
-                self.reg_pc = addr;
-                self.cycles(2);                        
+                self.reg_pc = addr.wrapping_add(1);
+                self.cycles(6);                        
             }
             0x38 => {
                 //SEC,IMP,1,2,CzidbVN
@@ -722,6 +1228,7 @@ impl MOS6502 {
                 let value = self.reg_sp;
                 self.update_flags_zn(value);
                 self.reg_x = value;
+                self.cycles(2);
             }
             0x9a => {
                 //TXS,IMP,1,2,czidbVN
@@ -729,6 +1236,7 @@ impl MOS6502 {
                 let value = self.reg_x;
                 self.update_flags_zn(value);
                 self.reg_sp = value;
+                self.cycles(2);
             }
             0xc9 => {
                 //CMP,IMM,2,2,CZidbVN
@@ -863,6 +1371,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(address);
                 value = value.wrapping_sub(1);
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(5);
             }
             0xd6 => {
@@ -873,6 +1382,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(address);
                 value = value.wrapping_sub(1);
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(6);
             }
             0xce => {
@@ -882,6 +1392,7 @@ impl MOS6502 {
                 let mut value = self.read_u8(address);
                 value = value.wrapping_sub(1);
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(6);
             }
             0xde => {
@@ -892,7 +1403,8 @@ impl MOS6502 {
                 let mut value = self.read_u8(address);
                 value = value.wrapping_sub(1);
                 self.write_u8(address, value);
-                self.cycles(6);
+                self.update_flags_zn(value);
+                self.cycles(7);
             }
             0xca => {
                 //DEX,IMP,1,2,cZidbVN
@@ -900,7 +1412,7 @@ impl MOS6502 {
                 if self.reg_x == 0 {
                     self.reg_x = 0xff
                 } else {
-                    self.reg_x = self.reg_x - 1;
+                    self.reg_x -= 1;
                 }
                 let value = self.reg_x;
                 self.update_flags_zn(value);
@@ -912,7 +1424,7 @@ impl MOS6502 {
                 if self.reg_y == 0 {
                     self.reg_y = 0xff
                 } else {
-                    self.reg_y = self.reg_y - 1;
+                    self.reg_y -= 1;
                 }
                 let value = self.reg_y;
                 self.update_flags_zn(value);
@@ -921,11 +1433,11 @@ impl MOS6502 {
             0xe8 => {
                 //INX,IMP,1,2,cZidbVN
                 opcode_name = String::from("INX");
-//                self.reg_x = self.reg_x + 1;
+//                self.reg_x += 1;
                 if self.reg_x == 0xff {
                     self.reg_x = 0;                        
                 } else {
-                    self.reg_x = self.reg_x + 1;                        
+                    self.reg_x += 1;                        
                 }
                 let value = self.reg_x;
                 self.update_flags_zn(value);
@@ -934,11 +1446,11 @@ impl MOS6502 {
             0xc8 => {
                 //INY,IMP,1,2,cZidbVN
                 opcode_name = String::from("INY");
-//                self.reg_y = self.reg_y + 1;
+//                self.reg_y += 1;
                 if self.reg_y == 0xff {
                     self.reg_y = 0;                        
                 } else {
-                    self.reg_y = self.reg_y + 1;                        
+                    self.reg_y += 1;                        
                 }
                 let value = self.reg_y;
                 self.update_flags_zn(value);
@@ -1024,7 +1536,7 @@ impl MOS6502 {
                 let result = self.reg_a ^ value;
                 self.reg_a = result;                        
                 self.update_flags_zn(result);
-                self.cycles(6);
+                self.cycles(5);
             }
             0xe6 => {
                 //INC,ZP,2,5,cZidbVN
@@ -1034,9 +1546,10 @@ impl MOS6502 {
                 if value == 0xff {
                     value = 0;
                 } else {
-                    value = value + 1;
+                    value += 1;
                 }
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(5);
             }
             0xf6 => {
@@ -1048,9 +1561,10 @@ impl MOS6502 {
                 if value == 0xff {
                     value = 0;
                 } else {
-                    value = value + 1;
+                    value += 1;
                 }
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(6);
             }
             0xee => {
@@ -1061,9 +1575,10 @@ impl MOS6502 {
                 if value == 0xff {
                     value = 0;
                 } else {
-                    value = value + 1;
+                    value += 1;
                 }
                 self.write_u8(address, value);
+                self.update_flags_zn(value);
                 self.cycles(6);
             }
             0xfe => {
@@ -1075,10 +1590,11 @@ impl MOS6502 {
                 if value == 0xff {
                     value = 0;
                 } else {
-                    value = value + 1;
+                    value += 1;
                 }
                 self.write_u8(address, value);
-                self.cycles(6);
+                self.update_flags_zn(value);
+                self.cycles(7);
             }
             0x4c => {
                 //JMP,ABS,3,3,czidbVN
@@ -1086,25 +1602,39 @@ impl MOS6502 {
                 let mut addr = self.read_pc() as u16;
                 addr |= (self.read_pc() as u16) << 8;
                 self.reg_pc = addr;
+                self.cycles(3);
             }
             0x6c => {
                 //JMP,IND,3,5,czidbVN
                 opcode_name = String::from("JMP");
                 let mut addr = self.read_pc() as u16;
                 addr |= (self.read_pc() as u16) << 8;
-                let dest = self.get_indirect_addr(addr);
+                let dest = match self.variant {
+                    // the NMOS 6502 doesn't carry into the high byte, so
+                    // JMP ($xxFF) takes its high byte from $xx00
+                    Variant::Nmos => {
+                        let lo = self.read_u8(addr) as u16;
+                        let hi = self.read_u8((addr & 0xff00) | (addr.wrapping_add(1) & 0x00ff)) as u16;
+                        lo | (hi << 8)
+                    }
+                    Variant::Cmos => {
+                        self.cycles(1);
+                        self.get_indirect_addr(addr)
+                    }
+                };
                 self.reg_pc = dest;
+                self.cycles(5);
             }
             0x20 => {
                 //JSR,ABS,3,6,czidbVN
                 opcode_name = String::from("JSR");
                 let mut addr = self.read_pc() as u16;
                 addr |= (self.read_pc() as u16) << 8;
-                let reg_pc = self.reg_pc;
+                let reg_pc = self.reg_pc.wrapping_sub(1);
                 self.stack_push((reg_pc >> 8) as u8);
                 self.stack_push(reg_pc as u8);
                 self.reg_pc = addr;
-                self.cycles(3);                        
+                self.cycles(6);                        
             }
             0xa9 => {
                 //LDA,IMM,2,2,cZidbVN
@@ -1131,7 +1661,7 @@ impl MOS6502 {
                 let value = self.read_u8(addr);
                 self.reg_a = value;
                 self.update_flags_zn(value);
-                self.cycles(3);
+                self.cycles(4);
             }
             0xad => {
                 //LDA,ABS,3,4,cZidbVN
@@ -1297,7 +1827,7 @@ impl MOS6502 {
                 let value = self.read_u8(addr);
                 let result = self.lsr(value);
                 self.write_u8(addr, result);
-                self.cycles(5);
+                self.cycles(6);
             }
             0x4e => {
                 //LSR,ABS,3,6,CZidbVN
@@ -1444,7 +1974,7 @@ impl MOS6502 {
                 let value = self.read_u8(addr);
                 let result = self.rol(value);
                 self.write_u8(addr, result);                       
-                self.cycles(6);
+                self.cycles(7);
             }
             0x6a => {
                 opcode_name = String::from("ROR");
@@ -1482,7 +2012,7 @@ impl MOS6502 {
                 let result = self.ror(value);
                 self.write_u8(addr, result);                        
                 self.update_flags_zn(result);
-                self.cycles(6);
+                self.cycles(7);
             }
             0x6e => {
                 //ROR,ABS,3,6,CZidbv
@@ -1606,8 +2136,8 @@ impl MOS6502 {
                 //STA,INDX,2,6,czidbv
                 opcode_name = String::from("STA");
                 let offset = self.reg_x;
-                let index = (offset as u16) + (self.read_pc() as u16);
-                let addr = self.get_indirect_addr(index);
+                let index = self.read_pc().wrapping_add(offset);
+                let addr = self.get_zeropage_pointer(index);
                 let value = self.reg_a;
                 self.write_u8(addr, value);
                 self.cycles(6);
@@ -1670,6 +2200,9 @@ impl MOS6502 {
                 self.write_u8(addr, val);
                 self.cycles(4);
             }
+            _ if self.variant == Variant::Cmos => {
+                opcode_name = self.step_65c02(opcode);
+            }
             _ => {
 
                 for counter in &self.debug_vector {
@@ -1691,7 +2224,25 @@ impl MOS6502 {
             }
         }
 
+        let elapsed = (self.cycle_count - starting_cycles) as u32;
+        self.instruction_count += 1;
+        self.total_cycles += elapsed as u64;
+        self.platform.tick(elapsed);
+
         let r = self.get_status_registers();
+        if self.trace {
+            eprintln!("PC {:04X}, OP {:02X} {}, A {:02X}, X {:02X}, Y {:02X}, SP {:02X}, R {:08b}",
+                starting_pc,
+                opcode,
+                opcode_name,
+                self.reg_a,
+                self.reg_x,
+                self.reg_y,
+                self.reg_sp,
+                r
+            );
+        }
+
         self.debug_vector.push_front(DebugFrame {
             pc : self.reg_pc,
             op : opcode, 
@@ -1699,40 +2250,42 @@ impl MOS6502 {
             x : self.reg_x,
             y : self.reg_y,
             registers : r,
-            opcode_name : opcode_name
+            opcode_name
         });
 
         if self.debug_vector.len() > 1000 {
             self.debug_vector.pop_back();
         }
 
-        // if (starting_pc != 0xff2c ) && (starting_pc != 0xff29) {
-        // #[cfg(debug_assertions)]
-        // {
-        //     println!("PC {:04X}, OP {:02X} {}, A {:02X}, X {:02X}, Y {:02X}, SP {:02X}, R {:08b}, 0x2b {:02X}",
-        //         starting_pc,
-        //         opcode,                
-        //         opcode_name,
-        //         self.reg_a,
-        //         self.reg_x,
-        //         self.reg_y,
-        //         self.reg_sp,
-        //         r,
-        //         self.ram[0x2b]
-        //     );
-        // }
-        // }
+        if let Some(ref mut coverage) = self.coverage {
+            coverage.mark_executed(starting_pc);
+        }
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(starting_pc, opcode, elapsed, self.reg_pc);
+        }
+
+        // after the instruction is accounted, so the profiler sees the
+        // interrupt enter its handler after it
+        if self.platform.take_nmi() {
+            self.nmi();
+        } else if !self.f_interrupt && self.platform.irq() {
+            self.irq();
+        }
     }
 
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::unnecessary_cast)]
 mod tests {
     use super::*;
+    use apple1::Apple1;
+    use kim1::Kim1;
 
     #[test]
     fn adc() {
-        let mut cpu = MOS6502::new();
+        let mut cpu = MOS6502::new(Box::new(Apple1::new()));
 
         cpu.reset();
         cpu.reg_a = 0x50;
@@ -1773,7 +2326,7 @@ mod tests {
 
     #[test]
     fn ror () {
-        let mut cpu = MOS6502::new();
+        let mut cpu = MOS6502::new(Box::new(Apple1::new()));
         cpu.reset();
         cpu.f_carry = true;
         let result = cpu.ror(108);
@@ -1783,7 +2336,7 @@ mod tests {
 
     #[test]
     fn rol () {
-        let mut cpu = MOS6502::new();
+        let mut cpu = MOS6502::new(Box::new(Apple1::new()));
         cpu.reset();
         let result2 = cpu.rol(149);
         assert_eq!(result2 as u8, 42);
@@ -1809,4 +2362,325 @@ mod tests {
         assert_eq!(carry, false);
         assert_eq!(register, 218);
     }
+
+    #[test]
+    fn inc_dec_memory_flags() {
+        let mut apple1 = Apple1::new();
+        // DEC $10, INC $10, JMP $0300
+        for (i, &b) in [0xc6, 0x10, 0xe6, 0x10, 0x4c, 0x00, 0x03].iter().enumerate() {
+            apple1.poke(0x0300 + i as u16, b);
+        }
+        apple1.poke(0x0010, 0x00);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.step();
+        assert!(cpu.f_negative);
+        assert!(!cpu.f_zero);
+        cpu.step();
+        assert!(!cpu.f_negative);
+        assert!(cpu.f_zero);
+        let cycles = cpu.get_total_cycles();
+        cpu.step();
+        assert_eq!(cpu.get_total_cycles() - cycles, 3);
+    }
+
+    #[test]
+    fn jsr_pushes_return_minus_one() {
+        let mut apple1 = Apple1::new();
+        // JSR $0310 ... $0310: BIT $10, RTS
+        for (i, &b) in [0x20, 0x10, 0x03].iter().enumerate() {
+            apple1.poke(0x0300 + i as u16, b);
+        }
+        for (i, &b) in [0x24, 0x10, 0x60].iter().enumerate() {
+            apple1.poke(0x0310 + i as u16, b);
+        }
+        apple1.poke(0x0010, 0x80);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.step();
+        assert_eq!(cpu.read_u8(0x01fc), 0x02);
+        assert_eq!(cpu.read_u8(0x01fd), 0x03);
+        cpu.step();
+        assert!(cpu.f_negative);
+        assert!(cpu.f_zero);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0303);
+    }
+
+    #[test]
+    fn rti_returns_to_the_pushed_address() {
+        let mut apple1 = Apple1::new();
+        apple1.poke(0x0300, 0x40);
+        // status, then the return address $0305, as an interrupt pushes them
+        apple1.poke(0x01fb, 0x01);
+        apple1.poke(0x01fc, 0x05);
+        apple1.poke(0x01fd, 0x03);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.reg_sp = 0xfa;
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0305);
+        assert!(cpu.f_carry);
+        assert_eq!(cpu.reg_sp, 0xfd);
+    }
+
+    #[test]
+    fn brk_takes_the_irq_vector() {
+        let mut apple1 = Apple1::new();
+        // BRK, signature byte; the handler is a lone RTI
+        apple1.poke(0x0300, 0x00);
+        apple1.poke(0x0301, 0xff);
+        apple1.poke(0x0400, 0x40);
+        apple1.poke(0xfffe, 0x00);
+        apple1.poke(0xffff, 0x04);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.step();
+        assert!(!cpu.is_running());
+
+        cpu.reset();
+        cpu.set_brk_halts(false);
+        cpu.set_pc(0x0300);
+        cpu.step();
+        assert!(cpu.is_running());
+        assert_eq!(cpu.get_pc(), 0x0400);
+        assert_eq!(cpu.get_total_cycles(), 2 + 7);
+        assert!(cpu.f_interrupt);
+        assert_eq!(cpu.read_u8(0x01fb) & 0x14, 0x10);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0302);
+        assert!(!cpu.f_interrupt);
+    }
+
+    /// A CPU of `variant` about to run `program` at $0300.
+    fn cpu_with(variant: Variant, program: &[u8]) -> MOS6502 {
+        let mut apple1 = Apple1::new();
+        for (i, &b) in program.iter().enumerate() {
+            apple1.poke(0x0300 + i as u16, b);
+        }
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.set_variant(variant);
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu
+    }
+
+    #[test]
+    fn decimal_mode() {
+        // SED, CLC, LDA #$99, ADC #$01
+        let program = [0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01];
+        let mut nmos = cpu_with(Variant::Nmos, &program);
+        let mut cmos = cpu_with(Variant::Cmos, &program);
+        for _ in 0..4 {
+            nmos.step();
+            cmos.step();
+        }
+        assert_eq!((nmos.reg_a, nmos.f_carry), (0x00, true));
+        assert_eq!((cmos.reg_a, cmos.f_carry), (0x00, true));
+        // the NMOS flags come from the binary sum $9A and the unadjusted $A0
+        assert_eq!((nmos.f_zero, nmos.f_negative), (false, true));
+        assert_eq!((cmos.f_zero, cmos.f_negative), (true, false));
+        assert_eq!(cmos.get_total_cycles(), nmos.get_total_cycles() + 1);
+
+        // SED, SEC, LDA #$00, SBC #$01, SEC, LDA #$42, SBC #$15
+        let program = [0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01, 0x38, 0xa9, 0x42, 0xe9, 0x15];
+        for &variant in &[Variant::Nmos, Variant::Cmos] {
+            let mut cpu = cpu_with(variant, &program);
+            for _ in 0..4 {
+                cpu.step();
+            }
+            assert_eq!((cpu.reg_a, cpu.f_carry, cpu.f_negative), (0x99, false, true));
+            for _ in 0..3 {
+                cpu.step();
+            }
+            assert_eq!((cpu.reg_a, cpu.f_carry), (0x27, true));
+        }
+    }
+
+    #[test]
+    fn jmp_indirect_page_wrap() {
+        // JMP ($03FF)
+        let mut program = vec![0x6c, 0xff, 0x03];
+        program.resize(0x100, 0xea);
+        program[0xff] = 0x34;
+        program.push(0x12);
+        let mut nmos = cpu_with(Variant::Nmos, &program);
+        nmos.step();
+        // the high byte comes from $0300, the opcode itself
+        assert_eq!(nmos.get_pc(), 0x6c34);
+        let mut cmos = cpu_with(Variant::Cmos, &program);
+        cmos.step();
+        assert_eq!(cmos.get_pc(), 0x1234);
+        assert_eq!(cmos.get_total_cycles(), 6);
+    }
+
+    #[test]
+    fn cmos_instructions() {
+        let program = [
+            0xa2, 0x12,             // LDX #$12
+            0xda,                   // PHX
+            0x7a,                   // PLY
+            0x64, 0x10,             // STZ $10
+            0xa9, 0x0f,             // LDA #$0F
+            0x04, 0x10,             // TSB $10
+            0xa9, 0x03,             // LDA #$03
+            0x14, 0x10,             // TRB $10
+            0x1a,                   // INC A
+            0x89, 0x80,             // BIT #$80
+            0x87, 0x10,             // SMB0 $10
+            0x80, 0x02,             // BRA +2
+            0xdb, 0xdb,             // STP, skipped
+            0xaf, 0x10, 0x02,       // BBS2 $10,+2
+            0xdb, 0xdb,             // STP, skipped
+            0x02, 0x00,             // two-byte NOP
+            0x03,                   // one-byte NOP
+            0xb2, 0x20,             // LDA ($20)
+            0x7c, 0x40, 0x00        // JMP ($0040,X)
+        ];
+        let mut cpu = cpu_with(Variant::Cmos, &program);
+        cpu.write_u8(0x0010, 0xff);
+        cpu.write_u8(0x0020, 0x10);
+        cpu.write_u8(0x0021, 0x00);
+        cpu.write_u8(0x0052, 0x00);
+        cpu.write_u8(0x0053, 0x04);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.reg_y, 0x12);
+        cpu.step();
+        assert_eq!(cpu.read_u8(0x0010), 0x00);
+        cpu.step();
+        cpu.step();
+        assert!(cpu.f_zero);
+        assert_eq!(cpu.read_u8(0x0010), 0x0f);
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.f_zero);
+        assert_eq!(cpu.read_u8(0x0010), 0x0c);
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x04);
+        cpu.step();
+        // BIT #imm leaves N alone
+        assert!(cpu.f_zero);
+        assert!(!cpu.f_negative);
+        cpu.step();
+        assert_eq!(cpu.read_u8(0x0010), 0x0d);
+        for _ in 0..4 {
+            cpu.step();
+        }
+        assert!(cpu.is_running());
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x0d);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0400);
+    }
+
+    #[test]
+    fn cmos_interrupts_clear_decimal() {
+        // SED, BRK
+        let program = [0xf8, 0x00, 0x00];
+        for &(variant, decimal) in &[(Variant::Nmos, true), (Variant::Cmos, false)] {
+            let mut cpu = cpu_with(variant, &program);
+            cpu.set_brk_halts(false);
+            cpu.step();
+            cpu.step();
+            assert_eq!(cpu.f_decimal, decimal);
+            assert_eq!(cpu.read_u8(0x01fb) & 0x08, 0x08);
+        }
+    }
+
+    #[test]
+    fn wai_waits_for_an_interrupt() {
+        let mut kim1 = Kim1::new();
+        // SEI, WAI, INX
+        for (i, &b) in [0x78, 0xcb, 0xe8].iter().enumerate() {
+            kim1.write(0x0200 + i as u16, b);
+        }
+        let mut cpu = MOS6502::new(Box::new(kim1));
+        cpu.set_variant(Variant::Cmos);
+        cpu.reset();
+        cpu.set_pc(0x0200);
+        cpu.step();
+        cpu.step();
+        let cycles = cpu.get_total_cycles();
+        for _ in 0..10 {
+            cpu.step();
+        }
+        assert_eq!(cpu.get_pc(), 0x0202);
+        assert_eq!(cpu.get_total_cycles(), cycles + 10);
+        // U3's timer; with interrupts disabled the IRQ only wakes the CPU
+        cpu.platform_mut().write(::kim1::U3_IO + 0x0c, 20);
+        while cpu.get_pc() == 0x0202 && cpu.get_total_cycles() < cycles + 100 {
+            cpu.step();
+        }
+        assert_eq!(cpu.get_pc(), 0x0203);
+        assert_eq!(cpu.reg_x, 1);
+    }
+
+    #[test]
+    fn zero_page_index_wraps() {
+        let mut apple1 = Apple1::new();
+        // LDA $F0,X
+        apple1.poke(0x0300, 0xb5);
+        apple1.poke(0x0301, 0xf0);
+        apple1.poke(0x0010, 0x42);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.reg_x = 0x20;
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x42);
+    }
+
+    #[test]
+    fn indirect_pointers_wrap_in_zero_page() {
+        let mut apple1 = Apple1::new();
+        // LDA ($FF,X), LDA ($FF),Y, STA ($FE,X)
+        for (i, &b) in [0xa1, 0xff, 0xb1, 0xff, 0x81, 0xfe].iter().enumerate() {
+            apple1.poke(0x0300 + i as u16, b);
+        }
+        // The pointer at $FF is $0480; $0100 would make it $5580.
+        apple1.poke(0x00ff, 0x80);
+        apple1.poke(0x0000, 0x04);
+        apple1.poke(0x0100, 0x55);
+        apple1.poke(0x0480, 0x42);
+        apple1.poke(0x0482, 0x43);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        cpu.reg_x = 0x00;
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x42);
+        cpu.reg_y = 0x02;
+        cpu.step();
+        assert_eq!(cpu.reg_a, 0x43);
+        // $FE + X wraps round to the pointer at $FF
+        cpu.reg_x = 0x01;
+        cpu.step();
+        assert_eq!(cpu.read_u8(0x0480), 0x43);
+    }
+    #[test]
+    fn call_returns_to_the_caller() {
+        let mut apple1 = Apple1::new();
+        // $0400: LDA #$42, STA $10, RTS
+        for (i, &b) in [0xa9, 0x42, 0x85, 0x10, 0x60].iter().enumerate() {
+            apple1.poke(0x0400 + i as u16, b);
+        }
+        // $0410: JMP $0410
+        for (i, &b) in [0x4c, 0x10, 0x04].iter().enumerate() {
+            apple1.poke(0x0410 + i as u16, b);
+        }
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0x0300);
+        assert!(cpu.call(0x0400, 1000));
+        assert_eq!(cpu.get_pc(), 0x0300);
+        assert_eq!(cpu.reg_sp, 0xfd);
+        assert_eq!(cpu.read_u8(0x0010), 0x42);
+        assert!(!cpu.call(0x0410, 1000));
+    }
 }
@@ -0,0 +1,336 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Lets a machine get the chips it gave a `Scheduler` back as their own
+/// types. Every `Device` has it.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A chip on the bus, addressed by register number. Devices run lazily:
+/// `tick` brings one up to date all at once when the bus touches it or
+/// when the deadline from `next_event` arrives, so a chip that is only
+/// waiting costs nothing per instruction.
+pub trait Device: AsAny {
+    fn read(&mut self, register: u16) -> u8;
+    fn write(&mut self, register: u16, value: u8);
+
+    /// Advances the device's clock.
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// The RESET line, for chips that have one.
+    fn reset(&mut self) {}
+
+    /// Cycles until the device next changes by itself, e.g. a timer
+    /// interrupting or a serial bit arriving, or None while only the bus
+    /// can change it. Answering early is harmless; late is not.
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+
+    /// The level of the device's IRQ output.
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// The CPU line a device's IRQ output is wired to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Line {
+    None,
+    Irq,
+    Nmi
+}
+
+struct Slot {
+    device: Box<dyn Device>,
+    line: Line,
+    synced: u64,
+    deadline: Option<u64>
+}
+
+/// Runs devices against the CPU's cycle count. Each device is ticked only
+/// when it is accessed or its next event is due, and always exactly up to
+/// the event's cycle. The IRQ line is the OR of the devices wired to it;
+/// NMI is taken once each time any device wired to it becomes active.
+pub struct Scheduler {
+    now: u64,
+    slots: Vec<Slot>,
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+    irq: u64,
+    nmi_level: u64,
+    nmi: bool
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            now: 0,
+            slots: Vec::new(),
+            queue: BinaryHeap::new(),
+            irq: 0,
+            nmi_level: 0,
+            nmi: false
+        }
+    }
+
+    /// Adds a device, returning the id used to address it. At most 64
+    /// devices can drive interrupt lines.
+    pub fn add(&mut self, device: Box<dyn Device>, line: Line) -> usize {
+        let id = self.slots.len();
+        assert!(line == Line::None || id < 64, "too many interrupting devices");
+        self.slots.push(Slot { device, line, synced: self.now, deadline: None });
+        self.update(id);
+        id
+    }
+
+    /// Swaps in a new device in place of device `id`, keeping its wiring.
+    pub fn replace(&mut self, id: usize, device: Box<dyn Device>) {
+        self.slots[id].device = device;
+        self.slots[id].synced = self.now;
+        self.update(id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// The cycle count the devices have been run up to.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn read(&mut self, id: usize, register: u16) -> u8 {
+        self.sync(id, self.now);
+        let value = self.slots[id].device.read(register);
+        self.update(id);
+        value
+    }
+
+    pub fn write(&mut self, id: usize, register: u16, value: u8) {
+        self.sync(id, self.now);
+        self.slots[id].device.write(register, value);
+        self.update(id);
+    }
+
+    /// Device `id` as the chip it was added as, e.g. to peek at a register.
+    /// It is as of its last access or event, which may be before `now`.
+    pub fn device<T: Device>(&self, id: usize) -> &T {
+        let device : &dyn Device = &*self.slots[id].device;
+        AsAny::as_any(device).downcast_ref().expect("device is a different chip")
+    }
+
+    /// Brings device `id` up to date and hands it to `f` as the chip it
+    /// was added as, for what the bus can't do, like driving its pins.
+    /// Its interrupt output is followed afterwards.
+    pub fn with<T: Device, R, F: FnOnce(&mut T) -> R>(&mut self, id: usize, f: F) -> R {
+        self.sync(id, self.now);
+        let result = {
+            let device : &mut dyn Device = &mut *self.slots[id].device;
+            f(AsAny::as_any_mut(device).downcast_mut().expect("device is a different chip"))
+        };
+        self.update(id);
+        result
+    }
+
+    /// Pulls every device's RESET line.
+    pub fn reset(&mut self) {
+        for id in 0..self.slots.len() {
+            self.sync(id, self.now);
+            self.slots[id].device.reset();
+            self.update(id);
+        }
+    }
+
+    /// Moves the clock on, running each device whose event falls due.
+    pub fn advance(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+        while let Some(&Reverse((deadline, id))) = self.queue.peek() {
+            if deadline > self.now {
+                break;
+            }
+            self.queue.pop();
+            // rescheduling leaves the old entry behind
+            if self.slots[id].deadline != Some(deadline) {
+                continue;
+            }
+            self.sync(id, deadline);
+            self.update(id);
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq != 0
+    }
+
+    /// True once for each time the NMI line became active.
+    pub fn take_nmi(&mut self) -> bool {
+        let nmi = self.nmi;
+        self.nmi = false;
+        nmi
+    }
+
+    fn sync(&mut self, id: usize, cycle: u64) {
+        let slot = &mut self.slots[id];
+        let mut elapsed = cycle - slot.synced;
+        while elapsed > 0 {
+            let step = elapsed.min(u32::MAX as u64);
+            slot.device.tick(step as u32);
+            elapsed -= step;
+        }
+        slot.synced = cycle;
+    }
+
+    /// Follows the device's interrupt output and reschedules it.
+    fn update(&mut self, id: usize) {
+        let slot = &mut self.slots[id];
+        let bit = 1u64.checked_shl(id as u32).unwrap_or(0);
+        let active = slot.device.irq();
+        match slot.line {
+            Line::Irq if active => self.irq |= bit,
+            Line::Irq => self.irq &= !bit,
+            Line::Nmi if active => {
+                if self.nmi_level & bit == 0 {
+                    self.nmi = true;
+                }
+                self.nmi_level |= bit;
+            }
+            Line::Nmi => self.nmi_level &= !bit,
+            Line::None => {}
+        }
+
+        let deadline = slot.device.next_event().map(|cycles| slot.synced + cycles.max(1));
+        if deadline != slot.deadline {
+            slot.deadline = deadline;
+            if let Some(deadline) = deadline {
+                self.queue.push(Reverse((deadline, id)));
+            }
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use via;
+    use via::Via;
+
+    /// Counts down from a value written to it, interrupting at zero.
+    struct Countdown {
+        remaining: u64,
+        ticks: Rc<Cell<u32>>
+    }
+
+    impl Device for Countdown {
+        fn read(&mut self, _register: u16) -> u8 {
+            self.remaining as u8
+        }
+
+        fn write(&mut self, _register: u16, value: u8) {
+            self.remaining = value as u64;
+        }
+
+        fn tick(&mut self, cycles: u32) {
+            self.ticks.set(self.ticks.get() + 1);
+            self.remaining = self.remaining.saturating_sub(cycles as u64);
+        }
+
+        fn next_event(&self) -> Option<u64> {
+            if self.remaining > 0 { Some(self.remaining) } else { None }
+        }
+
+        fn irq(&self) -> bool {
+            self.remaining == 0
+        }
+    }
+
+    #[test]
+    fn runs_devices_at_deadlines() {
+        let ticks = Rc::new(Cell::new(0));
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.add(Box::new(Countdown { remaining: 0, ticks: ticks.clone() }), Line::Irq);
+        assert!(scheduler.irq());
+        scheduler.write(id, 0, 100);
+        assert!(!scheduler.irq());
+        for _ in 0..33 {
+            scheduler.advance(3);
+        }
+        assert_eq!(ticks.get(), 0);
+        assert_eq!(scheduler.read(id, 0), 1);
+        assert_eq!(ticks.get(), 1);
+        scheduler.advance(3);
+        assert_eq!(ticks.get(), 2);
+        assert!(scheduler.irq());
+        scheduler.advance(1000);
+        assert_eq!(ticks.get(), 2);
+    }
+
+    #[test]
+    fn nmi_edges() {
+        let ticks = Rc::new(Cell::new(0));
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.add(Box::new(Countdown { remaining: 5, ticks: ticks.clone() }), Line::Nmi);
+        scheduler.advance(4);
+        assert!(!scheduler.take_nmi());
+        scheduler.advance(4);
+        assert!(scheduler.take_nmi());
+        assert!(!scheduler.take_nmi());
+        // still active, so no new edge until it goes inactive and back
+        scheduler.advance(10);
+        assert!(!scheduler.take_nmi());
+        scheduler.write(id, 0, 2);
+        scheduler.advance(2);
+        assert!(scheduler.take_nmi());
+        assert!(!scheduler.irq());
+    }
+
+    #[test]
+    fn typed_access() {
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.add(Box::new(Via::new()), Line::Irq);
+        scheduler.write(id, via::IER, 0x80 | via::IRQ_CA1);
+        scheduler.write(id, via::PCR, 0x01);
+        scheduler.with(id, |via: &mut Via| via.set_c1(::pia::Port::A, true));
+        assert!(scheduler.irq());
+        assert_eq!(scheduler.device::<Via>(id).peek(via::IFR) & via::IRQ_CA1, via::IRQ_CA1);
+    }
+
+    #[test]
+    fn via_timer_on_time() {
+        let mut scheduler = Scheduler::new();
+        let id = scheduler.add(Box::new(Via::new()), Line::Irq);
+        scheduler.write(id, via::IER, 0x80 | via::IRQ_T1);
+        scheduler.write(id, via::T1C_L, 0x00);
+        scheduler.write(id, via::T1C_H, 0x10);
+        // the flag sets N+1 cycles after the counter is loaded
+        scheduler.advance(0x1000);
+        assert!(!scheduler.irq());
+        scheduler.advance(1);
+        assert!(scheduler.irq());
+        assert_eq!(scheduler.read(id, via::IFR) & via::IRQ_T1, via::IRQ_T1);
+    }
+}
@@ -0,0 +1,215 @@
+use clock::APPLE1_HZ;
+
+pub const COLUMNS : usize = 40;
+pub const ROWS : usize = 24;
+
+/// The terminal section writes one character per video frame.
+pub const CYCLES_PER_CHAR : u32 = (APPLE1_HZ / 60) as u32;
+
+const CR : u8 = 0x0d;
+
+/// Apple 1 terminal section: a 40x24 screen fed by the 2513 character
+/// generator, which only has upper case, digits and punctuation ($20-$5F).
+/// Lower case is folded to upper case and control characters other than CR
+/// are ignored. Text wraps at column 40 and scrolls at the bottom line.
+///
+/// With the rate limit on, each character keeps the display busy for one
+/// frame (about 60 characters per second), which software sees through DSP
+/// bit 7.
+///
+/// It can also keep a transcript of everything it showed, for comparing a
+/// program's output against what was expected.
+pub struct Display {
+    screen: [[u8; COLUMNS]; ROWS],
+    column: usize,
+    row: usize,
+    busy_cycles: u32,
+    rate_limit: bool,
+    output_count: u64,
+    transcript: Option<String>
+}
+
+impl Display {
+    pub fn new() -> Display {
+        Display {
+            screen: [[b' '; COLUMNS]; ROWS],
+            column: 0,
+            row: 0,
+            busy_cycles: 0,
+            rate_limit: false,
+            output_count: 0,
+            transcript: None
+        }
+    }
+
+    pub fn set_rate_limit(&mut self, rate_limit: bool) {
+        self.rate_limit = rate_limit;
+        if !rate_limit {
+            self.busy_cycles = 0;
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy_cycles > 0
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+    }
+
+    /// Maps a 7-bit code to what the character generator shows, or `None`
+    /// for codes the terminal ignores. CR comes back unchanged.
+    pub fn glyph(value: u8) -> Option<u8> {
+        match value & 0x7f {
+            CR => Some(CR),
+            ch @ 0x20..=0x5f => Some(ch),
+            ch @ 0x60..=0x7e => Some(ch - 0x20),
+            _ => None
+        }
+    }
+
+    /// Sends a character to the terminal. Returns what was displayed, or
+    /// `None` when the character was ignored or dropped while busy.
+    pub fn output(&mut self, value: u8) -> Option<u8> {
+        if self.is_busy() {
+            return None;
+        }
+        let glyph = Display::glyph(value)?;
+        self.output_count += 1;
+        if let Some(ref mut transcript) = self.transcript {
+            transcript.push(if glyph == CR { '\n' } else { glyph as char });
+        }
+        if glyph == CR {
+            self.newline();
+        } else {
+            self.screen[self.row][self.column] = glyph;
+            self.column += 1;
+            if self.column == COLUMNS {
+                self.newline();
+            }
+        }
+        if self.rate_limit {
+            self.busy_cycles = CYCLES_PER_CHAR;
+        }
+        Some(glyph)
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            self.screen.rotate_left(1);
+            self.screen[ROWS - 1] = [b' '; COLUMNS];
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.screen = [[b' '; COLUMNS]; ROWS];
+        self.column = 0;
+        self.row = 0;
+        self.busy_cycles = 0;
+        if let Some(ref mut transcript) = self.transcript {
+            transcript.clear();
+        }
+    }
+
+    /// Starts keeping a transcript of the output.
+    pub fn record_transcript(&mut self) {
+        if self.transcript.is_none() {
+            self.transcript = Some(String::new());
+        }
+    }
+
+    /// Everything displayed since recording started, with CR as a line
+    /// end and no wrapping.
+    pub fn transcript(&self) -> Option<&str> {
+        self.transcript.as_deref()
+    }
+
+    /// Number of characters displayed so far.
+    pub fn output_count(&self) -> u64 {
+        self.output_count
+    }
+
+    /// Cursor position as (column, row).
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// The row as shown, trailing blanks included.
+    pub fn row(&self, row: usize) -> String {
+        String::from_utf8_lossy(&self.screen[row]).to_string()
+    }
+
+    pub fn line(&self, row: usize) -> String {
+        String::from_utf8_lossy(&self.screen[row]).trim_end().to_string()
+    }
+
+    /// The whole screen, one line per row with trailing blanks removed.
+    pub fn text(&self) -> String {
+        (0..ROWS).map(|row| self.line(row)).collect::<Vec<String>>().join("\n")
+    }
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str(display: &mut Display, text: &str) {
+        for b in text.bytes() {
+            display.output(b);
+        }
+    }
+
+    #[test]
+    fn character_set() {
+        let mut display = Display::new();
+        type_str(&mut display, "hello\x07\x7f World_\r");
+        assert_eq!(display.line(0), "HELLO WORLD_");
+        assert_eq!(display.cursor(), (0, 1));
+        assert_eq!(display.transcript(), None);
+        display.record_transcript();
+        type_str(&mut display, &format!("{}\rok", "x".repeat(COLUMNS + 1)));
+        assert_eq!(display.transcript(), Some(format!("{}\nOK", "X".repeat(COLUMNS + 1)).as_str()));
+    }
+
+    #[test]
+    fn wraps_and_scrolls() {
+        let mut display = Display::new();
+        type_str(&mut display, &"A".repeat(COLUMNS + 1));
+        assert_eq!(display.line(0).len(), COLUMNS);
+        assert_eq!(display.line(1), "A");
+        assert_eq!(display.cursor(), (1, 1));
+
+        let mut display = Display::new();
+        for i in 0..ROWS + 1 {
+            type_str(&mut display, &format!("{}\r", i));
+        }
+        assert_eq!(display.line(0), "2");
+        assert_eq!(display.line(ROWS - 2), "24");
+        assert_eq!(display.line(ROWS - 1), "");
+        assert_eq!(display.cursor(), (0, ROWS - 1));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut display = Display::new();
+        display.set_rate_limit(true);
+        assert_eq!(display.output(b'A'), Some(b'A'));
+        assert!(display.is_busy());
+        assert_eq!(display.output(b'B'), None);
+        display.tick(CYCLES_PER_CHAR - 1);
+        assert!(display.is_busy());
+        display.tick(1);
+        assert!(!display.is_busy());
+        assert_eq!(display.output(b'B'), Some(b'B'));
+        assert_eq!(display.line(0), "AB");
+    }
+}
@@ -0,0 +1,344 @@
+use device::Device;
+
+pub const COLUMNS : usize = 16;
+pub const ROWS : usize = 2;
+
+/// Instruction bits: the highest set bit picks the instruction.
+pub const CLEAR : u8 = 0x01;
+pub const HOME : u8 = 0x02;
+pub const ENTRY_MODE : u8 = 0x04;
+pub const DISPLAY_CONTROL : u8 = 0x08;
+pub const SHIFT : u8 = 0x10;
+pub const FUNCTION_SET : u8 = 0x20;
+pub const SET_CGRAM : u8 = 0x40;
+pub const SET_DDRAM : u8 = 0x80;
+
+pub const BUSY : u8 = 0x80;
+
+const DDRAM_SIZE : usize = 0x80;
+const CGRAM_SIZE : usize = 0x40;
+/// Characters per line in two-line mode; the second line starts at $40.
+const LINE_LENGTH : u8 = 40;
+
+/// Execution times in microseconds at the usual 270 kHz oscillator.
+const CLEAR_US : u64 = 1520;
+const COMMAND_US : u64 = 37;
+
+/// Hitachi HD44780 character LCD controller driving a 16x2 panel.
+///
+/// The host decodes the E, RS and R/W pins and calls `write` on the
+/// falling edge of E and `read` on the rising edge, with RS picking data
+/// (true) or instructions (false). In 4-bit mode each transfer is two
+/// nibbles on D7-D4, high nibble first. Every operation keeps the busy
+/// flag set for its execution time; commands sent while busy still run.
+pub struct Hd44780 {
+    ddram: [u8; DDRAM_SIZE],
+    cgram: [u8; CGRAM_SIZE],
+    address: u8,
+    cgram_selected: bool,
+    increment: bool,
+    shift_display: bool,
+    display_on: bool,
+    cursor_on: bool,
+    blink: bool,
+    eight_bit: bool,
+    two_lines: bool,
+    shift: u8,
+    write_nibble: Option<u8>,
+    read_nibble: Option<u8>,
+    cpu_hz: u64,
+    busy_cycles: u64
+}
+
+impl Hd44780 {
+    pub fn new(cpu_hz: u64) -> Hd44780 {
+        Hd44780 {
+            ddram: [b' '; DDRAM_SIZE],
+            cgram: [0; CGRAM_SIZE],
+            address: 0,
+            cgram_selected: false,
+            increment: true,
+            shift_display: false,
+            display_on: false,
+            cursor_on: false,
+            blink: false,
+            eight_bit: true,
+            two_lines: false,
+            shift: 0,
+            write_nibble: None,
+            read_nibble: None,
+            cpu_hz,
+            busy_cycles: 0
+        }
+    }
+
+    /// Power-on reset: blank display, 8-bit interface, one line.
+    pub fn reset(&mut self) {
+        *self = Hd44780::new(self.cpu_hz);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.busy_cycles > 0
+    }
+
+    /// The address counter.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Whether the cursor shows as an underline or a blinking block.
+    pub fn cursor_visible(&self) -> bool {
+        self.display_on && (self.cursor_on || self.blink)
+    }
+
+    fn busy_for(&mut self, us: u64) {
+        self.busy_cycles = (us * self.cpu_hz / 1_000_000).max(1);
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles as u64);
+    }
+
+    pub fn write(&mut self, rs: bool, value: u8) {
+        let value = if self.eight_bit {
+            value
+        } else {
+            match self.write_nibble.take() {
+                Some(high) => high | (value >> 4),
+                None => {
+                    self.write_nibble = Some(value & 0xf0);
+                    return;
+                }
+            }
+        };
+        if rs {
+            self.write_data(value);
+        } else {
+            self.command(value);
+        }
+    }
+
+    pub fn read(&mut self, rs: bool) -> u8 {
+        if let Some(low) = self.read_nibble.take() {
+            return low << 4;
+        }
+        let value = if rs {
+            let value = self.ddram_or_cgram();
+            self.advance();
+            self.busy_for(COMMAND_US);
+            value
+        } else {
+            self.status()
+        };
+        if self.eight_bit {
+            value
+        } else {
+            self.read_nibble = Some(value & 0x0f);
+            value & 0xf0
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let busy = if self.is_busy() { BUSY } else { 0 };
+        busy | (self.address & 0x7f)
+    }
+
+    fn ddram_or_cgram(&self) -> u8 {
+        if self.cgram_selected {
+            self.cgram[self.address as usize & (CGRAM_SIZE - 1)]
+        } else {
+            self.ddram[self.address as usize & (DDRAM_SIZE - 1)]
+        }
+    }
+
+    fn command(&mut self, value: u8) {
+        self.busy_for(COMMAND_US);
+        if value & SET_DDRAM != 0 {
+            self.address = value & 0x7f;
+            self.cgram_selected = false;
+        } else if value & SET_CGRAM != 0 {
+            self.address = value & 0x3f;
+            self.cgram_selected = true;
+        } else if value & FUNCTION_SET != 0 {
+            self.eight_bit = value & 0x10 != 0;
+            self.two_lines = value & 0x08 != 0;
+            self.write_nibble = None;
+            self.read_nibble = None;
+        } else if value & SHIFT != 0 {
+            let right = value & 0x04 != 0;
+            if value & 0x08 != 0 {
+                self.shift_by(right);
+            } else {
+                self.move_cursor(right);
+            }
+        } else if value & DISPLAY_CONTROL != 0 {
+            self.display_on = value & 0x04 != 0;
+            self.cursor_on = value & 0x02 != 0;
+            self.blink = value & 0x01 != 0;
+        } else if value & ENTRY_MODE != 0 {
+            self.increment = value & 0x02 != 0;
+            self.shift_display = value & 0x01 != 0;
+        } else if value & HOME != 0 {
+            self.address = 0;
+            self.cgram_selected = false;
+            self.shift = 0;
+            self.busy_for(CLEAR_US);
+        } else if value & CLEAR != 0 {
+            self.ddram = [b' '; DDRAM_SIZE];
+            self.address = 0;
+            self.cgram_selected = false;
+            self.shift = 0;
+            self.increment = true;
+            self.busy_for(CLEAR_US);
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        if self.cgram_selected {
+            self.cgram[self.address as usize & (CGRAM_SIZE - 1)] = value;
+        } else {
+            self.ddram[self.address as usize & (DDRAM_SIZE - 1)] = value;
+            if self.shift_display {
+                self.shift_by(!self.increment);
+            }
+        }
+        self.advance();
+        self.busy_for(COMMAND_US);
+    }
+
+    /// Moves the address counter after a data access.
+    fn advance(&mut self) {
+        if self.cgram_selected {
+            self.address = if self.increment { self.address + 1 } else { self.address.wrapping_sub(1) } & 0x3f;
+        } else {
+            self.move_cursor(self.increment);
+        }
+    }
+
+    /// Steps the DDRAM address, jumping between the lines in two-line
+    /// mode and wrapping through all 80 characters in one-line mode.
+    fn move_cursor(&mut self, right: bool) {
+        self.address = match (self.two_lines, right, self.address) {
+            (true, true, 0x27) => 0x40,
+            (true, true, 0x67) => 0x00,
+            (true, false, 0x00) => 0x67,
+            (true, false, 0x40) => 0x27,
+            (false, true, 0x4f) => 0x00,
+            (false, false, 0x00) => 0x4f,
+            (_, true, address) => address + 1,
+            (_, false, address) => address - 1
+        };
+    }
+
+    fn shift_by(&mut self, right: bool) {
+        let length = if self.two_lines { LINE_LENGTH } else { 2 * LINE_LENGTH };
+        // shifting the display right shows earlier characters
+        self.shift = if right { (self.shift + length - 1) % length } else { (self.shift + 1) % length };
+    }
+
+    /// What the panel shows on `row`: blank while the display is off, and
+    /// user-defined characters from CGRAM as `#`.
+    pub fn line(&self, row: usize) -> String {
+        if !self.display_on || (row > 0 && !self.two_lines) {
+            return " ".repeat(COLUMNS);
+        }
+        let (base, length) = if self.two_lines { (row * 0x40, LINE_LENGTH) } else { (0, 2 * LINE_LENGTH) };
+        (0..COLUMNS).map(|column| {
+            let offset = (self.shift as usize + column) % length as usize;
+            glyph(self.ddram[base + offset])
+        }).collect()
+    }
+
+    /// Both rows, one per line.
+    pub fn text(&self) -> String {
+        (0..ROWS).map(|row| self.line(row)).collect::<Vec<String>>().join("\n")
+    }
+}
+
+/// As a `Device` on the bus, register bit 0 is RS. The busy flag only
+/// shows through reads, so there is no `next_event`, and like the chip it
+/// ignores RESET.
+impl Device for Hd44780 {
+    fn read(&mut self, register: u16) -> u8 {
+        Hd44780::read(self, register & 1 != 0)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        Hd44780::write(self, register & 1 != 0, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Hd44780::tick(self, cycles)
+    }
+}
+
+/// The A00 character ROM: ASCII apart from yen and the arrows, with the
+/// Japanese half of the set shown as `?`.
+fn glyph(code: u8) -> char {
+    match code {
+        0x00..=0x0f => '#',
+        0x5c => '\u{a5}',
+        0x7e => '\u{2192}',
+        0x7f => '\u{2190}',
+        0x20..=0x7d => code as char,
+        0xa0 => ' ',
+        _ => '?'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lcd() -> Hd44780 {
+        let mut lcd = Hd44780::new(1_000_000);
+        // 8-bit, two lines; display on; increment
+        lcd.write(false, FUNCTION_SET | 0x18);
+        lcd.write(false, DISPLAY_CONTROL | 0x04);
+        lcd.write(false, ENTRY_MODE | 0x02);
+        lcd.write(false, CLEAR);
+        lcd
+    }
+
+    #[test]
+    fn writes_text() {
+        let mut lcd = lcd();
+        assert_eq!(lcd.read(false) & BUSY, BUSY);
+        lcd.tick(1520);
+        assert_eq!(lcd.read(false), 0x00);
+        for b in "Hello".bytes() {
+            lcd.write(true, b);
+        }
+        lcd.write(false, SET_DDRAM | 0x40);
+        lcd.write(true, b'~');
+        assert_eq!(lcd.line(0), "Hello           ");
+        assert_eq!(lcd.line(1), "\u{2192}               ");
+        assert_eq!(lcd.read(false) & 0x7f, 0x41);
+
+        lcd.write(false, SET_DDRAM | 0x27);
+        lcd.write(true, b'!');
+        assert_eq!(lcd.address(), 0x40);
+        lcd.write(false, SHIFT | 0x0c);
+        assert!(lcd.line(0).starts_with("!Hello"));
+    }
+
+    #[test]
+    fn four_bit_mode() {
+        let mut lcd = lcd();
+        lcd.write(false, FUNCTION_SET);
+        // now two nibbles per transfer
+        lcd.write(false, (FUNCTION_SET | 0x08) & 0xf0);
+        lcd.write(false, (FUNCTION_SET | 0x08) << 4);
+        lcd.write(true, b'A' & 0xf0);
+        assert_eq!(lcd.line(0), " ".repeat(COLUMNS));
+        lcd.write(true, b'A' << 4);
+        assert_eq!(lcd.line(0).trim_end(), "A");
+
+        lcd.write(false, SET_DDRAM & 0xf0);
+        lcd.write(false, SET_DDRAM << 4);
+        lcd.tick(100);
+        assert_eq!(lcd.read(true), b'A' & 0xf0);
+        assert_eq!(lcd.read(true), b'A' << 4);
+        assert_eq!(lcd.address(), 1);
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use cpu::MOS6502;
+use terminal;
+use terminal::KeyEvent;
+
+const CR : u8 = 0x8d;
+
+/// Types text into the machine's keyboard one key at a time, only handing
+/// over the next key once the program has read the previous one. After each
+/// Return it can pause for a number of cycles and/or until the display shows
+/// a prompt again, so pasted programs are not dropped while BASIC is busy.
+pub struct Injector {
+    keys: VecDeque<u8>,
+    line_delay: u64,
+    prompt: Option<String>,
+    resume_at: u64,
+    awaiting_prompt: Option<u64>
+}
+
+impl Injector {
+    pub fn new() -> Injector {
+        Injector {
+            keys: VecDeque::new(),
+            line_delay: 0,
+            prompt: None,
+            resume_at: 0,
+            awaiting_prompt: None
+        }
+    }
+
+    /// Queues text using the same key mapping as the terminal: line ends
+    /// become Return and letters are upper case.
+    pub fn push_text(&mut self, text: &str) {
+        for event in terminal::translate(text.as_bytes()) {
+            if let KeyEvent::Key(key) = event {
+                self.keys.push_back(key);
+            }
+        }
+    }
+
+    /// Cycles to wait after each Return.
+    pub fn set_line_delay(&mut self, cycles: u64) {
+        self.line_delay = cycles;
+    }
+
+    /// Text the current display line must end with before the next line is typed.
+    pub fn set_prompt(&mut self, prompt: Option<String>) {
+        self.prompt = prompt.map(|p| p.to_ascii_uppercase());
+    }
+
+    pub fn pending(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn prompt_seen(&self, cpu: &MOS6502, since: u64) -> bool {
+        let prompt = match self.prompt {
+            Some(ref prompt) => prompt,
+            None => return true
+        };
+        match cpu.platform().display() {
+            Some(display) => {
+                // untrimmed, so prompts ending in a space match
+                let (column, row) = display.cursor();
+                display.output_count() > since && display.row(row)[..column].ends_with(prompt.as_str())
+            }
+            // callers only set a prompt on machines with a display
+            None => true
+        }
+    }
+
+    /// Hands the next key to the machine if it is ready for one. Returns true
+    /// when a key was sent.
+    pub fn poll(&mut self, cpu: &mut MOS6502) -> bool {
+        if self.keys.is_empty() || cpu.get_total_cycles() < self.resume_at {
+            return false;
+        }
+        if let Some(since) = self.awaiting_prompt {
+            if !self.prompt_seen(cpu, since) {
+                return false;
+            }
+            self.awaiting_prompt = None;
+        }
+        if !cpu.key_ready() {
+            return false;
+        }
+
+        let key = self.keys.pop_front().unwrap();
+        cpu.key_pressed(key);
+        if key == CR {
+            self.resume_at = cpu.get_total_cycles() + self.line_delay;
+            if self.prompt.is_some() {
+                let count = cpu.platform().display().map_or(0, |d| d.output_count());
+                self.awaiting_prompt = Some(count);
+            }
+        }
+        true
+    }
+}
+
+impl Default for Injector {
+    fn default() -> Injector {
+        Injector::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apple1::{Apple1, KBD, KBDCR, DSP};
+    use platform::Platform;
+
+    fn machine() -> MOS6502 {
+        let mut apple1 = Apple1::new();
+        apple1.load(Vec::new(), 0);
+        MOS6502::new(Box::new(apple1))
+    }
+
+    #[test]
+    fn waits_for_key_to_be_read() {
+        let mut cpu = machine();
+        let mut injector = Injector::new();
+        injector.push_text("ab");
+        assert!(injector.poll(&mut cpu));
+        assert!(!injector.poll(&mut cpu));
+        assert_eq!(cpu.read_u8(KBD), 0xc1);
+        assert_eq!(cpu.read_u8(KBDCR) & 0x80, 0);
+        assert!(injector.poll(&mut cpu));
+        assert!(injector.is_done());
+    }
+
+    #[test]
+    fn waits_for_prompt() {
+        let mut cpu = machine();
+        let mut injector = Injector::new();
+        injector.set_prompt(Some(String::from(">")));
+        injector.push_text("1\n2\n");
+        assert!(injector.poll(&mut cpu));
+        cpu.read_u8(KBD);
+        assert!(injector.poll(&mut cpu));
+        cpu.read_u8(KBD);
+        assert!(!injector.poll(&mut cpu));
+        cpu.write_u8(DSP, b'>' | 0x80);
+        assert!(injector.poll(&mut cpu));
+        assert_eq!(injector.pending(), 1);
+    }
+
+    #[test]
+    fn prompt_with_trailing_space() {
+        let mut cpu = machine();
+        let mut injector = Injector::new();
+        injector.set_prompt(Some(String::from("> ")));
+        injector.push_text("1\n2");
+        assert!(injector.poll(&mut cpu));
+        cpu.read_u8(KBD);
+        assert!(injector.poll(&mut cpu));
+        cpu.read_u8(KBD);
+        cpu.write_u8(DSP, b'>' | 0x80);
+        assert!(!injector.poll(&mut cpu));
+        cpu.write_u8(DSP, b' ' | 0x80);
+        assert!(injector.poll(&mut cpu));
+    }
+
+    #[test]
+    fn line_delay() {
+        let mut cpu = machine();
+        let mut injector = Injector::new();
+        injector.set_line_delay(1_000);
+        injector.push_text("\nA");
+        assert!(injector.poll(&mut cpu));
+        cpu.read_u8(KBD);
+        assert!(!injector.poll(&mut cpu));
+    }
+}
@@ -0,0 +1,529 @@
+mod tty;
+
+pub use self::tty::Tty;
+
+use platform::Platform;
+use console::{OutputSink, RawSink};
+use display::Display;
+use pia::Port;
+use riot::Riot;
+use device::{Line, Scheduler};
+
+pub const RAM_SIZE : usize = 0x400;
+pub const ROM_SIZE : usize = 0x400;
+
+/// The 6530s' I/O and timer registers, 64 bytes each.
+pub const U3_IO : u16 = 0x1700;
+pub const U2_IO : u16 = 0x1740;
+/// The 6530s' RAM, 64 bytes each.
+pub const U3_RAM : u16 = 0x1780;
+pub const U2_RAM : u16 = 0x17c0;
+/// The 6530-003 ROM holds the cassette routines, the 6530-002 ROM the
+/// monitor with the reset and interrupt vectors.
+pub const ROM_003 : u16 = 0x1800;
+pub const ROM_002 : u16 = 0x1c00;
+
+/// Keys without an ASCII equivalent, as sent by the host terminal.
+pub const KEY_AD : u8 = 0x01;
+pub const KEY_DA : u8 = 0x04;
+pub const KEY_PC : u8 = 0x10;
+pub const KEY_ST : u8 = 0x13;
+pub const KEY_GO : u8 = 0x0d;
+
+/// The keypad matrix: PA6 down to PA0 on rows 0-2 of the U2 port B decoder.
+const KEYPAD : [[u8; 7]; 3] = [
+    [b'0', b'1', b'2', b'3', b'4', b'5', b'6'],
+    [b'7', b'8', b'9', b'A', b'B', b'C', b'D'],
+    [b'E', b'F', KEY_AD, KEY_DA, b'+', KEY_GO, KEY_PC]
+];
+
+/// Cycles a key stays down, then up before the next one; the monitor
+/// debounces for a few milliseconds and waits for the release.
+const KEY_DOWN_CYCLES : u32 = 40_000;
+const KEY_UP_CYCLES : u32 = 20_000;
+
+/// Seven-segment patterns for the hex digits, segment a in bit 0.
+const SEGMENTS : [u8; 16] = [
+    0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07,
+    0x7f, 0x6f, 0x77, 0x7c, 0x39, 0x5e, 0x79, 0x71];
+
+/// Teletype bit time in cycles, 1200 baud at 1 MHz.
+pub const TTY_BIT_CYCLES : u64 = 833;
+
+const RUBOUT : u8 = 0x7f;
+
+/// The character a seven-segment pattern shows: a hex digit, blank, or `?`.
+pub fn led_char(segments: u8) -> char {
+    match SEGMENTS.iter().position(|&pattern| pattern == segments & 0x7f) {
+        Some(digit) => std::char::from_digit(digit as u32, 16).unwrap().to_ascii_uppercase(),
+        None if segments & 0x7f == 0 => ' ',
+        None => '?'
+    }
+}
+
+/// Where a key sits in the matrix, as (row, PA bit).
+fn keypad_position(key: u8) -> Option<(u8, u8)> {
+    KEYPAD.iter().enumerate().find_map(|(row, keys)| {
+        keys.iter().position(|&k| k == key).map(|column| (row as u8, 6 - column as u8))
+    })
+}
+
+/// MOS KIM-1: 1K of RAM at $0000, the 6530-002 (U2) and 6530-003 (U3) with
+/// their ROM, RAM and I/O at $1700-$1FFF, and the rest of the 8K space
+/// mirrored through the 64K. Each 6530 is a 6532 with the RAM cut to 64
+/// bytes and no PA7 edge detect.
+///
+/// U2's port A drives the LED segments and reads the keypad, and PB1-PB4
+/// select a digit or keypad row through a decoder. The display is kept as
+/// the last pattern each digit was lit with and printed as "AAAA DD" when
+/// it changes. In TTY mode a jumper ties PA0 to decoder output 3, which
+/// the monitor checks at reset, and a teletype on PA7 (in) and PB0 (out)
+/// replaces the keypad.
+///
+/// Both 6530s run on a `Scheduler`. U3's PB7, which the 6530 drives low
+/// for its timer interrupt, is jumpered to IRQ on the application
+/// connector.
+pub struct Kim1 {
+    ram: [u8; RAM_SIZE],
+    rom_002: [u8; ROM_SIZE],
+    rom_003: [u8; ROM_SIZE],
+    scheduler: Scheduler,
+    u2: usize,
+    u3: usize,
+    leds: [u8; 6],
+    rendered: String,
+    key: Option<(u8, u8)>,
+    key_cycles: u32,
+    nmi: bool,
+    tty: Option<Tty>,
+    display: Display,
+    output: Box<dyn OutputSink>
+}
+
+impl Kim1 {
+    pub fn new() -> Kim1 {
+        let mut scheduler = Scheduler::new();
+        let u2 = scheduler.add(Box::new(Riot::new()), Line::None);
+        let u3 = scheduler.add(Box::new(Riot::new()), Line::Irq);
+        Kim1 {
+            ram: [0; RAM_SIZE],
+            rom_002: [0; ROM_SIZE],
+            rom_003: [0; ROM_SIZE],
+            scheduler,
+            u2,
+            u3,
+            leds: [0; 6],
+            rendered: String::new(),
+            key: None,
+            key_cycles: 0,
+            nmi: false,
+            tty: None,
+            display: Display::new(),
+            output: Box::new(RawSink::stdout())
+        }
+    }
+
+    /// Installs the 6530-002 monitor ROM.
+    pub fn set_rom_002(&mut self, data: &[u8]) {
+        let len = data.len().min(ROM_SIZE);
+        self.rom_002[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Installs the 6530-003 cassette ROM.
+    pub fn set_rom_003(&mut self, data: &[u8]) {
+        let len = data.len().min(ROM_SIZE);
+        self.rom_003[..len].copy_from_slice(&data[..len]);
+    }
+
+    /// Fits the TTY jumper and connects a teletype.
+    pub fn attach_tty(&mut self, tty: Tty) {
+        // the monitor's DETCPS times the start bit of the first RUBOUT
+        let mut tty = tty;
+        tty.send(RUBOUT);
+        self.tty = Some(tty);
+        self.update_inputs();
+    }
+
+    /// Sends the LED display and teletype output somewhere other than
+    /// stdout.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    pub fn u2(&self) -> &Riot {
+        self.scheduler.device(self.u2)
+    }
+
+    /// The segment patterns last shown on the six digits, left to right.
+    pub fn leds(&self) -> [u8; 6] {
+        self.leds
+    }
+
+    /// The display as address and data, e.g. "1C00 D8".
+    pub fn led_text(&self) -> String {
+        let digits : String = self.leds.iter().map(|&segments| led_char(segments)).collect();
+        format!("{} {}", &digits[..4], &digits[4..])
+    }
+
+    fn select(&self) -> u8 {
+        (self.u2().peek(::riot::ORB) >> 1) & 0x0f
+    }
+
+    /// Drives U2's port A from the keypad row the decoder selects, the
+    /// TTY jumper and the teletype line.
+    fn update_inputs(&mut self) {
+        let select = self.select();
+        let mut input = 0xff;
+        match self.key {
+            Some((row, bit)) if row == select && self.key_cycles > KEY_UP_CYCLES => input &= !(1 << bit),
+            _ => {}
+        }
+        if let Some(ref tty) = self.tty {
+            if select == 3 {
+                input &= 0xfe;
+            }
+            if !tty.level() {
+                input &= 0x7f;
+            }
+        }
+        self.scheduler.with(self.u2, |u2: &mut Riot| u2.set_input(Port::A, input));
+    }
+
+    /// Picks up LED segments and teletype output after U2's ports change.
+    fn update_outputs(&mut self) {
+        let select = self.select() as usize;
+        let segments = self.u2().output(Port::A) & 0x7f;
+        if (4..=9).contains(&select) && segments != 0 && self.leds[select - 4] != segments {
+            self.leds[select - 4] = segments;
+            if self.tty.is_none() {
+                self.render();
+            }
+        }
+        if let Some(ref mut tty) = self.tty {
+            // undriven pins float high
+            tty.set_line(self.scheduler.device::<Riot>(self.u2).peek(::riot::ORB) & 0x01 != 0);
+        }
+    }
+
+    fn render(&mut self) {
+        let text = self.led_text();
+        if text != self.rendered {
+            self.output.output(0x0d);
+            for byte in text.bytes() {
+                self.output.output(byte);
+            }
+            self.output.flush();
+            self.rendered = text;
+        }
+    }
+
+    fn print_tty(&mut self) {
+        let tty = match self.tty {
+            Some(ref mut tty) => tty,
+            None => return
+        };
+        while let Some(byte) = tty.receive() {
+            let ch = byte & 0x7f;
+            if ch == 0 || ch == RUBOUT {
+                continue;
+            }
+            self.display.output(ch);
+            if ch != 0x0d {
+                self.output.output(ch);
+            }
+        }
+        self.output.flush();
+    }
+}
+
+impl Default for Kim1 {
+    fn default() -> Kim1 {
+        Kim1::new()
+    }
+}
+
+/// A 6530 register from the low address bits. Timer writes need A4 on a
+/// 6532, which the 6530 does not decode.
+fn riot_register(address: u16, write: bool) -> u16 {
+    let register = address & 0x0f;
+    if write && register & 0x04 != 0 {
+        0x14 | (register & 0x0b)
+    } else {
+        register
+    }
+}
+
+impl Platform for Kim1 {
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address & 0x1fff {
+            a @ 0x0000..=0x03ff => self.ram[a as usize],
+            a @ 0x1700..=0x173f => self.scheduler.read(self.u3, riot_register(a, false)),
+            a @ 0x1740..=0x177f => {
+                self.update_inputs();
+                self.scheduler.read(self.u2, riot_register(a, false))
+            }
+            a @ 0x1780..=0x17bf => self.scheduler.read(self.u3, 0x80 | (a & 0x3f)),
+            a @ 0x17c0..=0x17ff => self.scheduler.read(self.u2, 0x80 | (a & 0x3f)),
+            a @ 0x1800..=0x1bff => self.rom_003[(a - ROM_003) as usize],
+            a @ 0x1c00..=0x1fff => self.rom_002[(a - ROM_002) as usize],
+            _ => (address >> 8) as u8
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address & 0x1fff {
+            a @ 0x0000..=0x03ff => self.ram[a as usize] = value,
+            a @ 0x1700..=0x173f => self.scheduler.write(self.u3, riot_register(a, true), value),
+            a @ 0x1740..=0x177f => {
+                self.scheduler.write(self.u2, riot_register(a, true), value);
+                self.update_outputs();
+            }
+            a @ 0x1780..=0x17bf => self.scheduler.write(self.u3, 0x80 | (a & 0x3f), value),
+            a @ 0x17c0..=0x17ff => self.scheduler.write(self.u2, 0x80 | (a & 0x3f), value),
+            _ => {}
+        }
+    }
+
+    /// Like a write, but also patches the ROMs, e.g. for --reset-vector.
+    fn poke(&mut self, address: u16, value: u8) {
+        match address & 0x1fff {
+            a @ 0x1800..=0x1bff => self.rom_003[(a - ROM_003) as usize] = value,
+            a @ 0x1c00..=0x1fff => self.rom_002[(a - ROM_002) as usize] = value,
+            _ => self.write(address, value)
+        }
+    }
+
+    fn load(&mut self, program: Vec<u8>, address: u16) {
+        self.ram = [0; RAM_SIZE];
+        self.display.clear();
+        for (i, &byte) in program.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.scheduler.reset();
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.scheduler.advance(cycles);
+        if self.key.is_some() {
+            self.key_cycles = self.key_cycles.saturating_sub(cycles);
+            if self.key_cycles == 0 {
+                self.key = None;
+            }
+        }
+        if let Some(ref mut tty) = self.tty {
+            tty.tick(cycles);
+        }
+        self.print_tty();
+    }
+
+    fn display(&self) -> Option<&Display> {
+        Some(&self.display)
+    }
+
+    fn key_ready(&self) -> bool {
+        match self.tty {
+            Some(ref tty) => tty.is_idle(),
+            None => self.key.is_none()
+        }
+    }
+
+    /// On the keypad, Ctrl-A, Ctrl-D and Ctrl-P are AD, DA and PC, Return
+    /// is GO and space is +. Ctrl-S is ST, which pulls NMI in either mode.
+    fn key_pressed(&mut self, key: u8) {
+        let key = key & 0x7f;
+        if key == KEY_ST {
+            self.nmi = true;
+            return;
+        }
+        if let Some(ref mut tty) = self.tty {
+            match key {
+                0x0a => {}
+                // the host terminal sends backspace as _
+                b'_' => tty.send(RUBOUT),
+                _ => tty.send(key)
+            }
+            return;
+        }
+        let key = if key == b' ' { b'+' } else { key };
+        if let Some(position) = keypad_position(key) {
+            self.key = Some(position);
+            self.key_cycles = KEY_DOWN_CYCLES + KEY_UP_CYCLES;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.scheduler.irq()
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        let nmi = self.nmi;
+        self.nmi = false;
+        nmi
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riot::{ORA, DDRA, ORB, DDRB};
+    use cpu::MOS6502;
+    use console::Buffer;
+
+    const SAD : u16 = U2_IO + ORA;
+    const PADD : u16 = U2_IO + DDRA;
+    const SBD : u16 = U2_IO + ORB;
+    const PBDD : u16 = U2_IO + DDRB;
+
+    #[test]
+    fn memory_map() {
+        let mut kim = Kim1::new();
+        let mut rom = [0xea; ROM_SIZE];
+        rom[0x3fc] = 0x22;
+        rom[0x3fd] = 0x1c;
+        kim.set_rom_002(&rom);
+        kim.write(0x0200, 0x55);
+        kim.write(0x17c5, 0x66);
+        kim.write(0x1c00, 0x00);
+        assert_eq!(kim.read(0x2200), 0x55);
+        assert_eq!(kim.read(0x17c5), 0x66);
+        assert_eq!(kim.read(0x17c5 + 0x40), 0);
+        assert_eq!(kim.read(0xfffc), 0x22);
+        assert_eq!(kim.read(0x1c00), 0xea);
+        assert_eq!(kim.read(0x0400), 0x04);
+
+        // the 6530 timer is written without A4
+        kim.write(U3_IO + 0x04, 10);
+        assert_eq!(kim.read(U3_IO + 0x06), 10);
+    }
+
+    /// Lights each digit with the monitor's port setup.
+    fn show(kim: &mut Kim1, digits: &[u8; 6]) {
+        kim.write(PADD, 0x7f);
+        kim.write(PBDD, 0x1e);
+        for (i, &digit) in digits.iter().enumerate() {
+            kim.write(SAD, 0);
+            kim.write(SBD, (4 + i as u8) << 1);
+            kim.write(SAD, SEGMENTS[digit as usize]);
+        }
+    }
+
+    #[test]
+    fn leds() {
+        let output = Buffer::new();
+        let mut kim = Kim1::new();
+        kim.set_output(Box::new(output.clone()));
+        assert_eq!(kim.led_text(), "       ");
+        show(&mut kim, &[1, 0xc, 0, 0, 0xd, 8]);
+        assert_eq!(kim.led_text(), "1C00 D8");
+        assert!(output.contents().ends_with("\n1C00 D8"));
+        assert_eq!(kim.leds()[5], 0x7f);
+        assert_eq!(led_char(0x40), '?');
+    }
+
+    #[test]
+    fn keypad() {
+        let mut kim = Kim1::new();
+        kim.write(PADD, 0x00);
+        kim.write(PBDD, 0x1e);
+        assert!(kim.key_ready());
+        kim.key_pressed(b'B' | 0x80);
+        assert!(!kim.key_ready());
+        kim.write(SBD, 1 << 1);
+        assert_eq!(kim.read(SAD), 0xfb);
+        kim.write(SBD, 0);
+        assert_eq!(kim.read(SAD), 0xff);
+
+        // released, then ready for the next key
+        kim.tick(KEY_DOWN_CYCLES);
+        kim.write(SBD, 1 << 1);
+        assert_eq!(kim.read(SAD), 0xff);
+        kim.tick(KEY_UP_CYCLES);
+        assert!(kim.key_ready());
+
+        kim.key_pressed(KEY_GO | 0x80);
+        kim.write(SBD, 2 << 1);
+        assert_eq!(kim.read(SAD), 0xfd);
+        assert!(!kim.take_nmi());
+    }
+
+    #[test]
+    fn stop_key() {
+        let mut kim = Kim1::new();
+        let mut rom = [0xea; ROM_SIZE];
+        // NMI to $1C00, reset to $1C10
+        rom[0x3fa] = 0x00;
+        rom[0x3fb] = 0x1c;
+        rom[0x3fc] = 0x10;
+        rom[0x3fd] = 0x1c;
+        kim.set_rom_002(&rom);
+        let mut cpu = MOS6502::new(Box::new(kim));
+        cpu.reset();
+        assert_eq!(cpu.get_pc(), 0x1c10);
+        cpu.step();
+        cpu.key_pressed(KEY_ST | 0x80);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x1c00);
+    }
+
+    #[test]
+    fn brk_and_timer_irq() {
+        let mut kim = Kim1::new();
+        let mut rom = [0xea; ROM_SIZE];
+        // the monitor's IRQ entry at $1FFE jumps through $17FE
+        rom[0x3fe] = 0x00;
+        rom[0x3ff] = 0x1c;
+        rom[0x000] = 0x6c;
+        rom[0x001] = 0xfe;
+        rom[0x002] = 0x17;
+        kim.set_rom_002(&rom);
+        // BRK; the handler at $0300 is a lone RTI
+        kim.write(0x0200, 0x00);
+        kim.write(0x0300, 0x40);
+        kim.write(0x17fe, 0x00);
+        kim.write(0x17ff, 0x03);
+        let mut cpu = MOS6502::new(Box::new(kim));
+        cpu.reset();
+        cpu.set_brk_halts(false);
+        cpu.set_pc(0x0200);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0300);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x0202);
+
+        // U3's timer, divide by 1 with the interrupt on
+        cpu.platform_mut().write(U3_IO + 0x0c, 4);
+        assert!(!cpu.platform().irq());
+        cpu.platform_mut().tick(5);
+        assert!(cpu.platform().irq());
+    }
+
+    #[test]
+    fn tty() {
+        let output = Buffer::new();
+        let mut kim = Kim1::new();
+        kim.set_output(Box::new(output.clone()));
+        kim.attach_tty(Tty::new(TTY_BIT_CYCLES));
+        kim.write(PBDD, 0x1f);
+        kim.write(SBD, 3 << 1 | 0x01);
+        // the jumper, then the RUBOUT's start bit
+        assert_eq!(kim.read(SAD) & 0x01, 0);
+        kim.tick(1);
+        assert_eq!(kim.read(SAD) & 0x80, 0);
+        kim.tick(11 * TTY_BIT_CYCLES as u32);
+        assert!(kim.key_ready());
+
+        // send "K" from PB0
+        let frame = (b'K' as u16) << 1 | 0x600;
+        for bit in 0..11 {
+            kim.write(SBD, 3 << 1 | ((frame >> bit) & 1) as u8);
+            kim.tick(TTY_BIT_CYCLES as u32);
+        }
+        assert_eq!(kim.display().unwrap().line(0), "K");
+        assert_eq!(output.contents(), "K");
+    }
+}
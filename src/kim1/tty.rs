@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+
+/// The far end of the KIM-1's bit-banged teletype port. The monitor sends
+/// on PB0 and receives on PA7 with software timing, so this side works at
+/// the bit level too: it shifts queued bytes out as 8N2 frames and samples
+/// the printer side in the middle of each bit. The monitor measures the
+/// bit time from the first RUBOUT it receives, so any rate works.
+///
+/// Keyboard and printer share one current loop, so the printer sees the
+/// KIM's output ANDed with the keyboard's and typed characters echo without
+/// the monitor's help.
+pub struct Tty {
+    bit_cycles: u64,
+    now: u64,
+    queue: VecDeque<u8>,
+    tx_frame: u16,
+    tx_bits: u8,
+    tx_timer: u64,
+    line: bool,
+    printer: bool,
+    rx_sample: Option<u64>,
+    rx_byte: u8,
+    rx_bits: u8,
+    received: VecDeque<u8>
+}
+
+impl Tty {
+    pub fn new(bit_cycles: u64) -> Tty {
+        Tty {
+            bit_cycles,
+            now: 0,
+            queue: VecDeque::new(),
+            tx_frame: 0,
+            tx_bits: 0,
+            tx_timer: 0,
+            line: true,
+            printer: true,
+            rx_sample: None,
+            rx_byte: 0,
+            rx_bits: 0,
+            received: VecDeque::new()
+        }
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        self.queue.push_back(byte);
+    }
+
+    /// Whether everything queued has been sent.
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.tx_bits == 0
+    }
+
+    /// The level on the KIM's input, high while idle.
+    pub fn level(&self) -> bool {
+        self.tx_bits == 0 || self.tx_frame & 1 != 0
+    }
+
+    /// The KIM's output changed.
+    pub fn set_line(&mut self, level: bool) {
+        self.line = level;
+        self.update_printer();
+    }
+
+    pub fn receive(&mut self) -> Option<u8> {
+        self.received.pop_front()
+    }
+
+    /// A falling edge on the printer side while idle is a start bit.
+    fn update_printer(&mut self) {
+        let printer = self.line && self.level();
+        if self.printer && !printer && self.rx_sample.is_none() {
+            self.rx_sample = Some(self.now + self.bit_cycles * 3 / 2);
+            self.rx_bits = 0;
+            self.rx_byte = 0;
+        }
+        self.printer = printer;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let end = self.now + cycles as u64;
+        loop {
+            if let Some(sample) = self.rx_sample.filter(|&sample| sample <= self.now) {
+                self.rx_byte |= (self.printer as u8) << self.rx_bits;
+                self.rx_bits += 1;
+                if self.rx_bits == 8 {
+                    self.received.push_back(self.rx_byte);
+                    self.rx_sample = None;
+                } else {
+                    self.rx_sample = Some(sample + self.bit_cycles);
+                }
+                continue;
+            }
+            if self.tx_bits == 0 {
+                if let Some(byte) = self.queue.pop_front() {
+                    // start bit, eight data bits from bit 0, two stop bits
+                    self.tx_frame = (byte as u16) << 1 | 0x600;
+                    self.tx_bits = 11;
+                    self.tx_timer = self.bit_cycles;
+                    self.update_printer();
+                }
+            }
+            if self.now >= end {
+                break;
+            }
+
+            let mut step = end - self.now;
+            if self.tx_bits > 0 {
+                step = step.min(self.tx_timer);
+            }
+            if let Some(sample) = self.rx_sample {
+                step = step.min(sample - self.now);
+            }
+            self.now += step;
+            if self.tx_bits > 0 {
+                self.tx_timer -= step;
+                if self.tx_timer == 0 {
+                    self.tx_frame >>= 1;
+                    self.tx_bits -= 1;
+                    self.tx_timer = self.bit_cycles;
+                    self.update_printer();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_frames() {
+        let mut tty = Tty::new(10);
+        tty.send(0x41);
+        let mut levels = Vec::new();
+        for _ in 0..12 {
+            tty.tick(0);
+            levels.push(tty.level());
+            tty.tick(10);
+        }
+        // 0x41 is 1000 0010 read from bit 0
+        let bits : Vec<bool> = [0, 1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1].iter().map(|&b| b == 1).collect();
+        assert_eq!(levels, bits);
+        assert!(tty.is_idle());
+        // the printer echoes the keyboard
+        assert_eq!(tty.receive(), Some(0x41));
+    }
+
+    #[test]
+    fn receives_frames() {
+        let mut tty = Tty::new(10);
+        tty.tick(5);
+        for (i, &bit) in [0, 1, 1, 0, 1, 0, 0, 1, 0, 1].iter().enumerate() {
+            // the KIM's timing drifts a little from ours
+            tty.set_line(bit == 1);
+            tty.tick(if i % 2 == 0 { 9 } else { 11 });
+        }
+        assert_eq!(tty.receive(), Some(0x4b));
+        assert_eq!(tty.receive(), None);
+    }
+}
@@ -1,3 +1,31 @@
 pub mod cpu;
 pub mod platform;
-pub mod apple1;
\ No newline at end of file
+pub mod device;
+pub mod apple1;
+pub mod symbols;
+pub mod profiler;
+pub mod listing;
+pub mod coverage;
+pub mod loader;
+pub mod cli;
+pub mod clock;
+pub mod display;
+pub mod terminal;
+#[cfg(unix)]
+pub mod termios;
+pub mod console;
+pub mod injector;
+pub mod runner;
+pub mod aci;
+pub mod cffa1;
+pub mod profile;
+pub mod pia;
+pub mod via;
+pub mod acia;
+pub mod riot;
+pub mod kim1;
+pub mod hd44780;
+pub mod breadboard;
+pub mod osi;
+pub mod sim65;
+pub mod board;
\ No newline at end of file
@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+/// One line of an assembler listing. `address` and `bytes` are only present
+/// for lines that emitted code or data.
+pub struct ListingLine {
+    pub number: usize,
+    pub text: String,
+    pub address: Option<u16>,
+    pub bytes: Vec<u8>
+}
+
+/// Assembler listing in the `C000   A0 04      LDY #$04` layout used by ehbasic.asm.
+pub struct Listing {
+    pub filename: String,
+    pub lines: Vec<ListingLine>
+}
+
+impl Listing {
+    pub fn from_file(filename: &str) -> io::Result<Listing> {
+        let mut f = File::open(filename)?;
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+        Ok(Listing::parse(filename, &text))
+    }
+
+    pub fn parse(filename: &str, text: &str) -> Listing {
+        let lines = text.lines().enumerate().map(|(i, line)| {
+            let (address, bytes) = parse_line(line);
+            ListingLine {
+                number: i + 1,
+                text: line.to_string(),
+                address,
+                bytes
+            }
+        }).collect();
+        Listing {
+            filename: filename.to_string(),
+            lines
+        }
+    }
+
+    pub fn line_for(&self, address: u16) -> Option<&ListingLine> {
+        self.lines.iter().find(|line| line.address == Some(address))
+    }
+}
+
+fn is_hex(token: &str, len: usize) -> bool {
+    token.len() == len && token.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn parse_line(line: &str) -> (Option<u16>, Vec<u8>) {
+    let mut tokens = line.split_whitespace();
+    let address = match tokens.next() {
+        Some(token) if is_hex(token, 4) => u16::from_str_radix(token, 16).unwrap(),
+        _ => return (None, Vec::new())
+    };
+    let bytes = tokens
+        .take_while(|token| is_hex(token, 2))
+        .map(|token| u8::from_str_radix(token, 16).unwrap())
+        .collect();
+    (Some(address), bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let listing = Listing::parse("test.asm", "* = C000\nC000   A0 04      LDY #$04\nC002   B9 F6 E0   LDA $E0F6,Y\n");
+        assert_eq!(listing.lines.len(), 3);
+        assert_eq!(listing.lines[0].address, None);
+        assert_eq!(listing.lines[1].address, Some(0xc000));
+        assert_eq!(listing.lines[1].bytes, vec![0xa0, 0x04]);
+        assert_eq!(listing.line_for(0xc002).unwrap().number, 3);
+        assert_eq!(listing.line_for(0xc002).unwrap().bytes, vec![0xb9, 0xf6, 0xe0]);
+    }
+}
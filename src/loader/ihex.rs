@@ -0,0 +1,87 @@
+use super::{hex_bytes, parse_error, Image, LoadError};
+
+pub fn matches(line: &str) -> bool {
+    line.len() >= 11 && line.starts_with(':') && line[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Intel HEX: data (00), end of file (01), extended segment (02) and linear
+/// (04) addresses, and start segment (03) / start linear (05) entry points.
+pub fn parse(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::new();
+    let mut base : u32 = 0;
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !line.starts_with(':') {
+            return Err(parse_error(number, "record does not start with ':'"));
+        }
+        let bytes = hex_bytes(&line[1..], number)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(parse_error(number, "record length mismatch"));
+        }
+        let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if sum != 0 {
+            return Err(parse_error(number, "checksum mismatch"));
+        }
+        let offset = ((bytes[1] as u32) << 8) | bytes[2] as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => image.push(base + offset, data)?,
+            0x01 => break,
+            0x02 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 4,
+            0x04 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 16,
+            0x03 if data.len() == 4 => {
+                let segment = ((data[0] as u32) << 8) | data[1] as u32;
+                let offset = ((data[2] as u32) << 8) | data[3] as u32;
+                image.entry = Some(entry_point((segment << 4) + offset, number)?);
+            }
+            0x05 if data.len() == 4 => {
+                let address = data.iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+                image.entry = Some(entry_point(address, number)?);
+            }
+            _ => return Err(parse_error(number, "unsupported record type"))
+        }
+    }
+    Ok(image)
+}
+
+fn entry_point(address: u32, line: usize) -> Result<u16, LoadError> {
+    if address > 0xffff {
+        return Err(parse_error(line, "entry point outside the 64K address space"));
+    }
+    Ok(address as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_and_entry() {
+        let image = parse(":0300300002337A1E\n:0400000500000030C7\n:00000001FF\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x0030);
+        assert_eq!(image.segments[0].data, vec![0x02, 0x33, 0x7a]);
+        assert_eq!(image.entry, Some(0x0030));
+    }
+
+    #[test]
+    fn errors_carry_line_numbers() {
+        match parse(":0300300002337A1E\n:020033004C00\n") {
+            Err(LoadError::Parse { line: 2, .. }) => {}
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(parse(":0300300002337A1F\n").is_err());
+    }
+
+    #[test]
+    fn beyond_64k() {
+        assert!(parse(":020000040001F9\n:0100000000FF\n").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+use platform::Platform;
+use cpu::Variant;
+
+mod ihex;
+mod srec;
+mod wozmon;
+mod prg;
+mod o65;
+mod xex;
+mod sim65;
+
+/// A contiguous run of bytes destined for `address`.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub address: u16,
+    pub data: Vec<u8>
+}
+
+/// The result of parsing a program file: one or more segments plus the entry
+/// point when the file format carries one. `c_stack` is the zero-page
+/// address of the C stack pointer and `cpu` the processor, both from sim65
+/// headers. Each `init` entry is the number of segments to load before
+/// calling the routine at its address, for XEX init vectors.
+#[derive(Debug, Default, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    pub entry: Option<u16>,
+    pub c_stack: Option<u8>,
+    pub cpu: Option<Variant>,
+    pub init: Vec<(usize, u16)>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    SRecord,
+    Wozmon,
+    Prg,
+    O65,
+    Xex,
+    Sim65
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Format(String),
+    OutOfRange { address: u32 },
+    NoAddress
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(f, "{}", err),
+            LoadError::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+            LoadError::Format(ref message) => write!(f, "{}", message),
+            LoadError::OutOfRange { address } => write!(f, "address ${:X} is outside the 64K address space", address),
+            LoadError::NoAddress => write!(f, "raw binary needs a load address")
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> LoadError {
+    LoadError::Parse { line, message: message.to_string() }
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image {
+            segments: Vec::new(),
+            entry: None,
+            c_stack: None,
+            cpu: None,
+            init: Vec::new()
+        }
+    }
+
+    /// Appends bytes at `address`, extending the last segment when contiguous
+    /// and no init routine runs in between.
+    pub fn push(&mut self, address: u32, bytes: &[u8]) -> Result<(), LoadError> {
+        if address as usize + bytes.len() > 0x10000 {
+            return Err(LoadError::OutOfRange { address: address + bytes.len() as u32 - 1 });
+        }
+        let init_pending = self.init.last().is_some_and(|&(segments, _)| segments == self.segments.len());
+        if let Some(last) = self.segments.last_mut().filter(|_| !init_pending) {
+            if last.address as usize + last.data.len() == address as usize {
+                last.data.extend_from_slice(bytes);
+                return Ok(());
+            }
+        }
+        self.segments.push(Segment { address: address as u16, data: bytes.to_vec() });
+        Ok(())
+    }
+
+    /// Lowest address covered by the image.
+    pub fn start(&self) -> Option<u16> {
+        self.segments.iter().map(|s| s.address).min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies every segment into the platform's memory, without running
+    /// init routines.
+    pub fn install(&self, platform: &mut dyn Platform) {
+        self.install_segments(platform, 0..self.segments.len());
+    }
+
+    /// Copies the segments in `range` into the platform's memory.
+    pub fn install_segments(&self, platform: &mut dyn Platform, range: Range<usize>) {
+        for segment in &self.segments[range] {
+            for (i, value) in segment.data.iter().enumerate() {
+                platform.poke(segment.address.wrapping_add(i as u16), *value);
+            }
+        }
+    }
+}
+
+/// Guesses the format from the content. Anything without a recognisable
+/// header or text layout is treated as a raw binary.
+pub fn detect(data: &[u8]) -> Format {
+    if data.starts_with(&o65::MAGIC) {
+        return Format::O65;
+    }
+    if data.starts_with(&sim65::MAGIC) {
+        return Format::Sim65;
+    }
+    if xex::matches(data) {
+        return Format::Xex;
+    }
+    let text = match ::std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return Format::Binary
+    };
+    let first = match text.lines().map(|l| l.trim()).find(|l| !l.is_empty()) {
+        Some(line) => line,
+        None => return Format::Binary
+    };
+    if ihex::matches(first) {
+        Format::IntelHex
+    } else if srec::matches(first) {
+        Format::SRecord
+    } else if wozmon::matches(first) {
+        Format::Wozmon
+    } else {
+        Format::Binary
+    }
+}
+
+/// Picks the format from the file extension, falling back to `detect`.
+/// PRG files have no magic number so they are only recognised this way.
+pub fn detect_file(filename: &str, data: &[u8]) -> Format {
+    let extension = Path::new(filename).extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("hex") | Some("ihx") | Some("ihex") => Format::IntelHex,
+        Some("s19") | Some("s28") | Some("s37") | Some("srec") | Some("mot") => Format::SRecord,
+        Some("prg") => Format::Prg,
+        Some("o65") => Format::O65,
+        Some("xex") => Format::Xex,
+        _ => detect(data)
+    }
+}
+
+/// Parses `data` in the given format. `address` is the load address for raw
+/// binaries, overrides the header address of PRG files, and is the relocation
+/// target for o65 files. Other formats carry their own addresses.
+pub fn parse(data: &[u8], format: Format, address: Option<u16>) -> Result<Image, LoadError> {
+    match format {
+        Format::Binary => {
+            let mut image = Image::new();
+            image.push(address.ok_or(LoadError::NoAddress)? as u32, data)?;
+            return Ok(image);
+        }
+        Format::Prg => return prg::parse(data, address),
+        Format::O65 => return o65::parse(data, address),
+        Format::Xex => return xex::parse(data),
+        Format::Sim65 => return sim65::parse(data),
+        _ => {}
+    }
+    let text = match ::std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return Err(parse_error(0, "file is not text"))
+    };
+    match format {
+        Format::IntelHex => ihex::parse(text),
+        Format::SRecord => srec::parse(text),
+        Format::Wozmon => wozmon::parse(text),
+        _ => unreachable!()
+    }
+}
+
+pub fn read_file(filename: &str) -> Result<Vec<u8>, LoadError> {
+    let mut f = File::open(filename)?;
+    let mut buf: Vec<u8> = Vec::new();
+    f.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+pub fn load_file(filename: &str, address: Option<u16>) -> Result<Image, LoadError> {
+    let buf = read_file(filename)?;
+    parse(&buf, detect_file(filename, &buf), address)
+}
+
+fn hex_byte(text: &str, index: usize, line: usize) -> Result<u8, LoadError> {
+    text.get(index * 2..index * 2 + 2)
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| parse_error(line, "invalid hex digits"))
+}
+
+/// Decodes a record body made of hex digit pairs.
+fn hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, LoadError> {
+    if !text.len().is_multiple_of(2) {
+        return Err(parse_error(line, "odd number of hex digits"));
+    }
+    (0..text.len() / 2).map(|i| hex_byte(text, i, line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(detect(b":0300300002337A1E\n:00000001FF\n"), Format::IntelHex);
+        assert_eq!(detect(b"S00600004844521B\nS9030000FC\n"), Format::SRecord);
+        assert_eq!(detect(b"\nC000: A0 04\n"), Format::Wozmon);
+        assert_eq!(detect(&[0xa9, 0x01, 0x00]), Format::Binary);
+        assert_eq!(detect(b"hello"), Format::Binary);
+        assert_eq!(detect(&[0x01, 0x00, 0x6f, 0x36, 0x35, 0x00]), Format::O65);
+        assert_eq!(detect(&[0xff, 0xff, 0x00, 0x20, 0x00, 0x20, 0x00]), Format::Xex);
+        assert_eq!(detect(b"sim65\x02\x00\x00\x00\x02\x00\x02"), Format::Sim65);
+        assert_eq!(detect_file("GAME.PRG", &[0x01, 0x08]), Format::Prg);
+        assert_eq!(detect_file("prog.s19", b""), Format::SRecord);
+    }
+
+    #[test]
+    fn binary() {
+        let image = parse(&[1, 2, 3], Format::Binary, Some(0x4000)).unwrap();
+        assert_eq!(image.segments, vec![Segment { address: 0x4000, data: vec![1, 2, 3] }]);
+        assert_eq!(image.entry, None);
+        assert!(parse(&[1, 2, 3], Format::Binary, Some(0xfffe)).is_err());
+        assert!(parse(&[1, 2, 3], Format::Binary, None).is_err());
+    }
+
+    #[test]
+    fn push_merges_contiguous() {
+        let mut image = Image::new();
+        image.push(0x1000, &[1, 2]).unwrap();
+        image.push(0x1002, &[3]).unwrap();
+        image.push(0x2000, &[4]).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].data, vec![1, 2, 3]);
+        assert_eq!(image.start(), Some(0x1000));
+        assert_eq!(image.len(), 4);
+    }
+}
@@ -0,0 +1,323 @@
+use super::{Image, LoadError};
+
+pub const MAGIC : [u8; 5] = [0x01, 0x00, 0x6f, 0x36, 0x35];
+
+const MODE_65816 : u16 = 0x8000;
+const MODE_PAGED : u16 = 0x4000;
+const MODE_LONG : u16 = 0x2000;
+const MODE_CHAIN : u16 = 0x0400;
+
+const RELOC_WORD : u8 = 0x80;
+const RELOC_HIGH : u8 = 0x40;
+const RELOC_LOW : u8 = 0x20;
+
+const SEG_UNDEFINED : u8 = 0;
+const SEG_ABSOLUTE : u8 = 1;
+const SEG_TEXT : u8 = 2;
+const SEG_DATA : u8 = 3;
+const SEG_BSS : u8 = 4;
+const SEG_ZERO : u8 = 5;
+
+fn error(message: String) -> LoadError {
+    LoadError::Format(message)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        match self.data.get(self.pos) {
+            Some(value) => {
+                self.pos += 1;
+                Ok(*value)
+            }
+            None => Err(error(String::from("o65 file is truncated")))
+        }
+    }
+
+    fn word(&mut self) -> Result<u16, LoadError> {
+        let lo = self.byte()? as u16;
+        let hi = self.byte()? as u16;
+        Ok(lo | (hi << 8))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        if self.data.len() - self.pos < len {
+            return Err(error(String::from("o65 file is truncated")));
+        }
+        let result = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(result)
+    }
+}
+
+/// Where the next file of a chain goes when relocating: its text segment,
+/// and its zero-page segment once an earlier file has placed one.
+struct Placement {
+    text: u16,
+    zero: Option<u16>
+}
+
+/// Loads a 6502 o65 executable or object file, relocating the text segment
+/// to `address` when given. Data and bss follow the text segment; zero page
+/// stays where the file put it. When relocating a chain of files, each one's
+/// segments follow the previous file's, zero page included. The entry point
+/// is the first file's text. Files with unresolved external references are
+/// rejected since there is nothing to link them against.
+pub fn parse(data: &[u8], address: Option<u16>) -> Result<Image, LoadError> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut image = Image::new();
+    let mut placement = address.map(|text| Placement { text, zero: None });
+    loop {
+        let (tbase, mode) = parse_file(&mut reader, &mut image, placement.as_mut())?;
+        if image.entry.is_none() {
+            image.entry = Some(tbase);
+        }
+        if mode & MODE_CHAIN == 0 {
+            return Ok(image);
+        }
+    }
+}
+
+/// Parses one file of a chain into `image`, returning where its text went
+/// and its mode word.
+fn parse_file(reader: &mut Reader, image: &mut Image, placement: Option<&mut Placement>) -> Result<(u16, u16), LoadError> {
+    if !reader.data[reader.pos..].starts_with(&MAGIC) {
+        return Err(error(String::from("missing o65 header")));
+    }
+    reader.pos += MAGIC.len();
+    let version = reader.byte()?;
+    if version != 0 {
+        return Err(error(format!("unsupported o65 version {}", version)));
+    }
+    let mode = reader.word()?;
+    if mode & (MODE_65816 | MODE_LONG) != 0 {
+        return Err(error(String::from("only 16-bit 6502 o65 files are supported")));
+    }
+
+    let tbase = reader.word()?;
+    let tlen = reader.word()?;
+    let dbase = reader.word()?;
+    let dlen = reader.word()?;
+    let bbase = reader.word()?;
+    let blen = reader.word()?;
+    let zbase = reader.word()?;
+    let zlen = reader.word()?;
+    let _stack = reader.word()?;
+
+    loop {
+        let len = reader.byte()? as usize;
+        if len == 0 {
+            break;
+        }
+        if len < 2 {
+            return Err(error(String::from("malformed o65 header option")));
+        }
+        reader.bytes(len - 1)?;
+    }
+
+    let mut text = reader.bytes(tlen as usize)?.to_vec();
+    let mut data_segment = reader.bytes(dlen as usize)?.to_vec();
+
+    let undefined = reader.word()?;
+    for _ in 0..undefined {
+        while reader.byte()? != 0 {}
+    }
+
+    let (new_tbase, new_dbase, new_bbase, new_zbase) = match placement {
+        Some(placement) => {
+            let new_tbase = placement.text;
+            let new_dbase = new_tbase.wrapping_add(tlen);
+            let new_bbase = new_dbase.wrapping_add(dlen);
+            let new_zbase = placement.zero.unwrap_or(zbase);
+            placement.text = new_bbase.wrapping_add(blen);
+            placement.zero = Some(new_zbase.wrapping_add(zlen));
+            (new_tbase, new_dbase, new_bbase, new_zbase)
+        }
+        None => (tbase, dbase, bbase, zbase)
+    };
+    if new_zbase as u32 + zlen as u32 > 0x100 {
+        return Err(error(format!("o65 zero page segment ${:04X}-${:04X} does not fit in zero page",
+            new_zbase, new_zbase as u32 + zlen as u32 - 1)));
+    }
+    let deltas = Deltas {
+        text: new_tbase.wrapping_sub(tbase),
+        data: new_dbase.wrapping_sub(dbase),
+        bss: new_bbase.wrapping_sub(bbase),
+        zero: new_zbase.wrapping_sub(zbase)
+    };
+
+    relocate(reader, &mut text, &deltas, mode & MODE_PAGED != 0)?;
+    relocate(reader, &mut data_segment, &deltas, mode & MODE_PAGED != 0)?;
+
+    let exported = reader.word()?;
+    for _ in 0..exported {
+        while reader.byte()? != 0 {}
+        reader.bytes(3)?;
+    }
+
+    image.push(new_tbase as u32, &text)?;
+    image.push(new_dbase as u32, &data_segment)?;
+    image.push(new_bbase as u32, &vec![0; blen as usize])?;
+    Ok((new_tbase, mode))
+}
+
+/// How far each segment moved.
+struct Deltas {
+    text: u16,
+    data: u16,
+    bss: u16,
+    zero: u16
+}
+
+fn relocate(reader: &mut Reader, segment: &mut [u8], deltas: &Deltas, paged: bool) -> Result<(), LoadError> {
+    let mut offset : isize = -1;
+    loop {
+        let step = reader.byte()?;
+        match step {
+            0 => return Ok(()),
+            255 => {
+                offset += 254;
+                continue;
+            }
+            _ => offset += step as isize
+        }
+
+        let kind = reader.byte()?;
+        let segment_id = kind & 0x0f;
+        let delta = match segment_id {
+            SEG_UNDEFINED => return Err(error(String::from("o65 file has unresolved external references"))),
+            SEG_ABSOLUTE => 0,
+            SEG_TEXT => deltas.text,
+            SEG_DATA => deltas.data,
+            SEG_BSS => deltas.bss,
+            SEG_ZERO => deltas.zero,
+            _ => return Err(error(format!("unknown o65 segment {}", segment_id)))
+        };
+
+        let at = offset as usize;
+        let in_range = |len: usize| offset >= 0 && at + len <= segment.len();
+        match kind & 0xe0 {
+            RELOC_WORD if in_range(2) => {
+                let value = ((segment[at] as u16) | ((segment[at + 1] as u16) << 8)).wrapping_add(delta);
+                segment[at] = value as u8;
+                segment[at + 1] = (value >> 8) as u8;
+            }
+            RELOC_HIGH if in_range(1) => {
+                let low = if paged { 0 } else { reader.byte()? as u16 };
+                let value = ((segment[at] as u16) << 8 | low).wrapping_add(delta);
+                segment[at] = (value >> 8) as u8;
+            }
+            RELOC_LOW if in_range(1) => {
+                segment[at] = segment[at].wrapping_add(delta as u8);
+            }
+            RELOC_WORD | RELOC_HIGH | RELOC_LOW => {
+                return Err(error(format!("o65 relocation at offset {} is outside its segment", offset)));
+            }
+            _ => return Err(error(format!("unsupported o65 relocation type ${:02X}", kind)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[
+            0x00, 0x00, 0x00,
+            0x00, 0x10, 0x08, 0x00,
+            0x08, 0x10, 0x01, 0x00,
+            0x09, 0x10, 0x02, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+            0x00,
+            // text: LDA $1008 ; STA $1009 ; LDA #>$1000 ; (padding)
+            0xad, 0x08, 0x10, 0x8d, 0x09, 0x10, 0xa9, 0x10,
+            // data
+            0x42,
+            0x00, 0x00,
+            // text relocations: word at 1 (data), word at 4 (bss), high at 7 (text)
+            0x02, 0x83, 0x03, 0x84, 0x03, 0x42, 0x00, 0x00,
+            0x00,
+            0x00, 0x00
+        ]);
+        data
+    }
+
+    #[test]
+    fn load_in_place() {
+        let image = parse(&sample(), None).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0x1000);
+        assert_eq!(image.segments[0].data, vec![0xad, 0x08, 0x10, 0x8d, 0x09, 0x10, 0xa9, 0x10, 0x42, 0, 0]);
+        assert_eq!(image.entry, Some(0x1000));
+    }
+
+    #[test]
+    fn relocated() {
+        let image = parse(&sample(), Some(0x3000)).unwrap();
+        assert_eq!(image.segments[0].address, 0x3000);
+        assert_eq!(image.segments[0].data, vec![0xad, 0x08, 0x30, 0x8d, 0x09, 0x30, 0xa9, 0x30, 0x42, 0, 0]);
+        assert_eq!(image.entry, Some(0x3000));
+    }
+
+    /// LDA $10, with $10 in a two-byte zero page segment.
+    fn zero_page_sample(mode: u8) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[
+            0x00, 0x00, mode,
+            0x00, 0x10, 0x02, 0x00,
+            0x02, 0x10, 0x00, 0x00,
+            0x02, 0x10, 0x00, 0x00,
+            0x10, 0x00, 0x02, 0x00,
+            0x00, 0x00,
+            0x00,
+            0xa5, 0x10,
+            0x00, 0x00,
+            // text relocations: low byte at 1 (zero page)
+            0x02, 0x25, 0x00,
+            0x00,
+            0x00, 0x00
+        ]);
+        data
+    }
+
+    #[test]
+    fn chained() {
+        let mut data = sample();
+        data[7] |= 0x04;
+        data.extend(sample());
+        let image = parse(&data, Some(0x3000)).unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].data[11..], [0xad, 0x13, 0x30, 0x8d, 0x14, 0x30, 0xa9, 0x30, 0x42, 0, 0]);
+        assert_eq!(image.entry, Some(0x3000));
+
+        let mut data = zero_page_sample(0x04);
+        data.extend(zero_page_sample(0x00));
+        let image = parse(&data, Some(0x2000)).unwrap();
+        assert_eq!(image.segments[0].data, vec![0xa5, 0x10, 0xa5, 0x12]);
+        assert_eq!(parse(&data, None).unwrap().segments[0].data, vec![0xa5, 0x10]);
+    }
+
+    #[test]
+    fn zero_page_overflow() {
+        let mut data = zero_page_sample(0x00);
+        data[20] = 0xff;
+        let err = parse(&data, None).unwrap_err();
+        assert!(err.to_string().contains("$00FF-$0100"), "{}", err);
+    }
+
+    #[test]
+    fn unresolved() {
+        let mut data = sample();
+        let at = data.len() - 10;
+        data[at] = 0x80;
+        assert!(parse(&data, None).is_err());
+        assert!(parse(&data[..20], None).is_err());
+    }
+}
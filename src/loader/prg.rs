@@ -0,0 +1,27 @@
+use super::{Image, LoadError};
+
+/// Commodore PRG: a little-endian load address followed by the program.
+/// `address` overrides the load address from the header.
+pub fn parse(data: &[u8], address: Option<u16>) -> Result<Image, LoadError> {
+    if data.len() < 2 {
+        return Err(LoadError::Format(String::from("PRG file is missing its load address")));
+    }
+    let header = (data[0] as u16) | ((data[1] as u16) << 8);
+    let mut image = Image::new();
+    image.push(address.unwrap_or(header) as u32, &data[2..])?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_address() {
+        let image = parse(&[0x01, 0x08, 0x0b, 0x08], None).unwrap();
+        assert_eq!(image.segments[0].address, 0x0801);
+        assert_eq!(image.segments[0].data, vec![0x0b, 0x08]);
+        assert_eq!(parse(&[0x01, 0x08, 0x0b], Some(0x1000)).unwrap().segments[0].address, 0x1000);
+        assert!(parse(&[0x01], None).is_err());
+    }
+}
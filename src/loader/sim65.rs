@@ -0,0 +1,54 @@
+use super::{Image, LoadError};
+use cpu::Variant;
+
+pub const MAGIC : [u8; 5] = *b"sim65";
+
+const VERSION : u8 = 2;
+const HEADER_LEN : usize = 12;
+
+/// cc65's sim65 format: "sim65", version 2, CPU type (0 for the 6502, 1 for
+/// the 65C02), the zero-page address of the C stack pointer, then the load
+/// and reset addresses. The reset address is written to $FFFC like sim65
+/// does, so the program starts on reset.
+pub fn parse(data: &[u8]) -> Result<Image, LoadError> {
+    if data.len() < HEADER_LEN || !data.starts_with(&MAGIC) {
+        return Err(LoadError::Format(String::from("sim65 file is missing its header")));
+    }
+    if data[5] != VERSION {
+        return Err(LoadError::Format(format!("sim65 header version {} is not supported", data[5])));
+    }
+    let cpu = match data[6] {
+        0 => Variant::Nmos,
+        1 => Variant::Cmos,
+        cpu => return Err(LoadError::Format(format!("sim65 CPU type {} is not supported", cpu)))
+    };
+    let load = (data[8] as u32) | ((data[9] as u32) << 8);
+    let mut image = Image::new();
+    image.push(load, &data[HEADER_LEN..])?;
+    image.push(0xfffc, &data[10..12])?;
+    image.c_stack = Some(data[7]);
+    image.cpu = Some(cpu);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() {
+        let file = b"sim65\x02\x00\x00\x00\x02\x00\x02\xa9\x00";
+        let image = parse(file).unwrap();
+        assert_eq!(image.segments[0].address, 0x0200);
+        assert_eq!(image.segments[0].data, vec![0xa9, 0x00]);
+        assert_eq!(image.segments[1].address, 0xfffc);
+        assert_eq!(image.segments[1].data, vec![0x00, 0x02]);
+        assert_eq!(image.c_stack, Some(0x00));
+        assert_eq!(image.cpu, Some(Variant::Nmos));
+        assert_eq!(parse(b"sim65\x02\x01\x00\x00\x02\x00\x02").unwrap().cpu, Some(Variant::Cmos));
+
+        assert!(parse(b"sim65\x01\x00\x00\x00\x02\x00\x02").is_err());
+        assert!(parse(b"sim65\x02\x02\x00\x00\x02\x00\x02").is_err());
+        assert!(parse(b"sim65\x02").is_err());
+    }
+}
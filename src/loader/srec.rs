@@ -0,0 +1,82 @@
+use super::{hex_bytes, parse_error, Image, LoadError};
+
+pub fn matches(line: &str) -> bool {
+    let bytes = line.as_bytes();
+    bytes.len() >= 4 && (bytes[0] == b'S' || bytes[0] == b's') && bytes[1].is_ascii_digit()
+        && line[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Motorola S-records: S1/S2/S3 data with 16/24/32-bit addresses and the
+/// matching S9/S8/S7 termination records carrying the entry point.
+pub fn parse(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::new();
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !matches(line) {
+            return Err(parse_error(number, "record does not start with 'S'"));
+        }
+        let kind = line.as_bytes()[1] - b'0';
+        let bytes = hex_bytes(&line[2..], number)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(parse_error(number, "record length mismatch"));
+        }
+        let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if sum != 0xff {
+            return Err(parse_error(number, "checksum mismatch"));
+        }
+        let address_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(parse_error(number, "unsupported record type"))
+        };
+        if bytes.len() < address_len + 2 {
+            return Err(parse_error(number, "record too short"));
+        }
+        let address = bytes[1..=address_len].iter().fold(0u32, |acc, b| (acc << 8) | *b as u32);
+        let data = &bytes[address_len + 1..bytes.len() - 1];
+        match kind {
+            1..=3 => image.push(address, data)?,
+            7..=9 => {
+                if address > 0xffff {
+                    return Err(parse_error(number, "entry point outside the 64K address space"));
+                }
+                image.entry = Some(address as u16);
+                break;
+            }
+            _ => {}
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s19() {
+        let image = parse("S00600004844521B\nS1060300A9018DBF\nS5030001FB\nS9030300F9\n").unwrap();
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(image.segments[0].address, 0x0300);
+        assert_eq!(image.segments[0].data, vec![0xa9, 0x01, 0x8d]);
+        assert_eq!(image.entry, Some(0x0300));
+    }
+
+    #[test]
+    fn s28() {
+        let image = parse("S2070003004C0003A6\nS804000300F8\n").unwrap();
+        assert_eq!(image.segments[0].address, 0x0300);
+        assert_eq!(image.segments[0].data, vec![0x4c, 0x00, 0x03]);
+        assert_eq!(image.entry, Some(0x0300));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(parse("S1060300A9018DBE\n").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use super::{parse_error, Image, LoadError};
+
+fn split_address(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let address = line[..colon].trim();
+    if address.is_empty() || address.len() > 4 || !address.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((address, &line[colon + 1..]))
+}
+
+pub fn matches(line: &str) -> bool {
+    split_address(line).is_some()
+}
+
+/// Text in the form Wozmon accepts and prints: `C000: A0 04 B9`, with `: ...`
+/// continuing at the next address and `C000R` setting the entry point.
+pub fn parse(text: &str) -> Result<Image, LoadError> {
+    let mut image = Image::new();
+    let mut next : Option<u32> = None;
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let upper = line.to_ascii_uppercase();
+        if let Some(run) = upper.strip_suffix('R') {
+            let run = run.trim();
+            if !run.is_empty() && run.len() <= 4 && run.chars().all(|c| c.is_ascii_hexdigit()) {
+                image.entry = Some(u16::from_str_radix(run, 16).unwrap());
+                continue;
+            }
+        }
+
+        let (mut address, data) = if let Some(rest) = line.strip_prefix(':') {
+            match next {
+                Some(address) => (address, rest),
+                None => return Err(parse_error(number, "continuation line without an address"))
+            }
+        } else {
+            match split_address(line) {
+                Some((address, rest)) => (u32::from_str_radix(address, 16).unwrap(), rest),
+                None => return Err(parse_error(number, "expected 'ADDR: bytes'"))
+            }
+        };
+
+        for token in data.split_whitespace() {
+            if token.is_empty() || token.len() > 2 {
+                return Err(parse_error(number, "expected hex bytes"));
+            }
+            let value = match u8::from_str_radix(token, 16) {
+                Ok(value) => value,
+                Err(_) => return Err(parse_error(number, "expected hex bytes"))
+            };
+            image.push(address, &[value])?;
+            address += 1;
+        }
+        next = Some(address);
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_with_continuation() {
+        let image = parse("C000: A0 04 B9\n: F6 E0\n0300: a9 01\n0300R\n").unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].address, 0xc000);
+        assert_eq!(image.segments[0].data, vec![0xa0, 0x04, 0xb9, 0xf6, 0xe0]);
+        assert_eq!(image.segments[1].data, vec![0xa9, 0x01]);
+        assert_eq!(image.entry, Some(0x0300));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(": A0\n").is_err());
+        assert!(parse("C000: A0 XYZ\n").is_err());
+        assert!(parse("FFFF: 01 02\n").is_err());
+    }
+}
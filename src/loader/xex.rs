@@ -0,0 +1,109 @@
+use super::{Image, LoadError};
+
+const RUNAD : u32 = 0x02e0;
+const INITAD : u32 = 0x02e2;
+
+/// True when the data starts with `FF FF` and a plausible first segment.
+pub fn matches(data: &[u8]) -> bool {
+    if data.len() < 7 || data[0] != 0xff || data[1] != 0xff {
+        return false;
+    }
+    let start = (data[2] as usize) | ((data[3] as usize) << 8);
+    let end = (data[4] as usize) | ((data[5] as usize) << 8);
+    end >= start && end - start < data.len() - 6
+}
+
+/// Atari XEX: `FF FF` followed by start/end delimited segments. A write to
+/// RUNAD ($02E0) becomes the entry point rather than memory contents, and
+/// each write to INITAD ($02E2) becomes an init routine called once the
+/// segments before it are loaded, as DOS does.
+pub fn parse(data: &[u8]) -> Result<Image, LoadError> {
+    let mut image = Image::new();
+    let mut pos = 0;
+    let word = |pos: usize| (data[pos] as u32) | ((data[pos + 1] as u32) << 8);
+
+    if data.len() < 2 || word(0) != 0xffff {
+        return Err(LoadError::Format(String::from("XEX file does not start with $FFFF")));
+    }
+    while pos < data.len() {
+        if data.len() - pos < 4 {
+            return Err(LoadError::Format(format!("truncated segment header at offset {}", pos)));
+        }
+        if word(pos) == 0xffff {
+            pos += 2;
+            continue;
+        }
+        let start = word(pos);
+        let end = word(pos + 2);
+        pos += 4;
+        if end < start {
+            return Err(LoadError::Format(format!("segment ${:04X}-${:04X} ends before it starts", start, end)));
+        }
+        let len = (end - start + 1) as usize;
+        if data.len() - pos < len {
+            return Err(LoadError::Format(format!("truncated segment ${:04X}-${:04X}", start, end)));
+        }
+        let bytes = &data[pos..pos + len];
+        pos += len;
+
+        let vector = |at: u32| -> Option<u16> {
+            if at >= start && at < end {
+                let i = (at - start) as usize;
+                Some((bytes[i] as u16) | ((bytes[i + 1] as u16) << 8))
+            } else {
+                None
+            }
+        };
+        if let Some(run) = vector(RUNAD) {
+            image.entry = Some(run);
+        }
+        if start < RUNAD || end > INITAD + 1 {
+            image.push(start, bytes)?;
+        }
+        if let Some(init) = vector(INITAD) {
+            image.init.push((image.segments.len(), init));
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_and_vectors() {
+        let data = [
+            0xff, 0xff, 0x00, 0x20, 0x02, 0x20, 0xa9, 0x01, 0x60,
+            0xff, 0xff, 0x00, 0x30, 0x00, 0x30, 0x00,
+            0xe0, 0x02, 0xe1, 0x02, 0x00, 0x30
+        ];
+        let image = parse(&data).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].address, 0x2000);
+        assert_eq!(image.segments[0].data, vec![0xa9, 0x01, 0x60]);
+        assert_eq!(image.segments[1].address, 0x3000);
+        assert_eq!(image.entry, Some(0x3000));
+    }
+
+    #[test]
+    fn init_segments() {
+        let data = [
+            0xff, 0xff, 0x00, 0x20, 0x02, 0x20, 0xa9, 0x01, 0x60,
+            0xe2, 0x02, 0xe3, 0x02, 0x00, 0x20,
+            0x03, 0x20, 0x03, 0x20, 0x00,
+            0xe2, 0x02, 0xe3, 0x02, 0x03, 0x20
+        ];
+        let image = parse(&data).unwrap();
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[1].address, 0x2003);
+        assert_eq!(image.init, vec![(1, 0x2000), (2, 0x2003)]);
+        assert_eq!(image.entry, None);
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(parse(&[0xff, 0xff, 0x00, 0x20, 0x05, 0x20, 0x00]).is_err());
+        assert!(parse(&[0x00, 0x20]).is_err());
+    }
+}
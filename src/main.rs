@@ -2,91 +2,707 @@ extern crate magpie;
 
 use std::env;
 use std::fs::File;
+use std::io;
+use std::process;
 
-use std::io::prelude::*;
-use std::thread;
-use std::sync::mpsc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
 
 use magpie::platform::Platform;
-use magpie::cpu::MOS6502;
-use magpie::apple1::Apple1;
+use magpie::cpu::{MOS6502, Variant};
+use magpie::symbols::SymbolTable;
+use magpie::listing::Listing;
+use magpie::loader;
+use magpie::loader::{Format, Image, LoadError};
+use magpie::cli;
+use magpie::clock;
+use magpie::clock::Throttle;
+use magpie::terminal;
+use magpie::terminal::{KeyEvent, RawMode};
+use magpie::injector::Injector;
+use magpie::console::{OutputSink, WriteSink, TelnetServer};
+use magpie::runner;
+use magpie::runner::{TestCase, Until, DEFAULT_MAX_CYCLES};
+use magpie::aci::{Aci, Tape};
+use magpie::cffa1::Cffa1;
+use magpie::acia::{Acia, Mc6850, SerialLine, StdioLine, PtyLine, TcpLine};
+use magpie::profile;
+use magpie::kim1::{Kim1, Tty, TTY_BIT_CYCLES};
+use magpie::breadboard::{Breadboard, BREADBOARD_HZ};
+use magpie::osi::{Osi, OSI_HZ, ACIA_CLOCK_HZ};
+use magpie::sim65::{Sim65, ExitStatus};
+use magpie::board::{Board, Description};
+use magpie::profile::RamSize;
+use magpie::cli::{Machine, Options, SerialLineSpec, EXIT_OK, EXIT_STOPPED, EXIT_USAGE, EXIT_LOAD, EXIT_LIMIT, EXIT_FAIL, EXIT_WRITE};
+
+/// How long an XEX init routine may run before loading gives up on it.
+const INIT_MAX_CYCLES : u64 = 10_000_000;
 
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("missing argument(s)");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    if options.list_machines {
+        list_machines();
         return;
     }
+    let has_roms = !options.roms.is_empty() || options.board.is_some()
+        || profile::find(&options.machine_name).is_some_and(|profile| !profile.roms.is_empty());
+    if options.loads.is_empty() && !has_roms {
+        eprintln!("missing argument(s)\n\n{}", cli::USAGE);
+        process::exit(EXIT_USAGE);
+    }
 
-    let buf = load_file(&args[1]);
-    let mut apple1 = Apple1::new();
-    apple1.load(buf, 0x4000);
-    let mut cpu = MOS6502::new(Box::new(apple1));
-    
-    cpu.reset();
-    cpu.run(1024);
+    process::exit(run(&options));
+}
 
-    let (tx, rx) = mpsc::channel();
-    let handle = thread::spawn(move || {
-        loop {
-            let mut line = String::new();
-            std::io::stdin().read_line(&mut line).expect("Failed to read line");
-            let quit = line.as_str().starts_with("quit");
-            tx.send(line).unwrap();
-            if quit {
-                break;
+fn run(options: &Options) -> i32 {
+    let description = match options.board {
+        Some(ref filename) => match Description::from_file(filename) {
+            Ok(description) => Some(description),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        None => None
+    };
+    let report_files = match ReportFiles::read(options) {
+        Ok(report_files) => report_files,
+        Err(message) => {
+            eprintln!("{}", message);
+            return EXIT_LOAD;
+        }
+    };
+    let cpu_hz = description.as_ref().map_or(options.machine.clock_hz(), |description| description.clock_hz);
+    let stdio_serial = match options.acia {
+        Some(spec) => spec.line == SerialLineSpec::Stdio,
+        None => options.machine == Machine::Breadboard || description.as_ref().is_some_and(|description| {
+            description.devices.iter().any(|device| device.line == Some(SerialLineSpec::Stdio))
+        })
+    };
+    let (rx, serial_input) = if options.headless {
+        (None, None)
+    } else if stdio_serial {
+        let (events, bytes) = terminal::spawn_serial_reader();
+        (Some(events), Some(bytes))
+    } else {
+        (Some(terminal::spawn_reader()), None)
+    };
+
+    if options.address_map.is_some() && options.machine != Machine::Breadboard {
+        eprintln!("--map is only for the breadboard");
+        return EXIT_LOAD;
+    }
+    if options.listen.is_some() && options.machine != Machine::Apple1 {
+        eprintln!("--listen is only for the Apple 1");
+        return EXIT_LOAD;
+    }
+    if options.run_test && options.machine != Machine::Apple1 {
+        eprintln!("run-test compares the Apple 1's display output, so needs an Apple 1 machine");
+        return EXIT_USAGE;
+    }
+    // a test program's output is all that goes to stdout
+    let quiet = options.machine == Machine::Sim65 || options.run_test;
+    let mut images = Vec::new();
+    for spec in &options.loads {
+        if !quiet {
+            eprintln!("loading file {}", spec.filename);
+        }
+        let image = match load_image(&spec.filename, spec.address) {
+            Ok(image) => image,
+            Err(err) => {
+                eprintln!("error loading {}: {}", spec.filename, err);
+                return EXIT_LOAD;
+            }
+        };
+        if !quiet {
+            eprintln!("loaded {} bytes", image.len());
+        }
+        images.push(image);
+    }
+
+    let mut exit_status = None;
+    let platform : Box<dyn Platform> = match options.machine {
+        Machine::Breadboard => match build_breadboard(options, serial_input) {
+            Ok(board) => Box::new(board),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        Machine::Board => match build_board(options, description.as_ref().unwrap(), serial_input) {
+            Ok(board) => Box::new(board),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        Machine::Sim65 => match build_sim65(options, images.iter().find_map(|image| image.c_stack)) {
+            Ok(sim) => {
+                exit_status = Some(sim.exit_status());
+                Box::new(sim)
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        Machine::Osi => match build_osi(options, serial_input) {
+            Ok(osi) => Box::new(osi),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        Machine::Kim1 => match build_kim1(options) {
+            Ok(kim1) => Box::new(kim1),
+            Err(message) => {
+                eprintln!("{}", message);
+                return EXIT_LOAD;
+            }
+        },
+        Machine::Apple1 => {
+            if options.tty {
+                eprintln!("--tty is only for the KIM-1");
+                return EXIT_LOAD;
+            }
+            let profile = profile::find(&options.machine_name).expect("unknown Apple 1 profile");
+            let mut apple1 = match profile.build(options.ram, &options.roms) {
+                Ok(apple1) => apple1,
+                Err(message) => {
+                    eprintln!("{}", message);
+                    return EXIT_LOAD;
+                }
+            };
+            apple1.load(Vec::new(), 0);
+            apple1.display_mut().set_rate_limit(options.slow_display);
+            if options.run_test {
+                apple1.display_mut().record_transcript();
+            }
+            if profile.aci {
+                match build_aci(options) {
+                    Ok(aci) => apple1.attach_aci(aci),
+                    Err(message) => {
+                        eprintln!("{}", message);
+                        return EXIT_LOAD;
+                    }
+                }
+            } else if options.aci_rom.is_some() || options.tape_in.is_some() || options.tape_out.is_some() {
+                eprintln!("{} has no cassette interface", profile.name);
+                return EXIT_LOAD;
+            }
+            if let Some(spec) = options.acia {
+                match open_serial_line(spec.line, serial_input) {
+                    Ok(line) => apple1.attach_acia(spec.address, Acia::with_line(clock::APPLE1_HZ, line)),
+                    Err(err) => {
+                        eprintln!("error opening serial line: {}", err);
+                        return EXIT_LOAD;
+                    }
+                }
+            }
+            if let Some(port) = options.listen {
+                match TelnetServer::listen(port) {
+                    Ok(server) => {
+                        eprintln!("console on 127.0.0.1:{}", server.port());
+                        let sinks : Vec<Box<dyn OutputSink>> = vec![Box::new(WriteSink::stdout()), Box::new(server.output())];
+                        apple1.set_output(Box::new(sinks));
+                        apple1.set_input(Box::new(server));
+                    }
+                    Err(err) => {
+                        eprintln!("error opening console: {}", err);
+                        return EXIT_LOAD;
+                    }
+                }
             }
+            if let Some(ref path) = options.cffa1 {
+                match Cffa1::open(path) {
+                    Ok(card) => apple1.attach_cffa1(card),
+                    Err(err) => {
+                        eprintln!("error opening {}: {}", path, err);
+                        return EXIT_LOAD;
+                    }
+                }
+            }
+            Box::new(apple1)
+        }
+    };
 
-            thread::sleep(Duration::from_millis(5));
+    if options.wait_for.is_some() && platform.display().is_none() {
+        eprintln!("--wait-for watches the display, which {} does not have", options.machine_name);
+        return EXIT_USAGE;
+    }
+
+    let mut cpu = MOS6502::new(platform);
+
+    let mut entry : Option<u16> = None;
+    for image in &images {
+        if let Err(message) = install_image(&mut cpu, image) {
+            eprintln!("{}", message);
+            return EXIT_LOAD;
         }
+        if image.entry.is_some() {
+            entry = image.entry;
+        }
+    }
+
+    if let Some(vector) = options.reset_vector {
+        cpu.platform_mut().poke(0xfffc, vector as u8);
+        cpu.platform_mut().poke(0xfffd, (vector >> 8) as u8);
+    }
+
+    cpu.reset();
+    if let Some(pc) = options.pc.or(entry) {
+        cpu.set_pc(pc);
+    }
+    cpu.set_trace(options.trace);
+    let image_cpu = images.iter().find_map(|image| image.cpu);
+    cpu.set_variant(match description {
+        Some(ref description) => description.cpu,
+        None if options.machine == Machine::Breadboard => Variant::Cmos,
+        None => image_cpu.unwrap_or(Variant::Nmos)
     });
+    // KIM-1 programs set the BRK handler at $17FE to get back to the monitor
+    cpu.set_brk_halts(options.machine != Machine::Kim1);
+    if options.profile.is_some() {
+        cpu.enable_profiler();
+    }
+    if options.coverage.is_some() {
+        cpu.enable_coverage();
+    }
+
+    let mut injector = Injector::new();
+    injector.set_line_delay(options.line_delay_ms * cpu_hz / 1000);
+    injector.set_prompt(options.wait_for.clone());
+    for filename in &options.paste {
+        match loader::read_file(filename) {
+            Ok(buf) => injector.push_text(&String::from_utf8_lossy(&buf)),
+            Err(err) => {
+                eprintln!("error reading {}: {}", filename, err);
+                return EXIT_LOAD;
+            }
+        }
+    }
+    for text in &options.type_text {
+        injector.push_text(text);
+    }
+    if options.run_test {
+        return run_test(&mut cpu, options, injector, &report_files);
+    }
+
+    let raw_mode = if options.headless { None } else { Some(RawMode::enable()) };
+
+    let mut throttle = Throttle::with_rate(options.target_hz(cpu_hz));
+    let slice = throttle.slice();
+    let started = Instant::now();
+    let mut last_report = started;
+    throttle.sync(cpu.get_total_cycles());
 
     let mut c : u64 = 0;
     let mut key_buffer : VecDeque<u8> = VecDeque::new();
 
-    loop {
-            if cpu.is_running() {
-                let received = rx.try_recv();
-                if !received.is_err() {
-                    let val = received.unwrap();
-                    for b in val.bytes() {
-                        key_buffer.push_back(b);
-                    }
-                    if val.starts_with("quit") {
-                        break;
+    let code = loop {
+            if let Some(ref rx) = rx {
+                let mut quit = false;
+                while let Ok(event) = rx.try_recv() {
+                    match event {
+                        KeyEvent::Key(key) => key_buffer.push_back(key),
+                        KeyEvent::Reset => {
+                            key_buffer.clear();
+                            cpu.platform_mut().reset();
+                            cpu.reset();
+                        }
+                        KeyEvent::Quit => quit = true
                     }
                 }
-
-                if !key_buffer.is_empty() && cpu.key_ready() {
-                    let v = key_buffer.pop_front().unwrap();
-                    cpu.key_pressed(v);
+                if quit {
+                    break EXIT_OK;
                 }
+            }
+
+            if injector.is_done() && !key_buffer.is_empty() && cpu.key_ready() {
+                let v = key_buffer.pop_front().unwrap();
+                cpu.key_pressed(v);
+            }
+
+            if let Some(code) = run_slice(&mut cpu, slice, options, &mut injector, exit_status.as_ref()) {
+                break code;
+            }
+
+            throttle.pace(cpu.get_total_cycles());
+
+            if options.show_speed && last_report.elapsed() >= Duration::from_secs(1) {
+                eprintln!("{:.3} MHz", throttle.effective_mhz(cpu.get_total_cycles()));
+                last_report = Instant::now();
+            }
+
+            c += 1;
+    };
+
+    drop(raw_mode);
+    if !quiet {
+        eprintln!();
+        eprintln!("done, iteration count = {:?}", c);
+    }
+    let seconds = started.elapsed().as_secs_f64();
+    if seconds > 0.0 && !quiet {
+        eprintln!("{} cycles in {:.3}s, effective speed {:.3} MHz",
+            cpu.get_total_cycles(), seconds, cpu.get_total_cycles() as f64 / seconds / 1_000_000.0);
+    }
+    if let Some(lcd) = cpu.platform().lcd() {
+        println!("{}", lcd.text());
+    }
+    if let Some(text) = cpu.platform().video_text() {
+        println!("{}", text.trim_end());
+    }
+    let code = match write_reports(&cpu, options, &report_files) {
+        Ok(()) => code,
+        Err(message) => {
+            eprintln!("{}", message);
+            EXIT_WRITE
+        }
+    };
+    if let (Some(filename), Some(aci)) = (options.tape_out.as_ref(), cpu.platform().aci()) {
+        match aci.recording().write_file(filename) {
+            Ok(()) => eprintln!("tape written to {}", filename),
+            Err(err) => eprintln!("error writing {}: {}", filename, err)
+        }
+    }
+    code
+}
+
+fn list_machines() {
+    for profile in profile::profiles() {
+        println!("{:<16}{}", profile.name, profile.description);
+        for slot in profile.roms.iter().filter(|slot| slot.builtin.is_none()) {
+            println!("{:<16}  needs --rom {}=<file> (up to {} bytes at ${:04X})", "", slot.name, slot.max_len, slot.address);
+        }
+    }
+    println!("{:<16}KIM-1 with 1K of RAM, LED display and keypad (--tty for the teletype)", "kim1");
+    println!("{:<16}  needs --rom 002=<file> (1024 bytes at $1C00), takes --rom 003=<file> (1024 bytes at $1800)", "");
+    println!("{:<16}65C02 breadboard computer with a VIA-driven 16x2 LCD and an ACIA", "breadboard");
+    println!("{:<16}  needs --rom rom=<file> (up to 32768 bytes ending at $FFFF)", "");
+    println!("{:<16}Ohio Scientific Superboard II / C1P with 32x32 video, polled keyboard and a 6850", "osi");
+    println!("{:<16}  needs --rom monitor=<file> (2048 bytes at $F800), takes --rom basic=<file> (8192 bytes at $A000)", "");
+    println!("{:<16}any machine described in a TOML file, with --board <file>", "board");
+    println!("{:<16}cc65 sim65-compatible test machine: 64K RAM, paravirtualized I/O, exit codes", "sim65");
+}
+
+/// The KIM-1 with its monitor ROMs. The Apple 1's RAM sizes and cards
+/// don't fit it.
+fn build_kim1(options: &Options) -> Result<Kim1, String> {
+    if options.ram.is_some() || options.aci_rom.is_some() || options.tape_in.is_some() || options.tape_out.is_some()
+        || options.cffa1.is_some() || options.acia.is_some() {
+        return Err(String::from("the KIM-1 takes no --ram, cassette interface, CFFA1 or ACIA options"));
+    }
+    if !options.roms.iter().any(|(name, _)| name == "002") {
+        return Err(String::from("kim1 needs its monitor ROM: --rom 002=<file>"));
+    }
+    let mut kim1 = Kim1::new();
+    for (name, filename) in &options.roms {
+        let data = loader::read_file(filename).map_err(|err| format!("error loading {}: {}", filename, err))?;
+        if data.is_empty() || data.len() > 1024 {
+            return Err(format!("{} ROM must be 1 to 1024 bytes, got {}", name, data.len()));
+        }
+        match name.as_str() {
+            "002" => kim1.set_rom_002(&data),
+            "003" => kim1.set_rom_003(&data),
+            _ => return Err(format!("kim1 has no {} ROM", name))
+        }
+    }
+    if options.tty {
+        kim1.attach_tty(Tty::new(TTY_BIT_CYCLES));
+    }
+    Ok(kim1)
+}
+
+/// The far end of an ACIA's serial line. On stdio the bytes come from the
+/// terminal reader, or from nowhere when running headless.
+fn open_serial_line(line: SerialLineSpec, input: Option<Receiver<u8>>) -> std::io::Result<Box<dyn SerialLine>> {
+    Ok(match line {
+        SerialLineSpec::Stdio => Box::new(StdioLine::new(input.unwrap_or_else(|| mpsc::channel().1))),
+        SerialLineSpec::Pty => {
+            let pty = PtyLine::open()?;
+            eprintln!("serial line on {}", pty.path());
+            Box::new(pty)
+        }
+        SerialLineSpec::Tcp(port) => {
+            let tcp = TcpLine::listen(port)?;
+            eprintln!("serial line on 127.0.0.1:{}", tcp.port());
+            Box::new(tcp)
+        }
+    })
+}
+
+/// The breadboard computer with its ROM and the ACIA on the requested
+/// line, stdio by default.
+fn build_breadboard(options: &Options, serial_input: Option<Receiver<u8>>) -> Result<Breadboard, String> {
+    if options.ram.is_some() || options.tty || options.aci_rom.is_some() || options.tape_in.is_some()
+        || options.tape_out.is_some() || options.cffa1.is_some() {
+        return Err(String::from("the breadboard takes no --ram, --tty, cassette interface or CFFA1 options"));
+    }
+    let mut board = Breadboard::new(options.address_map.unwrap_or_default());
+    for (name, filename) in &options.roms {
+        if name != "rom" {
+            return Err(format!("breadboard has no {} ROM", name));
+        }
+        let data = loader::read_file(filename).map_err(|err| format!("error loading {}: {}", filename, err))?;
+        board.install_rom(&data);
+    }
+    if options.roms.is_empty() {
+        return Err(String::from("breadboard needs its ROM image: --rom rom=<file>"));
+    }
+    let line = options.acia.map_or(SerialLineSpec::Stdio, |spec| spec.line);
+    let line = open_serial_line(line, serial_input).map_err(|err| format!("error opening serial line: {}", err))?;
+    board.attach_acia(Acia::with_line(BREADBOARD_HZ, line));
+    Ok(board)
+}
+
+/// A machine from a description file, its ACIAs on the lines it names.
+/// Only one of them can have stdio.
+fn build_board(options: &Options, description: &Description, serial_input: Option<Receiver<u8>>) -> Result<Board, String> {
+    if options.ram.is_some() || !options.roms.is_empty() || options.tty || options.aci_rom.is_some()
+        || options.tape_in.is_some() || options.tape_out.is_some() || options.cffa1.is_some() || options.acia.is_some() {
+        return Err(String::from("a described board takes no --ram, --rom, --tty, cassette interface, CFFA1 or ACIA options"));
+    }
+    let mut board = Board::from_description(description)?;
+    let mut serial_input = serial_input;
+    for (i, device) in description.devices.iter().enumerate() {
+        if let Some(line) = device.line {
+            if line == SerialLineSpec::Stdio && serial_input.is_none() && !options.headless {
+                return Err(String::from("only one ACIA can be on stdio"));
+            }
+            let input = if line == SerialLineSpec::Stdio { serial_input.take() } else { None };
+            let line = open_serial_line(line, input).map_err(|err| format!("error opening serial line: {}", err))?;
+            board.connect(i, line);
+        }
+    }
+    Ok(board)
+}
+
+/// The sim65 test machine, with the first file loaded as the program name
+/// in `argv` and the C stack where the program's header puts it.
+fn build_sim65(options: &Options, c_stack: Option<u8>) -> Result<Sim65, String> {
+    if options.ram.is_some() || !options.roms.is_empty() || options.tty || options.aci_rom.is_some()
+        || options.tape_in.is_some() || options.tape_out.is_some() || options.cffa1.is_some() || options.acia.is_some() {
+        return Err(String::from("sim65 takes no --ram, --rom, --tty, cassette interface, CFFA1 or ACIA options"));
+    }
+    let mut sim = Sim65::new();
+    if let Some(address) = c_stack {
+        sim.set_c_stack(address);
+    }
+    sim.set_args(options.loads.iter().take(1).map(|spec| spec.filename.clone()).collect());
+    Ok(sim)
+}
 
-                cpu.run(2*1024);
+/// The OSI with its ROMs, the screen drawn unless headless and the 6850
+/// on a serial line if one is asked for.
+fn build_osi(options: &Options, serial_input: Option<Receiver<u8>>) -> Result<Osi, String> {
+    if options.tty || options.aci_rom.is_some() || options.tape_in.is_some() || options.tape_out.is_some()
+        || options.cffa1.is_some() {
+        return Err(String::from("the OSI takes no --tty, cassette interface or CFFA1 options"));
+    }
+    let ram_top = match options.ram {
+        Some(RamSize::K4) => 0x0fff,
+        None | Some(RamSize::K8) => 0x1fff,
+        Some(RamSize::K32) => 0x7fff,
+        Some(_) => return Err(String::from("the OSI takes 4K, 8K or 32K of RAM"))
+    };
+    let mut osi = Osi::new(ram_top);
+    osi.load(Vec::new(), 0);
+    osi.set_render(!options.headless);
+    let mut monitor = false;
+    for (name, filename) in &options.roms {
+        let data = loader::read_file(filename).map_err(|err| format!("error loading {}: {}", filename, err))?;
+        let max_len = match name.as_str() {
+            "basic" => 0x2000,
+            "monitor" => 0x800,
+            _ => return Err(format!("osi has no {} ROM", name))
+        };
+        if data.is_empty() || data.len() > max_len {
+            return Err(format!("{} ROM must be 1 to {} bytes, got {}", name, max_len, data.len()));
+        }
+        if name == "basic" {
+            osi.set_basic_rom(&data);
+        } else {
+            osi.set_monitor_rom(&data);
+            monitor = true;
+        }
+    }
+    if !monitor {
+        return Err(String::from("osi needs its monitor ROM: --rom monitor=<file>"));
+    }
+    if let Some(spec) = options.acia {
+        let line = open_serial_line(spec.line, serial_input).map_err(|err| format!("error opening serial line: {}", err))?;
+        osi.attach_acia(Mc6850::with_line(OSI_HZ, ACIA_CLOCK_HZ, line));
+    }
+    Ok(osi)
+}
 
-                thread::sleep(Duration::from_millis(100));
+/// The cassette interface with its ROM and, if asked for, a tape to play.
+fn build_aci(options: &Options) -> Result<Aci, String> {
+    let mut aci = match options.aci_rom {
+        Some(ref filename) => {
+            let buf = loader::read_file(filename).map_err(|err| format!("error loading {}: {}", filename, err))?;
+            if buf.len() != 256 {
+                return Err(format!("error loading {}: ACI ROM must be 256 bytes", filename));
+            }
+            let mut rom = [0; 256];
+            rom.copy_from_slice(&buf);
+            Aci::with_rom(rom)
+        }
+        None => Aci::new()
+    };
+    if let Some(ref filename) = options.tape_in {
+        let tape = Tape::read_file(filename).map_err(|err| format!("error loading {}: {}", filename, err))?;
+        aci.insert_tape(tape);
+    }
+    Ok(aci)
+}
 
-            } else {
-                break;
+/// Runs the program as a test case built from the options and prints
+/// whether it passed.
+fn run_test(cpu: &mut MOS6502, options: &Options, input: Injector, report_files: &ReportFiles) -> i32 {
+    let expected = match options.expect {
+        Some(ref filename) => match loader::read_file(filename) {
+            Ok(buf) => Some(String::from_utf8_lossy(&buf).into_owned()),
+            Err(err) => {
+                eprintln!("error reading {}: {}", filename, err);
+                return EXIT_LOAD;
             }
+        },
+        None => None
+    };
+    let until = options.exit_on.iter().map(|&address| Until::Address(address))
+        .chain(options.until_output.iter().map(|text| Until::Output(text.clone())))
+        .chain(if options.until_halted { Some(Until::Halted) } else { None })
+        .collect();
+    let mut case = TestCase {
+        input,
+        until,
+        max_cycles: options.max_cycles.unwrap_or(DEFAULT_MAX_CYCLES),
+        expected
+    };
+    let result = runner::run(cpu, &mut case);
+    println!();
+    println!("{}", result.summary());
+    if let Err(message) = write_reports(cpu, options, report_files) {
+        eprintln!("{}", message);
+        return EXIT_WRITE;
+    }
+    if result.passed() { EXIT_OK } else { EXIT_FAIL }
+}
 
-            c = c + 1;
+/// Runs about `cycles` cycles, returning an exit code once the program
+/// exits, the CPU stops, an exit address is reached or a limit is exhausted.
+fn run_slice(cpu: &mut MOS6502, cycles: u64, options: &Options, injector: &mut Injector,
+             exit_status: Option<&ExitStatus>) -> Option<i32> {
+    let target = cpu.get_total_cycles() + cycles;
+    while cpu.get_total_cycles() < target {
+        if let Some(code) = exit_status.and_then(|status| status.code()) {
+            return Some(code);
+        }
+        if !cpu.is_running() {
+            eprintln!("CPU stopped at ${:04X}", cpu.get_pc());
+            return Some(EXIT_STOPPED);
+        }
+        if options.exit_on.contains(&cpu.get_pc()) {
+            return Some(EXIT_OK);
+        }
+        if options.max_cycles.is_some_and(|max| cpu.get_total_cycles() >= max)
+            || options.max_instructions.is_some_and(|max| cpu.get_instruction_count() >= max) {
+            return Some(EXIT_LIMIT);
+        }
+        injector.poll(cpu);
+        cpu.step();
+    }
+    None
+}
+
+/// The symbols and listing the reports use, read before the run so that a
+/// bad file stops it from starting.
+struct ReportFiles {
+    symbols: SymbolTable,
+    listing: Option<Listing>
+}
+
+impl ReportFiles {
+    fn read(options: &Options) -> Result<ReportFiles, String> {
+        let symbols = match options.symbols {
+            Some(ref filename) => SymbolTable::from_file(filename)
+                .map_err(|err| format!("error reading {}: {}", filename, err))?,
+            None => SymbolTable::new()
+        };
+        let listing = match options.listing {
+            Some(ref filename) => Some(Listing::from_file(filename)
+                .map_err(|err| format!("error reading {}: {}", filename, err))?),
+            None => None
+        };
+        Ok(ReportFiles { symbols, listing })
+    }
+}
+
+fn write_reports(cpu: &MOS6502, options: &Options, report_files: &ReportFiles) -> Result<(), String> {
+    let symbols = &report_files.symbols;
+    if let (Some(prefix), Some(profiler)) = (options.profile.as_ref(), cpu.profiler()) {
+        write_report(&format!("{}.txt", prefix), |file| profiler.write_report(file, Some(symbols), 50))?;
+        write_report(&format!("{}.folded", prefix), |file| profiler.write_collapsed(file, Some(symbols)))?;
+        eprintln!("profile written to {}.txt and {}.folded", prefix, prefix);
     }
 
-    let _result = handle.join();
-    println!("done, iteration count = {:?}", c);
-    
+    if let (Some(prefix), Some(coverage)) = (options.coverage.as_ref(), cpu.coverage()) {
+        write_report(&format!("{}.txt", prefix), |file| coverage.write_summary(file))?;
+        if let Some(ref listing) = report_files.listing {
+            write_report(&format!("{}.lst", prefix), |file| coverage.write_listing(listing, file))?;
+            write_report(&format!("{}.info", prefix), |file| coverage.write_lcov(listing, Some(symbols), file))?;
+        }
+        eprintln!("coverage written to {}.*", prefix);
+    }
+    Ok(())
 }
 
-fn load_file(filename: &String) -> Vec<u8> {
-    //let filename = &args[1];
-    println!("loading file {}", filename);
-    let mut f = File::open(filename).expect("file not found");
-    let mut buf: Vec<u8> = Vec::new();
-    f.read_to_end(&mut buf).expect("error reading file");
-    println!("loaded {} bytes", buf.len());
-    buf
-}
\ No newline at end of file
+/// Creates `filename` and writes a report into it.
+fn write_report<F>(filename: &str, write: F) -> Result<(), String>
+    where F: FnOnce(&mut File) -> io::Result<()> {
+    File::create(filename).and_then(|mut file| write(&mut file))
+        .map_err(|err| format!("error writing {}: {}", filename, err))
+}
+
+/// Copies the image into memory, calling each of its init routines once the
+/// segments before it are in place.
+fn install_image(cpu: &mut MOS6502, image: &Image) -> Result<(), String> {
+    let mut installed = 0;
+    for &(segments, address) in &image.init {
+        image.install_segments(cpu.platform_mut(), installed..segments);
+        installed = segments;
+        if !cpu.call(address, INIT_MAX_CYCLES) {
+            return Err(format!("init routine at ${:04X} did not return", address));
+        }
+    }
+    image.install_segments(cpu.platform_mut(), installed..image.segments.len());
+    Ok(())
+}
+
+/// Loads a file in any supported format. Raw binaries without an explicit
+/// address go to $4000 as they always have.
+fn load_image(filename: &str, address: Option<u16>) -> Result<Image, LoadError> {
+    let buf = loader::read_file(filename)?;
+    let format = loader::detect_file(filename, &buf);
+    let address = match (address, format) {
+        (None, Format::Binary) => Some(0x4000),
+        _ => address
+    };
+    loader::parse(&buf, format, address)
+}
@@ -0,0 +1,156 @@
+const RETURN : u8 = 0x0d;
+const LINE_FEED : u8 = 0x0a;
+const ESC : u8 = 0x1b;
+const RUBOUT : u8 = 0x7f;
+
+/// Row 0 holds the modifiers as (row, column bit).
+const SHIFT_LOCK : (u8, u8) = (0, 0x01);
+const LEFT_SHIFT : (u8, u8) = (0, 0x04);
+const CTRL : (u8, u8) = (0, 0x40);
+
+/// The 542 keyboard matrix, column bit 0 first in each row; 0 is no key.
+const MATRIX : [[u8; 8]; 8] = [
+    [0, 0, 0, 0, 0, ESC, 0, 0],
+    [0, b'P', b';', b'/', b' ', b'Z', b'A', b'Q'],
+    [0, b',', b'M', b'N', b'B', b'V', b'C', b'X'],
+    [0, b'K', b'J', b'H', b'G', b'F', b'D', b'S'],
+    [0, b'I', b'U', b'Y', b'T', b'R', b'E', b'W'],
+    [0, 0, 0, RETURN, LINE_FEED, b'O', b'L', b'.'],
+    [0, RUBOUT, b'-', b':', b'0', b'9', b'8', b'7'],
+    [0, b'6', b'5', b'4', b'3', b'2', b'1', 0]
+];
+
+/// Characters typed with shift, and the key that gives them.
+const SHIFTED : [(u8, u8); 15] = [
+    (b'!', b'1'), (b'"', b'2'), (b'#', b'3'), (b'$', b'4'), (b'%', b'5'),
+    (b'&', b'6'), (b'\'', b'7'), (b'(', b'8'), (b')', b'9'), (b'*', b':'),
+    (b'=', b'-'), (b'+', b';'), (b'<', b','), (b'>', b'.'), (b'?', b'/')];
+
+/// Cycles a key stays down, then up before the next one. The ROM scans
+/// the keyboard twice and waits for the key to repeat or change.
+const KEY_DOWN_CYCLES : u32 = 30_000;
+const KEY_UP_CYCLES : u32 = 20_000;
+
+fn position(key: u8) -> Option<(u8, u8)> {
+    MATRIX.iter().enumerate().find_map(|(row, keys)| {
+        keys.iter().position(|&k| k != 0 && k == key).map(|bit| (row as u8, 1 << bit))
+    })
+}
+
+/// The Superboard II / C1P polled keyboard at $DF00. Writing selects rows
+/// with 0 bits and reading returns 0 bits for the keys down in them, so
+/// an idle keyboard reads $FF. SHIFT LOCK is held down, which is how the
+/// ROMs expect upper case. Host characters are pressed one at a time with
+/// SHIFT or CTRL as needed.
+pub struct Keyboard {
+    rows: u8,
+    keys: Vec<(u8, u8)>,
+    cycles: u32
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard {
+            rows: 0xff,
+            keys: Vec::new(),
+            cycles: 0
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.rows = value;
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut columns = 0xff;
+        let down = self.cycles > KEY_UP_CYCLES;
+        let keys = self.keys.iter().filter(|_| down).chain(Some(&SHIFT_LOCK));
+        for &(row, bit) in keys {
+            if self.rows & (1 << row) == 0 {
+                columns &= !bit;
+            }
+        }
+        columns
+    }
+
+    /// Ready for another key once the last was pressed and released.
+    pub fn is_ready(&self) -> bool {
+        self.cycles == 0
+    }
+
+    /// Presses the keys for an ASCII character, returning false if the
+    /// keyboard can't type it.
+    pub fn press(&mut self, ch: u8) -> bool {
+        let ch = ch.to_ascii_uppercase();
+        let keys = if let Some(key) = position(ch) {
+            vec![key]
+        } else if let Some(&(_, base)) = SHIFTED.iter().find(|&&(shifted, _)| shifted == ch) {
+            vec![position(base).unwrap(), LEFT_SHIFT]
+        } else if (0x01..=0x1a).contains(&ch) {
+            vec![position(ch + 0x40).unwrap(), CTRL]
+        } else {
+            return false;
+        };
+        self.keys = keys;
+        self.cycles = KEY_DOWN_CYCLES + KEY_UP_CYCLES;
+        true
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles = self.cycles.saturating_sub(cycles);
+        if self.cycles == 0 {
+            self.keys.clear();
+        }
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scanning() {
+        let mut keyboard = Keyboard::new();
+        keyboard.write(!0x01);
+        assert_eq!(keyboard.read(), !0x01);
+        keyboard.write(!0x02);
+        assert_eq!(keyboard.read(), 0xff);
+
+        assert!(keyboard.press(b'a'));
+        assert!(!keyboard.is_ready());
+        assert_eq!(keyboard.read(), !0x40);
+        // both rows at once
+        keyboard.write(!0x03);
+        assert_eq!(keyboard.read(), !0x41);
+
+        keyboard.tick(KEY_DOWN_CYCLES);
+        assert_eq!(keyboard.read(), !0x01);
+        keyboard.tick(KEY_UP_CYCLES);
+        assert!(keyboard.is_ready());
+    }
+
+    #[test]
+    fn modifiers() {
+        let mut keyboard = Keyboard::new();
+        assert!(keyboard.press(b'"'));
+        keyboard.write(!0x01);
+        assert_eq!(keyboard.read(), !0x05);
+        keyboard.write(!0x80);
+        assert_eq!(keyboard.read(), !0x20);
+
+        assert!(keyboard.press(0x03));
+        keyboard.write(!0x01);
+        assert_eq!(keyboard.read(), !0x41);
+        keyboard.write(!0x04);
+        assert_eq!(keyboard.read(), !0x40);
+
+        assert!(keyboard.press(RETURN));
+        assert!(!keyboard.press(b'@'));
+    }
+}
@@ -0,0 +1,285 @@
+mod keyboard;
+
+pub use self::keyboard::Keyboard;
+
+use platform::Platform;
+use console::{OutputSink, RawSink};
+use acia::Mc6850;
+use device::{Line, Scheduler};
+
+/// The C1P's 3.93216 MHz crystal divided by 4.
+pub const OSI_HZ : u64 = 983_040;
+/// The ACIA's clock, which with divide by 16 gives 300 baud.
+pub const ACIA_CLOCK_HZ : u64 = 4800;
+
+pub const BASIC_ROM : u16 = 0xa000;
+pub const BASIC_ROM_SIZE : usize = 0x2000;
+pub const VIDEO : u16 = 0xd000;
+pub const VIDEO_SIZE : usize = 0x400;
+pub const KEYBOARD : u16 = 0xdf00;
+pub const ACIA : u16 = 0xf000;
+pub const MONITOR_ROM : u16 = 0xf800;
+pub const MONITOR_ROM_SIZE : usize = 0x800;
+
+pub const COLUMNS : usize = 32;
+pub const ROWS : usize = 32;
+
+/// How often a changed screen is redrawn, about 50 times a second.
+const RENDER_CYCLES : u32 = 20_000;
+
+const RUBOUT : u8 = 0x7f;
+
+/// Ohio Scientific Superboard II / Challenger 1P: RAM from $0000, BASIC in
+/// ROM at $A000-$BFFF, 1K of video RAM at $D000, the keyboard at $DF00,
+/// a 6850 ACIA at $F000 (RS on A0, repeating through $F0FF) and the
+/// SYNMON monitor at $F800-$FFFF. Unmapped addresses read the floating
+/// bus.
+///
+/// The video RAM is a 32x32 character screen, of which a real monitor
+/// shows about 24x24. It is redrawn on the terminal when it changes;
+/// the graphics characters outside printable ASCII show as `#`.
+///
+/// The ACIA runs on a `Scheduler`. Its IRQ output isn't connected on the
+/// Superboard, so it drives no CPU line.
+pub struct Osi {
+    memory: Vec<u8>,
+    ram_top: u16,
+    basic: bool,
+    monitor: bool,
+    keyboard: Keyboard,
+    scheduler: Scheduler,
+    acia: usize,
+    render: bool,
+    render_cycles: u32,
+    rendered: String,
+    output: Box<dyn OutputSink>
+}
+
+impl Osi {
+    /// An OSI with RAM up to `ram_top` and empty ROM sockets.
+    pub fn new(ram_top: u16) -> Osi {
+        let mut scheduler = Scheduler::new();
+        let acia = scheduler.add(Box::new(Mc6850::new(OSI_HZ, ACIA_CLOCK_HZ)), Line::None);
+        Osi {
+            memory: vec![0; 0x10000],
+            ram_top,
+            basic: false,
+            monitor: false,
+            keyboard: Keyboard::new(),
+            scheduler,
+            acia,
+            render: true,
+            render_cycles: 0,
+            rendered: String::new(),
+            output: Box::new(RawSink::stdout())
+        }
+    }
+
+    /// Installs the BASIC ROMs, up to 8K at $A000.
+    pub fn set_basic_rom(&mut self, data: &[u8]) {
+        let len = data.len().min(BASIC_ROM_SIZE);
+        let start = BASIC_ROM as usize;
+        self.memory[start..start + len].copy_from_slice(&data[..len]);
+        self.basic = true;
+    }
+
+    /// Installs the monitor ROM, 2K ending at $FFFF.
+    pub fn set_monitor_rom(&mut self, data: &[u8]) {
+        let data = &data[data.len().saturating_sub(MONITOR_ROM_SIZE)..];
+        let start = 0x10000 - data.len();
+        self.memory[start..].copy_from_slice(data);
+        self.monitor = true;
+    }
+
+    /// Replaces the ACIA, e.g. with one connected to a serial line.
+    pub fn attach_acia(&mut self, acia: Mc6850) {
+        self.scheduler.replace(self.acia, Box::new(acia));
+    }
+
+    /// Turns redrawing the screen on the terminal on or off.
+    pub fn set_render(&mut self, render: bool) {
+        self.render = render;
+    }
+
+    /// Sends the screen's redraws somewhere other than stdout.
+    pub fn set_output(&mut self, output: Box<dyn OutputSink>) {
+        self.output = output;
+    }
+
+    fn is_rom(&self, address: u16) -> bool {
+        (self.basic && (BASIC_ROM..BASIC_ROM + BASIC_ROM_SIZE as u16).contains(&address))
+            || (self.monitor && address >= MONITOR_ROM)
+    }
+
+    fn is_video(address: u16) -> bool {
+        (VIDEO..VIDEO + VIDEO_SIZE as u16).contains(&address)
+    }
+
+    /// The whole 32x32 screen, one line per row with trailing blanks
+    /// removed.
+    pub fn screen_text(&self) -> String {
+        let video = &self.memory[VIDEO as usize..VIDEO as usize + VIDEO_SIZE];
+        video.chunks(COLUMNS).map(|row| {
+            let line : String = row.iter().map(|&code| match code {
+                0x20..=0x7e => code as char,
+                _ => '#'
+            }).collect();
+            line.trim_end().to_string()
+        }).collect::<Vec<String>>().join("\n")
+    }
+
+    fn draw(&mut self) {
+        let text = self.screen_text();
+        if text == self.rendered {
+            return;
+        }
+        // home the cursor and clear each line as it is redrawn, clearing
+        // the whole terminal the first time
+        let mut out = String::from(if self.rendered.is_empty() { "\x1b[2J\x1b[H" } else { "\x1b[H" });
+        for line in text.lines() {
+            out.push_str(line);
+            out.push_str("\x1b[K\r\n");
+        }
+        for byte in out.bytes() {
+            self.output.output(byte);
+        }
+        self.output.flush();
+        self.rendered = text;
+    }
+}
+
+impl Default for Osi {
+    fn default() -> Osi {
+        Osi::new(0x1fff)
+    }
+}
+
+impl Platform for Osi {
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            _ if address <= self.ram_top || self.is_rom(address) || Osi::is_video(address) => self.memory[address as usize],
+            0xdf00..=0xdfff => self.keyboard.read(),
+            0xf000..=0xf0ff => self.scheduler.read(self.acia, address & 0x01),
+            _ => (address >> 8) as u8
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            _ if self.is_rom(address) => {}
+            _ if address <= self.ram_top || Osi::is_video(address) => self.memory[address as usize] = value,
+            0xdf00..=0xdfff => self.keyboard.write(value),
+            0xf000..=0xf0ff => self.scheduler.write(self.acia, address & 0x01, value),
+            _ => {}
+        }
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn load(&mut self, program: Vec<u8>, address: u16) {
+        for a in 0..=self.ram_top as usize {
+            self.memory[a] = 0;
+        }
+        for b in &mut self.memory[VIDEO as usize..VIDEO as usize + VIDEO_SIZE] {
+            *b = b' ';
+        }
+        for (i, &byte) in program.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.keyboard.tick(cycles);
+        self.scheduler.advance(cycles);
+        self.render_cycles += cycles;
+        if self.render_cycles >= RENDER_CYCLES {
+            self.render_cycles = 0;
+            if self.render {
+                self.draw();
+            }
+        }
+    }
+
+    fn video_text(&self) -> Option<String> {
+        Some(self.screen_text())
+    }
+
+    fn key_ready(&self) -> bool {
+        self.keyboard.is_ready()
+    }
+
+    /// The host terminal sends rubout as `_`, which the OSI keyboard
+    /// doesn't have.
+    fn key_pressed(&mut self, key: u8) {
+        let key = match key & 0x7f {
+            b'_' => RUBOUT,
+            key => key
+        };
+        self.keyboard.press(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::MOS6502;
+
+    #[test]
+    fn memory_map() {
+        let mut osi = Osi::new(0x7fff);
+        osi.set_basic_rom(&[0x60; 0x2000]);
+        osi.set_monitor_rom(&[0xea; 0x800]);
+        osi.write(0x7fff, 0x55);
+        osi.write(0x8000, 0x55);
+        osi.write(0xa000, 0x55);
+        osi.write(0xd3ff, b'!');
+        assert_eq!(osi.read(0x7fff), 0x55);
+        assert_eq!(osi.read(0x8000), 0x80);
+        assert_eq!(osi.read(0xa000), 0x60);
+        assert_eq!(osi.read(0xfffc), 0xea);
+        assert_eq!(osi.read(0xd3ff), b'!');
+        assert_eq!(osi.read(0xdf00), 0xff);
+        // the ACIA waits in master reset
+        assert_eq!(osi.read(0xf000) & ::acia::mc6850::TDRE, 0);
+        osi.write(0xf000, 0x11);
+        assert_eq!(osi.read(0xf0fe) & ::acia::mc6850::TDRE, ::acia::mc6850::TDRE);
+    }
+
+    #[test]
+    fn keyboard_poll_and_screen() {
+        // a monitor that waits for Q on row 1, then writes HI on the
+        // second screen line
+        let program = [
+            0xa9, 0xfd, 0x8d, 0x00, 0xdf,       // lda #$fd; sta $df00
+            0xad, 0x00, 0xdf,                   // wait: lda $df00
+            0x30, 0xfb,                         // bmi wait
+            0xa9, b'H', 0x8d, 0x20, 0xd0,       // lda #'H'; sta $d020
+            0xa9, b'I', 0x8d, 0x21, 0xd0,       // lda #'I'; sta $d021
+            0x00                                // brk
+        ];
+        let mut monitor = vec![0xea; MONITOR_ROM_SIZE];
+        monitor[..program.len()].copy_from_slice(&program);
+        monitor[0x7fc] = 0x00;
+        monitor[0x7fd] = 0xf8;
+        let mut osi = Osi::default();
+        osi.set_monitor_rom(&monitor);
+        osi.set_render(false);
+        osi.load(Vec::new(), 0);
+
+        let mut cpu = MOS6502::new(Box::new(osi));
+        cpu.reset();
+        for _ in 0..100 {
+            cpu.step();
+        }
+        assert!(cpu.is_running());
+        assert!(cpu.key_ready());
+        cpu.key_pressed(b'Q' | 0x80);
+        while cpu.is_running() && cpu.get_total_cycles() < 100_000 {
+            cpu.step();
+        }
+        assert_eq!(cpu.platform().video_text().unwrap().lines().nth(1), Some("HI"));
+    }
+}
@@ -0,0 +1,331 @@
+use device::Device;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Port {
+    A,
+    B
+}
+
+/// Control register bits.
+pub const C1_IRQ_ENABLE : u8 = 0x01;
+pub const C1_RISING : u8 = 0x02;
+pub const SELECT_OUTPUT : u8 = 0x04;
+pub const C2_IRQ_ENABLE : u8 = 0x08;
+pub const C2_RISING : u8 = 0x10;
+pub const C2_OUTPUT : u8 = 0x20;
+pub const IRQ2_FLAG : u8 = 0x40;
+pub const IRQ1_FLAG : u8 = 0x80;
+
+#[derive(Debug, Clone, Default)]
+struct Side {
+    output: u8,
+    direction: u8,
+    control: u8,
+    input: u8,
+    c1: bool,
+    c2_in: bool,
+    c2_out: bool,
+    pulse: bool
+}
+
+impl Side {
+    fn new() -> Side {
+        Side { c2_out: true, ..Side::default() }
+    }
+
+    /// What the port pins read: outputs from the output register, inputs
+    /// from the peripheral.
+    fn pins(&self) -> u8 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+
+    fn control(&self) -> u8 {
+        if self.control & C2_OUTPUT != 0 {
+            self.control & !IRQ2_FLAG
+        } else {
+            self.control
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.control = (self.control & (IRQ1_FLAG | IRQ2_FLAG)) | (value & 0x3f);
+        if value & C2_OUTPUT != 0 {
+            self.control &= !IRQ2_FLAG;
+            if value & C2_RISING != 0 {
+                self.c2_out = value & C2_IRQ_ENABLE != 0;
+                self.pulse = false;
+            }
+        }
+    }
+
+    /// Handshake and pulse modes pull C2 low when the port is accessed.
+    fn strobe(&mut self) {
+        if self.control & (C2_OUTPUT | C2_RISING) == C2_OUTPUT {
+            self.c2_out = false;
+            self.pulse = self.control & C2_IRQ_ENABLE != 0;
+        }
+    }
+
+    fn set_c1(&mut self, level: bool) {
+        let active = level == (self.control & C1_RISING != 0);
+        if level != self.c1 && active {
+            self.control |= IRQ1_FLAG;
+            if self.control & (C2_OUTPUT | C2_RISING | C2_IRQ_ENABLE) == C2_OUTPUT {
+                self.c2_out = true;
+            }
+        }
+        self.c1 = level;
+    }
+
+    fn set_c2(&mut self, level: bool) {
+        let active = level == (self.control & C2_RISING != 0);
+        if level != self.c2_in && active && self.control & C2_OUTPUT == 0 {
+            self.control |= IRQ2_FLAG;
+        }
+        self.c2_in = level;
+    }
+
+    fn irq(&self) -> bool {
+        let control = self.control();
+        (control & IRQ1_FLAG != 0 && control & C1_IRQ_ENABLE != 0)
+            || (control & IRQ2_FLAG != 0 && control & C2_IRQ_ENABLE != 0)
+    }
+}
+
+/// MOS 6821 Peripheral Interface Adapter.
+///
+/// Registers are selected by RS1-RS0, so callers pass `address & 3`:
+/// 0 is ORA or DDRA, 1 is CRA, 2 is ORB or DDRB and 3 is CRB. Bit 2 of a
+/// control register picks the output register over the data direction
+/// register. The host drives the port inputs and the C1/C2 lines and reads
+/// back the port outputs, C2 and the two interrupt lines.
+#[derive(Debug, Clone)]
+pub struct Pia {
+    a: Side,
+    b: Side
+}
+
+impl Pia {
+    pub fn new() -> Pia {
+        Pia {
+            a: Side::new(),
+            b: Side::new()
+        }
+    }
+
+    /// The RESET line clears every register.
+    pub fn reset(&mut self) {
+        let (a_input, b_input) = (self.a.input, self.b.input);
+        *self = Pia::new();
+        self.a.input = a_input;
+        self.b.input = b_input;
+    }
+
+    fn side(&self, port: Port) -> &Side {
+        match port {
+            Port::A => &self.a,
+            Port::B => &self.b
+        }
+    }
+
+    fn side_mut(&mut self, port: Port) -> &mut Side {
+        match port {
+            Port::A => &mut self.a,
+            Port::B => &mut self.b
+        }
+    }
+
+    fn port(register: u16) -> Port {
+        if register & 0x02 == 0 { Port::A } else { Port::B }
+    }
+
+    /// Reads a register without the side effects of a bus read.
+    pub fn peek(&self, register: u16) -> u8 {
+        let side = self.side(Pia::port(register));
+        match (register & 0x01, side.control & SELECT_OUTPUT != 0) {
+            (1, _) => side.control(),
+            (_, true) => side.pins(),
+            (_, false) => side.direction
+        }
+    }
+
+    /// Reading a port clears its interrupt flags, and on port A starts the
+    /// CA2 handshake.
+    pub fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        let port = Pia::port(register);
+        let side = self.side_mut(port);
+        if register & 0x01 == 0 && side.control & SELECT_OUTPUT != 0 {
+            side.control &= !(IRQ1_FLAG | IRQ2_FLAG);
+            if port == Port::A {
+                side.strobe();
+            }
+        }
+        value
+    }
+
+    /// Writing port B's output register starts the CB2 handshake.
+    pub fn write(&mut self, register: u16, value: u8) {
+        let port = Pia::port(register);
+        let side = self.side_mut(port);
+        match (register & 0x01, side.control & SELECT_OUTPUT != 0) {
+            (1, _) => side.write_control(value),
+            (_, true) => {
+                side.output = value;
+                if port == Port::B {
+                    side.strobe();
+                }
+            }
+            (_, false) => side.direction = value
+        }
+    }
+
+    /// Whether register 0 or 2 currently reaches the output register
+    /// rather than the data direction register.
+    pub fn selects_output(&self, port: Port) -> bool {
+        self.side(port).control & SELECT_OUTPUT != 0
+    }
+
+    /// Levels the peripheral drives onto the port's input pins.
+    pub fn set_input(&mut self, port: Port, value: u8) {
+        self.side_mut(port).input = value;
+    }
+
+    /// The port's output bits; input bits read as 0.
+    pub fn output(&self, port: Port) -> u8 {
+        let side = self.side(port);
+        side.output & side.direction
+    }
+
+    pub fn set_c1(&mut self, port: Port, level: bool) {
+        self.side_mut(port).set_c1(level);
+    }
+
+    pub fn set_c2(&mut self, port: Port, level: bool) {
+        self.side_mut(port).set_c2(level);
+    }
+
+    /// The C2 level while it is an output, high otherwise.
+    pub fn c2(&self, port: Port) -> bool {
+        let side = self.side(port);
+        side.control & C2_OUTPUT == 0 || side.c2_out
+    }
+
+    pub fn irq_a(&self) -> bool {
+        self.a.irq()
+    }
+
+    pub fn irq_b(&self) -> bool {
+        self.b.irq()
+    }
+
+    /// Ends C2 pulses, which last one cycle.
+    pub fn tick(&mut self, _cycles: u32) {
+        for side in [&mut self.a, &mut self.b] {
+            if side.pulse {
+                side.pulse = false;
+                side.c2_out = true;
+            }
+        }
+    }
+}
+
+impl Default for Pia {
+    fn default() -> Pia {
+        Pia::new()
+    }
+}
+
+impl Device for Pia {
+    fn read(&mut self, register: u16) -> u8 {
+        Pia::read(self, register)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        Pia::write(self, register, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Pia::tick(self, cycles)
+    }
+
+    fn reset(&mut self) {
+        Pia::reset(self)
+    }
+
+    /// IRQA and IRQB tied together.
+    fn irq(&self) -> bool {
+        self.irq_a() || self.irq_b()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_and_output_registers() {
+        let mut pia = Pia::new();
+        pia.write(2, 0x7f);
+        pia.write(3, SELECT_OUTPUT);
+        pia.set_input(Port::B, 0x80);
+        pia.write(2, 0xc1);
+        assert_eq!(pia.output(Port::B), 0x41);
+        assert_eq!(pia.read(2), 0xc1);
+        pia.set_input(Port::B, 0x00);
+        assert_eq!(pia.read(2), 0x41);
+        pia.write(3, 0);
+        assert_eq!(pia.read(2), 0x7f);
+    }
+
+    #[test]
+    fn c1_edge_sets_flag_until_port_read() {
+        let mut pia = Pia::new();
+        pia.write(1, SELECT_OUTPUT | C1_RISING);
+        pia.set_input(Port::A, 0xc1);
+        pia.set_c1(Port::A, true);
+        assert_eq!(pia.peek(1) & IRQ1_FLAG, IRQ1_FLAG);
+        assert!(!pia.irq_a());
+        pia.write(1, SELECT_OUTPUT | C1_RISING | C1_IRQ_ENABLE | IRQ1_FLAG);
+        assert!(pia.irq_a());
+        assert_eq!(pia.read(0), 0xc1);
+        assert_eq!(pia.peek(1) & IRQ1_FLAG, 0);
+        assert!(!pia.irq_a());
+        // a falling edge is ignored while C1 is set for rising edges
+        pia.set_c1(Port::A, false);
+        assert_eq!(pia.peek(1) & IRQ1_FLAG, 0);
+    }
+
+    #[test]
+    fn c2_input_and_outputs() {
+        let mut pia = Pia::new();
+        pia.write(3, SELECT_OUTPUT | C2_IRQ_ENABLE);
+        pia.set_c2(Port::B, true);
+        assert_eq!(pia.peek(3) & IRQ2_FLAG, 0);
+        pia.set_c2(Port::B, false);
+        assert_eq!(pia.peek(3) & IRQ2_FLAG, IRQ2_FLAG);
+        assert!(pia.irq_b());
+
+        // manual output follows bit 3
+        pia.write(3, C2_OUTPUT | C2_RISING);
+        assert!(!pia.c2(Port::B));
+        assert_eq!(pia.peek(3) & IRQ2_FLAG, 0);
+        pia.write(3, C2_OUTPUT | C2_RISING | C2_IRQ_ENABLE);
+        assert!(pia.c2(Port::B));
+
+        // handshake: CB2 drops on a write and returns on the CB1 edge
+        pia.write(3, SELECT_OUTPUT | C2_OUTPUT);
+        pia.write(2, 0x55);
+        assert!(!pia.c2(Port::B));
+        pia.set_c1(Port::B, true);
+        pia.set_c1(Port::B, false);
+        assert!(pia.c2(Port::B));
+
+        // pulse: CA2 drops on a read for one cycle
+        pia.write(1, SELECT_OUTPUT | C2_OUTPUT | C2_IRQ_ENABLE);
+        pia.read(0);
+        assert!(!pia.c2(Port::A));
+        pia.tick(1);
+        assert!(pia.c2(Port::A));
+    }
+}
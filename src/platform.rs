@@ -1,8 +1,65 @@
+use display::Display;
+use aci::Aci;
+use hd44780::Hd44780;
+
+/// The CPU registers a platform sees when it traps a call.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16
+}
+
 pub trait Platform {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, value: u8);
+    fn poke(&mut self, addr: u16, value: u8);
     fn load(&mut self, program: Vec<u8>, address: u16);
+    fn tick(&mut self, _cycles: u32) {}
+
+    /// Pulls the RESET line, which returns the chips wired to it to their
+    /// power-on state. The CPU is reset separately; memory is kept.
+    fn reset(&mut self) {}
     fn key_ready(&self) -> bool;
     fn key_pressed(&mut self, key: u8);
+    fn display(&self) -> Option<&Display> {
+        None
+    }
+
+    /// The level of the IRQ line, which the CPU takes between instructions
+    /// while interrupts are enabled.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// True once for each falling edge on the NMI line, which the CPU takes
+    /// after the current instruction.
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    /// The cassette interface, on machines that have one.
+    fn aci(&self) -> Option<&Aci> {
+        None
+    }
+
+    /// The character LCD, on machines that have one.
+    fn lcd(&self) -> Option<&Hd44780> {
+        None
+    }
+
+    /// The text on a memory-mapped video screen, on machines that have one.
+    fn video_text(&self) -> Option<String> {
+        None
+    }
+
+    /// Called before each instruction. A platform that runs the routine at
+    /// `registers.pc` itself updates the registers and returns true, and
+    /// the CPU skips fetching the instruction.
+    fn trap(&mut self, _registers: &mut Registers) -> bool {
+        false
+    }
 }
 
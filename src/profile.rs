@@ -0,0 +1,221 @@
+use apple1::Apple1;
+use aci::Aci;
+use loader;
+
+/// The EhBASIC build in the repository root, assembled for $C000.
+const EHBASIC : &[u8] = include_bytes!("../ehbasic.bin");
+/// EhBASIC without its simulator reset page, $C000-$E8FF.
+const EHBASIC_LEN : usize = 0x2900;
+
+/// I/O glue for EhBASIC at $E900, listed in ehbasic-apple1.asm.
+pub const EHBASIC_GLUE : [u8; 88] = [
+    0xd8, 0xa2, 0xff, 0x9a, 0xa0, 0x1c, 0xb9, 0x32, 0xe9, 0x99, 0x04, 0x02, 0x88, 0xd0, 0xf7, 0x4c,
+    0x00, 0xc0, 0xad, 0x11, 0xd0, 0x10, 0x0d, 0xad, 0x10, 0xd0, 0x29, 0x7f, 0xc9, 0x5f, 0xd0, 0x02,
+    0xa9, 0x08, 0x38, 0x60, 0x18, 0x60, 0xc9, 0x0a, 0xf0, 0x07, 0x48, 0x09, 0x80, 0x20, 0xef, 0xff,
+    0x68, 0x60, 0x60, 0x12, 0xe9, 0x26, 0xe9, 0x32, 0xe9, 0x32, 0xe9, 0x48, 0xa5, 0xdf, 0x4a, 0x05,
+    0xdf, 0x85, 0xdf, 0x68, 0x40, 0x48, 0xa5, 0xdc, 0x4a, 0x05, 0xdc, 0x85, 0xdc, 0x68, 0x40, 0x98,
+    0x65, 0xa5, 0x85, 0x96, 0xa8, 0xa5, 0x95, 0x60];
+
+/// Moves EhBASIC code off the PIA registers at $D010-$D013; see
+/// ehbasic-apple1.asm.
+const EHBASIC_PATCHES : &[(u16, &[u8])] = &[
+    (0xd00b, &[0x4c, 0x4f, 0xe9, 0x60]),
+    (0xcfbf, &[0x4e]),
+    (0xd06e, &[0x9f])
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamSize {
+    K4,
+    K8,
+    K32,
+    K48,
+    K64
+}
+
+impl RamSize {
+    pub fn from_name(name: &str) -> Option<RamSize> {
+        match name.to_ascii_lowercase().trim_end_matches('k') {
+            "4" => Some(RamSize::K4),
+            "8" => Some(RamSize::K8),
+            "32" => Some(RamSize::K32),
+            "48" => Some(RamSize::K48),
+            "64" => Some(RamSize::K64),
+            _ => None
+        }
+    }
+
+    /// Where the RAM sits. An 8K Apple 1 has its second 4K bank at $E000,
+    /// where Integer BASIC was loaded from tape.
+    pub fn ranges(&self) -> Vec<(u16, u16)> {
+        match *self {
+            RamSize::K4 => vec![(0x0000, 0x0fff)],
+            RamSize::K8 => vec![(0x0000, 0x0fff), (0xe000, 0xefff)],
+            RamSize::K32 => vec![(0x0000, 0x7fff)],
+            RamSize::K48 => vec![(0x0000, 0xbfff)],
+            RamSize::K64 => vec![(0x0000, 0xffff)]
+        }
+    }
+}
+
+/// A ROM image a profile maps in. Images without built-in contents must be
+/// supplied with `--rom <name>=<file>`.
+#[derive(Debug, Clone, Copy)]
+pub struct RomSlot {
+    pub name: &'static str,
+    pub address: u16,
+    pub max_len: usize,
+    pub builtin: Option<&'static [u8]>
+}
+
+/// A named Apple 1 configuration: RAM size, ROMs and cards.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub ram: RamSize,
+    pub roms: Vec<RomSlot>,
+    /// Bytes changed in the ROM images once they are mapped.
+    pub patches: &'static [(u16, &'static [u8])],
+    pub pia_mirrors: bool,
+    pub aci: bool
+}
+
+const BASIC : RomSlot = RomSlot { name: "basic", address: 0xe000, max_len: 0x1000, builtin: None };
+/// Krusader sits below Wozmon; a 4K image that carries its own monitor page
+/// replaces Wozmon too.
+const KRUSADER : RomSlot = RomSlot { name: "krusader", address: 0xf000, max_len: 0x1000, builtin: None };
+
+pub fn profiles() -> Vec<Profile> {
+    vec![
+        Profile {
+            name: "apple1",
+            description: "Wozmon and ACI with 64K of RAM for test programs",
+            ram: RamSize::K64,
+            roms: vec![],
+            patches: &[],
+            pia_mirrors: true,
+            aci: true
+        },
+        Profile {
+            name: "apple1-basic",
+            description: "8K Apple 1 with Integer BASIC at $E000 (E000R cold, E2B3R warm)",
+            ram: RamSize::K8,
+            roms: vec![BASIC],
+            patches: &[],
+            pia_mirrors: true,
+            aci: true
+        },
+        Profile {
+            name: "replica1",
+            description: "32K with Integer BASIC at $E000 and Krusader at $F000",
+            ram: RamSize::K32,
+            roms: vec![BASIC, KRUSADER],
+            patches: &[],
+            pia_mirrors: true,
+            aci: true
+        },
+        Profile {
+            name: "apple1-ehbasic",
+            description: "48K with EhBASIC at $C000 (E900R to start)",
+            ram: RamSize::K48,
+            roms: vec![
+                RomSlot { name: "ehbasic", address: 0xc000, max_len: EHBASIC_LEN, builtin: Some(&EHBASIC[..EHBASIC_LEN]) },
+                RomSlot { name: "ehbasic-glue", address: 0xe900, max_len: EHBASIC_GLUE.len(), builtin: Some(&EHBASIC_GLUE) }
+            ],
+            patches: EHBASIC_PATCHES,
+            pia_mirrors: false,
+            aci: false
+        }
+    ]
+}
+
+pub fn find(name: &str) -> Option<Profile> {
+    let name = name.to_ascii_lowercase();
+    let name = if name == "apple-1" { "apple1" } else { name.as_str() };
+    profiles().into_iter().find(|profile| profile.name == name)
+}
+
+impl Profile {
+    /// Builds the machine. `ram` overrides the profile's RAM size and
+    /// `rom_files` supplies (name, file) pairs for the ROM slots.
+    pub fn build(&self, ram: Option<RamSize>, rom_files: &[(String, String)]) -> Result<Apple1, String> {
+        let mut apple1 = Apple1::new();
+        apple1.set_ram(&ram.unwrap_or(self.ram).ranges());
+        for (name, _) in rom_files {
+            if !self.roms.iter().any(|slot| slot.name == name) {
+                return Err(format!("{} has no {} ROM", self.name, name));
+            }
+        }
+        for slot in &self.roms {
+            let data = match rom_files.iter().find(|file| file.0 == slot.name) {
+                Some((_, filename)) => loader::read_file(filename)
+                    .map_err(|err| format!("error loading {}: {}", filename, err))?,
+                None => match slot.builtin {
+                    Some(data) => data.to_vec(),
+                    None => return Err(format!("{} needs the {} ROM image: --rom {}=<file>", self.name, slot.name, slot.name))
+                }
+            };
+            if data.is_empty() || data.len() > slot.max_len {
+                return Err(format!("{} ROM must be 1 to {} bytes, got {}", slot.name, slot.max_len, data.len()));
+            }
+            apple1.install_rom(slot.address, &data);
+        }
+        for &(address, data) in self.patches {
+            apple1.install_rom(address, data);
+        }
+        apple1.set_pia_mirrors(self.pia_mirrors);
+        if self.aci {
+            apple1.attach_aci(Aci::new());
+        }
+        Ok(apple1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::MOS6502;
+    use platform::Platform;
+
+    #[test]
+    fn names() {
+        assert_eq!(find("Apple-1").unwrap().name, "apple1");
+        assert!(find("replica1").is_some());
+        assert!(find("c64").is_none());
+        assert_eq!(RamSize::from_name("32K"), Some(RamSize::K32));
+        assert_eq!(RamSize::from_name("16k"), None);
+    }
+
+    #[test]
+    fn missing_rom() {
+        let profile = find("apple1-basic").unwrap();
+        match profile.build(None, &[]) {
+            Err(message) => assert!(message.contains("--rom basic="), "{}", message),
+            Ok(_) => panic!("built without the BASIC ROM")
+        }
+        let extra = [(String::from("krusader"), String::from("k.rom"))];
+        assert!(profile.build(None, &extra).is_err());
+    }
+
+    #[test]
+    fn ehbasic_starts() {
+        let mut apple1 = find("apple1-ehbasic").unwrap().build(None, &[]).unwrap();
+        apple1.load(Vec::new(), 0);
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu.set_pc(0xe900);
+        let mut keys = "\rPRINT 6*7\r".bytes().map(|b| b | 0x80);
+        while cpu.get_total_cycles() < 5_000_000 {
+            if cpu.key_ready() {
+                if let Some(key) = keys.next() {
+                    cpu.key_pressed(key);
+                }
+            }
+            cpu.step();
+        }
+        let text = cpu.platform().display().unwrap().text();
+        assert!(text.contains("MEMORY SIZE"), "{}", text);
+        assert!(text.contains(" 42"), "{}", text);
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use symbols::SymbolTable;
+
+const ADDRESS_SPACE : usize = 65536;
+const MAX_CALL_DEPTH : usize = 256;
+const OP_JSR : u8 = 0x20;
+const OP_RTS : u8 = 0x60;
+const OP_RTI : u8 = 0x40;
+const OP_BRK : u8 = 0x00;
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    pub exclusive_cycles: u64,
+    pub inclusive_cycles: u64
+}
+
+/// Per-address and per-routine cycle accounting.
+///
+/// Routines are identified by their JSR entry point. Everything executed before
+/// the first JSR is attributed to the entry the profiler was started at.
+pub struct Profiler {
+    instructions: Vec<u64>,
+    cycles: Vec<u64>,
+    calls: HashMap<u16, u64>,
+    call_stack: Vec<u16>,
+    stacks: HashMap<Vec<u16>, u64>,
+    pending_cycles: u64,
+    total_instructions: u64,
+    total_cycles: u64
+}
+
+impl Profiler {
+    pub fn new(entry: u16) -> Profiler {
+        Profiler {
+            instructions: vec![0; ADDRESS_SPACE],
+            cycles: vec![0; ADDRESS_SPACE],
+            calls: HashMap::new(),
+            call_stack: vec![entry],
+            stacks: HashMap::new(),
+            pending_cycles: 0,
+            total_instructions: 0,
+            total_cycles: 0
+        }
+    }
+
+    /// Accounts one executed instruction. `next_pc` is the PC after execution.
+    pub fn record(&mut self, pc: u16, opcode: u8, cycles: u32, next_pc: u16) {
+        let cycles = cycles as u64;
+        self.instructions[pc as usize] += 1;
+        self.cycles[pc as usize] += cycles;
+        self.total_instructions += 1;
+        self.total_cycles += cycles;
+        self.pending_cycles += cycles;
+
+        match opcode {
+            OP_JSR => self.call(next_pc),
+            // a BRK that halts the CPU leaves PC on the byte after it
+            OP_BRK if next_pc != pc.wrapping_add(1) => self.call(next_pc),
+            // the root frame is never popped; unbalanced returns are
+            // common with stack tricks like pushing an address and RTS
+            OP_RTS | OP_RTI if self.call_stack.len() > 1 => {
+                self.flush();
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    /// Accounts an interrupt, which spends `cycles` in the interrupted
+    /// routine and enters `handler` as a call that ends at its RTI.
+    pub fn interrupt(&mut self, cycles: u32, handler: u16) {
+        self.total_cycles += cycles as u64;
+        self.pending_cycles += cycles as u64;
+        self.call(handler);
+    }
+
+    fn call(&mut self, entry: u16) {
+        self.flush();
+        *self.calls.entry(entry).or_insert(0) += 1;
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            self.call_stack.remove(1);
+        }
+        self.call_stack.push(entry);
+    }
+
+    fn flush(&mut self) {
+        if self.pending_cycles > 0 {
+            let stack = self.call_stack.clone();
+            *self.stacks.entry(stack).or_insert(0) += self.pending_cycles;
+            self.pending_cycles = 0;
+        }
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.total_instructions
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn instructions_at(&self, address: u16) -> u64 {
+        self.instructions[address as usize]
+    }
+
+    pub fn cycles_at(&self, address: u16) -> u64 {
+        self.cycles[address as usize]
+    }
+
+    /// Collapsed call stacks with their cycle counts, including any cycles
+    /// not yet attributed to a stack.
+    fn collapsed(&self) -> Vec<(Vec<u16>, u64)> {
+        let mut stacks = self.stacks.clone();
+        if self.pending_cycles > 0 {
+            *stacks.entry(self.call_stack.clone()).or_insert(0) += self.pending_cycles;
+        }
+        let mut result: Vec<(Vec<u16>, u64)> = stacks.into_iter().collect();
+        result.sort();
+        result
+    }
+
+    pub fn routines(&self) -> HashMap<u16, RoutineStats> {
+        let mut routines: HashMap<u16, RoutineStats> = HashMap::new();
+        for (stack, cycles) in self.collapsed() {
+            let mut seen: Vec<u16> = Vec::new();
+            for entry in &stack {
+                if !seen.contains(entry) {
+                    seen.push(*entry);
+                    routines.entry(*entry).or_default().inclusive_cycles += cycles;
+                }
+            }
+            if let Some(top) = stack.last() {
+                routines.entry(*top).or_default().exclusive_cycles += cycles;
+            }
+        }
+        for (entry, calls) in &self.calls {
+            routines.entry(*entry).or_default().calls = *calls;
+        }
+        routines
+    }
+
+    pub fn write_report<W: Write>(&self, out: &mut W, symbols: Option<&SymbolTable>, limit: usize) -> io::Result<()> {
+        let label = |addr: u16| match symbols {
+            Some(table) => table.label(addr),
+            None => format!("${:04X}", addr)
+        };
+
+        writeln!(out, "instructions {}, cycles {}", self.total_instructions, self.total_cycles)?;
+        writeln!(out)?;
+
+        let mut routines: Vec<(u16, RoutineStats)> = self.routines().into_iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive_cycles.cmp(&a.1.inclusive_cycles).then(a.0.cmp(&b.0)));
+        writeln!(out, "{:<24} {:>10} {:>14} {:>14} {:>7}", "routine", "calls", "exclusive", "inclusive", "%")?;
+        for (entry, stats) in routines.iter().take(limit) {
+            writeln!(out, "{:<24} {:>10} {:>14} {:>14} {:>6.2}%",
+                label(*entry),
+                stats.calls,
+                stats.exclusive_cycles,
+                stats.inclusive_cycles,
+                percent(stats.inclusive_cycles, self.total_cycles))?;
+        }
+        writeln!(out)?;
+
+        let mut addresses: Vec<usize> = (0..ADDRESS_SPACE).filter(|a| self.instructions[*a] > 0).collect();
+        addresses.sort_by(|a, b| self.cycles[*b].cmp(&self.cycles[*a]).then(a.cmp(b)));
+        writeln!(out, "{:<24} {:>10} {:>14} {:>7}", "address", "count", "cycles", "%")?;
+        for addr in addresses.iter().take(limit) {
+            let name = match symbols.and_then(|table| table.nearest(*addr as u16)) {
+                Some((name, 0)) => format!("${:04X} {}", addr, name),
+                Some((name, offset)) => format!("${:04X} {}+{}", addr, name, offset),
+                None => format!("${:04X}", addr)
+            };
+            writeln!(out, "{:<24} {:>10} {:>14} {:>6.2}%",
+                name,
+                self.instructions[*addr],
+                self.cycles[*addr],
+                percent(self.cycles[*addr], self.total_cycles))?;
+        }
+        Ok(())
+    }
+
+    /// Writes `frame;frame;frame cycles` lines as consumed by flamegraph.pl and inferno.
+    pub fn write_collapsed<W: Write>(&self, out: &mut W, symbols: Option<&SymbolTable>) -> io::Result<()> {
+        for (stack, cycles) in self.collapsed() {
+            let frames: Vec<String> = stack.iter().map(|addr| match symbols {
+                Some(table) => table.label(*addr),
+                None => format!("${:04X}", addr)
+            }).collect();
+            writeln!(out, "{} {}", frames.join(";"), cycles)?;
+        }
+        Ok(())
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        (part as f64) * 100.0 / (total as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Profiler {
+        let mut profiler = Profiler::new(0x0400);
+        profiler.record(0x0400, 0xa9, 2, 0x0402);
+        profiler.record(0x0402, OP_JSR, 6, 0x1000);
+        profiler.record(0x1000, 0xea, 2, 0x1001);
+        profiler.record(0x1001, OP_RTS, 6, 0x0405);
+        profiler.record(0x0405, 0xea, 2, 0x0406);
+        profiler
+    }
+
+    #[test]
+    fn per_address() {
+        let profiler = sample();
+        assert_eq!(profiler.total_instructions(), 5);
+        assert_eq!(profiler.total_cycles(), 18);
+        assert_eq!(profiler.instructions_at(0x0402), 1);
+        assert_eq!(profiler.cycles_at(0x1001), 6);
+    }
+
+    #[test]
+    fn routines() {
+        let routines = sample().routines();
+        assert_eq!(routines[&0x1000], RoutineStats { calls: 1, exclusive_cycles: 8, inclusive_cycles: 8 });
+        assert_eq!(routines[&0x0400], RoutineStats { calls: 0, exclusive_cycles: 10, inclusive_cycles: 18 });
+    }
+
+    #[test]
+    fn collapsed() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x1000, "SUB");
+        let mut out: Vec<u8> = Vec::new();
+        sample().write_collapsed(&mut out, Some(&symbols)).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "$0400 10\n$0400;SUB 8\n");
+    }
+
+    #[test]
+    fn unbalanced_return() {
+        let mut profiler = Profiler::new(0x0400);
+        profiler.record(0x0400, OP_RTS, 6, 0x0500);
+        profiler.record(0x0500, 0xea, 2, 0x0501);
+        assert_eq!(profiler.routines()[&0x0400].exclusive_cycles, 8);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut profiler = Profiler::new(0x0400);
+        profiler.record(0x0400, 0xea, 2, 0x0401);
+        profiler.interrupt(7, 0x2000);
+        profiler.record(0x2000, 0xea, 2, 0x2001);
+        profiler.record(0x2001, OP_RTI, 6, 0x0401);
+        profiler.record(0x0401, 0xea, 2, 0x0402);
+        assert_eq!(profiler.total_cycles(), 19);
+        let routines = profiler.routines();
+        assert_eq!(routines[&0x2000], RoutineStats { calls: 1, exclusive_cycles: 8, inclusive_cycles: 8 });
+        assert_eq!(routines[&0x0400].exclusive_cycles, 11);
+    }
+
+    #[test]
+    fn brk() {
+        let mut profiler = Profiler::new(0x0400);
+        profiler.record(0x0400, OP_BRK, 7, 0x2000);
+        profiler.record(0x2000, OP_RTI, 6, 0x0402);
+        assert_eq!(profiler.routines()[&0x2000].calls, 1);
+
+        // halted: no handler to enter
+        let mut profiler = Profiler::new(0x0400);
+        profiler.record(0x0400, OP_BRK, 2, 0x0401);
+        assert_eq!(profiler.routines().len(), 1);
+    }
+}
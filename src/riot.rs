@@ -0,0 +1,309 @@
+use pia::Port;
+use device::Device;
+
+pub const RAM_SIZE : usize = 128;
+
+/// I/O registers, selected with A2 low.
+pub const ORA : u16 = 0x00;
+pub const DDRA : u16 = 0x01;
+pub const ORB : u16 = 0x02;
+pub const DDRB : u16 = 0x03;
+/// Timer writes with A4 and A2 set: A1-A0 pick the prescaler and A3
+/// enables the interrupt, e.g. $14 is divide by 1 and $1F divide by 1024
+/// with the interrupt on.
+pub const TIM1T : u16 = 0x14;
+pub const TIM8T : u16 = 0x15;
+pub const TIM64T : u16 = 0x16;
+pub const T1024T : u16 = 0x17;
+/// Reading with A2 set and A0 low gives the timer, A0 high the flags.
+pub const TIMER : u16 = 0x04;
+pub const FLAGS : u16 = 0x05;
+
+/// Interrupt flag bits.
+pub const TIMER_FLAG : u8 = 0x80;
+pub const PA7_FLAG : u8 = 0x40;
+
+const PRESCALE : [u32; 4] = [1, 8, 64, 1024];
+
+/// MOS 6532 RAM-I/O-Timer.
+///
+/// The host decodes the RS pin: RAM goes through `read_ram`/`write_ram`
+/// with `address & 0x7f`, everything else through `read`/`write` with
+/// `address & 0x1f`. The timer counts down once every 1, 8, 64 or 1024
+/// cycles and interrupts N*P+1 cycles after it is written with N; after
+/// passing zero it keeps counting down every cycle until written again.
+#[derive(Debug, Clone)]
+pub struct Riot {
+    ram: [u8; RAM_SIZE],
+    output: [u8; 2],
+    direction: [u8; 2],
+    input: [u8; 2],
+    timer: u8,
+    prescale: u32,
+    prescale_count: u32,
+    timer_irq: bool,
+    edge_rising: bool,
+    pa7_irq: bool,
+    pa7: bool,
+    flags: u8
+}
+
+fn index(port: Port) -> usize {
+    match port {
+        Port::A => 0,
+        Port::B => 1
+    }
+}
+
+impl Riot {
+    pub fn new() -> Riot {
+        Riot {
+            ram: [0; RAM_SIZE],
+            output: [0; 2],
+            direction: [0; 2],
+            input: [0xff; 2],
+            timer: 0xff,
+            prescale: 1024,
+            prescale_count: 1024,
+            timer_irq: false,
+            edge_rising: false,
+            pa7_irq: false,
+            pa7: true,
+            flags: 0
+        }
+    }
+
+    /// The RESET line clears the ports and disables the PA7 interrupt. RAM
+    /// and the timer are left alone.
+    pub fn reset(&mut self) {
+        self.output = [0; 2];
+        self.direction = [0; 2];
+        self.edge_rising = false;
+        self.pa7_irq = false;
+        self.timer_irq = false;
+        self.flags = 0;
+        self.pa7 = self.pins(Port::A) & 0x80 != 0;
+    }
+
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.ram[address as usize & (RAM_SIZE - 1)]
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.ram[address as usize & (RAM_SIZE - 1)] = value;
+    }
+
+    fn pins(&self, port: Port) -> u8 {
+        let i = index(port);
+        (self.output[i] & self.direction[i]) | (self.input[i] & !self.direction[i])
+    }
+
+    /// Sets the PA7 flag on the selected edge of the PA7 pin.
+    fn detect_edge(&mut self) {
+        let level = self.pins(Port::A) & 0x80 != 0;
+        if level != self.pa7 && level == self.edge_rising {
+            self.flags |= PA7_FLAG;
+        }
+        self.pa7 = level;
+    }
+
+    /// Reads a register without the side effects of a bus read.
+    pub fn peek(&self, register: u16) -> u8 {
+        match (register & 0x04 != 0, register & 0x03) {
+            (false, 0) => self.pins(Port::A),
+            (false, 1) => self.direction[0],
+            (false, 2) => self.pins(Port::B),
+            (false, _) => self.direction[1],
+            (true, r) if r & 0x01 == 0 => self.timer,
+            (true, _) => self.flags
+        }
+    }
+
+    /// Reading the timer clears its flag and sets its interrupt enable from
+    /// A3; reading the flags clears the PA7 flag.
+    pub fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        if register & 0x04 != 0 {
+            if register & 0x01 == 0 {
+                self.flags &= !TIMER_FLAG;
+                self.timer_irq = register & 0x08 != 0;
+            } else {
+                self.flags &= !PA7_FLAG;
+            }
+        }
+        value
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        if register & 0x04 == 0 {
+            let i = (register as usize >> 1) & 0x01;
+            if register & 0x01 == 0 {
+                self.output[i] = value;
+            } else {
+                self.direction[i] = value;
+            }
+            self.detect_edge();
+        } else if register & 0x10 != 0 {
+            self.timer = value;
+            self.prescale = PRESCALE[(register & 0x03) as usize];
+            self.prescale_count = 1;
+            self.timer_irq = register & 0x08 != 0;
+            self.flags &= !TIMER_FLAG;
+        } else {
+            // edge detect control: A0 picks the edge, A1 enables the interrupt
+            self.edge_rising = register & 0x01 != 0;
+            self.pa7_irq = register & 0x02 != 0;
+        }
+    }
+
+    /// Levels the peripheral drives onto the port's input pins.
+    pub fn set_input(&mut self, port: Port, value: u8) {
+        self.input[index(port)] = value;
+        self.detect_edge();
+    }
+
+    /// The port's output bits; input bits read as 0.
+    pub fn output(&self, port: Port) -> u8 {
+        let i = index(port);
+        self.output[i] & self.direction[i]
+    }
+
+    pub fn irq(&self) -> bool {
+        (self.flags & TIMER_FLAG != 0 && self.timer_irq) || (self.flags & PA7_FLAG != 0 && self.pa7_irq)
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        let mut cycles = cycles;
+        while cycles > 0 {
+            let step = cycles.min(self.prescale_count);
+            cycles -= step;
+            self.prescale_count -= step;
+            if self.prescale_count == 0 {
+                self.timer = self.timer.wrapping_sub(1);
+                if self.timer == 0xff {
+                    self.flags |= TIMER_FLAG;
+                    self.prescale = 1;
+                }
+                self.prescale_count = self.prescale;
+            }
+        }
+    }
+}
+
+impl Default for Riot {
+    fn default() -> Riot {
+        Riot::new()
+    }
+}
+
+/// As a `Device` the RS pin is register bit 7: registers $00-$1F are the
+/// I/O and timer, $80-$FF the RAM.
+impl Device for Riot {
+    fn read(&mut self, register: u16) -> u8 {
+        if register & 0x80 != 0 {
+            self.read_ram(register)
+        } else {
+            Riot::read(self, register)
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        if register & 0x80 != 0 {
+            self.write_ram(register, value);
+        } else {
+            Riot::write(self, register, value);
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Riot::tick(self, cycles)
+    }
+
+    fn reset(&mut self) {
+        Riot::reset(self)
+    }
+
+    /// When the timer next passes zero and sets its flag.
+    fn next_event(&self) -> Option<u64> {
+        if self.flags & TIMER_FLAG == 0 {
+            Some(self.prescale_count as u64 + self.timer as u64 * self.prescale as u64)
+        } else {
+            None
+        }
+    }
+
+    fn irq(&self) -> bool {
+        Riot::irq(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_and_ports() {
+        let mut riot = Riot::new();
+        riot.write_ram(0x80 | 0x12, 0x55);
+        assert_eq!(riot.read_ram(0x12), 0x55);
+
+        riot.write(DDRA, 0xf0);
+        riot.write(ORA, 0xa5);
+        riot.set_input(Port::A, 0x0c);
+        assert_eq!(riot.read(ORA), 0xac);
+        assert_eq!(riot.output(Port::A), 0xa0);
+        riot.write(DDRB, 0xff);
+        riot.write(ORB, 0x3c);
+        assert_eq!(riot.read(ORB), 0x3c);
+        assert_eq!(riot.read(DDRB), 0xff);
+    }
+
+    #[test]
+    fn timer_prescale_and_underflow() {
+        let mut riot = Riot::new();
+        riot.write(TIM8T | 0x08, 3);
+        riot.tick(1);
+        assert_eq!(riot.peek(TIMER), 2);
+        riot.tick(8 * 2);
+        assert_eq!(riot.peek(TIMER), 0);
+        assert!(!riot.irq());
+        riot.tick(7);
+        assert_eq!(riot.peek(TIMER), 0);
+        riot.tick(1);
+        assert_eq!(riot.peek(TIMER), 0xff);
+        assert_eq!(riot.peek(FLAGS), TIMER_FLAG);
+        assert!(riot.irq());
+        // counts every cycle after passing zero
+        riot.tick(2);
+        assert_eq!(riot.peek(TIMER), 0xfd);
+        assert_eq!(riot.read(TIMER), 0xfd);
+        assert_eq!(riot.peek(FLAGS), 0);
+        assert!(!riot.irq());
+
+        riot.write(T1024T, 1);
+        riot.tick(1 + 1024);
+        assert_eq!(riot.peek(FLAGS), TIMER_FLAG);
+        assert!(!riot.irq());
+    }
+
+    #[test]
+    fn pa7_edge_interrupt() {
+        let mut riot = Riot::new();
+        // A2 set, A4 clear: A1 enables the interrupt, A0 picks a rising edge
+        riot.write(0x07, 0);
+        riot.set_input(Port::A, 0x7f);
+        assert!(!riot.irq());
+        riot.set_input(Port::A, 0xff);
+        assert_eq!(riot.peek(FLAGS), PA7_FLAG);
+        assert!(riot.irq());
+        assert_eq!(riot.read(FLAGS), PA7_FLAG);
+        assert!(!riot.irq());
+
+        // falling edges only, interrupt off, driven from the output register
+        riot.write(0x04, 0);
+        riot.write(DDRA, 0x80);
+        riot.write(ORA, 0x00);
+        assert_eq!(riot.peek(FLAGS), PA7_FLAG);
+        assert!(!riot.irq());
+    }
+}
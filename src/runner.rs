@@ -0,0 +1,275 @@
+use cpu::MOS6502;
+use injector::Injector;
+
+/// Cycles a test may run when it doesn't set its own budget: nearly two
+/// minutes of Apple 1 time.
+pub const DEFAULT_MAX_CYCLES : u64 = 100_000_000;
+
+/// A condition that ends a test run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Until {
+    /// The PC reaches the address.
+    Address(u16),
+    /// The display's output ends with the text, matched exactly: the Apple
+    /// 1 prints upper case only.
+    Output(String),
+    /// The CPU stops, e.g. on BRK or STP at the end of a test program.
+    Halted
+}
+
+/// Why a test run ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// The PC reached one of the addresses.
+    Address(u16),
+    /// The output ended with one of the texts.
+    Output(String),
+    /// The budget ran out in a test with nothing to wait for.
+    Budget,
+    /// The budget ran out while waiting for a condition.
+    Timeout,
+    /// The CPU stopped in a test waiting for it to.
+    Halted,
+    /// The CPU stopped in a test waiting for something else.
+    Crashed
+}
+
+/// The first line where the output differs from the expected text. A line
+/// missing on one side is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>
+}
+
+/// A program run to check: keys to type, when to stop and, optionally, the
+/// output it must produce.
+pub struct TestCase {
+    pub input: Injector,
+    pub until: Vec<Until>,
+    pub max_cycles: u64,
+    pub expected: Option<String>
+}
+
+impl TestCase {
+    pub fn new() -> TestCase {
+        TestCase {
+            input: Injector::new(),
+            until: Vec::new(),
+            max_cycles: DEFAULT_MAX_CYCLES,
+            expected: None
+        }
+    }
+}
+
+impl Default for TestCase {
+    fn default() -> TestCase {
+        TestCase::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    pub stop: Stop,
+    pub cycles: u64,
+    pub instructions: u64,
+    /// The display's transcript.
+    pub output: String,
+    pub mismatch: Option<Mismatch>
+}
+
+impl TestResult {
+    /// A test passes when it ended the way it was meant to and printed what
+    /// was expected.
+    pub fn passed(&self) -> bool {
+        self.stop != Stop::Timeout && self.stop != Stop::Crashed && self.mismatch.is_none()
+    }
+
+    /// How the test went, e.g. `PASS reached $FF1F after 1000 cycles, 250
+    /// instructions`, followed by the first difference in the output.
+    pub fn summary(&self) -> String {
+        let verdict = if self.passed() { "PASS" } else { "FAIL" };
+        let how = match self.stop {
+            Stop::Address(address) => format!("reached ${:04X}", address),
+            Stop::Output(ref text) => format!("printed {:?}", text),
+            Stop::Budget => String::from("ran"),
+            Stop::Timeout => String::from("timed out"),
+            Stop::Halted => String::from("CPU stopped"),
+            Stop::Crashed => String::from("CPU stopped unexpectedly")
+        };
+        let mut summary = format!("{} {} after {} cycles, {} instructions", verdict, how, self.cycles, self.instructions);
+        if let Some(ref mismatch) = self.mismatch {
+            summary.push_str(&format!("\noutput differs at line {}\n  expected: {}\n  actual:   {}", mismatch.line,
+                mismatch.expected.as_ref().map_or("(end of output)", |line| line.as_str()),
+                mismatch.actual.as_ref().map_or("(end of output)", |line| line.as_str())));
+        }
+        summary
+    }
+}
+
+/// Compares output line by line, ignoring trailing blanks, trailing empty
+/// lines and CRLF line ends.
+pub fn compare(expected: &str, actual: &str) -> Option<Mismatch> {
+    fn lines(text: &str) -> Vec<&str> {
+        let mut lines : Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+        while lines.last() == Some(&"") {
+            lines.pop();
+        }
+        lines
+    }
+    let expected = lines(expected);
+    let actual = lines(actual);
+    (0..expected.len().max(actual.len()))
+        .find(|&i| expected.get(i) != actual.get(i))
+        .map(|i| Mismatch {
+            line: i + 1,
+            expected: expected.get(i).map(|line| line.to_string()),
+            actual: actual.get(i).map(|line| line.to_string())
+        })
+}
+
+fn output(cpu: &MOS6502) -> &str {
+    cpu.platform().display().and_then(|display| display.transcript()).unwrap_or("")
+}
+
+fn stop(cpu: &mut MOS6502, case: &TestCase, start: u64, output_count: &mut u64) -> Option<Stop> {
+    if !cpu.is_running() {
+        return Some(if case.until.contains(&Until::Halted) { Stop::Halted } else { Stop::Crashed });
+    }
+    let pc = cpu.get_pc();
+    if case.until.contains(&Until::Address(pc)) {
+        return Some(Stop::Address(pc));
+    }
+    // at most one character arrives per instruction
+    let count = cpu.platform().display().map_or(0, |display| display.output_count());
+    if count != *output_count {
+        *output_count = count;
+        for until in &case.until {
+            if let Until::Output(ref text) = *until {
+                if output(cpu).ends_with(text.as_str()) {
+                    return Some(Stop::Output(text.clone()));
+                }
+            }
+        }
+    }
+    if cpu.get_total_cycles() - start >= case.max_cycles {
+        return Some(if case.until.is_empty() { Stop::Budget } else { Stop::Timeout });
+    }
+    None
+}
+
+/// Runs the machine until one of the case's conditions holds or its budget
+/// runs out, typing its input as the program asks for keys. The machine's
+/// display must be recording a transcript for its output to be seen.
+pub fn run(cpu: &mut MOS6502, case: &mut TestCase) -> TestResult {
+    let start = cpu.get_total_cycles();
+    let start_instructions = cpu.get_instruction_count();
+    let mut output_count = 0;
+    let stop = loop {
+        if let Some(stop) = stop(cpu, case, start, &mut output_count) {
+            break stop;
+        }
+        case.input.poll(cpu);
+        cpu.step();
+    };
+    let output = output(cpu).to_string();
+    let mismatch = case.expected.as_ref().and_then(|expected| compare(expected, &output));
+    TestResult {
+        stop,
+        cycles: cpu.get_total_cycles() - start,
+        instructions: cpu.get_instruction_count() - start_instructions,
+        output,
+        mismatch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apple1::Apple1;
+    use console::Buffer;
+    use platform::Platform;
+
+    /// An Apple 1 in the Woz Monitor, recording its display.
+    fn machine() -> MOS6502 {
+        let mut apple1 = Apple1::new();
+        apple1.set_output(Box::new(Buffer::new()));
+        apple1.load(Vec::new(), 0);
+        apple1.display_mut().record_transcript();
+        let mut cpu = MOS6502::new(Box::new(apple1));
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn types_and_waits_for_output() {
+        let mut cpu = machine();
+        let mut case = TestCase::new();
+        case.input.push_text("ff00\n");
+        case.until.push(Until::Output(String::from("ff00: d8")));
+        case.until.push(Until::Output(String::from("FF00: D8")));
+        case.expected = Some(String::from("\\\nFF00\r\n\nFF00: D8\n"));
+        let result = run(&mut cpu, &mut case);
+        assert_eq!(result.stop, Stop::Output(String::from("FF00: D8")));
+        assert_eq!(result.output, "\\\nFF00\n\nFF00: D8");
+        assert!(result.passed(), "{}", result.summary());
+        assert!(result.summary().starts_with("PASS printed \"FF00: D8\" after"));
+    }
+
+    #[test]
+    fn addresses_and_budgets() {
+        // the monitor waits for a key at $FF29
+        let mut cpu = machine();
+        let mut case = TestCase::new();
+        case.until.push(Until::Address(0xff29));
+        let result = run(&mut cpu, &mut case);
+        assert_eq!(result.stop, Stop::Address(0xff29));
+        assert!(result.passed());
+
+        case.until = vec![Until::Output(String::from("never"))];
+        case.max_cycles = 1_000;
+        let result = run(&mut cpu, &mut case);
+        assert_eq!(result.stop, Stop::Timeout);
+        assert!(result.cycles >= 1_000 && result.cycles < 1_010);
+        assert!(!result.passed());
+
+        case.until.clear();
+        assert_eq!(run(&mut cpu, &mut case).stop, Stop::Budget);
+    }
+
+    #[test]
+    fn halts() {
+        // BRK stops the CPU
+        for (until, stop, passed) in [(Some(Until::Halted), Stop::Halted, true), (None, Stop::Crashed, false)] {
+            let mut cpu = machine();
+            let mut case = TestCase::new();
+            case.input.push_text("300: 0\n300R\n");
+            case.until.extend(until);
+            case.until.push(Until::Output(String::from("never")));
+            let result = run(&mut cpu, &mut case);
+            assert_eq!(result.stop, stop);
+            assert_eq!(result.passed(), passed, "{}", result.summary());
+        }
+    }
+
+    #[test]
+    fn golden_output() {
+        assert_eq!(compare("A\r\nB  \n\n", "A\nB"), None);
+        assert_eq!(compare("A\nB\n", "A\nC\nD"), Some(Mismatch {
+            line: 2,
+            expected: Some(String::from("B")),
+            actual: Some(String::from("C"))
+        }));
+        let mismatch = compare("A\nB", "A").unwrap();
+        assert_eq!((mismatch.line, mismatch.actual), (2, None));
+
+        let mut cpu = machine();
+        let mut case = TestCase::new();
+        case.until.push(Until::Address(0xff29));
+        case.expected = Some(String::from("*"));
+        let result = run(&mut cpu, &mut case);
+        assert!(!result.passed());
+        assert!(result.summary().contains("expected: *\n  actual:   \\"));
+    }
+}
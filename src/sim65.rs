@@ -0,0 +1,407 @@
+use std::cell::Cell;
+use std::fs::{File, OpenOptions};
+use std::io::{stderr, stdin, stdout, Read, Write};
+use std::rc::Rc;
+use platform::{Platform, Registers};
+
+pub const SIM65_HZ : u64 = 1_000_000;
+
+/// sim65's paravirtualized calls, which programs JSR to.
+pub const PV_OPEN : u16 = 0xfff4;
+pub const PV_CLOSE : u16 = 0xfff5;
+pub const PV_READ : u16 = 0xfff6;
+pub const PV_WRITE : u16 = 0xfff7;
+pub const PV_ARGS : u16 = 0xfff8;
+pub const PV_EXIT : u16 = 0xfff9;
+
+/// Console registers for programs that store rather than call: writing
+/// EXIT exits with the value, writing CHAR prints it and reading CHAR
+/// reads a character, $FF at end of input.
+pub const EXIT : u16 = 0xfff8;
+pub const CHAR : u16 = 0xfff9;
+
+/// Open flags as cc65's fcntl.h defines them.
+const O_RDONLY : u16 = 0x01;
+const O_WRONLY : u16 = 0x02;
+const O_CREAT : u16 = 0x10;
+const O_TRUNC : u16 = 0x20;
+const O_APPEND : u16 = 0x40;
+const O_EXCL : u16 = 0x80;
+
+/// Returned in AX when a call fails.
+const FAILED : u16 = 0xffff;
+
+/// The status a program exited with, once it has. Clones share it, so
+/// whoever runs the machine can keep one to watch for the exit.
+#[derive(Clone, Default)]
+pub struct ExitStatus {
+    code: Rc<Cell<Option<i32>>>
+}
+
+impl ExitStatus {
+    pub fn code(&self) -> Option<i32> {
+        self.code.get()
+    }
+
+    fn set(&self, code: i32) {
+        self.code.set(Some(code));
+    }
+}
+
+/// A test machine compatible with cc65's sim65: 64K of RAM and nothing
+/// else, with the paravirtualized open, close, read, write, args and exit
+/// calls at $FFF4-$FFF9 handled by the host before the CPU fetches from
+/// there. File descriptors 0-2 are the machine's input, its output and the
+/// host's stderr; others are host files. Programs that store rather than
+/// call can use the console registers at $FFF8 and $FFF9, as llvm-mos's
+/// sim target does.
+///
+/// The program's exit is reported through `exit_status`; the exit call
+/// doesn't return, so the CPU stays on it until the caller stops.
+pub struct Sim65 {
+    memory: Vec<u8>,
+    c_stack: u8,
+    args: Vec<String>,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    files: Vec<Option<File>>,
+    exit: ExitStatus
+}
+
+impl Sim65 {
+    /// A machine on the host's stdin and stdout.
+    pub fn new() -> Sim65 {
+        Sim65::with_io(Box::new(stdin()), Box::new(stdout()))
+    }
+
+    pub fn with_io(input: Box<dyn Read>, output: Box<dyn Write>) -> Sim65 {
+        Sim65 {
+            memory: vec![0; 0x10000],
+            c_stack: 0,
+            args: Vec::new(),
+            input,
+            output,
+            files: Vec::new(),
+            exit: ExitStatus::default()
+        }
+    }
+
+    /// Where the loaded program keeps its C stack pointer in zero page, as
+    /// its sim65 header says.
+    pub fn set_c_stack(&mut self, address: u8) {
+        self.c_stack = address;
+    }
+
+    /// A handle on the program's exit status.
+    pub fn exit_status(&self) -> ExitStatus {
+        self.exit.clone()
+    }
+
+    /// The arguments the program's `main` gets, starting with its name.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    fn word(&self, address: u16) -> u16 {
+        self.memory[address as usize] as u16 | ((self.memory[address.wrapping_add(1) as usize] as u16) << 8)
+    }
+
+    fn set_word(&mut self, address: u16, value: u16) {
+        self.memory[address as usize] = value as u8;
+        self.memory[address.wrapping_add(1) as usize] = (value >> 8) as u8;
+    }
+
+    fn c_sp(&self) -> u16 {
+        self.word(self.c_stack as u16)
+    }
+
+    fn set_c_sp(&mut self, value: u16) {
+        let address = self.c_stack as u16;
+        self.set_word(address, value);
+    }
+
+    /// Takes a parameter off the C stack, dropping `size` bytes.
+    fn pop_param(&mut self, size: u16) -> u16 {
+        let sp = self.c_sp();
+        let value = self.word(sp);
+        self.set_c_sp(sp.wrapping_add(size));
+        value
+    }
+
+    fn string(&self, mut address: u16) -> String {
+        let mut bytes = Vec::new();
+        while self.memory[address as usize] != 0 {
+            bytes.push(self.memory[address as usize]);
+            address = address.wrapping_add(1);
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    fn file(&mut self, fd: u16) -> Option<&mut File> {
+        self.files.get_mut((fd as usize).wrapping_sub(3)).and_then(|file| file.as_mut())
+    }
+
+    /// `open(name, flags, ...)`; Y holds the size of the arguments.
+    fn open(&mut self, registers: &Registers) -> u16 {
+        self.pop_param(registers.y.wrapping_sub(4) as u16);
+        let flags = self.pop_param(2);
+        let name = self.pop_param(2);
+        let path = self.string(name);
+        let mut options = OpenOptions::new();
+        options.read(flags & O_WRONLY == 0 || flags & O_RDONLY != 0)
+            .write(flags & O_WRONLY != 0)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0);
+        if flags & O_EXCL != 0 {
+            options.create_new(true);
+        } else {
+            options.create(flags & O_CREAT != 0);
+        }
+        match options.open(path) {
+            Ok(file) => {
+                let slot = self.files.iter().position(|file| file.is_none()).unwrap_or(self.files.len());
+                if slot == self.files.len() {
+                    self.files.push(None);
+                }
+                self.files[slot] = Some(file);
+                slot as u16 + 3
+            }
+            Err(_) => FAILED
+        }
+    }
+
+    fn close(&mut self, fd: u16) -> u16 {
+        match self.files.get_mut((fd as usize).wrapping_sub(3)) {
+            Some(file) if file.is_some() => {
+                *file = None;
+                0
+            }
+            _ => FAILED
+        }
+    }
+
+    /// `read(fd, buf, count)` with count in AX.
+    fn read_fd(&mut self, count: u16) -> u16 {
+        let buf = self.pop_param(2);
+        let fd = self.pop_param(2);
+        let mut data = vec![0; count as usize];
+        let result = match fd {
+            0 => self.input.read(&mut data),
+            _ => match self.file(fd) {
+                Some(file) => file.read(&mut data),
+                None => return FAILED
+            }
+        };
+        match result {
+            Ok(len) => {
+                for (i, &byte) in data[..len].iter().enumerate() {
+                    self.memory[buf.wrapping_add(i as u16) as usize] = byte;
+                }
+                len as u16
+            }
+            Err(_) => FAILED
+        }
+    }
+
+    /// `write(fd, buf, count)` with count in AX.
+    fn write_fd(&mut self, count: u16) -> u16 {
+        let buf = self.pop_param(2);
+        let fd = self.pop_param(2);
+        let data : Vec<u8> = (0..count).map(|i| self.memory[buf.wrapping_add(i) as usize]).collect();
+        let result = match fd {
+            1 => self.output.write_all(&data).and_then(|_| self.output.flush()),
+            2 => stderr().write_all(&data),
+            _ => match self.file(fd) {
+                Some(file) => file.write_all(&data),
+                None => return FAILED
+            }
+        };
+        result.map_or(FAILED, |_| count)
+    }
+
+    /// Copies the arguments onto the C stack and points `argv` at them,
+    /// returning argc.
+    fn args(&mut self, argv: u16) -> u16 {
+        let count = self.args.len() as u16;
+        let table = self.c_sp().wrapping_sub((count + 1) * 2);
+        self.set_word(argv, table);
+        let mut sp = table;
+        for (i, arg) in self.args.clone().iter().enumerate() {
+            sp = sp.wrapping_sub(arg.len() as u16 + 1);
+            for (j, &byte) in arg.as_bytes().iter().chain(Some(&0)).enumerate() {
+                self.memory[sp.wrapping_add(j as u16) as usize] = byte;
+            }
+            self.set_word(table.wrapping_add(i as u16 * 2), sp);
+        }
+        self.set_word(table.wrapping_add(count * 2), 0);
+        self.set_c_sp(sp);
+        count
+    }
+
+    fn read_char(&mut self) -> u8 {
+        let mut byte = [0];
+        match self.input.read(&mut byte) {
+            Ok(1) => byte[0],
+            _ => 0xff
+        }
+    }
+}
+
+impl Default for Sim65 {
+    fn default() -> Sim65 {
+        Sim65::new()
+    }
+}
+
+impl Platform for Sim65 {
+
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            CHAR => self.read_char(),
+            _ => self.memory[address as usize]
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            EXIT => self.exit.set(value as i32),
+            CHAR => {
+                let _ = self.output.write_all(&[value]).and_then(|_| self.output.flush());
+            }
+            _ => self.memory[address as usize] = value
+        }
+    }
+
+    fn poke(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn load(&mut self, program: Vec<u8>, address: u16) {
+        for (i, &byte) in program.iter().enumerate() {
+            self.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+
+    /// Runs the call at PC, then returns from it like RTS.
+    fn trap(&mut self, registers: &mut Registers) -> bool {
+        if !(PV_OPEN..=PV_EXIT).contains(&registers.pc) {
+            return false;
+        }
+        if registers.pc == PV_EXIT {
+            self.exit.set(registers.a as i32);
+            return true;
+        }
+        let ax = registers.a as u16 | ((registers.x as u16) << 8);
+        let result = match registers.pc {
+            PV_OPEN => self.open(registers),
+            PV_CLOSE => self.close(ax),
+            PV_READ => self.read_fd(ax),
+            PV_WRITE => self.write_fd(ax),
+            _ => self.args(ax)
+        };
+        registers.a = result as u8;
+        registers.x = (result >> 8) as u8;
+        let sp = registers.sp;
+        let lo = self.memory[0x100 + sp.wrapping_add(1) as usize] as u16;
+        let hi = self.memory[0x100 + sp.wrapping_add(2) as usize] as u16;
+        registers.sp = sp.wrapping_add(2);
+        registers.pc = (lo | (hi << 8)).wrapping_add(1);
+        true
+    }
+
+    fn key_ready(&self) -> bool {
+        false
+    }
+
+    fn key_pressed(&mut self, _key: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use cpu::MOS6502;
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs the program until it exits, returning the machine and the
+    /// exit code.
+    fn run(sim: Sim65, program: &[u8]) -> (MOS6502, Option<i32>) {
+        let exit = sim.exit_status();
+        let mut cpu = MOS6502::new(Box::new(sim));
+        cpu.platform_mut().load(program.to_vec(), 0x0200);
+        cpu.platform_mut().load(vec![0x00, 0x02], 0xfffc);
+        cpu.reset();
+        while exit.code().is_none() && cpu.is_running() && cpu.get_total_cycles() < 10_000 {
+            cpu.step();
+        }
+        (cpu, exit.code())
+    }
+
+    #[test]
+    fn paravirt_calls() {
+        let output = Output::default();
+        let mut sim = Sim65::with_io(Box::new(io::empty()), Box::new(output.clone()));
+        // write(1, $0300, 5) with the C stack at $BFFC
+        sim.set_c_stack(0x02);
+        sim.load(vec![0xfc, 0xbf], 0x0002);
+        sim.load(vec![0x00, 0x03, 0x01, 0x00], 0xbffc);
+        sim.load(b"hello".to_vec(), 0x0300);
+        sim.set_args(vec![String::from("prog")]);
+        let program = [
+            0xa9, 0x05, 0xa2, 0x00,     // lda #5; ldx #0
+            0x20, 0xf7, 0xff,           // jsr write
+            0x85, 0x10,                 // sta $10
+            0xa9, 0x20, 0xa2, 0x00,     // lda #<$0020; ldx #>$0020
+            0x20, 0xf8, 0xff,           // jsr args
+            0x85, 0x11,                 // sta $11
+            0xa9, 0x2a,                 // lda #42
+            0x20, 0xf9, 0xff,           // jsr exit
+            0x00
+        ];
+        let (mut cpu, code) = run(sim, &program);
+        assert_eq!(*output.0.borrow(), b"hello");
+        assert_eq!(cpu.read_u8(0x10), 5);
+        assert_eq!(code, Some(42));
+        // exit doesn't return
+        cpu.step();
+        assert_eq!(cpu.get_pc(), PV_EXIT);
+
+        // argv at $BFFC, "prog" below it and the C stack below that
+        assert_eq!(cpu.read_u8(0x11), 1);
+        assert_eq!((cpu.read_u8(0x20), cpu.read_u8(0x21)), (0xfc, 0xbf));
+        assert_eq!((cpu.read_u8(0xbffc), cpu.read_u8(0xbffd)), (0xf7, 0xbf));
+        assert_eq!(cpu.read_u8(0xbff7), b'p');
+        assert_eq!(cpu.read_u8(0x02), 0xf7);
+    }
+
+    #[test]
+    fn console_registers() {
+        let output = Output::default();
+        let sim = Sim65::with_io(Box::new(&b"x"[..]), Box::new(output.clone()));
+        let program = [
+            0xad, 0xf9, 0xff,           // lda $fff9
+            0x8d, 0xf9, 0xff,           // sta $fff9
+            0xad, 0xf9, 0xff,           // lda $fff9
+            0x8d, 0xf8, 0xff,           // sta $fff8
+            0x00
+        ];
+        let (_, code) = run(sim, &program);
+        assert_eq!(*output.0.borrow(), b"x");
+        assert_eq!(code, Some(0xff));
+    }
+}
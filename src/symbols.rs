@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+/// Address to label mapping, loaded from assembler symbol/label files.
+///
+/// Accepted line formats:
+///   `NAME = $C000`, `NAME = 0xC000`, `NAME EQU $C000` and VICE style `al C:c000 .NAME`.
+/// Anything else (comments, blank lines) is ignored.
+pub struct SymbolTable {
+    symbols: BTreeMap<u16, String>
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: BTreeMap::new()
+        }
+    }
+
+    pub fn from_file(filename: &str) -> io::Result<SymbolTable> {
+        let mut f = File::open(filename)?;
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+        Ok(SymbolTable::parse(&text))
+    }
+
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut table = SymbolTable::new();
+        for line in text.lines() {
+            let line = match line.find(';') {
+                Some(i) => &line[..i],
+                None => line
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["al", addr, name] => {
+                    let addr = addr.trim_start_matches("C:").trim_start_matches("c:");
+                    if let Some(addr) = parse_address(addr) {
+                        table.insert(addr, name.trim_start_matches('.'));
+                    }
+                }
+                [name, op, addr] if *op == "=" || op.eq_ignore_ascii_case("equ") => {
+                    if let Some(addr) = parse_address(addr) {
+                        table.insert(addr, name.trim_end_matches(':'));
+                    }
+                }
+                _ => {}
+            }
+        }
+        table
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        self.symbols.insert(address, name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn lookup(&self, address: u16) -> Option<&str> {
+        self.symbols.get(&address).map(|s| s.as_str())
+    }
+
+    /// Closest symbol at or below `address`, with the offset from it.
+    pub fn nearest(&self, address: u16) -> Option<(&str, u16)> {
+        self.symbols.range(..=address).next_back().map(|(a, s)| (s.as_str(), address - a))
+    }
+
+    /// Symbol name for `address`, or `$XXXX` when there is none.
+    pub fn label(&self, address: u16) -> String {
+        match self.lookup(address) {
+            Some(name) => name.to_string(),
+            None => format!("${:04X}", address)
+        }
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+/// Parses `$C000`, `0xC000`, `C000h` or plain hex.
+pub fn parse_address(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = if let Some(rest) = text.strip_prefix('$') {
+        rest
+    } else if let Some(rest) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        rest
+    } else if let Some(rest) = text.strip_suffix('h').or_else(|| text.strip_suffix('H')) {
+        rest
+    } else {
+        text
+    };
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+        let table = SymbolTable::parse("; comment\nRESET = $C000\nCHROUT EQU 0xFFEF\nal C:ff00 .WOZ\n\nbogus line here too\n");
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.lookup(0xc000), Some("RESET"));
+        assert_eq!(table.lookup(0xffef), Some("CHROUT"));
+        assert_eq!(table.lookup(0xff00), Some("WOZ"));
+        assert_eq!(table.label(0x1234), "$1234");
+    }
+
+    #[test]
+    fn nearest() {
+        let mut table = SymbolTable::new();
+        table.insert(0xc000, "START");
+        table.insert(0xc100, "NEXT");
+        assert_eq!(table.nearest(0xc0ff), Some(("START", 0xff)));
+        assert_eq!(table.nearest(0xc100), Some(("NEXT", 0)));
+        assert_eq!(table.nearest(0x0010), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+#[cfg(unix)]
+use termios;
+#[cfg(unix)]
+use termios::Termios;
+
+/// Ctrl-\ presses the RESET button.
+pub const RESET_KEY : u8 = 0x1c;
+/// Ctrl-] leaves the emulator, as in telnet.
+pub const QUIT_KEY : u8 = 0x1d;
+
+const ESC : u8 = 0x1b;
+/// Wozmon and Apple 1 BASIC use `_` as rubout.
+const RUBOUT : u8 = b'_' | 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyEvent {
+    Key(u8),
+    Reset,
+    Quit
+}
+
+/// Translates host terminal input into Apple 1 keyboard codes: upper case
+/// ASCII with bit 7 set. Enter becomes CR, backspace/delete and the left
+/// arrow become rubout, other escape sequences are dropped.
+pub fn translate(input: &[u8]) -> Vec<KeyEvent> {
+    let mut events = Vec::new();
+    let mut i = 0;
+    while i < input.len() {
+        let b = input[i];
+        i += 1;
+        let event = match b {
+            QUIT_KEY => KeyEvent::Quit,
+            RESET_KEY => KeyEvent::Reset,
+            ESC if i < input.len() && (input[i] == b'[' || input[i] == b'O') => {
+                let start = i + 1;
+                let mut end = start;
+                while end < input.len() && !(0x40..=0x7e).contains(&input[end]) {
+                    end += 1;
+                }
+                i = end + 1;
+                match input.get(end) {
+                    Some(b'D') => KeyEvent::Key(RUBOUT),
+                    _ => continue
+                }
+            }
+            b'\r' | b'\n' => KeyEvent::Key(0x8d),
+            0x08 | 0x7f => KeyEvent::Key(RUBOUT),
+            0x00..=0x1f => KeyEvent::Key(b | 0x80),
+            0x20..=0x7e => KeyEvent::Key(b.to_ascii_uppercase() | 0x80),
+            _ => continue
+        };
+        // a CR LF pair from a pasted line is a single Return
+        if b == b'\n' && i >= 2 && input[i - 2] == b'\r' {
+            continue;
+        }
+        events.push(event);
+    }
+    events
+}
+
+/// Puts the controlling terminal into character-at-a-time mode with echo
+/// and signal keys off, and restores the previous settings when dropped.
+/// Does nothing when stdin is not a terminal.
+pub struct RawMode {
+    #[cfg(unix)]
+    saved: Option<Termios>
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    pub fn enable() -> RawMode {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return RawMode { saved: None };
+        }
+        let saved = termios::get(stdin.as_raw_fd()).ok()
+            .filter(|saved| termios::set(stdin.as_raw_fd(), &termios::keyboard_mode(saved)).is_ok());
+        RawMode { saved }
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable() -> RawMode {
+        RawMode {}
+    }
+
+    #[cfg(unix)]
+    pub fn is_active(&self) -> bool {
+        self.saved.is_some()
+    }
+
+    #[cfg(not(unix))]
+    pub fn is_active(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(ref saved) = self.saved {
+            let _ = termios::set(io::stdin().as_raw_fd(), saved);
+        }
+    }
+}
+
+/// Reads stdin on a background thread, handing each chunk to `deliver`
+/// until it returns false.
+fn spawn_stdin<F: FnMut(&[u8]) -> bool + Send + 'static>(mut deliver: F) {
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut handle = stdin.lock();
+        let mut buf = [0u8; 64];
+        loop {
+            let n = match handle.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n
+            };
+            if !deliver(&buf[..n]) {
+                return;
+            }
+        }
+    });
+}
+
+/// Reads stdin on a background thread and delivers translated key events.
+pub fn spawn_reader() -> Receiver<KeyEvent> {
+    let (tx, rx) = mpsc::channel();
+    spawn_stdin(move |input| translate(input).into_iter().all(|event| tx.send(event).is_ok()));
+    rx
+}
+
+/// Reads stdin for a serial line: the reset and quit keys still arrive as
+/// key events, everything else is passed through untranslated.
+pub fn spawn_serial_reader() -> (Receiver<KeyEvent>, Receiver<u8>) {
+    let (events_tx, events_rx) = mpsc::channel();
+    let (bytes_tx, bytes_rx) = mpsc::channel();
+    spawn_stdin(move |input| input.iter().all(|&b| match b {
+        QUIT_KEY => events_tx.send(KeyEvent::Quit).is_ok(),
+        RESET_KEY => events_tx.send(KeyEvent::Reset).is_ok(),
+        _ => bytes_tx.send(b).is_ok()
+    }));
+    (events_rx, bytes_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(translate(b"a1\r"), vec![KeyEvent::Key(0xc1), KeyEvent::Key(0xb1), KeyEvent::Key(0x8d)]);
+        assert_eq!(translate(b"x\r\n"), vec![KeyEvent::Key(0xd8), KeyEvent::Key(0x8d)]);
+        assert_eq!(translate(b"\n"), vec![KeyEvent::Key(0x8d)]);
+        assert_eq!(translate(&[0x7f, 0x08, 0x03]), vec![KeyEvent::Key(RUBOUT), KeyEvent::Key(RUBOUT), KeyEvent::Key(0x83)]);
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(translate(&[ESC]), vec![KeyEvent::Key(0x9b)]);
+        assert_eq!(translate(b"\x1b[A\x1b[D\x1bOBz"), vec![KeyEvent::Key(RUBOUT), KeyEvent::Key(0xda)]);
+        assert_eq!(translate(b"\x1b[1;5C"), vec![]);
+    }
+
+    #[test]
+    fn hotkeys() {
+        assert_eq!(translate(&[RESET_KEY, QUIT_KEY]), vec![KeyEvent::Reset, KeyEvent::Quit]);
+    }
+}
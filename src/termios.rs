@@ -0,0 +1,152 @@
+use std::io;
+use std::os::raw::c_int;
+
+pub use self::os::{Termios, O_NOCTTY};
+use self::os::{ISIG, ICANON, ECHO, IXON, ICRNL, VMIN, VTIME};
+
+// `struct termios`, its flag bits and O_NOCTTY differ between C
+// libraries, so each supported system spells out its own.
+#[cfg(not(any(
+    all(target_os = "linux", not(any(target_arch = "mips", target_arch = "mips64", target_arch = "powerpc",
+        target_arch = "powerpc64", target_arch = "sparc64"))),
+    target_os = "macos", target_os = "ios",
+    target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd", target_os = "openbsd")))]
+compile_error!("termios is only described for Linux, macOS and the BSDs");
+
+#[cfg(target_os = "linux")]
+mod os {
+    use std::os::raw::c_int;
+
+    pub type Flags = u32;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: Flags,
+        pub c_oflag: Flags,
+        pub c_cflag: Flags,
+        pub c_lflag: Flags,
+        c_line: u8,
+        pub c_cc: [u8; 32],
+        c_ispeed: u32,
+        c_ospeed: u32
+    }
+
+    pub const ICRNL : Flags = 0o400;
+    pub const IXON : Flags = 0o2000;
+    pub const ISIG : Flags = 0o1;
+    pub const ICANON : Flags = 0o2;
+    pub const ECHO : Flags = 0o10;
+    pub const VTIME : usize = 5;
+    pub const VMIN : usize = 6;
+
+    pub const O_NOCTTY : c_int = 0o400;
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios",
+    target_os = "freebsd", target_os = "dragonfly", target_os = "netbsd", target_os = "openbsd"))]
+mod os {
+    use std::os::raw::c_int;
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub type Flags = ::std::os::raw::c_ulong;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub type Flags = u32;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: Flags,
+        pub c_oflag: Flags,
+        pub c_cflag: Flags,
+        pub c_lflag: Flags,
+        pub c_cc: [u8; 20],
+        // speed_t, the same width as the flags on each of these
+        c_ispeed: Flags,
+        c_ospeed: Flags
+    }
+
+    pub const ICRNL : Flags = 0x100;
+    pub const IXON : Flags = 0x200;
+    pub const ISIG : Flags = 0x80;
+    pub const ICANON : Flags = 0x100;
+    pub const ECHO : Flags = 0x8;
+    pub const VMIN : usize = 16;
+    pub const VTIME : usize = 17;
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub const O_NOCTTY : c_int = 0x20000;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    pub const O_NOCTTY : c_int = 0x8000;
+}
+
+extern "C" {
+    fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+    fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+    fn cfmakeraw(termios: *mut Termios);
+}
+
+const TCSANOW : c_int = 0;
+
+/// The terminal settings of `fd`.
+pub fn get(fd: c_int) -> io::Result<Termios> {
+    unsafe {
+        let mut termios = ::std::mem::zeroed();
+        if tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(termios)
+    }
+}
+
+/// Changes the terminal settings of `fd` straight away.
+pub fn set(fd: c_int, termios: &Termios) -> io::Result<()> {
+    if unsafe { tcsetattr(fd, TCSANOW, termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Puts a terminal in raw mode, so it neither echoes nor translates.
+pub fn make_raw(fd: c_int) -> io::Result<()> {
+    let mut termios = get(fd)?;
+    unsafe { cfmakeraw(&mut termios) };
+    set(fd, &termios)
+}
+
+/// Settings for reading a keyboard a key at a time: no line editing, echo,
+/// signal keys, flow control or CR translation. Output is left alone.
+pub fn keyboard_mode(termios: &Termios) -> Termios {
+    let mut keyboard = *termios;
+    keyboard.c_lflag &= !(ICANON | ECHO | ISIG);
+    keyboard.c_iflag &= !(IXON | ICRNL);
+    keyboard.c_cc[VMIN] = 1;
+    keyboard.c_cc[VTIME] = 0;
+    keyboard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    use acia::PtyLine;
+
+    #[test]
+    fn keyboard() {
+        let line = PtyLine::open().unwrap();
+        let terminal = OpenOptions::new().read(true).write(true).open(line.path()).unwrap();
+        let fd = terminal.as_raw_fd();
+        let mut cooked = get(fd).unwrap();
+        cooked.c_lflag |= ICANON | ECHO | ISIG;
+        cooked.c_iflag |= ICRNL;
+        cooked.c_cc[VMIN] = 4;
+        set(fd, &cooked).unwrap();
+        set(fd, &keyboard_mode(&get(fd).unwrap())).unwrap();
+        let keyboard = get(fd).unwrap();
+        assert_eq!(keyboard.c_lflag & (ICANON | ECHO | ISIG), 0);
+        assert_eq!(keyboard.c_iflag & ICRNL, 0);
+        assert_eq!(keyboard.c_oflag, cooked.c_oflag);
+        assert_eq!((keyboard.c_cc[VMIN], keyboard.c_cc[VTIME]), (1, 0));
+    }
+}
@@ -0,0 +1,666 @@
+use pia::Port;
+use device::Device;
+
+pub const ORB : u16 = 0x0;
+pub const ORA : u16 = 0x1;
+pub const DDRB : u16 = 0x2;
+pub const DDRA : u16 = 0x3;
+pub const T1C_L : u16 = 0x4;
+pub const T1C_H : u16 = 0x5;
+pub const T1L_L : u16 = 0x6;
+pub const T1L_H : u16 = 0x7;
+pub const T2C_L : u16 = 0x8;
+pub const T2C_H : u16 = 0x9;
+pub const SR : u16 = 0xa;
+pub const ACR : u16 = 0xb;
+pub const PCR : u16 = 0xc;
+pub const IFR : u16 = 0xd;
+pub const IER : u16 = 0xe;
+pub const ORA_NO_HANDSHAKE : u16 = 0xf;
+
+/// Interrupt flag and enable bits.
+pub const IRQ_CA2 : u8 = 0x01;
+pub const IRQ_CA1 : u8 = 0x02;
+pub const IRQ_SR : u8 = 0x04;
+pub const IRQ_CB2 : u8 = 0x08;
+pub const IRQ_CB1 : u8 = 0x10;
+pub const IRQ_T2 : u8 = 0x20;
+pub const IRQ_T1 : u8 = 0x40;
+
+const ACR_PA_LATCH : u8 = 0x01;
+const ACR_PB_LATCH : u8 = 0x02;
+const ACR_T2_COUNT : u8 = 0x20;
+const ACR_T1_CONTINUOUS : u8 = 0x40;
+const ACR_T1_PB7 : u8 = 0x80;
+
+/// Shift register modes, ACR bits 2-4.
+const SR_DISABLED : u8 = 0;
+const SR_IN_T2 : u8 = 1;
+const SR_IN_PHI2 : u8 = 2;
+const SR_IN_CB1 : u8 = 3;
+const SR_OUT_FREE : u8 = 4;
+const SR_OUT_T2 : u8 = 5;
+const SR_OUT_PHI2 : u8 = 6;
+const SR_OUT_CB1 : u8 = 7;
+
+/// C2 control, PCR bits 1-3 for CA2 and 5-7 for CB2.
+const C2_HANDSHAKE : u8 = 4;
+const C2_PULSE : u8 = 5;
+const C2_LOW : u8 = 6;
+const C2_HIGH : u8 = 7;
+
+#[derive(Debug, Clone, Default)]
+struct Side {
+    output: u8,
+    direction: u8,
+    input: u8,
+    latch: u8,
+    c1: bool,
+    c2_in: bool,
+    c2_out: bool,
+    pulse: bool
+}
+
+impl Side {
+    fn new() -> Side {
+        Side { c2_out: true, ..Side::default() }
+    }
+
+    fn pins(&self) -> u8 {
+        (self.output & self.direction) | (self.input & !self.direction)
+    }
+}
+
+/// MOS 6522 Versatile Interface Adapter.
+///
+/// Registers are selected by RS3-RS0, so callers pass `address & 0xf` and
+/// the VIA can sit anywhere in a memory map. `tick` clocks the timers and
+/// shift register from the CPU cycle counter; the host drives the port
+/// inputs and the C1/C2 lines and polls `irq`.
+///
+/// T1 interrupts N+1.5 cycles after it is loaded with N, rounded here to
+/// N+1, and free-runs with a period of N+2. T2 times out the same way, or
+/// in pulse counting mode on the Nth falling edge of PB6. In the T2-clocked
+/// shift modes a bit moves every 2(N+2) cycles, N being T2's low latch; the
+/// phi2 modes shift every 2 cycles.
+#[derive(Debug, Clone)]
+pub struct Via {
+    a: Side,
+    b: Side,
+    t1_counter: u16,
+    t1_latch: u16,
+    t1_armed: bool,
+    t1_reload: bool,
+    pb7: bool,
+    t2_counter: u16,
+    t2_latch: u8,
+    t2_armed: bool,
+    shift: u8,
+    shift_count: u8,
+    shift_timer: u32,
+    shift_out: bool,
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            a: Side::new(),
+            b: Side::new(),
+            t1_counter: 0xffff,
+            t1_latch: 0xffff,
+            t1_armed: false,
+            t1_reload: false,
+            pb7: true,
+            t2_counter: 0xffff,
+            t2_latch: 0xff,
+            t2_armed: false,
+            shift: 0,
+            shift_count: 0,
+            shift_timer: 0,
+            shift_out: true,
+            acr: 0,
+            pcr: 0,
+            ifr: 0,
+            ier: 0
+        }
+    }
+
+    /// The RESET line clears the port, control and interrupt registers;
+    /// the timers and shift register keep their contents.
+    pub fn reset(&mut self) {
+        let (a_input, b_input) = (self.a.input, self.b.input);
+        self.a = Side::new();
+        self.b = Side::new();
+        self.a.input = a_input;
+        self.b.input = b_input;
+        self.t1_armed = false;
+        self.t2_armed = false;
+        self.shift_count = 0;
+        self.acr = 0;
+        self.pcr = 0;
+        self.ifr = 0;
+        self.ier = 0;
+    }
+
+    fn shift_mode(&self) -> u8 {
+        (self.acr >> 2) & 0x07
+    }
+
+    fn ca2_control(&self) -> u8 {
+        (self.pcr >> 1) & 0x07
+    }
+
+    fn cb2_control(&self) -> u8 {
+        (self.pcr >> 5) & 0x07
+    }
+
+    fn port_b(&self) -> u8 {
+        let pins = self.b.pins();
+        if self.acr & ACR_T1_PB7 != 0 {
+            (pins & 0x7f) | if self.pb7 { 0x80 } else { 0x00 }
+        } else {
+            pins
+        }
+    }
+
+    /// Reads a register without the side effects of a bus read.
+    pub fn peek(&self, register: u16) -> u8 {
+        match register & 0x0f {
+            ORB => if self.acr & ACR_PB_LATCH != 0 { self.b.latch } else { self.port_b() },
+            ORA | ORA_NO_HANDSHAKE => if self.acr & ACR_PA_LATCH != 0 { self.a.latch } else { self.a.pins() },
+            DDRB => self.b.direction,
+            DDRA => self.a.direction,
+            T1C_L => self.t1_counter as u8,
+            T1C_H => (self.t1_counter >> 8) as u8,
+            T1L_L => self.t1_latch as u8,
+            T1L_H => (self.t1_latch >> 8) as u8,
+            T2C_L => self.t2_counter as u8,
+            T2C_H => (self.t2_counter >> 8) as u8,
+            SR => self.shift,
+            ACR => self.acr,
+            PCR => self.pcr,
+            IFR => if self.irq() { self.ifr | 0x80 } else { self.ifr },
+            _ => self.ier | 0x80
+        }
+    }
+
+    /// Clears the C1 and C2 flags after a port access. Independent C2
+    /// interrupts are left for the program to clear through IFR.
+    fn clear_port_flags(&mut self, port: Port) {
+        let (c1, c2, control) = match port {
+            Port::A => (IRQ_CA1, IRQ_CA2, self.ca2_control()),
+            Port::B => (IRQ_CB1, IRQ_CB2, self.cb2_control())
+        };
+        self.ifr &= !c1;
+        if control & 0x05 != 0x01 {
+            self.ifr &= !c2;
+        }
+    }
+
+    fn strobe(&mut self, port: Port) {
+        let control = match port {
+            Port::A => self.ca2_control(),
+            Port::B => self.cb2_control()
+        };
+        let side = match port {
+            Port::A => &mut self.a,
+            Port::B => &mut self.b
+        };
+        if control == C2_HANDSHAKE || control == C2_PULSE {
+            side.c2_out = false;
+            side.pulse = control == C2_PULSE;
+        }
+    }
+
+    pub fn read(&mut self, register: u16) -> u8 {
+        let value = self.peek(register);
+        match register & 0x0f {
+            ORB => self.clear_port_flags(Port::B),
+            ORA => {
+                self.clear_port_flags(Port::A);
+                self.strobe(Port::A);
+            }
+            T1C_L => self.ifr &= !IRQ_T1,
+            T2C_L => self.ifr &= !IRQ_T2,
+            SR => self.start_shift(),
+            _ => {}
+        }
+        value
+    }
+
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0x0f {
+            ORB => {
+                self.b.output = value;
+                self.clear_port_flags(Port::B);
+                self.strobe(Port::B);
+            }
+            ORA => {
+                self.a.output = value;
+                self.clear_port_flags(Port::A);
+                self.strobe(Port::A);
+            }
+            ORA_NO_HANDSHAKE => self.a.output = value,
+            DDRB => self.b.direction = value,
+            DDRA => self.a.direction = value,
+            T1C_L | T1L_L => self.t1_latch = (self.t1_latch & 0xff00) | value as u16,
+            T1C_H => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | (value as u16) << 8;
+                self.t1_counter = self.t1_latch;
+                self.t1_armed = true;
+                self.t1_reload = false;
+                self.ifr &= !IRQ_T1;
+                if self.acr & ACR_T1_PB7 != 0 {
+                    self.pb7 = false;
+                }
+            }
+            T1L_H => {
+                self.t1_latch = (self.t1_latch & 0x00ff) | (value as u16) << 8;
+                self.ifr &= !IRQ_T1;
+            }
+            T2C_L => self.t2_latch = value,
+            T2C_H => {
+                self.t2_counter = (value as u16) << 8 | self.t2_latch as u16;
+                self.t2_armed = true;
+                self.ifr &= !IRQ_T2;
+            }
+            SR => {
+                self.shift = value;
+                self.start_shift();
+            }
+            ACR => {
+                self.acr = value;
+                if self.shift_mode() == SR_OUT_FREE && self.shift_count == 0 {
+                    self.start_shift();
+                }
+            }
+            PCR => {
+                self.pcr = value;
+                for &(port, control) in &[(Port::A, self.ca2_control()), (Port::B, self.cb2_control())] {
+                    let side = match port {
+                        Port::A => &mut self.a,
+                        Port::B => &mut self.b
+                    };
+                    match control {
+                        C2_LOW => side.c2_out = false,
+                        C2_HIGH => side.c2_out = true,
+                        _ => {}
+                    }
+                }
+            }
+            IFR => self.ifr &= !value,
+            _ => {
+                if value & 0x80 != 0 {
+                    self.ier |= value & 0x7f;
+                } else {
+                    self.ier &= !value;
+                }
+            }
+        }
+    }
+
+    /// Levels the peripheral drives onto the port's input pins. On port B a
+    /// falling edge on PB6 counts down T2 in pulse counting mode.
+    pub fn set_input(&mut self, port: Port, value: u8) {
+        if port == Port::B && self.acr & ACR_T2_COUNT != 0 && self.b.input & 0x40 != 0 && value & 0x40 == 0 {
+            self.count_t2();
+        }
+        match port {
+            Port::A => self.a.input = value,
+            Port::B => self.b.input = value
+        }
+    }
+
+    /// The port's output bits, with PB7 following T1 when ACR asks for it;
+    /// input bits read as 0.
+    pub fn output(&self, port: Port) -> u8 {
+        match port {
+            Port::A => self.a.output & self.a.direction,
+            Port::B => {
+                let value = self.b.output & self.b.direction;
+                match (self.acr & ACR_T1_PB7 != 0, self.pb7) {
+                    (true, true) => value | 0x80,
+                    (true, false) => value & 0x7f,
+                    _ => value
+                }
+            }
+        }
+    }
+
+    /// Drives CA1 or CB1. Active edges set the flag, latch the port when
+    /// latching is on and end a C2 handshake; CB1 also clocks the shift
+    /// register in the external clock modes.
+    pub fn set_c1(&mut self, port: Port, level: bool) {
+        let (rising, flag, latch, control) = match port {
+            Port::A => (self.pcr & 0x01 != 0, IRQ_CA1, ACR_PA_LATCH, self.ca2_control()),
+            Port::B => (self.pcr & 0x10 != 0, IRQ_CB1, ACR_PB_LATCH, self.cb2_control())
+        };
+        let pins = match port {
+            Port::A => self.a.pins(),
+            Port::B => self.port_b()
+        };
+        let acr = self.acr;
+        let side = match port {
+            Port::A => &mut self.a,
+            Port::B => &mut self.b
+        };
+        let edge = level != side.c1;
+        side.c1 = level;
+        if edge && level == rising {
+            self.ifr |= flag;
+            if acr & latch != 0 {
+                side.latch = pins;
+            }
+            if control == C2_HANDSHAKE {
+                side.c2_out = true;
+            }
+        }
+        let mode = self.shift_mode();
+        if port == Port::B && edge && level && (mode == SR_IN_CB1 || mode == SR_OUT_CB1) && self.shift_count > 0 {
+            self.shift_bit();
+        }
+    }
+
+    /// Drives CA2 or CB2 while they are inputs. CB2 is also the data input
+    /// for the shift-in modes.
+    pub fn set_c2(&mut self, port: Port, level: bool) {
+        let (control, flag) = match port {
+            Port::A => (self.ca2_control(), IRQ_CA2),
+            Port::B => (self.cb2_control(), IRQ_CB2)
+        };
+        let side = match port {
+            Port::A => &mut self.a,
+            Port::B => &mut self.b
+        };
+        let rising = control & 0x02 != 0;
+        if control & 0x04 == 0 && level != side.c2_in && level == rising {
+            self.ifr |= flag;
+        }
+        side.c2_in = level;
+    }
+
+    /// The C2 level: the shift register's output bit on CB2 in the shift
+    /// out modes, the handshake, pulse or manual level, or high while C2 is
+    /// an input.
+    pub fn c2(&self, port: Port) -> bool {
+        match port {
+            Port::A => self.ca2_control() & 0x04 == 0 || self.a.c2_out,
+            Port::B if self.shift_mode() >= SR_OUT_FREE => self.shift_out,
+            Port::B => self.cb2_control() & 0x04 == 0 || self.b.c2_out
+        }
+    }
+
+    /// The IRQ output, active while an enabled flag is set.
+    pub fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7f != 0
+    }
+
+    fn shift_rate(&self) -> u32 {
+        match self.shift_mode() {
+            SR_IN_T2 | SR_OUT_FREE | SR_OUT_T2 => 2 * (self.t2_latch as u32 + 2),
+            SR_IN_PHI2 | SR_OUT_PHI2 => 2,
+            _ => 0
+        }
+    }
+
+    fn start_shift(&mut self) {
+        self.ifr &= !IRQ_SR;
+        if self.shift_mode() != SR_DISABLED {
+            self.shift_count = 8;
+            self.shift_timer = self.shift_rate();
+        }
+    }
+
+    fn shift_bit(&mut self) {
+        if self.shift_mode() >= SR_OUT_FREE {
+            let bit = self.shift >> 7;
+            self.shift = self.shift << 1 | bit;
+            self.shift_out = bit != 0;
+        } else {
+            self.shift = self.shift << 1 | self.b.c2_in as u8;
+        }
+        self.shift_count -= 1;
+        if self.shift_count == 0 {
+            if self.shift_mode() == SR_OUT_FREE {
+                self.shift_count = 8;
+            } else {
+                self.ifr |= IRQ_SR;
+            }
+        }
+    }
+
+    /// Timed mode interrupts when the counter rolls past zero, pulse
+    /// counting mode on the Nth pulse.
+    fn count_t2(&mut self) {
+        self.t2_counter = self.t2_counter.wrapping_sub(1);
+        let end = if self.acr & ACR_T2_COUNT != 0 { 0x0000 } else { 0xffff };
+        if self.t2_counter == end && self.t2_armed {
+            self.t2_armed = false;
+            self.ifr |= IRQ_T2;
+        }
+    }
+
+    fn cycle(&mut self) {
+        if self.t1_reload {
+            self.t1_counter = self.t1_latch;
+            self.t1_reload = false;
+        } else {
+            self.t1_counter = self.t1_counter.wrapping_sub(1);
+            if self.t1_counter == 0xffff {
+                let continuous = self.acr & ACR_T1_CONTINUOUS != 0;
+                if self.t1_armed {
+                    self.ifr |= IRQ_T1;
+                    self.pb7 = if continuous { !self.pb7 } else { true };
+                    self.t1_armed = continuous;
+                }
+                self.t1_reload = continuous;
+            }
+        }
+
+        if self.acr & ACR_T2_COUNT == 0 {
+            self.count_t2();
+        }
+
+        if self.shift_count > 0 && self.shift_timer > 0 {
+            self.shift_timer -= 1;
+            if self.shift_timer == 0 {
+                self.shift_bit();
+                self.shift_timer = self.shift_rate();
+            }
+        }
+
+        for side in [&mut self.a, &mut self.b] {
+            if side.pulse {
+                side.pulse = false;
+                side.c2_out = true;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.cycle();
+        }
+    }
+}
+
+impl Default for Via {
+    fn default() -> Via {
+        Via::new()
+    }
+}
+
+impl Device for Via {
+    fn read(&mut self, register: u16) -> u8 {
+        Via::read(self, register)
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        Via::write(self, register, value)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        Via::tick(self, cycles)
+    }
+
+    fn reset(&mut self) {
+        Via::reset(self)
+    }
+
+    /// The next timer underflow that sets a flag, shifted bit or end of a
+    /// C2 pulse.
+    fn next_event(&self) -> Option<u64> {
+        let t1 = match (self.t1_armed, self.t1_reload) {
+            (false, _) => None,
+            (true, true) => Some(self.t1_latch as u64 + 2),
+            (true, false) => Some(self.t1_counter as u64 + 1)
+        };
+        let t2 = if self.t2_armed && self.acr & ACR_T2_COUNT == 0 {
+            Some(self.t2_counter as u64 + 1)
+        } else {
+            None
+        };
+        let shift = if self.shift_count > 0 && self.shift_timer > 0 {
+            Some(self.shift_timer as u64)
+        } else {
+            None
+        };
+        let pulse = if self.a.pulse || self.b.pulse { Some(1) } else { None };
+        [t1, t2, shift, pulse].iter().flatten().min().cloned()
+    }
+
+    fn irq(&self) -> bool {
+        Via::irq(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_and_handshake() {
+        let mut via = Via::new();
+        via.write(DDRA, 0x0f);
+        via.set_input(Port::A, 0xa0);
+        via.write(ORA, 0x35);
+        assert_eq!(via.read(ORA), 0xa5);
+        assert_eq!(via.output(Port::A), 0x05);
+
+        // latched input on a rising CA1 edge, with the CA2 read handshake
+        via.write(ACR, ACR_PA_LATCH);
+        via.write(PCR, 0x01 | C2_HANDSHAKE << 1);
+        via.set_c1(Port::A, true);
+        via.set_input(Port::A, 0x00);
+        assert_eq!(via.peek(IFR) & IRQ_CA1, IRQ_CA1);
+        assert_eq!(via.read(ORA), 0xa5);
+        assert_eq!(via.peek(IFR) & IRQ_CA1, 0);
+        assert!(!via.c2(Port::A));
+        via.set_c1(Port::A, false);
+        via.set_c1(Port::A, true);
+        assert!(via.c2(Port::A));
+    }
+
+    #[test]
+    fn interrupt_enable() {
+        let mut via = Via::new();
+        via.write(PCR, 0x00);
+        via.set_c1(Port::B, true);
+        via.set_c1(Port::B, false);
+        assert_eq!(via.peek(IFR), IRQ_CB1);
+        assert!(!via.irq());
+        via.write(IER, 0x80 | IRQ_CB1 | IRQ_T1);
+        assert_eq!(via.read(IER), 0x80 | IRQ_CB1 | IRQ_T1);
+        assert!(via.irq());
+        assert_eq!(via.peek(IFR), 0x80 | IRQ_CB1);
+        via.write(IER, IRQ_CB1);
+        assert!(!via.irq());
+        via.write(IFR, IRQ_CB1);
+        assert_eq!(via.peek(IFR), 0);
+    }
+
+    #[test]
+    fn timer1_one_shot_and_free_run() {
+        let mut via = Via::new();
+        via.write(ACR, ACR_T1_PB7);
+        via.write(T1C_L, 10);
+        via.write(T1C_H, 0);
+        assert_eq!(via.output(Port::B) & 0x80, 0);
+        via.tick(10);
+        assert_eq!(via.peek(IFR) & IRQ_T1, 0);
+        via.tick(1);
+        assert_eq!(via.peek(IFR) & IRQ_T1, IRQ_T1);
+        assert_eq!(via.output(Port::B) & 0x80, 0x80);
+        via.read(T1C_L);
+        via.tick(0x10000);
+        assert_eq!(via.peek(IFR) & IRQ_T1, 0);
+
+        via.write(ACR, ACR_T1_CONTINUOUS | ACR_T1_PB7);
+        via.write(T1C_H, 0);
+        via.tick(11);
+        assert_eq!(via.peek(IFR) & IRQ_T1, IRQ_T1);
+        assert_eq!(via.output(Port::B) & 0x80, 0x80);
+        via.read(T1C_L);
+        via.tick(11);
+        assert_eq!(via.peek(IFR) & IRQ_T1, 0);
+        via.tick(1);
+        assert_eq!(via.peek(IFR) & IRQ_T1, IRQ_T1);
+        assert_eq!(via.output(Port::B) & 0x80, 0);
+    }
+
+    #[test]
+    fn timer2_counts_cycles_and_pulses() {
+        let mut via = Via::new();
+        via.write(T2C_L, 4);
+        via.write(T2C_H, 0);
+        via.tick(4);
+        assert_eq!(via.peek(IFR) & IRQ_T2, 0);
+        via.tick(1);
+        assert_eq!(via.peek(IFR) & IRQ_T2, IRQ_T2);
+        via.read(T2C_L);
+        via.tick(0x10000);
+        assert_eq!(via.peek(IFR) & IRQ_T2, 0);
+
+        via.write(ACR, ACR_T2_COUNT);
+        via.write(T2C_L, 3);
+        via.write(T2C_H, 0);
+        via.tick(100);
+        for _ in 0..2 {
+            via.set_input(Port::B, 0x40);
+            via.set_input(Port::B, 0x00);
+        }
+        assert_eq!(via.peek(IFR) & IRQ_T2, 0);
+        via.set_input(Port::B, 0x40);
+        via.set_input(Port::B, 0x00);
+        assert_eq!(via.peek(IFR) & IRQ_T2, IRQ_T2);
+    }
+
+    #[test]
+    fn shift_register() {
+        let mut via = Via::new();
+        via.write(ACR, SR_OUT_PHI2 << 2);
+        via.write(SR, 0xa5);
+        let mut bits = Vec::new();
+        for _ in 0..8 {
+            via.tick(2);
+            bits.push(via.c2(Port::B));
+        }
+        assert_eq!(bits, vec![true, false, true, false, false, true, false, true]);
+        assert_eq!(via.peek(IFR) & IRQ_SR, IRQ_SR);
+
+        via.write(ACR, SR_IN_CB1 << 2);
+        via.read(SR);
+        assert_eq!(via.peek(IFR) & IRQ_SR, 0);
+        for &bit in &[true, true, false, false, true, false, false, true] {
+            via.set_c2(Port::B, bit);
+            via.set_c1(Port::B, true);
+            via.set_c1(Port::B, false);
+        }
+        assert_eq!(via.peek(SR), 0xc9);
+        assert_eq!(via.peek(IFR) & IRQ_SR, IRQ_SR);
+    }
+}